@@ -0,0 +1,295 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::error::SyntaxError;
+use crate::interpret::{module_path, parse_program, read_lang_line, resolve_includes, FunctionInfo, FunctionMap, STDLIB_MODULES};
+use crate::interpret_function_call::check_arity;
+use crate::interpret_struct::{struct_definition, StructFunction};
+use crate::parser::{
+    constant_definition, contract_definition, function_definition, include_name, parameter_annotation, type_declaration,
+};
+use crate::reader::{read_program, Datum, DatumKind};
+use crate::tokenizer::Span;
+
+// What editors are told about a program without running it: what it defines, the modules it
+// includes, and the errors that reading and parsing it find.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DefinitionKind {
+    Function,
+    Constant,
+    Struct,
+    Macro,
+}
+
+#[derive(Debug, Clone)]
+pub struct Definition {
+    pub name: String,
+    pub kind: DefinitionKind,
+    // How the definition is used, such as (list::nth lst n) for a function.
+    pub signature: String,
+    // The type given by a (: name Type) declaration.
+    pub declared_type: Option<String>,
+    // The whole definition, and the name in it.
+    pub span: Span,
+    pub name_span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Module {
+    pub name: String,
+    pub path: PathBuf,
+    pub datums: Vec<Datum>,
+    pub definitions: Vec<Definition>,
+}
+
+// An error in the program, from reading or parsing it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+}
+
+impl From<SyntaxError> for Diagnostic {
+    fn from(error: SyntaxError) -> Self {
+        Diagnostic {
+            span: error.span,
+            message: error.message,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Analysis {
+    // The program with its #lang line blanked out, which is the text the spans refer to.
+    pub text: String,
+    pub definitions: Vec<Definition>,
+    // The standard library modules the program includes, directly or through other modules.
+    pub modules: Vec<Module>,
+    // Every function the program can call, from the function map of the parsed program.
+    pub functions: Vec<String>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Analysis {
+    // The definition of a name in the program, or else in one of the modules it includes, with the
+    // module it was found in.
+    pub fn find_definition(&self, name: &str) -> Option<(Option<&Module>, &Definition)> {
+        if let Some(definition) = self.definitions.iter().find(|definition| definition.name == name) {
+            return Some((None, definition));
+        }
+        self.modules.iter().find_map(|module| {
+            let definition = module.definitions.iter().find(|definition| definition.name == name)?;
+            Some((Some(module), definition))
+        })
+    }
+}
+
+pub fn analyze(source: &str) -> Analysis {
+    let mut analysis = Analysis {
+        text: source.to_string(),
+        ..Analysis::default()
+    };
+    let text = match read_lang_line(source.to_string()) {
+        Ok((_, text)) => text,
+        Err(error) => {
+            analysis.diagnostics.push(Diagnostic::from(error));
+            return analysis;
+        }
+    };
+    analysis.text = text.clone();
+    let datums = match read_program(text.clone()) {
+        Ok(datums) => datums,
+        Err(error) => {
+            analysis.diagnostics.push(Diagnostic::from(error));
+            return analysis;
+        }
+    };
+    // Malformed definitions are left out here and reported by the parser below.
+    analysis.definitions = definitions(&datums);
+    analysis.modules = included_modules(&datums);
+
+    for datum in &datums {
+        match include_name(datum) {
+            Some(name) if !STDLIB_MODULES.contains(&name) => analysis.diagnostics.push(Diagnostic {
+                span: datum.span,
+                message: format!("Unknown module name {}", name),
+            }),
+            _ => {}
+        }
+    }
+    // Including an unknown module stops the parser before it finds anything else.
+    if !analysis.diagnostics.is_empty() {
+        return analysis;
+    }
+
+    match resolve_includes(text, &HashMap::new()).and_then(|(program, _)| parse_program(program)) {
+        Ok((function_map, _, _, checks)) => {
+            let malformed = checks.into_iter().filter_map(Result::err);
+            analysis
+                .diagnostics
+                .extend(malformed.map(|malformed| Diagnostic::from(malformed.error)));
+            for datum in &datums {
+                check_calls(datum, &function_map, &mut Vec::new(), &mut analysis.diagnostics);
+            }
+            analysis.functions = function_map.into_keys().collect();
+            analysis.functions.sort();
+        }
+        Err(error) => analysis.diagnostics.push(Diagnostic::from(error)),
+    }
+    analysis
+}
+
+// The top level definitions of a program or module, in order.
+pub fn definitions(datums: &[Datum]) -> Vec<Definition> {
+    let declarations: HashMap<&str, &Datum> = datums.iter().filter_map(type_declaration).collect();
+    let mut definitions = Vec::new();
+    for datum in datums {
+        let define = contract_definition(datum).map(|(define, _)| define);
+        let define = define.as_ref().unwrap_or(datum);
+        let items = match datum.as_list() {
+            Some(items) if items.len() >= 2 => items,
+            _ => continue,
+        };
+        let (name, kind, signature, name_span) = if let Some((name, _)) = function_definition(define) {
+            let parameters = define.as_list().unwrap()[1].as_list().unwrap()[1..]
+                .iter()
+                .filter_map(|parameter| Some(parameter_annotation(parameter)?.0));
+            let signature = format!("({})", [name].into_iter().chain(parameters).collect::<Vec<_>>().join(" "));
+            (name, DefinitionKind::Function, signature, items[1].as_list().unwrap()[0].span)
+        } else if let Some((name, _)) = constant_definition(datum) {
+            (name, DefinitionKind::Constant, name.to_string(), items[1].span)
+        } else if let Some((name, _)) = struct_definition(datum) {
+            (name, DefinitionKind::Struct, datum.to_string(), items[1].span)
+        } else if items[0].as_symbol() == Some("define-syntax") && items[1].as_symbol().is_some() {
+            let name = items[1].as_symbol().unwrap();
+            (name, DefinitionKind::Macro, name.to_string(), items[1].span)
+        } else {
+            continue;
+        };
+        definitions.push(Definition {
+            name: name.to_string(),
+            kind,
+            signature,
+            declared_type: declarations.get(name).map(|ty| ty.to_string()),
+            span: datum.span,
+            name_span,
+        });
+    }
+    definitions
+}
+
+// The standard library modules included by the datums and by the modules they include. Modules that
+// cannot be read are left out.
+pub fn included_modules(datums: &[Datum]) -> Vec<Module> {
+    let mut names: Vec<String> = datums.iter().filter_map(include_name).map(str::to_string).collect();
+    let mut seen = HashSet::new();
+    let mut modules = Vec::new();
+    while let Some(name) = names.pop() {
+        if !STDLIB_MODULES.contains(&name.as_str()) || !seen.insert(name.clone()) {
+            continue;
+        }
+        let path = module_path(&name);
+        let Some(datums) = fs::read_to_string(&path).ok().and_then(|text| read_program(text).ok()) else {
+            continue;
+        };
+        names.extend(datums.iter().filter_map(include_name).map(str::to_string));
+        modules.push(Module {
+            name,
+            path,
+            definitions: definitions(&datums),
+            datums,
+        });
+    }
+    modules
+}
+
+// The number of positional arguments a function takes.
+fn arity(function: &FunctionInfo) -> usize {
+    match function {
+        FunctionInfo::Defined { parameter_names, .. } => parameter_names.len(),
+        FunctionInfo::Struct(StructFunction::Constructor(struct_type)) => struct_type.fields.len(),
+        FunctionInfo::Struct(_) => 1,
+        FunctionInfo::Native(native) => native.arity,
+    }
+}
+
+// Reports calls to functions in the function map with the wrong number of arguments, which would
+// otherwise only be found when the call runs. `bound` holds the parameters in scope, which hide
+// functions of the same name.
+fn check_calls(datum: &Datum, function_map: &FunctionMap, bound: &mut Vec<String>, diagnostics: &mut Vec<Diagnostic>) {
+    let Some(items) = datum.as_list() else {
+        return;
+    };
+    let check_all = |items: &[Datum], bound: &mut Vec<String>, diagnostics: &mut Vec<Diagnostic>| {
+        for item in items {
+            check_calls(item, function_map, bound, diagnostics);
+        }
+    };
+    // Checks the body forms with the parameters in scope.
+    let check_body = |parameters: &[Datum], body: &[Datum], bound: &mut Vec<String>, diagnostics: &mut Vec<Diagnostic>| {
+        let scope = bound.len();
+        bound.extend(
+            parameters
+                .iter()
+                .filter_map(|parameter| Some(parameter_annotation(parameter)?.0.to_string())),
+        );
+        check_all(body, bound, diagnostics);
+        bound.truncate(scope);
+    };
+
+    match items.first().and_then(|head| head.as_symbol()) {
+        // Data, types and macro templates are not calls.
+        Some("quote" | "quasiquote" | "define-syntax" | "struct" | ":" | "provide") => {}
+        Some("define/contract") => {
+            if let Some((define, _)) = contract_definition(datum) {
+                check_calls(&define, function_map, bound, diagnostics);
+            }
+        }
+        Some("define") if items.len() > 2 => match items[1].as_list() {
+            Some(signature) if !signature.is_empty() => check_body(&signature[1..], &items[2..], bound, diagnostics),
+            _ => check_all(&items[2..], bound, diagnostics),
+        },
+        Some("lambda") if items.len() > 2 => check_body(list_items(&items[1]), &items[2..], bound, diagnostics),
+        // Clauses and handlers are written in brackets but are not calls.
+        Some("cond") => {
+            for clause in &items[1..] {
+                check_all(list_items(clause), bound, diagnostics)
+            }
+        }
+        Some("with-handlers") if items.len() > 1 => {
+            for handler in list_items(&items[1]) {
+                check_all(list_items(handler), bound, diagnostics)
+            }
+            check_all(&items[2..], bound, diagnostics)
+        }
+        Some(name) => {
+            if let Some(function) = function_map.get(name).filter(|_| !bound.iter().any(|bound| bound == name)) {
+                let mut arguments = 0;
+                let mut rest = items[1..].iter();
+                while let Some(argument) = rest.next() {
+                    match argument.kind {
+                        DatumKind::Keyword(_) => {
+                            rest.next();
+                        }
+                        _ => arguments += 1,
+                    }
+                }
+                if let Err(error) = check_arity(name, arity(function), arguments) {
+                    diagnostics.push(Diagnostic {
+                        span: datum.span,
+                        message: error.to_string(),
+                    });
+                }
+            }
+            check_all(&items[1..], bound, diagnostics)
+        }
+        None => check_all(items, bound, diagnostics),
+    }
+}
+
+// The items of a list, or nothing for other datums.
+fn list_items(datum: &Datum) -> &[Datum] {
+    datum.as_list().map_or(&[], |items| items.as_slice())
+}
@@ -0,0 +1,105 @@
+use crate::error::{EvalResult, RacketError};
+use crate::interpret::{Value, ValueList, N};
+
+// Conversions between Rust values and interpreter values, for passing arguments to and reading results
+// from an Interpreter. Conversions fail with a contract error when the value does not fit.
+pub trait IntoValue {
+    fn into_value(self) -> EvalResult;
+}
+
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> EvalResult<Self>;
+}
+
+fn unexpected<T>(expected: &str, value: &Value) -> EvalResult<T> {
+    Err(RacketError::contract(format!("Expected {} but got {}", expected, value)))
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> EvalResult {
+        Ok(self)
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> EvalResult<Self> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> EvalResult {
+        match N::try_from(self) {
+            Ok(n) => Ok(Value::NumValue(n)),
+            Err(_) => Err(RacketError::contract(format!(
+                "Integer {} is too large to be a number value",
+                self
+            ))),
+        }
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Value) -> EvalResult<Self> {
+        match value {
+            Value::NumValue(n) => Ok(*n as i64),
+            x => unexpected("an integer", x),
+        }
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> EvalResult {
+        Ok(Value::BoolValue(self))
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> EvalResult<Self> {
+        match value {
+            Value::BoolValue(b) => Ok(*b),
+            x => unexpected("a boolean", x),
+        }
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> EvalResult {
+        Ok(Value::StringValue(self))
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> EvalResult {
+        Ok(Value::StringValue(self.to_string()))
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> EvalResult<Self> {
+        match value {
+            Value::StringValue(s) => Ok(s.clone()),
+            x => unexpected("a string", x),
+        }
+    }
+}
+
+// Vectors convert to and from lists.
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> EvalResult {
+        let values = self
+            .into_iter()
+            .map(IntoValue::into_value)
+            .collect::<EvalResult<Vec<Value>>>()?;
+        Ok(Value::ListValue(ValueList::from_vec(values)))
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> EvalResult<Self> {
+        match value {
+            Value::ListValue(list) => list.to_vec().iter().map(T::from_value).collect(),
+            x => unexpected("a list", x),
+        }
+    }
+}
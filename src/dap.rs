@@ -0,0 +1,386 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use serde_json::{json, Value as Json};
+
+use crate::debugger::{with_debugger, Breakpoint, DebugHandler, Pause, Resume};
+use crate::interpret::{
+    interpret_racket_program, module_path, read_lang_line, source_module, try_interpret_program, Language, Value,
+};
+use crate::protocol::{read_message, write_message};
+use crate::racket_printer::to_racket_string;
+use crate::stack_trace::CallSite;
+
+// The interpreter runs programs on a single thread, which is the only thread clients see.
+const THREAD_ID: i64 = 1;
+// Variable reference of the bindings visible in the paused frame. Larger references are values
+// whose elements can be expanded.
+const LOCALS_REFERENCE: usize = 1;
+
+struct Connection {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    seq: i64,
+    // Set once the client disconnects, after which nothing more is sent.
+    disconnected: bool,
+}
+
+impl Connection {
+    fn read_message(&mut self) -> Option<Json> {
+        read_message(&mut self.input)
+    }
+
+    fn send(&mut self, mut message: Json) {
+        if self.disconnected {
+            return;
+        }
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        // A client that stopped reading is handled when its next request is read.
+        let _ = write_message(&mut self.output, &message);
+    }
+
+    fn respond(&mut self, request: &Json, body: Json) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }));
+    }
+
+    fn respond_error(&mut self, request: &Json, message: &str) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }));
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+}
+
+// Serves the Debug Adapter Protocol until the client disconnects. The client launches a program with
+// {"program": path, "stopOnEntry": bool}, which runs once configuration is done.
+pub fn run_dap_server(input: impl BufRead + 'static, output: impl Write + 'static) {
+    let connection = Rc::new(RefCell::new(Connection {
+        input: Box::new(input),
+        output: Box::new(output),
+        seq: 0,
+        disconnected: false,
+    }));
+    let mut breakpoints = Vec::new();
+    let mut launch = None;
+
+    loop {
+        let Some(request) = connection.borrow_mut().read_message() else {
+            return;
+        };
+        let arguments = &request["arguments"];
+        let mut connection_ref = connection.borrow_mut();
+        match request["command"].as_str().unwrap_or_default() {
+            "initialize" => {
+                connection_ref.respond(
+                    &request,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsFunctionBreakpoints": true,
+                        "supportsEvaluateForHovers": true,
+                    }),
+                );
+                connection_ref.event("initialized", json!({}));
+            }
+            "launch" => match arguments["program"].as_str() {
+                Some(program) => {
+                    launch = Some((program.to_string(), arguments["stopOnEntry"].as_bool().unwrap_or(false)));
+                    connection_ref.respond(&request, json!({}));
+                }
+                None => connection_ref.respond_error(&request, "Expected the path of a program to launch"),
+            },
+            "setBreakpoints" | "setFunctionBreakpoints" => {
+                let body = set_breakpoints(&mut breakpoints, &request);
+                connection_ref.respond(&request, body);
+            }
+            "setExceptionBreakpoints" => connection_ref.respond(&request, json!({})),
+            "threads" => connection_ref.respond(&request, threads()),
+            "configurationDone" => {
+                connection_ref.respond(&request, json!({}));
+                drop(connection_ref);
+                match launch.take() {
+                    Some((program_path, stop_on_entry)) => {
+                        run_program(&connection, &program_path, stop_on_entry, breakpoints.clone())
+                    }
+                    None => connection.borrow_mut().event("terminated", json!({})),
+                }
+            }
+            "disconnect" => {
+                connection_ref.respond(&request, json!({}));
+                return;
+            }
+            command => connection_ref.respond_error(&request, &format!("Unsupported request {}", command)),
+        }
+    }
+}
+
+fn threads() -> Json {
+    json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })
+}
+
+// Runs the program with the debugger attached, reporting its results as output.
+fn run_program(connection: &Rc<RefCell<Connection>>, program_path: &str, stop_on_entry: bool, breakpoints: Vec<Breakpoint>) {
+    let handler = DapHandler {
+        connection: connection.clone(),
+        program_path: program_path.to_string(),
+        stop_on_entry,
+        values: Vec::new(),
+    };
+    let resume = if stop_on_entry { Resume::StepIn } else { Resume::Continue };
+
+    let results = fs::read_to_string(program_path)
+        .map_err(|error| format!("Unable to read {}: {}", program_path, error))
+        .map(|program| {
+            with_debugger(Box::new(handler), breakpoints, resume, || {
+                match read_lang_line(program.clone()) {
+                    Ok((Language::RustyRacket, _)) => vec![try_interpret_program(program)],
+                    Ok((Language::Racket, _)) => interpret_racket_program(program),
+                    Err(error) => vec![Err(error.into())],
+                }
+            })
+        });
+
+    let mut connection = connection.borrow_mut();
+    let mut exit_code = 0;
+    let mut output = |category: &str, text: String| {
+        connection.event("output", json!({ "category": category, "output": text + "\n" }));
+    };
+    match results {
+        Ok(results) => {
+            for result in results {
+                match result {
+                    Ok(value) => output("stdout", to_racket_string(&value)),
+                    Err(error) => {
+                        exit_code = 1;
+                        output("stderr", format!("{}\n{}", error, error.backtrace()))
+                    }
+                }
+            }
+        }
+        Err(message) => {
+            exit_code = 1;
+            output("stderr", message)
+        }
+    }
+    connection.event("exited", json!({ "exitCode": exit_code }));
+    connection.event("terminated", json!({}));
+}
+
+// Replaces the breakpoints of one source, or the function breakpoints, returning the response body.
+fn set_breakpoints(breakpoints: &mut Vec<Breakpoint>, request: &Json) -> Json {
+    let arguments = &request["arguments"];
+    let requested = arguments["breakpoints"].as_array().cloned().unwrap_or_default();
+
+    if request["command"] == "setFunctionBreakpoints" {
+        breakpoints.retain(|breakpoint| !matches!(breakpoint, Breakpoint::Function(_)));
+        let names: Vec<&str> = requested
+            .iter()
+            .filter_map(|breakpoint| breakpoint["name"].as_str())
+            .collect();
+        breakpoints.extend(names.iter().map(|name| Breakpoint::Function(name.to_string())));
+        return json!({ "breakpoints": names.iter().map(|_| json!({ "verified": true })).collect::<Vec<Json>>() });
+    }
+
+    let module = source_module(arguments["source"]["path"].as_str().unwrap_or_default());
+    breakpoints.retain(|breakpoint| !matches!(breakpoint, Breakpoint::Line { module: existing, .. } if *existing == module));
+    let lines: Vec<usize> = requested
+        .iter()
+        .filter_map(|breakpoint| breakpoint["line"].as_u64())
+        .map(|line| line as usize)
+        .collect();
+    breakpoints.extend(lines.iter().map(|line| Breakpoint::Line {
+        module: module.clone(),
+        line: *line,
+    }));
+    json!({ "breakpoints": lines.iter().map(|line| json!({ "verified": true, "line": line })).collect::<Vec<Json>>() })
+}
+
+struct DapHandler {
+    connection: Rc<RefCell<Connection>>,
+    program_path: String,
+    stop_on_entry: bool,
+    // Values the client can expand, by variable reference minus LOCALS_REFERENCE + 1. References are
+    // only valid while the program stays paused.
+    values: Vec<Value>,
+}
+
+impl DebugHandler for DapHandler {
+    fn paused(&mut self, pause: &mut Pause) -> Resume {
+        let reason = if std::mem::take(&mut self.stop_on_entry) {
+            "entry"
+        } else if pause.breakpoints.iter().any(|breakpoint| breakpoint.hit_by(&pause.event)) {
+            "breakpoint"
+        } else {
+            "step"
+        };
+        self.values.clear();
+        let connection = self.connection.clone();
+        connection.borrow_mut().event(
+            "stopped",
+            json!({ "reason": reason, "description": pause.event.to_string(), "threadId": THREAD_ID, "allThreadsStopped": true }),
+        );
+
+        loop {
+            let request = connection.borrow_mut().read_message();
+            let Some(request) = request else {
+                return self.disconnect(pause);
+            };
+            let arguments = &request["arguments"];
+            let body = match request["command"].as_str().unwrap_or_default() {
+                "threads" => threads(),
+                "stackTrace" => self.stack_trace(pause),
+                "scopes" if arguments["frameId"] == 0 => {
+                    json!({ "scopes": [{ "name": "Locals", "variablesReference": LOCALS_REFERENCE, "expensive": false }] })
+                }
+                // Scoping is dynamic, so only the innermost frame's bindings are known.
+                "scopes" => json!({ "scopes": [] }),
+                "variables" => self.variables(pause, arguments["variablesReference"].as_u64().unwrap_or_default() as usize),
+                "evaluate" => match pause.eval(arguments["expression"].as_str().unwrap_or_default()) {
+                    Ok(value) => {
+                        let variable = self.variable("", value);
+                        json!({ "result": variable["value"], "variablesReference": variable["variablesReference"] })
+                    }
+                    Err(message) => {
+                        connection.borrow_mut().respond_error(&request, &message);
+                        continue;
+                    }
+                },
+                "setBreakpoints" | "setFunctionBreakpoints" => set_breakpoints(pause.breakpoints, &request),
+                "continue" => {
+                    connection
+                        .borrow_mut()
+                        .respond(&request, json!({ "allThreadsContinued": true }));
+                    return Resume::Continue;
+                }
+                command @ ("next" | "stepIn" | "stepOut") => {
+                    connection.borrow_mut().respond(&request, json!({}));
+                    return match command {
+                        "next" => Resume::StepOver,
+                        "stepIn" => Resume::StepIn,
+                        _ => Resume::StepOut,
+                    };
+                }
+                "disconnect" => {
+                    connection.borrow_mut().respond(&request, json!({}));
+                    return self.disconnect(pause);
+                }
+                command => {
+                    connection
+                        .borrow_mut()
+                        .respond_error(&request, &format!("Unsupported request {} while paused", command));
+                    continue;
+                }
+            };
+            connection.borrow_mut().respond(&request, body);
+        }
+    }
+}
+
+impl DapHandler {
+    // Lets the program finish without pausing again or sending anything.
+    fn disconnect(&mut self, pause: &mut Pause) -> Resume {
+        self.connection.borrow_mut().disconnected = true;
+        pause.breakpoints.clear();
+        Resume::Continue
+    }
+
+    // The innermost frame is where the program is paused, and every other frame is where it called
+    // the frame inside it.
+    fn stack_trace(&self, pause: &Pause) -> Json {
+        let mut location = Some(pause.event.call_site().clone());
+        let frames: Vec<Json> = pause
+            .stack()
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(id, frame)| {
+                let frame_location = std::mem::replace(&mut location, frame.call_site.clone());
+                let mut json = json!({ "id": id, "name": frame.function, "line": 0, "column": 0 });
+                if let Some(call_site) = frame_location {
+                    json["line"] = json!(call_site.span.line);
+                    json["column"] = json!(call_site.span.column);
+                    json["source"] = self.source(&call_site);
+                }
+                json
+            })
+            .collect();
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    fn source(&self, call_site: &CallSite) -> Json {
+        match &call_site.module {
+            Some(module) => {
+                let path = module_path(module);
+                json!({ "name": module, "path": path.to_string_lossy() })
+            }
+            None => {
+                let name = Path::new(&self.program_path)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string());
+                json!({ "name": name, "path": self.program_path })
+            }
+        }
+    }
+
+    fn variables(&mut self, pause: &Pause, reference: usize) -> Json {
+        let children: Vec<(String, Value)> = if reference == LOCALS_REFERENCE {
+            pause.bindings()
+        } else {
+            match self.values.get(reference.wrapping_sub(LOCALS_REFERENCE + 1)).cloned() {
+                Some(Value::ListValue(list)) => indexed(list.to_vec()),
+                Some(Value::VectorValue(vector)) => indexed(vector.to_vec()),
+                Some(Value::StructValue(instance)) => indexed(instance.fields.to_vec()),
+                Some(Value::HashValue(hash)) => hash.iter().map(|(key, value)| (key.to_string(), value.clone())).collect(),
+                _ => Vec::new(),
+            }
+        };
+        let variables: Vec<Json> = children
+            .into_iter()
+            .map(|(name, value)| self.variable(&name, value))
+            .collect();
+        json!({ "variables": variables })
+    }
+
+    // A variable the client shows, with a reference to expand it by if it has elements.
+    fn variable(&mut self, name: &str, value: Value) -> Json {
+        let expandable = match &value {
+            Value::ListValue(list) => !list.to_vec().is_empty(),
+            Value::VectorValue(vector) => !vector.is_empty(),
+            Value::HashValue(hash) => !hash.is_empty(),
+            Value::StructValue(instance) => !instance.fields.is_empty(),
+            _ => false,
+        };
+        let text = value.to_string();
+        let reference = if expandable {
+            self.values.push(value);
+            self.values.len() + LOCALS_REFERENCE
+        } else {
+            0
+        };
+        json!({ "name": name, "value": text, "variablesReference": reference })
+    }
+}
+
+fn indexed(values: Vec<Value>) -> Vec<(String, Value)> {
+    values
+        .into_iter()
+        .enumerate()
+        .map(|(i, value)| (format!("[{}]", i), value))
+        .collect()
+}
@@ -0,0 +1,138 @@
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use crate::debugger::{Breakpoint, DebugHandler, Pause, PauseEvent, Resume};
+use crate::interpret::STDLIB_MODULES;
+
+const HELP: &str = "\
+Commands:
+  step (s)              pause at the next call or return
+  next (n)              pause at the next call or return in this function
+  out (o)               pause when this function returns
+  continue (c)          run until the next breakpoint
+  break (b) <where>     pause before calls to a function or calls on <file>:<line>
+  delete (d) [<where>]  remove a breakpoint, or all of them
+  breakpoints           list the breakpoints
+  bindings (l)          show the variables that are visible
+  backtrace (bt)        show the calls that are running
+  print (p) <expr>      evaluate an expression here
+  quit (q)              stop debugging and let the program finish";
+
+// The debugger of `--debug`, which reads commands from `input` whenever the program pauses.
+pub struct DebugConsole<R, W> {
+    input: R,
+    output: W,
+    // The program being debugged, which `<file>:<line>` breakpoints can name.
+    program_path: String,
+}
+
+impl<R: BufRead, W: Write> DebugConsole<R, W> {
+    pub fn new(input: R, output: W, program_path: &str) -> Self {
+        DebugConsole {
+            input,
+            output,
+            program_path: program_path.to_string(),
+        }
+    }
+
+    // Runs commands until one resumes the program. The end of the input quits.
+    fn run_commands(&mut self, pause: &mut Pause) -> std::io::Result<Resume> {
+        loop {
+            write!(self.output, "(debug) ")?;
+            self.output.flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                writeln!(self.output)?;
+                pause.breakpoints.clear();
+                return Ok(Resume::Continue);
+            }
+
+            let (command, argument) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+            let argument = argument.trim();
+            match command {
+                "" => {}
+                "s" | "step" => return Ok(Resume::StepIn),
+                "n" | "next" => return Ok(Resume::StepOver),
+                "o" | "out" => return Ok(Resume::StepOut),
+                "c" | "continue" => return Ok(Resume::Continue),
+                "q" | "quit" => {
+                    pause.breakpoints.clear();
+                    return Ok(Resume::Continue);
+                }
+                "b" | "break" => match parse_breakpoint(argument, &self.program_path) {
+                    Ok(breakpoint) => {
+                        writeln!(self.output, "Breakpoint at {}", breakpoint)?;
+                        if !pause.breakpoints.contains(&breakpoint) {
+                            pause.breakpoints.push(breakpoint);
+                        }
+                    }
+                    Err(message) => writeln!(self.output, "{}", message)?,
+                },
+                "d" | "delete" if argument.is_empty() => pause.breakpoints.clear(),
+                "d" | "delete" => match parse_breakpoint(argument, &self.program_path) {
+                    Ok(breakpoint) if pause.breakpoints.contains(&breakpoint) => {
+                        pause.breakpoints.retain(|existing| *existing != breakpoint)
+                    }
+                    Ok(breakpoint) => writeln!(self.output, "No breakpoint at {}", breakpoint)?,
+                    Err(message) => writeln!(self.output, "{}", message)?,
+                },
+                "breakpoints" => {
+                    for breakpoint in pause.breakpoints.iter() {
+                        writeln!(self.output, "{}", breakpoint)?;
+                    }
+                }
+                "l" | "bindings" => {
+                    for (name, value) in pause.bindings() {
+                        writeln!(self.output, "{} = {}", name, value)?;
+                    }
+                }
+                "bt" | "backtrace" => writeln!(self.output, "{}", pause.stack())?,
+                "p" | "print" => match pause.eval(argument) {
+                    Ok(value) => writeln!(self.output, "{}", value)?,
+                    Err(message) => writeln!(self.output, "error: {}", message)?,
+                },
+                "h" | "help" => writeln!(self.output, "{}", HELP)?,
+                _ => writeln!(self.output, "Unknown command {}. Type help for a list of commands.", command)?,
+            }
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> DebugHandler for DebugConsole<R, W> {
+    fn paused(&mut self, pause: &mut Pause) -> Resume {
+        let arrow = match pause.event {
+            PauseEvent::Call { .. } => "->",
+            PauseEvent::Return { .. } => "<-",
+        };
+        let result = writeln!(self.output, "{} {}", arrow, pause.event).and_then(|_| self.run_commands(pause));
+        // Without a console there is nothing left to debug with.
+        result.unwrap_or_else(|_| {
+            pause.breakpoints.clear();
+            Resume::Continue
+        })
+    }
+}
+
+// A function name, or <file>:<line> where the file is the program or a standard library module such
+// as stdlib::list.
+pub fn parse_breakpoint(spec: &str, program_path: &str) -> Result<Breakpoint, String> {
+    if let Some((file, line)) = spec.rsplit_once(':') {
+        if let Ok(line) = line.parse::<usize>() {
+            let file_name = Path::new(program_path).file_name().and_then(|name| name.to_str());
+            return if STDLIB_MODULES.contains(&file) {
+                Ok(Breakpoint::Line {
+                    module: Some(file.to_string()),
+                    line,
+                })
+            } else if file == program_path || Some(file) == file_name {
+                Ok(Breakpoint::Line { module: None, line })
+            } else {
+                Err(format!("Unknown file {}", file))
+            };
+        }
+    }
+    match spec {
+        "" => Err("Expected a function name or <file>:<line>".to_string()),
+        name => Ok(Breakpoint::Function(name.to_string())),
+    }
+}
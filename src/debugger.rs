@@ -0,0 +1,265 @@
+use std::cell::RefCell;
+use std::fmt;
+
+use crate::interpret::{interpret, FunctionMap, Value, VariableMap};
+use crate::parser::parse;
+use crate::stack_trace::{backtrace, depth, Backtrace, CallSite};
+
+// Where the program should pause. Lines are in the program itself, or in the module named by `module`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breakpoint {
+    // Pauses before every call to the function.
+    Function(String),
+    // Pauses before every call made on the line.
+    Line { module: Option<String>, line: usize },
+}
+
+impl Breakpoint {
+    // Whether the program pauses at the event because of this breakpoint.
+    pub fn hit_by(&self, event: &PauseEvent) -> bool {
+        let PauseEvent::Call { function, call_site, .. } = event else {
+            return false;
+        };
+        match self {
+            Breakpoint::Function(name) => name == function,
+            Breakpoint::Line { module, line } => *module == call_site.module && *line == call_site.span.line,
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Function(name) => write!(f, "{}", name),
+            Breakpoint::Line {
+                module: Some(module),
+                line,
+            } => write!(f, "{}:{}", module, line),
+            Breakpoint::Line { module: None, line } => write!(f, "line {}", line),
+        }
+    }
+}
+
+// How a paused program continues. Stepping is by calls: the program pauses before a function is
+// called and after it returns.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resume {
+    // Runs until the next breakpoint.
+    Continue,
+    // Pauses at the next call or return, wherever it is.
+    StepIn,
+    // Pauses at the next call or return in the function that is paused, without going into calls.
+    StepOver,
+    // Pauses when the function that is paused returns.
+    StepOut,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PauseEvent {
+    // A function is about to be called with arguments that have already been evaluated.
+    Call {
+        function: String,
+        arguments: Vec<Value>,
+        call_site: CallSite,
+    },
+    // A function called from `call_site` has returned to the function that is paused.
+    Return {
+        function: String,
+        result: Value,
+        call_site: CallSite,
+    },
+}
+
+impl fmt::Display for PauseEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PauseEvent::Call {
+                function,
+                arguments,
+                call_site,
+            } => {
+                write!(f, "{}: ({}", call_site, function)?;
+                for argument in arguments {
+                    write!(f, " {}", argument)?;
+                }
+                write!(f, ")")
+            }
+            PauseEvent::Return { function, result, .. } => write!(f, "{} returned {}", function, result),
+        }
+    }
+}
+
+impl PauseEvent {
+    // Where the program is paused.
+    pub fn call_site(&self) -> &CallSite {
+        match self {
+            PauseEvent::Call { call_site, .. } | PauseEvent::Return { call_site, .. } => call_site,
+        }
+    }
+}
+
+// The state of a paused program, which a debugger can inspect and evaluate expressions in.
+pub struct Pause<'a> {
+    pub event: PauseEvent,
+    // Breakpoints can be changed while the program is paused.
+    pub breakpoints: &'a mut Vec<Breakpoint>,
+    variable_map: &'a mut VariableMap,
+    function_map: &'a FunctionMap,
+}
+
+impl Pause<'_> {
+    // The calls running in the paused program, innermost first. A call that is about to be made is
+    // not on the stack yet.
+    pub fn stack(&self) -> Backtrace {
+        backtrace()
+    }
+
+    // The variables visible where the program is paused, by name.
+    pub fn bindings(&self) -> Vec<(String, Value)> {
+        let mut bindings: Vec<(String, Value)> = self
+            .variable_map
+            .iter()
+            .filter_map(|(name, values)| Some((name.clone(), values.last()?.clone())))
+            .collect();
+        bindings.sort_by(|a, b| a.0.cmp(&b.0));
+        bindings
+    }
+
+    // Evaluates an expression where the program is paused. Breakpoints do not apply while it runs.
+    pub fn eval(&mut self, source: &str) -> Result<Value, String> {
+        let expr = parse(source.to_string()).map_err(|error| error.to_string())?;
+        interpret(&expr, self.variable_map, self.function_map).map_err(|error| error.to_string())
+    }
+}
+
+// A front end for the debugger, such as the console or a DAP client.
+pub trait DebugHandler {
+    // Called whenever the program pauses. Returns how the program continues.
+    fn paused(&mut self, pause: &mut Pause) -> Resume;
+}
+
+struct Debugger {
+    handler: Box<dyn DebugHandler>,
+    breakpoints: Vec<Breakpoint>,
+    resume: Resume,
+    // The depth of the stack when the program last paused, which stepping over and out are relative to.
+    depth: usize,
+}
+
+thread_local! {
+    // The debugger of the evaluation running on this thread, if it is being debugged. It is taken out
+    // while the program is paused, so expressions evaluated then do not pause.
+    static DEBUGGER: RefCell<Option<Debugger>> = const { RefCell::new(None) };
+}
+
+// Runs f with the debugger attached to all evaluation it does on this thread. With Resume::StepIn the
+// program pauses at its first call.
+pub fn with_debugger<T>(
+    handler: Box<dyn DebugHandler>,
+    breakpoints: Vec<Breakpoint>,
+    resume: Resume,
+    f: impl FnOnce() -> T,
+) -> T {
+    // Detaches the debugger even if f panics.
+    struct Detach;
+    impl Drop for Detach {
+        fn drop(&mut self) {
+            DEBUGGER.with(|debugger| debugger.borrow_mut().take());
+        }
+    }
+
+    let debugger = Debugger {
+        handler,
+        breakpoints,
+        resume,
+        depth: 0,
+    };
+    DEBUGGER.with(|current| *current.borrow_mut() = Some(debugger));
+    let _detach = Detach;
+    f()
+}
+
+// Called by function calls before the function is entered.
+pub fn before_call(
+    function: &str,
+    arguments: &[Value],
+    call_site: CallSite,
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) {
+    // Building the event clones the arguments, so it is only built when a debugger is attached.
+    if DEBUGGER.with(|debugger| debugger.borrow().is_none()) {
+        return;
+    }
+    let event = PauseEvent::Call {
+        function: function.to_string(),
+        arguments: arguments.to_vec(),
+        call_site,
+    };
+    pause_if(
+        |debugger, depth| debugger.breakpoints.iter().any(|breakpoint| breakpoint.hit_by(&event)) || debugger.steps_to(depth),
+        || event.clone(),
+        variable_map,
+        function_map,
+    )
+}
+
+// Called by function calls after the function returned a result.
+pub fn after_call(
+    function: &str,
+    result: &Value,
+    call_site: CallSite,
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) {
+    pause_if(
+        |debugger, depth| debugger.steps_to(depth),
+        || PauseEvent::Return {
+            function: function.to_string(),
+            result: result.clone(),
+            call_site,
+        },
+        variable_map,
+        function_map,
+    )
+}
+
+impl Debugger {
+    // Whether stepping stops at an event in the function at the given depth.
+    fn steps_to(&self, depth: usize) -> bool {
+        match self.resume {
+            Resume::Continue => false,
+            Resume::StepIn => true,
+            Resume::StepOver => depth <= self.depth,
+            Resume::StepOut => depth < self.depth,
+        }
+    }
+}
+
+fn pause_if(
+    should_pause: impl FnOnce(&Debugger, usize) -> bool,
+    event: impl FnOnce() -> PauseEvent,
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) {
+    let depth = depth();
+    let Some(mut debugger) = DEBUGGER.with(|debugger| {
+        let mut debugger = debugger.borrow_mut();
+        match debugger.as_ref() {
+            Some(current) if should_pause(current, depth) => debugger.take(),
+            _ => None,
+        }
+    }) else {
+        return;
+    };
+
+    let mut pause = Pause {
+        event: event(),
+        breakpoints: &mut debugger.breakpoints,
+        variable_map,
+        function_map,
+    };
+    debugger.resume = debugger.handler.paused(&mut pause);
+    debugger.depth = depth;
+    DEBUGGER.with(|current| *current.borrow_mut() = Some(debugger));
+}
@@ -0,0 +1,128 @@
+use std::fmt;
+
+use crate::interpret::Value;
+use crate::limits::Limit;
+use crate::stack_trace::Backtrace;
+use crate::tokenizer::Span;
+
+// An exception value, as created by `error` or by a failing built in operation. The kind is a path in
+// Racket's exception hierarchy such as exn:fail:contract, and an exception is an instance of every
+// kind that prefixes its own.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Exn {
+    pub kind: String,
+    pub message: String,
+}
+
+impl Exn {
+    pub fn is_a(&self, kind: &str) -> bool {
+        self.kind == kind || self.kind.starts_with(&format!("{}:", kind))
+    }
+}
+
+// Errors that stop evaluation. Each has the backtrace of the calls that were running when it was
+// raised, which is empty until it leaves the call it was raised in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RacketError {
+    // A value passed to `raise`, or an exception from a built in operation. Programs can catch these
+    // with `with-handlers`.
+    Raise(Value, Backtrace),
+    // The program ran out of one of its evaluation limits. This cannot be caught, so a program
+    // cannot keep running after its budget is spent.
+    LimitExceeded(Limit, Backtrace),
+}
+
+pub type EvalResult<T = Value> = Result<T, RacketError>;
+
+impl RacketError {
+    pub fn raise(value: Value) -> Self {
+        RacketError::Raise(value, Backtrace::default())
+    }
+
+    pub fn limit_exceeded(limit: Limit) -> Self {
+        RacketError::LimitExceeded(limit, Backtrace::default())
+    }
+
+    pub fn exn(kind: &str, message: String) -> Self {
+        RacketError::raise(Value::ExnValue(Exn {
+            kind: kind.to_string(),
+            message,
+        }))
+    }
+
+    pub fn fail(message: String) -> Self {
+        RacketError::exn("exn:fail", message)
+    }
+
+    // A built in operation was given arguments it does not accept.
+    pub fn contract(message: String) -> Self {
+        RacketError::exn("exn:fail:contract", message)
+    }
+
+    pub fn divide_by_zero(message: String) -> Self {
+        RacketError::exn("exn:fail:contract:divide-by-zero", message)
+    }
+
+    pub fn variable(message: String) -> Self {
+        RacketError::exn("exn:fail:contract:variable", message)
+    }
+}
+
+impl RacketError {
+    pub fn backtrace(&self) -> &Backtrace {
+        match self {
+            RacketError::Raise(_, backtrace) | RacketError::LimitExceeded(_, backtrace) => backtrace,
+        }
+    }
+}
+
+// Only the message, since embedders show errors in their own way. The backtrace is printed separately.
+impl fmt::Display for RacketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RacketError::Raise(Value::ExnValue(exn), _) => write!(f, "{}", exn.message),
+            RacketError::Raise(value, _) => write!(f, "uncaught exception: {}", value),
+            RacketError::LimitExceeded(limit, _) => write!(f, "{}", limit),
+        }
+    }
+}
+
+// An error in the text of a program, found before it runs. The message says where the error is,
+// and the span is kept for editors. When a program runs, these become exceptions of the kind.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    // exn:fail:read for text that cannot be read, and exn:fail:syntax for forms that cannot be parsed.
+    pub kind: &'static str,
+    pub message: String,
+    pub span: Span,
+}
+
+impl SyntaxError {
+    pub fn read(span: Span, message: String) -> Self {
+        SyntaxError {
+            kind: "exn:fail:read",
+            message,
+            span,
+        }
+    }
+
+    pub fn syntax(span: Span, message: String) -> Self {
+        SyntaxError {
+            kind: "exn:fail:syntax",
+            message,
+            span,
+        }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<SyntaxError> for RacketError {
+    fn from(error: SyntaxError) -> Self {
+        RacketError::exn(error.kind, error.message)
+    }
+}
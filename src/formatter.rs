@@ -0,0 +1,328 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::error::SyntaxError;
+use crate::interpret::read_lang_line;
+use crate::reader::read_program;
+use crate::tokenizer::is_delimiter;
+
+// Formats programs in the usual Racket style. The reader drops comments, so programs are read again
+// here into a tree that keeps them, along with the brackets and literals exactly as they were
+// written. Only the whitespace between datums changes.
+
+// Lines are kept within this many characters where the nesting allows it, as in Racket's style guide.
+pub const LINE_WIDTH: usize = 102;
+
+#[derive(Debug, PartialEq)]
+enum Node {
+    // Symbols, numbers, strings and characters, as written.
+    Atom(String),
+    List {
+        open: &'static str,
+        close: &'static str,
+        items: Vec<Item>,
+    },
+    // ' ` , and ,@ before a datum.
+    Prefixed(String, Box<Node>),
+    // A `;` comment. Trailing comments follow code on the same line and stay there.
+    Comment {
+        text: String,
+        trailing: bool,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+struct Item {
+    node: Node,
+    // Whether an empty line separated the item from the one before, which is kept.
+    blank_line_before: bool,
+}
+
+// How the items of a list that does not fit on one line are laid out.
+enum Layout {
+    // The head and this many more items on the first line, and the body indented two spaces, as in
+    // define and lambda.
+    Body(usize),
+    // The head and the first argument on the first line, and the other arguments aligned with it,
+    // as in function calls.
+    Aligned,
+    // Every item aligned with the first, as in cond clauses and data.
+    Columns,
+}
+
+// Formats a program, or gives the error that stops it from being read.
+pub fn format_program(program: &str) -> Result<String, SyntaxError> {
+    read_program(read_lang_line(program.to_string())?.1)?;
+
+    let trimmed = program.trim_start();
+    let (lang_line, rest) = match trimmed.strip_prefix("#lang") {
+        Some(_) => trimmed.split_at(trimmed.find('\n').unwrap_or(trimmed.len())),
+        None => ("", program),
+    };
+    let mut reader = LayoutReader {
+        chars: rest.chars().peekable(),
+        newlines: 0,
+        line_has_code: !lang_line.is_empty(),
+    };
+    let items = reader.read_items();
+
+    let mut output = lang_line.trim_end().to_string();
+    for item in &items {
+        match &item.node {
+            Node::Comment { text, trailing: true } if !output.is_empty() => {
+                output += " ";
+                output += text;
+                continue;
+            }
+            _ if output.is_empty() => {}
+            _ if item.blank_line_before => output += "\n\n",
+            _ => output += "\n",
+        }
+        output += &render(&item.node, 0, 0);
+    }
+    if !output.is_empty() {
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+struct LayoutReader<'a> {
+    chars: Peekable<Chars<'a>>,
+    // Newlines since the last datum or comment.
+    newlines: usize,
+    // Whether anything has been read on the current line, which makes a comment trailing.
+    line_has_code: bool,
+}
+
+impl LayoutReader<'_> {
+    // Reads items until the end of the list being read, or of the program.
+    fn read_items(&mut self) -> Vec<Item> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let blank_line_before = self.newlines >= 2;
+            let node = match self.chars.peek() {
+                None => break,
+                Some(')' | ']') => {
+                    self.next();
+                    break;
+                }
+                Some(';') => {
+                    let mut text = String::new();
+                    while let Some(c) = self.chars.next_if(|c| *c != '\n') {
+                        text.push(c);
+                    }
+                    Node::Comment {
+                        text: text.trim_end().to_string(),
+                        trailing: self.line_has_code,
+                    }
+                }
+                Some(_) => self.read_datum(),
+            };
+            self.newlines = 0;
+            items.push(Item { node, blank_line_before });
+        }
+        items
+    }
+
+    fn read_datum(&mut self) -> Node {
+        self.line_has_code = true;
+        let c = self.next().unwrap();
+        match c {
+            '(' => self.read_list("(", ")"),
+            '[' => self.read_list("[", "]"),
+            '\'' | '`' => self.read_prefixed(c.to_string()),
+            ',' if self.chars.next_if_eq(&'@').is_some() => self.read_prefixed(",@".to_string()),
+            ',' => self.read_prefixed(",".to_string()),
+            '#' if self.chars.next_if_eq(&'(').is_some() => self.read_list("#(", ")"),
+            '"' => {
+                let mut text = c.to_string();
+                while let Some(c) = self.next() {
+                    text.push(c);
+                    match c {
+                        '\\' => text.extend(self.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+                Node::Atom(text)
+            }
+            _ => {
+                let mut text = c.to_string();
+                // Any character can follow #\, including a delimiter.
+                if c == '#' && self.chars.next_if_eq(&'\\').is_some() {
+                    text.push('\\');
+                    text.extend(self.next());
+                }
+                while let Some(c) = self.chars.next_if(|c| !is_delimiter(*c)) {
+                    text.push(c);
+                }
+                Node::Atom(text)
+            }
+        }
+    }
+
+    fn read_list(&mut self, open: &'static str, close: &'static str) -> Node {
+        Node::List {
+            open,
+            close,
+            items: self.read_items(),
+        }
+    }
+
+    fn read_prefixed(&mut self, prefix: String) -> Node {
+        self.skip_whitespace();
+        Node::Prefixed(prefix, Box::new(self.read_datum()))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.next();
+        }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.newlines += 1;
+            self.line_has_code = false;
+        }
+        Some(c)
+    }
+}
+
+// The node on one line, unless it has to be broken over several.
+fn flat(node: &Node) -> Option<String> {
+    match node {
+        Node::Atom(text) => Some(text.clone()),
+        Node::Prefixed(prefix, node) => Some(format!("{}{}", prefix, flat(node)?)),
+        Node::Comment { .. } => None,
+        // Forms with several clauses always have one clause per line.
+        Node::List { items, .. } if clauses(items).is_some_and(|clauses| clauses.len() > 1) => None,
+        Node::List { open, close, items } => {
+            let items: Option<Vec<String>> = items.iter().map(|item| flat(&item.node)).collect();
+            Some(format!("{}{}{}", open, items?.join(" "), close))
+        }
+    }
+}
+
+fn head_symbol(items: &[Item]) -> Option<&str> {
+    match &items.first()?.node {
+        Node::Atom(text) => Some(text),
+        _ => None,
+    }
+}
+
+// The clauses of a cond or syntax-rules form.
+fn clauses(items: &[Item]) -> Option<&[Item]> {
+    match head_symbol(items)? {
+        "cond" => Some(&items[1..]),
+        "syntax-rules" => items.get(2..),
+        _ => None,
+    }
+}
+
+fn layout(items: &[Item]) -> Layout {
+    match head_symbol(items) {
+        Some(
+            "define" | "define/contract" | "define-syntax" | "lambda" | "syntax-rules" | "with-handlers" | "struct"
+            | "check-property",
+        ) => Layout::Body(1),
+        Some("cond" | "contract-out") => Layout::Body(0),
+        Some(_) if items.len() > 1 => Layout::Aligned,
+        _ => Layout::Columns,
+    }
+}
+
+fn width(text: &str) -> usize {
+    text.chars().count()
+}
+
+// The width of the last line of rendered text, for text that starts at `column`.
+fn end_column(text: &str, column: usize) -> usize {
+    match text.rsplit_once('\n') {
+        Some((_, last_line)) => width(last_line),
+        None => column + width(text),
+    }
+}
+
+// Renders a node that starts at `column`, followed on its last line by `suffix` characters of
+// closing brackets.
+fn render(node: &Node, column: usize, suffix: usize) -> String {
+    if let Some(text) = flat(node) {
+        if column + width(&text) + suffix <= LINE_WIDTH {
+            return text;
+        }
+    }
+    match node {
+        Node::Atom(text) | Node::Comment { text, .. } => text.clone(),
+        Node::Prefixed(prefix, node) => format!("{}{}", prefix, render(node, column + width(prefix), suffix)),
+        Node::List { open, close, items } => render_list(open, close, items, column, suffix),
+    }
+}
+
+fn render_list(open: &str, close: &str, items: &[Item], column: usize, suffix: usize) -> String {
+    let start = column + width(open);
+    let (first_line_items, indent) = match layout(items) {
+        Layout::Body(distinguished) => (1 + distinguished, column + 2),
+        Layout::Aligned => {
+            let indent = start + width(head_symbol(items).unwrap()) + 1;
+            let suffix = if items.len() == 2 { suffix + width(close) } else { 0 };
+            let first_fits = flat(&items[1].node).is_some_and(|text| indent + width(&text) + suffix <= LINE_WIDTH);
+            // Arguments aligned far to the right leave little room, so they go under the head instead.
+            if first_fits && indent <= LINE_WIDTH / 2 {
+                (2, indent)
+            } else {
+                (1, start)
+            }
+        }
+        Layout::Columns => (1, start),
+    };
+
+    let mut output = open.to_string();
+    let mut on_first_line = true;
+    for (i, item) in items.iter().enumerate() {
+        let item_suffix = if i + 1 == items.len() { suffix + width(close) } else { 0 };
+        match &item.node {
+            Node::Comment { text, trailing: true } => {
+                if i > 0 {
+                    output.push(' ');
+                }
+                output += text;
+                on_first_line = false;
+                continue;
+            }
+            Node::Comment { .. } => {}
+            _ if on_first_line && i < first_line_items => {
+                if i > 0 {
+                    output.push(' ');
+                }
+                let text = render(&item.node, end_column(&output, column), item_suffix);
+                // Items after a multi-line item go on their own lines.
+                on_first_line = !text.contains('\n');
+                output += &text;
+                continue;
+            }
+            _ => {}
+        }
+        on_first_line = false;
+        output.push('\n');
+        if item.blank_line_before {
+            output.push('\n');
+        }
+        output += &" ".repeat(indent);
+        output += &render(&item.node, indent, item_suffix);
+    }
+    // A comment runs to the end of the line, so the closing bracket goes on the next.
+    if matches!(
+        items.last(),
+        Some(Item {
+            node: Node::Comment { .. },
+            ..
+        })
+    ) {
+        output.push('\n');
+        output += &" ".repeat(indent);
+    }
+    output + close
+}
@@ -1,35 +1,113 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::env;
 use std::fmt;
 use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-use itertools::peek_nth;
 use regex::Regex;
 
+use crate::error::{EvalResult, Exn, RacketError, SyntaxError};
 use crate::interpret_bool::*;
+use crate::interpret_char::char_to_string;
 use crate::interpret_cond::*;
+use crate::interpret_contract::{parse_function_contract, ContractParty, FunctionContract};
+use crate::interpret_define::{define_all, interpret_body_expr, BodyExpr, Definition};
 use crate::interpret_function::*;
-use crate::interpret_function_call::interpret_function_call;
 use crate::interpret_function_call::FunctionCallExpr;
+use crate::interpret_function_call::{
+    apply_function, interpret_application_expr, interpret_function_call, resolve_function, ApplicationExpr,
+};
+use crate::interpret_hash::hash_to_string;
+use crate::interpret_lambda::{interpret_lambda_expr, Closure, LambdaExpr};
 use crate::interpret_list::interpret_car_expr;
 use crate::interpret_list::CarExpr;
 use crate::interpret_list::{interpret_list_expr, ListExpr};
 use crate::interpret_num::*;
+use crate::interpret_quote::{interpret_quasiquote_expr, QuasiquoteExpr, QuoteExpr};
+use crate::interpret_struct::{define_structs, struct_definition, struct_to_string, StructFunction, StructValue};
 use crate::interpret_variable::*;
-use crate::parser::{parse, parse_expr};
-use crate::tokenizer::string_to_tokens;
-use crate::tokenizer::TokenIter;
+use crate::interpret_vector::vector_to_string;
+use crate::interpret_with_handlers::{interpret_with_handlers_expr, WithHandlersExpr};
+use crate::limits::{self, with_limits, EvalLimits};
+use crate::macro_expander::expand_macros;
+use crate::native::{NativeFunction, NativeModules};
+use crate::parser::{
+    constant_definition, contract_definition, function_definition, parse, parse_definitions, parse_expr, parse_function_expr,
+    provided_contracts, type_declaration, ParseResult,
+};
+use crate::reader::{read_program, Datum, DatumKind};
+use crate::stack_trace::{attach_backtrace, enter_frame};
+use crate::symbol_table::Symbol;
+use crate::testing::{parse_check, Check, MalformedCheck};
+use crate::tokenizer::Span;
+use crate::types::Type;
 
 // Concrete value types that represent results of expression evaluation.
 pub type N = i32;
 pub type B = bool;
 pub type L = ValueList;
+pub type S = String;
+pub type C = char;
+pub type H = im::HashMap<Value, Value>;
+pub type V = Rc<Vec<Value>>;
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum Value {
     NumValue(N),
     BoolValue(B),
     ListValue(L),
+    StringValue(S),
+    CharValue(C),
+    SymbolValue(Symbol),
+    FunctionValue(FunctionValue),
+    HashValue(H),
+    VectorValue(V),
+    ExnValue(Exn),
+    StructValue(StructValue),
+}
+
+// Values are compared structurally, so they can be used as hash keys.
+impl Eq for Value {}
+
+impl Hash for Value {
+    fn hash<T: Hasher>(&self, state: &mut T) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Value::NumValue(n) => n.hash(state),
+            Value::BoolValue(b) => b.hash(state),
+            Value::ListValue(l) => l.to_vec().hash(state),
+            Value::StringValue(s) => s.hash(state),
+            Value::CharValue(c) => c.hash(state),
+            Value::SymbolValue(s) => s.hash(state),
+            Value::FunctionValue(FunctionValue::Named(name)) => name.hash(state),
+            Value::FunctionValue(FunctionValue::Closure(_)) => {}
+            // Entries are unordered, so only the size contributes to the hash.
+            Value::HashValue(h) => h.len().hash(state),
+            Value::VectorValue(v) => v.hash(state),
+            Value::ExnValue(e) => e.hash(state),
+            Value::StructValue(s) => s.hash(state),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum FunctionValue {
+    // A top level function or a primitive, referred to by name.
+    Named(String),
+    Closure(Closure),
+}
+
+impl FunctionValue {
+    // The name errors and backtraces use for the function.
+    pub fn name(&self) -> &str {
+        match self {
+            FunctionValue::Named(name) => name,
+            FunctionValue::Closure(closure) => closure.name(),
+        }
+    }
 }
 
 impl fmt::Display for Value {
@@ -38,6 +116,15 @@ impl fmt::Display for Value {
             Value::NumValue(ref n) => write!(f, "{}", n),
             Value::BoolValue(ref b) => write!(f, "{}", b),
             Value::ListValue(ref l) => write!(f, "{}", l),
+            Value::StringValue(ref s) => write!(f, "{:?}", s),
+            Value::CharValue(ref c) => write!(f, "{}", char_to_string(*c)),
+            Value::SymbolValue(ref s) => write!(f, "'{}", s),
+            Value::FunctionValue(FunctionValue::Named(ref name)) => write!(f, "#<procedure:{}>", name),
+            Value::FunctionValue(FunctionValue::Closure(_)) => write!(f, "#<procedure>"),
+            Value::HashValue(ref h) => write!(f, "{}", hash_to_string(h)),
+            Value::VectorValue(ref v) => write!(f, "{}", vector_to_string(v)),
+            Value::ExnValue(ref e) => write!(f, "#<{}: {}>", e.kind, e.message),
+            Value::StructValue(ref s) => write!(f, "{}", struct_to_string(s)),
         }
     }
 }
@@ -65,6 +152,20 @@ fn get_value_list_elements(list: &ValueList) -> String {
 }
 
 impl ValueList {
+    pub fn from_vec(values: Vec<Value>) -> ValueList {
+        let mut result = ValueList::Empty;
+
+        limits::record_allocation(values.len());
+        for value in values.into_iter().rev() {
+            result = ValueList::Node(ValueNode {
+                data: Box::new(value),
+                next: Box::new(result),
+            });
+        }
+
+        result
+    }
+
     pub fn to_vec(&self) -> Vec<Value> {
         let mut result = Vec::new();
         let mut current = self;
@@ -99,46 +200,363 @@ pub enum Expr {
     BoolExpr(BoolExpr),
     NumExpr(NumExpr),
     CondExpr(CondExpr),
-    FunctionExpr(FunctionExpr),
     VariableExpr(VariableExpr),
     FunctionCallExpr(FunctionCallExpr),
     ListExpr(ListExpr),
     EmptyExpr(ListExpr),
     CarExpr(CarExpr),
+    StringExpr(S),
+    CharExpr(C),
+    QuoteExpr(QuoteExpr),
+    QuasiquoteExpr(QuasiquoteExpr),
+    LambdaExpr(LambdaExpr),
+    ApplicationExpr(ApplicationExpr),
+    BodyExpr(BodyExpr),
+    WithHandlersExpr(WithHandlersExpr),
 }
 
 #[derive(Debug)]
-pub struct FunctionInfo {
-    pub parameter_names: Vec<String>,
-    pub body: Expr,
+pub enum FunctionInfo {
+    // A function defined by the program. Functions with a type annotation have a signature, which
+    // is a Type::Function that their arguments and result are checked against.
+    Defined {
+        parameter_names: Vec<String>,
+        body: Expr,
+        signature: Option<Type>,
+        // The included module the function was defined in, or None for the program itself.
+        module: Option<String>,
+        contract: Option<Rc<FunctionContract>>,
+    },
+    // A constructor, predicate or field accessor of a struct.
+    Struct(StructFunction),
+    // A function registered by the program embedding the interpreter.
+    Native(NativeFunction),
 }
 
 pub type VariableMap = HashMap<String, Vec<Value>>;
 pub type FunctionMap = HashMap<String, FunctionInfo>;
 
-pub fn parse_functions(program: String) -> FunctionMap {
-    // Tokenize.
-    let tokens = string_to_tokens(program);
-    let mut token_iterator = peek_nth(TokenIter::new(&tokens));
+// The language named by a `#lang` line at the start of a program.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    // Programs start running from a zero argument main function.
+    RustyRacket,
+    // Top level expressions are evaluated and printed in order, as Racket does.
+    Racket,
+}
+
+// Splits off the `#lang` line, if there is one. The line is replaced by an empty line so that the
+// line numbers in the rest of the program stay the same.
+pub fn read_lang_line(program: String) -> Result<(Language, String), SyntaxError> {
+    let trimmed = program.trim_start();
+    let rest = match trimmed.strip_prefix("#lang") {
+        Some(rest) => rest,
+        None => return Ok((Language::RustyRacket, program)),
+    };
+
+    let line_end = rest.find('\n').unwrap_or(rest.len());
+    let language = match rest[..line_end].trim() {
+        "rusty-racket" => Language::RustyRacket,
+        "racket" | "racket/base" => Language::Racket,
+        name => {
+            let start = program.len() - trimmed.len();
+            return Err(SyntaxError::syntax(
+                span_at(&program, start, start + "#lang".len() + line_end),
+                format!("Unsupported language in #lang line: {}", name),
+            ));
+        }
+    };
+
+    let skipped_lines = program[..program.len() - trimmed.len()].matches('\n').count();
+    Ok((language, "\n".repeat(skipped_lines) + &rest[line_end..]))
+}
+
+// The span of the text between two byte offsets.
+fn span_at(text: &str, start: usize, end: usize) -> Span {
+    let line_start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+    Span {
+        start,
+        end,
+        line: text[..start].matches('\n').count() + 1,
+        column: text[line_start..start].chars().count() + 1,
+    }
+}
+
+// Splits the program into the functions it defines, its constant definitions in the order they
+// have to be evaluated in, and the remaining top level expressions. Check forms are left out, since
+// they only run as tests.
+pub fn parse_top_level(program: String) -> ParseResult<(FunctionMap, Vec<Definition>, Vec<Expr>)> {
+    let (function_map, definitions, expressions, _) = parse_program(program)?;
+    Ok((function_map, definitions, expressions))
+}
+
+// The functions, constant definitions, top level expressions and check forms of a program.
+pub type ParsedProgram = (FunctionMap, Vec<Definition>, Vec<Expr>, Vec<Result<Check, MalformedCheck>>);
+
+// Like parse_top_level, but also returns the check forms of the program, without those of the
+// modules it includes. A malformed check form does not stop the rest of the program from parsing.
+pub fn parse_program(program: String) -> ParseResult<ParsedProgram> {
+    // Read the program into s-expressions and expand macros before anything is parsed into expressions.
+    let mut datums = Vec::new();
+    // The module each datum comes from, with None for the program itself.
+    let mut datum_modules = Vec::new();
+    // Contracts by function name, with the party responsible for them.
+    let mut contracts = HashMap::new();
+    let mut modules = Vec::new();
+    for datum in expand_macros(restore_locations(read_program(program)?))? {
+        if let Some(boundary) = module_boundary(&datum) {
+            match boundary {
+                Some(module) => modules.push(module.to_string()),
+                None => {
+                    modules.pop();
+                }
+            }
+        } else if let Some(provided) = provided_contracts(&datum) {
+            for (name, contract) in provided {
+                contracts.insert(
+                    name.to_string(),
+                    (contract.clone(), ContractParty::Module(modules.last().cloned())),
+                );
+            }
+        } else if let Some((define, contract)) = contract_definition(&datum) {
+            let name = function_definition(&define).unwrap().0.to_string();
+            contracts.insert(name, (contract.clone(), ContractParty::Function));
+            datums.push(define);
+            datum_modules.push(modules.last().cloned());
+        } else {
+            datums.push(datum);
+            datum_modules.push(modules.last().cloned());
+        }
+    }
 
     let mut function_map = HashMap::new();
+    // Structs are defined first, since annotations anywhere in the program can refer to their types.
+    let type_names = define_structs(&datums, &mut function_map)?;
+    let declarations: HashMap<&str, &Datum> = datums.iter().filter_map(type_declaration).collect();
+
+    let mut function_bodies = HashMap::new();
+    let mut constants = Vec::new();
+    let mut expressions = Vec::new();
+    let mut checks = Vec::new();
     // Parse each function and add it to the function map.
-    while token_iterator.peek().is_some() {
-        if let Expr::FunctionExpr(function) = parse_expr(&mut token_iterator) {
-            interpret_function_expr(&function, &mut function_map);
-        } else {
-            panic!("Invalid program: cannot parse functions");
+    for (datum, module) in datums.iter().zip(datum_modules) {
+        if struct_definition(datum).is_some() || type_declaration(datum).is_some() {
+            continue;
+        }
+        if let Some(check) = parse_check(datum) {
+            if module.is_none() {
+                checks.push(check);
+            }
+            continue;
         }
+        if let Some(constant) = constant_definition(datum) {
+            constants.push(constant);
+            continue;
+        }
+        let Some((name, body)) = function_definition(datum) else {
+            expressions.push(parse_expr(datum)?);
+            continue;
+        };
+        function_bodies.insert(name.to_string(), body);
+        let function = parse_function_expr(datum)?;
+        let signature = function_signature(datum, declarations.get(function.name.as_str()).copied(), &type_names)?;
+        let contract = contracts
+            .remove(&function.name)
+            .map(|(contract, party)| parse_function_contract(&contract, function.parameters.len(), party))
+            .transpose()?;
+        interpret_function_expr(&function, signature, module, contract, &mut function_map)
+    }
+    if let Some((name, (contract, _))) = contracts.iter().next() {
+        return Err(SyntaxError::syntax(
+            contract.span,
+            format!(
+                "The contract at {} is for {}, which is not a defined function",
+                contract.span, name
+            ),
+        ));
     }
 
-    function_map
+    let definitions = parse_definitions(&constants, &function_bodies)?;
+    Ok((function_map, definitions, expressions, checks))
 }
 
+// Runs the program and panics with the error message if it raises an exception that is not caught.
+#[allow(dead_code)] // Used in tests
 pub fn interpret_program(program: String) -> Value {
-    // 1. Preprocessor: handle module includes. (can make this smarter in the future)
+    try_interpret_program(program).unwrap_or_else(|error| panic!("{}", error))
+}
+
+pub fn try_interpret_program(program: String) -> EvalResult {
+    try_interpret_program_with_limits(program, &EvalLimits::default())
+}
+
+// Runs the program, stopping with RacketError::LimitExceeded as soon as it goes over one of the limits.
+pub fn try_interpret_program_with_limits(program: String, limits: &EvalLimits) -> EvalResult {
+    with_limits(limits, || run_program(program))
+}
+
+fn run_program(program: String) -> EvalResult {
+    let (_, program) = read_lang_line(program)?;
+    let (function_map, definitions, expressions) = parse_top_level(resolve_includes(program, &HashMap::new())?.0)?;
+    // Only Racket programs can have top level expressions, everything else starts from main.
+    if !expressions.is_empty() {
+        return Err(RacketError::exn(
+            "exn:fail:syntax",
+            "Invalid program: top level expressions are only allowed with #lang racket".to_string(),
+        ));
+    }
+
+    // Top level constants are visible everywhere, unless a parameter with the same name shadows them.
+    let mut variable_map = HashMap::new();
+    define_all(&definitions, &mut variable_map, &function_map)?;
+
+    // Begin interpreting from the main function.
+    let main = resolve_function("main", &variable_map, &function_map)?;
+    let _frame = enter_frame("main", None);
+    apply_function(&main, Vec::new(), &[], &mut variable_map, &function_map).map_err(attach_backtrace)
+}
+
+// Racket compatibility mode: every top level expression is evaluated in order, after all
+// functions and constants have been defined. Returns the results of the expressions, stopping
+// at the first uncaught exception.
+pub fn interpret_racket_program(program: String) -> Vec<EvalResult> {
+    interpret_racket_program_with_limits(program, &EvalLimits::default())
+}
+
+// The limits apply to the program as a whole rather than to each top level expression.
+pub fn interpret_racket_program_with_limits(program: String, limits: &EvalLimits) -> Vec<EvalResult> {
+    with_limits(limits, || run_racket_program(program))
+}
+
+fn run_racket_program(program: String) -> Vec<EvalResult> {
+    let parsed = read_lang_line(program)
+        .and_then(|(_, program)| resolve_includes(program, &HashMap::new()))
+        .and_then(|(program, _)| parse_top_level(program));
+    let (function_map, definitions, expressions) = match parsed {
+        Ok(parsed) => parsed,
+        Err(error) => return vec![Err(error.into())],
+    };
+
+    let mut variable_map = HashMap::new();
+    if let Err(error) = define_all(&definitions, &mut variable_map, &function_map) {
+        return vec![Err(error)];
+    }
+
+    let mut results = Vec::new();
+    for expr in &expressions {
+        let result = interpret(expr, &mut variable_map, &function_map);
+        let is_error = result.is_err();
+        results.push(result);
+        if is_error {
+            break;
+        }
+    }
+    results
+}
+
+// Modules that can be included from examples/stdlib.
+pub const STDLIB_MODULES: &[&str] = &[
+    "stdlib::list",
+    "stdlib::num",
+    "stdlib::bool",
+    "stdlib::binary-tree",
+    "stdlib::bst",
+    "stdlib::quickcheck",
+];
+
+const MODULE_BEGIN: &str = "module-begin";
+const MODULE_END: &str = "module-end";
+
+// The markers resolve_includes puts around a module: (module-begin name) gives Some(name) and
+// (module-end) gives None.
+fn module_boundary(datum: &Datum) -> Option<Option<&str>> {
+    match datum.as_list().map(|items| &items[..]) {
+        Some([begin, name]) if begin.as_symbol() == Some(MODULE_BEGIN) => Some(name.as_symbol()),
+        Some([end]) if end.as_symbol() == Some(MODULE_END) => Some(None),
+        _ => None,
+    }
+}
+
+// resolve_includes splices each module into the text of the program, which moves everything after it.
+// This gives every datum the location it has in its own source, so that errors and backtraces point
+// into the right file.
+fn restore_locations(datums: Vec<Datum>) -> Vec<Datum> {
+    // How to move the datums of each source being read back to where they are in it, innermost last.
+    let mut shifts = vec![Shift::default()];
+    // The begin marker of each module being read, which is where its include was.
+    let mut begins: Vec<(Span, usize)> = Vec::new();
+    let mut result = Vec::new();
+    for mut datum in datums {
+        match module_boundary(&datum) {
+            // The module's first line is the line after its marker.
+            Some(Some(module)) => {
+                let include_length = format!("(include {})", module).len();
+                let shift = shifts.last().unwrap();
+                let column = datum.span.column as isize + shift.columns_on(datum.span.line);
+                begins.push((datum.span, column as usize + include_length));
+                shifts.push(Shift {
+                    lines: datum.span.line,
+                    ..Shift::default()
+                });
+            }
+            // The line that included the module continues after the end marker.
+            Some(None) => {
+                shifts.pop();
+                let (begin, column_after_include) = begins.pop().expect("Unmatched module end");
+                let shift = shifts.last_mut().unwrap();
+                shift.lines += datum.span.line - begin.line;
+                shift.line = datum.span.line;
+                shift.columns = column_after_include as isize - (datum.span.column + MODULE_END.len() + 2) as isize;
+            }
+            None => shifts.last().unwrap().apply(&mut datum),
+        }
+        result.push(datum);
+    }
+    result
+}
+
+#[derive(Default)]
+struct Shift {
+    // Lines to subtract from every datum.
+    lines: usize,
+    // Columns to add on one line, where the rest of the line with an include continues.
+    line: usize,
+    columns: isize,
+}
+
+impl Shift {
+    fn columns_on(&self, line: usize) -> isize {
+        if line == self.line {
+            self.columns
+        } else {
+            0
+        }
+    }
+
+    fn apply(&self, datum: &mut Datum) {
+        datum.span.column = (datum.span.column as isize + self.columns_on(datum.span.line)) as usize;
+        datum.span.line -= self.lines;
+        if let DatumKind::List(items) | DatumKind::Vector(items) = &mut datum.kind {
+            for item in items {
+                self.apply(item);
+            }
+        }
+    }
+}
+
+// Preprocessor: handle module includes. (can make this smarter in the future)
+// Native modules have no source to substitute, so their functions are returned to be added to the
+// function map instead.
+pub fn resolve_includes(program: String, native_modules: &NativeModules) -> Result<(String, Vec<NativeFunction>), SyntaxError> {
     let re = Regex::new(r"\(include ([^\)]+)\)").unwrap();
     let mut processed = program;
     let mut included_modules = HashSet::new();
+    let mut native_functions = Vec::new();
+    // The first include of a module that does not exist. Modules never include unknown modules, so
+    // it is always found in the program itself, before anything has been substituted into it.
+    let mut unknown = None;
+    // The first module that could not be read.
+    let mut unreadable = None;
     // Repeat substitution while possible. Do this because one substitution may
     // substitute in code that contains potential new substitutions.
     loop {
@@ -147,18 +565,38 @@ pub fn interpret_program(program: String) -> Value {
                 let module_name = &captures[1];
                 if included_modules.contains(module_name) {
                     "".to_string()
-                } else if module_name == "stdlib::list"
-                    || module_name == "stdlib::num"
-                    || module_name == "stdlib::binary-tree"
-                    || module_name == "stdlib::bst"
-                {
+                } else if STDLIB_MODULES.contains(&module_name) {
+                    included_modules.insert(module_name.to_string());
+                    let include = captures.get(0).unwrap();
+                    match get_module_content(module_name, span_at(&processed, include.start(), include.end())) {
+                        // Marks where the module's definitions start and end, so that they know their module.
+                        Ok(content) => format!("({} {})\n{}\n({})", MODULE_BEGIN, module_name, content, MODULE_END),
+                        Err(error) => {
+                            unreadable.get_or_insert(error);
+                            "".to_string()
+                        }
+                    }
+                } else if let Some(module) = native_modules.get(module_name) {
                     included_modules.insert(module_name.to_string());
-                    get_module_content(module_name)
+                    native_functions.extend(module.functions.iter().cloned());
+                    "".to_string()
                 } else {
-                    panic!("Unknown module name {}", module_name);
+                    let include = captures.get(0).unwrap();
+                    unknown.get_or_insert((module_name.to_string(), include.start(), include.end()));
+                    "".to_string()
                 }
             })
             .to_string();
+        if let Some((module_name, start, end)) = unknown {
+            let span = span_at(&processed, start, end);
+            return Err(SyntaxError::syntax(
+                span,
+                format!("Unknown module name {} at {}", module_name, span),
+            ));
+        }
+        if let Some(error) = unreadable {
+            return Err(error);
+        }
         if new_processed == processed {
             break;
         }
@@ -166,43 +604,88 @@ pub fn interpret_program(program: String) -> Value {
         processed = new_processed;
     }
 
-    // 2. Interpret all of the functions to fill the function map.
-    let function_map = parse_functions(processed);
-
-    // Begin interpreting from the main function.
-    interpret(&parse("(main)".to_string()), &mut HashMap::new(), &function_map)
+    Ok((processed, native_functions))
 }
 
 #[allow(dead_code)] // Used in tests
 pub fn interpret_program_snippet(program: String) -> Value {
-    interpret(&parse(program.to_string()), &mut HashMap::new(), &HashMap::new())
+    let expr = parse(program.to_string()).unwrap_or_else(|error| panic!("{}", error));
+    interpret(&expr, &mut HashMap::new(), &HashMap::new()).unwrap_or_else(|error| panic!("{}", error))
 }
 
-pub fn interpret(expr: &Expr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> Value {
+// When less than this much native stack is left, evaluation continues on a newly allocated segment.
+// It has to cover the stack used between one call to interpret and the next.
+const STACK_RED_ZONE: usize = 256 * 1024;
+const STACK_SEGMENT_SIZE: usize = 4 * 1024 * 1024;
+
+// Recursion in the program is recursion here, so the stack grows on demand instead of depending on
+// the thread that evaluation happens to run on.
+pub fn interpret(expr: &Expr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> EvalResult {
+    stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT_SIZE, || {
+        interpret_expr(expr, variable_map, function_map)
+    })
+}
+
+fn interpret_expr(expr: &Expr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> EvalResult {
+    limits::step()?;
     match expr {
-        Expr::NumExpr(x) => Value::NumValue(interpret_num_expr(&x, variable_map, function_map)),
-        Expr::BoolExpr(x) => Value::BoolValue(interpret_bool_expr(&x, variable_map, function_map)),
-        Expr::CondExpr(x) => interpret_cond_expr(&x, variable_map, function_map),
-        Expr::VariableExpr(x) => interpret_variable_expr(&x, variable_map),
-        Expr::FunctionCallExpr(x) => interpret_function_call(&x, variable_map, function_map),
+        Expr::NumExpr(x) => Ok(Value::NumValue(interpret_num_expr(x, variable_map, function_map)?)),
+        Expr::BoolExpr(x) => Ok(Value::BoolValue(interpret_bool_expr(x, variable_map, function_map)?)),
+        Expr::CondExpr(x) => interpret_cond_expr(x, variable_map, function_map),
+        Expr::VariableExpr(x) => interpret_variable_or_function_expr(x, variable_map, function_map),
+        Expr::FunctionCallExpr(x) => interpret_function_call(x, variable_map, function_map),
         Expr::CarExpr(x) => interpret_car_expr(x, variable_map, function_map),
-        Expr::ListExpr(x) | Expr::EmptyExpr(x) => Value::ListValue(interpret_list_expr(&x, variable_map, function_map)),
-        // Function definitions should be interpreted in the previous pass.
-        Expr::FunctionExpr(_) => panic!("Encountered function expr"),
+        Expr::ListExpr(x) | Expr::EmptyExpr(x) => Ok(Value::ListValue(interpret_list_expr(x, variable_map, function_map)?)),
+        Expr::StringExpr(x) => Ok(Value::StringValue(x.clone())),
+        Expr::CharExpr(x) => Ok(Value::CharValue(*x)),
+        Expr::QuoteExpr(x) => Ok(x.value.clone()),
+        Expr::QuasiquoteExpr(x) => interpret_quasiquote_expr(x, variable_map, function_map),
+        Expr::LambdaExpr(x) => Ok(interpret_lambda_expr(x, variable_map)),
+        Expr::ApplicationExpr(x) => interpret_application_expr(x, variable_map, function_map),
+        Expr::BodyExpr(x) => interpret_body_expr(x, variable_map, function_map),
+        Expr::WithHandlersExpr(x) => interpret_with_handlers_expr(x, variable_map, function_map),
     }
 }
 
-// module_name is something like 'stdlib::list'
-fn get_module_content(module_name: &str) -> String {
+// module_name is something like 'stdlib::list'. The span is that of the include, for the error if the
+// module cannot be read.
+pub fn get_module_content(module_name: &str, span: Span) -> Result<String, SyntaxError> {
+    let path = module_path(module_name);
+    fs::read_to_string(&path).map_err(|error| {
+        SyntaxError::syntax(
+            span,
+            format!(
+                "Failed to read module {} from {} at {}: {}",
+                module_name,
+                path.display(),
+                span,
+                error
+            ),
+        )
+    })
+}
+
+// The directory the standard library is read from. It is $RUSTY_RACKET_STDLIB if that is set, and
+// otherwise examples/stdlib in this crate, so that it does not depend on the working directory.
+pub fn stdlib_dir() -> PathBuf {
+    env::var_os("RUSTY_RACKET_STDLIB")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/stdlib"))
+}
+
+pub fn module_path(module_name: &str) -> PathBuf {
     let module_prefix = "stdlib::";
     if !module_name.starts_with(module_prefix) {
         panic!("Invalid module name {}", module_name);
     }
 
-    let module_file = format!("{}.rkt", &module_name[module_prefix.len()..]);
-
-    let program = fs::read_to_string(format!("examples/stdlib/{}", module_file))
-        .expect(&format!("Failed to read module file {}", module_name));
+    stdlib_dir().join(format!("{}.rkt", &module_name[module_prefix.len()..]))
+}
 
-    program
+// The standard library module a source file is, or None for the program.
+pub fn source_module(path: &str) -> Option<String> {
+    let path = Path::new(path);
+    let name = format!("stdlib::{}", path.file_stem()?.to_str()?);
+    let in_stdlib = path.parent()?.file_name()? == "stdlib";
+    (in_stdlib && STDLIB_MODULES.contains(&name.as_str())).then_some(name)
 }
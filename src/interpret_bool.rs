@@ -1,3 +1,4 @@
+use crate::error::{EvalResult, RacketError};
 use crate::interpret::{interpret, Expr, FunctionMap, Value, ValueList, VariableMap, B};
 use crate::interpret_function_call::{interpret_function_call, FunctionCallExpr};
 use crate::interpret_list::{interpret_car_expr, interpret_list_expr, CarExpr, ListExpr};
@@ -16,6 +17,8 @@ pub enum BoolExpr {
     EmptyHuhExpr(EmptyHuhExpr),
     ListHuhExpr(ListHuhExpr),
     CarExpr(CarExpr),
+    // Any other expression, checked to produce a boolean when it is evaluated.
+    DynamicExpr(Box<Expr>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -66,98 +69,105 @@ pub struct ListHuhExpr {
     pub expr: Box<Expr>,
 }
 
-pub fn interpret_bool_expr(expr: &BoolExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> B {
+pub fn interpret_bool_expr(expr: &BoolExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> EvalResult<B> {
     match expr {
-        BoolExpr::LiteralBoolExpr(x) => *x,
-        BoolExpr::VariableExpr(x) => interpret_variable_bool_expr(&x, variable_map),
-        BoolExpr::BinaryBoolExpr(x) => interpret_binary_bool_expr(&x, variable_map, function_map),
-        BoolExpr::UnaryBoolExpr(x) => interpret_unary_bool_expr(&x, variable_map, function_map),
-        BoolExpr::CmpExpr(x) => interpret_cmp_bool_expr(&x, variable_map, function_map),
+        BoolExpr::LiteralBoolExpr(x) => Ok(*x),
+        BoolExpr::VariableExpr(x) => interpret_variable_bool_expr(x, variable_map),
+        BoolExpr::BinaryBoolExpr(x) => interpret_binary_bool_expr(x, variable_map, function_map),
+        BoolExpr::UnaryBoolExpr(x) => interpret_unary_bool_expr(x, variable_map, function_map),
+        BoolExpr::CmpExpr(x) => interpret_cmp_bool_expr(x, variable_map, function_map),
         BoolExpr::EmptyHuhExpr(x) => interpret_empty_huh_expr(x, variable_map, function_map),
         BoolExpr::ListHuhExpr(x) => interpret_list_huh_expr(x, variable_map, function_map),
-        BoolExpr::FunctionCallExpr(x) => {
-            if let Value::BoolValue(x) = interpret_function_call(&x, variable_map, function_map) {
-                x
-            } else {
-                panic!(
-                    "Expected call to function {} to return boolean but returned something else instead",
-                    x.name
-                );
-            }
-        }
-        BoolExpr::CarExpr(x) => {
-            if let Value::BoolValue(y) = interpret_car_expr(x, variable_map, function_map) {
+        BoolExpr::FunctionCallExpr(x) => match interpret_function_call(x, variable_map, function_map)? {
+            Value::BoolValue(y) => Ok(y),
+            y => Err(RacketError::contract(format!(
+                "Expected call to function {} to return boolean but returned {}",
+                x.name, y
+            ))),
+        },
+        BoolExpr::CarExpr(x) => match interpret_car_expr(x, variable_map, function_map)? {
+            Value::BoolValue(y) => Ok(y),
+            y => Err(RacketError::contract(format!(
+                "Expected car to produce a boolean but got {}",
                 y
-            } else {
-                panic!()
-            }
-        }
+            ))),
+        },
+        BoolExpr::DynamicExpr(x) => match interpret(x, variable_map, function_map)? {
+            Value::BoolValue(y) => Ok(y),
+            y => Err(RacketError::contract(format!("Expected a boolean but got {}", y))),
+        },
     }
 }
 
-fn interpret_variable_bool_expr(expr: &VariableExpr, variable_map: &VariableMap) -> B {
-    let res = interpret_variable_expr(expr, variable_map);
-
-    match res {
-        Value::BoolValue(x) => x,
-        x => panic!("Variable {} does not refer to a bool value.", x),
+fn interpret_variable_bool_expr(expr: &VariableExpr, variable_map: &VariableMap) -> EvalResult<B> {
+    match interpret_variable_expr(expr, variable_map)? {
+        Value::BoolValue(x) => Ok(x),
+        x => Err(RacketError::contract(format!(
+            "Variable {} does not refer to a bool value.",
+            x
+        ))),
     }
 }
 
-fn interpret_binary_bool_expr(expr: &BinaryBoolExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> B {
+fn interpret_binary_bool_expr(
+    expr: &BinaryBoolExpr,
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult<B> {
     match expr.op {
         BinaryBoolOp::And => {
-            let left = interpret_bool_expr(&expr.left, variable_map, function_map);
+            let left = interpret_bool_expr(&expr.left, variable_map, function_map)?;
             // Short circuit.
-            if left == false {
-                return false;
+            if !left {
+                return Ok(false);
             }
-            let right = interpret_bool_expr(&expr.right, variable_map, function_map);
-            left && right
+            let right = interpret_bool_expr(&expr.right, variable_map, function_map)?;
+            Ok(left && right)
         }
         BinaryBoolOp::Or => {
-            let left = interpret_bool_expr(&expr.left, variable_map, function_map);
+            let left = interpret_bool_expr(&expr.left, variable_map, function_map)?;
             // Short circuit.
-            if left == true {
-                return true;
+            if left {
+                return Ok(true);
             }
-            let right = interpret_bool_expr(&expr.right, variable_map, function_map);
-            left || right
+            let right = interpret_bool_expr(&expr.right, variable_map, function_map)?;
+            Ok(left || right)
         }
     }
 }
 
-fn interpret_unary_bool_expr(expr: &UnaryBoolExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> B {
+fn interpret_unary_bool_expr(expr: &UnaryBoolExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> EvalResult<B> {
     match expr.op {
-        UnaryBoolOp::Not => !interpret_bool_expr(&expr.value, variable_map, function_map),
+        UnaryBoolOp::Not => Ok(!interpret_bool_expr(&expr.value, variable_map, function_map)?),
     }
 }
 
-fn interpret_cmp_bool_expr(expr: &CmpBoolExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> B {
-    let left = interpret_num_expr(&expr.left, variable_map, function_map);
-    let right = interpret_num_expr(&expr.right, variable_map, function_map);
+fn interpret_cmp_bool_expr(expr: &CmpBoolExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> EvalResult<B> {
+    let left = interpret_num_expr(&expr.left, variable_map, function_map)?;
+    let right = interpret_num_expr(&expr.right, variable_map, function_map)?;
 
-    match expr.op {
+    Ok(match expr.op {
         CmpBoolOp::Lt => left < right,
         CmpBoolOp::Eq => left == right,
         CmpBoolOp::Gt => left > right,
-    }
+    })
 }
 
-pub fn interpret_empty_huh_expr(list: &EmptyHuhExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> B {
-    let res = interpret_list_expr(&list.list, variable_map, function_map);
+pub fn interpret_empty_huh_expr(
+    list: &EmptyHuhExpr,
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult<B> {
+    let res = interpret_list_expr(&list.list, variable_map, function_map)?;
 
-    match res {
+    Ok(match res {
         ValueList::Empty => true,
         ValueList::Node(_) => false,
-    }
+    })
 }
 
-pub fn interpret_list_huh_expr(expr: &ListHuhExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> B {
-    let res = interpret(&expr.expr, variable_map, function_map);
+pub fn interpret_list_huh_expr(expr: &ListHuhExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> EvalResult<B> {
+    let res = interpret(&expr.expr, variable_map, function_map)?;
 
-    match res {
-        Value::ListValue(_) => true,
-        _ => false,
-    }
+    Ok(matches!(res, Value::ListValue(_)))
 }
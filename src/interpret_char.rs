@@ -0,0 +1,58 @@
+use crate::error::{EvalResult, RacketError};
+use crate::interpret::{Value, ValueList, C, N};
+use crate::tokenizer::CHAR_NAMES;
+
+// Native implementations of the character functions. They are exposed through primitives.rs.
+
+// Characters print the way they are written, e.g. #\a or #\space.
+pub fn char_to_string(c: C) -> String {
+    match CHAR_NAMES.iter().find(|(_, named)| *named == c) {
+        Some((name, _)) => format!("#\\{}", name),
+        None => format!("#\\{}", c),
+    }
+}
+
+pub fn integer_to_char(n: N) -> EvalResult {
+    match u32::try_from(n).ok().and_then(char::from_u32) {
+        Some(c) => Ok(Value::CharValue(c)),
+        None => Err(RacketError::contract(format!(
+            "Expected integer->char to be given a valid unicode scalar value but got {}",
+            n
+        ))),
+    }
+}
+
+// Characters whose case mapping is more than one character, such as ß, are left unchanged.
+pub fn char_upcase(c: C) -> C {
+    single_char(c.to_uppercase()).unwrap_or(c)
+}
+
+pub fn char_downcase(c: C) -> C {
+    single_char(c.to_lowercase()).unwrap_or(c)
+}
+
+fn single_char(mut chars: impl Iterator<Item = C>) -> Option<C> {
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+pub fn string_to_list(s: &str) -> Value {
+    Value::ListValue(ValueList::from_vec(s.chars().map(Value::CharValue).collect()))
+}
+
+pub fn list_to_string(list: &ValueList) -> EvalResult {
+    let chars = list
+        .to_vec()
+        .iter()
+        .map(|x| match x {
+            Value::CharValue(c) => Ok(*c),
+            x => Err(RacketError::contract(format!(
+                "Expected list->string to be given a list of characters but found {}",
+                x
+            ))),
+        })
+        .collect::<EvalResult<String>>()?;
+    Ok(Value::StringValue(chars))
+}
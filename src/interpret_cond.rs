@@ -1,3 +1,4 @@
+use crate::error::{EvalResult, RacketError};
 use crate::interpret::*;
 use crate::interpret_bool::*;
 
@@ -12,9 +13,9 @@ pub struct CondCase {
     pub result: Expr,
 }
 
-pub fn interpret_cond_expr(expr: &CondExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> Value {
+pub fn interpret_cond_expr(expr: &CondExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> EvalResult {
     for case in &expr.cases {
-        let condition_result = interpret_bool_expr(&case.condition, variable_map, function_map);
+        let condition_result = interpret_bool_expr(&case.condition, variable_map, function_map)?;
         // Return on the first condition that is true.
         if condition_result {
             // Evaluate the body.
@@ -22,5 +23,5 @@ pub fn interpret_cond_expr(expr: &CondExpr, variable_map: &mut VariableMap, func
         }
     }
 
-    panic!("No case in cond expression evaluated to true");
+    Err(RacketError::fail("No case in cond expression evaluated to true".to_string()))
 }
@@ -0,0 +1,233 @@
+use std::cell::RefCell;
+use std::fmt;
+
+use crate::error::{EvalResult, RacketError, SyntaxError};
+use crate::interpret::{interpret, Expr, FunctionMap, Value, VariableMap};
+use crate::interpret_function_call::ordinal;
+use crate::parser::{parse_expr, ParseResult};
+use crate::primitives::{call_function_value, is_truthy};
+use crate::reader::Datum;
+
+// A contract on a value, such as integer? or (listof string?).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Contract {
+    // any/c
+    Any,
+    // A predicate the value must satisfy, or any other value that it must be equal to. `text` is the
+    // contract as written.
+    Flat { predicate: Expr, text: String },
+    ListOf(Box<Contract>),
+    Or(Vec<Contract>),
+    And(Vec<Contract>),
+    // A function contract on an argument or result. The value is only checked to be a function.
+    Function(Vec<Contract>, Box<Contract>),
+}
+
+impl fmt::Display for Contract {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |contracts: &[Contract]| contracts.iter().map(|c| c.to_string()).collect::<Vec<String>>().join(" ");
+        match self {
+            Contract::Any => write!(f, "any/c"),
+            Contract::Flat { text, .. } => write!(f, "{}", text),
+            Contract::ListOf(element) => write!(f, "(listof {})", element),
+            Contract::Or(contracts) => write!(f, "(or/c {})", join(contracts)),
+            Contract::And(contracts) => write!(f, "(and/c {})", join(contracts)),
+            Contract::Function(domain, range) => write!(f, "(-> {} {})", join(domain), range),
+        }
+    }
+}
+
+// Who is responsible for a function contract, which decides which calls are checked and who is
+// blamed when the function returns a bad result.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContractParty {
+    // define/contract: every call is checked.
+    Function,
+    // contract-out: only calls from other modules are checked. None is the program itself.
+    Module(Option<String>),
+}
+
+// The contract of a function defined with define/contract or provided with contract-out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionContract {
+    pub domain: Vec<Contract>,
+    pub range: Contract,
+    pub party: ContractParty,
+}
+
+thread_local! {
+    // The modules of the functions being called, innermost last. The program itself is None.
+    static MODULES: RefCell<Vec<Option<String>>> = const { RefCell::new(Vec::new()) };
+}
+
+// Marks the code of a module as running until the returned guard is dropped.
+pub fn enter_module(module: Option<String>) -> ModuleGuard {
+    MODULES.with(|modules| modules.borrow_mut().push(module));
+    ModuleGuard
+}
+
+pub struct ModuleGuard;
+
+impl Drop for ModuleGuard {
+    fn drop(&mut self) {
+        MODULES.with(|modules| modules.borrow_mut().pop());
+    }
+}
+
+pub fn current_module() -> Option<String> {
+    MODULES.with(|modules| modules.borrow().last().cloned().flatten())
+}
+
+fn module_name(module: &Option<String>) -> String {
+    module.clone().unwrap_or_else(|| "top-level".to_string())
+}
+
+// Reads a contract such as (-> list? integer? any/c).
+pub fn parse_contract(datum: &Datum) -> ParseResult<Contract> {
+    if datum.as_symbol() == Some("any/c") {
+        return Ok(Contract::Any);
+    }
+
+    let contracts = |datums: &[Datum]| datums.iter().map(parse_contract).collect::<ParseResult<_>>();
+    let items = datum.as_list().map(|items| &items[..]);
+    Ok(
+        match (items.and_then(|items| items.first()).and_then(|head| head.as_symbol()), items) {
+            (Some("->"), Some([_, domain @ .., range])) => {
+                Contract::Function(contracts(domain)?, Box::new(parse_contract(range)?))
+            }
+            (Some("listof"), Some([_, element])) => Contract::ListOf(Box::new(parse_contract(element)?)),
+            (Some("or/c"), Some([_, alternatives @ ..])) => Contract::Or(contracts(alternatives)?),
+            (Some("and/c"), Some([_, conjuncts @ ..])) => Contract::And(contracts(conjuncts)?),
+            _ => Contract::Flat {
+                predicate: parse_expr(datum)?,
+                text: datum.to_string(),
+            },
+        },
+    )
+}
+
+// A contract on a function with the given number of parameters.
+pub fn parse_function_contract(datum: &Datum, parameter_count: usize, party: ContractParty) -> ParseResult<FunctionContract> {
+    match parse_contract(datum)? {
+        Contract::Function(domain, range) if domain.len() == parameter_count => Ok(FunctionContract {
+            domain,
+            range: *range,
+            party,
+        }),
+        _ => Err(SyntaxError::syntax(
+            datum.span,
+            format!(
+                "Expected a function contract with {} argument(s) at {}: {}",
+                parameter_count, datum.span, datum
+            ),
+        )),
+    }
+}
+
+fn satisfies(contract: &Contract, value: &Value, variable_map: &mut VariableMap, function_map: &FunctionMap) -> EvalResult<bool> {
+    match contract {
+        Contract::Any => Ok(true),
+        Contract::Flat { predicate, .. } => match interpret(predicate, variable_map, function_map)? {
+            predicate @ Value::FunctionValue(_) => Ok(is_truthy(&call_function_value(
+                &predicate,
+                vec![value.clone()],
+                variable_map,
+                function_map,
+            )?)),
+            expected => Ok(expected == *value),
+        },
+        Contract::ListOf(element) => match value {
+            Value::ListValue(list) => {
+                for item in list.to_vec() {
+                    if !satisfies(element, &item, variable_map, function_map)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            _ => Ok(false),
+        },
+        Contract::Or(contracts) => {
+            for contract in contracts {
+                if satisfies(contract, value, variable_map, function_map)? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }
+        Contract::And(contracts) => {
+            for contract in contracts {
+                if !satisfies(contract, value, variable_map, function_map)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+        Contract::Function(_, _) => Ok(matches!(value, Value::FunctionValue(_))),
+    }
+}
+
+impl FunctionContract {
+    // Calls from inside the module that provides a function are trusted.
+    pub fn applies(&self) -> bool {
+        match &self.party {
+            ContractParty::Function => true,
+            ContractParty::Module(module) => current_module() != *module,
+        }
+    }
+
+    // Checks the arguments of a call, blaming the caller for the first one that breaks the contract.
+    pub fn check_arguments(
+        &self,
+        name: &str,
+        arguments: &[Value],
+        variable_map: &mut VariableMap,
+        function_map: &FunctionMap,
+    ) -> EvalResult<()> {
+        for (i, (contract, argument)) in self.domain.iter().zip(arguments).enumerate() {
+            if !satisfies(contract, argument, variable_map, function_map)? {
+                let position = format!("the {} argument of {}", ordinal(i + 1), self.contract());
+                return Err(self.violation(name, contract, argument, &position, &module_name(&current_module())));
+            }
+        }
+        Ok(())
+    }
+
+    // Checks the result of a call, blaming the function or the module that provides it.
+    pub fn check_result(
+        &self,
+        name: &str,
+        result: &Value,
+        variable_map: &mut VariableMap,
+        function_map: &FunctionMap,
+    ) -> EvalResult<()> {
+        if satisfies(&self.range, result, variable_map, function_map)? {
+            return Ok(());
+        }
+        let position = format!("the range of {}", self.contract());
+        Err(self.violation(name, &self.range, result, &position, &self.party_name(name)))
+    }
+
+    fn contract(&self) -> Contract {
+        Contract::Function(self.domain.clone(), Box::new(self.range.clone()))
+    }
+
+    fn party_name(&self, name: &str) -> String {
+        match &self.party {
+            ContractParty::Function => format!("(function {})", name),
+            ContractParty::Module(module) => module_name(module),
+        }
+    }
+
+    fn violation(&self, name: &str, contract: &Contract, given: &Value, position: &str, blaming: &str) -> RacketError {
+        RacketError::contract(format!(
+            "{}: contract violation\n  expected: {}\n  given: {}\n  in: {}\n  contract from: {}\n  blaming: {}",
+            name,
+            contract,
+            given,
+            position,
+            self.party_name(name),
+            blaming
+        ))
+    }
+}
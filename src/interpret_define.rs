@@ -0,0 +1,192 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::error::{EvalResult, SyntaxError};
+use crate::interpret::{interpret, Expr, FunctionMap, VariableMap};
+use crate::interpret_lambda::{interpret_local_functions, LambdaExpr};
+use crate::parser::ParseResult;
+use crate::reader::{Datum, DatumKind};
+
+// (define name value). At the top level the value is computed once before the program runs, and
+// inside a function body it is computed every time the function is called.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub name: String,
+    pub value: Expr,
+}
+
+// A function or lambda body that starts with internal definitions. The definitions are stored in
+// the order they have to be evaluated in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BodyExpr {
+    // The functions defined in the body. They are bound before the other definitions are evaluated,
+    // since creating them does not depend on anything.
+    pub functions: Vec<(String, LambdaExpr)>,
+    pub definitions: Vec<Definition>,
+    pub result: Box<Expr>,
+}
+
+pub fn interpret_body_expr(body: &BodyExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> EvalResult {
+    for (name, function) in interpret_local_functions(&body.functions, variable_map) {
+        variable_map.entry(name).or_default().push(function);
+    }
+    let result = define_all(&body.definitions, variable_map, function_map).and_then(|()| {
+        let result = interpret(&body.result, variable_map, function_map);
+        undefine_all(&body.definitions, variable_map);
+        result
+    });
+    for (name, _) in &body.functions {
+        variable_map.get_mut(name).unwrap().pop();
+    }
+
+    result
+}
+
+// Evaluates each definition in order and binds its name, so that later definitions can use it. If a
+// definition raises an exception, the ones that were already bound are removed again.
+pub fn define_all(definitions: &[Definition], variable_map: &mut VariableMap, function_map: &FunctionMap) -> EvalResult<()> {
+    for (i, definition) in definitions.iter().enumerate() {
+        match interpret(&definition.value, variable_map, function_map) {
+            Ok(value) => variable_map.entry(definition.name.clone()).or_default().push(value),
+            Err(error) => {
+                undefine_all(&definitions[..i], variable_map);
+                return Err(error);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn undefine_all(definitions: &[Definition], variable_map: &mut VariableMap) {
+    for definition in definitions {
+        variable_map.get_mut(&definition.name).unwrap().pop();
+    }
+}
+
+// The order to evaluate definitions in so that every definition comes after the ones its value
+// needs. `definitions` are the names and value forms of the definitions in source order, and
+// `functions` are the bodies of the functions that may be called while computing the values.
+// Definitions that do not depend on each other keep their source order. Definitions that need each
+// other's values cannot be ordered and give an error.
+pub fn initialization_order(definitions: &[(String, &Datum)], functions: &HashMap<String, &[Datum]>) -> ParseResult<Vec<usize>> {
+    let names: HashMap<&str, usize> = definitions
+        .iter()
+        .enumerate()
+        .map(|(i, (name, _))| (name.as_str(), i))
+        .collect();
+    // Definitions whose value is a lambda can be called like functions.
+    let mut bodies = functions.clone();
+    bodies.extend(
+        definitions
+            .iter()
+            .filter_map(|(name, value)| Some((name.clone(), lambda_body(value)?))),
+    );
+
+    let dependencies: Vec<Vec<usize>> = definitions
+        .iter()
+        .map(|(_, value)| {
+            let mut references = Vec::new();
+            // The body of a lambda that is the value itself only runs once it is called.
+            if lambda_body(value).is_none() {
+                collect_references(value, &names, &bodies, &mut HashSet::new(), &mut references);
+            }
+            references
+        })
+        .collect();
+
+    let mut order = Vec::new();
+    let mut states = vec![VisitState::NotVisited; definitions.len()];
+    for i in 0..definitions.len() {
+        visit(i, definitions, &dependencies, &mut states, &mut Vec::new(), &mut order)?;
+    }
+
+    Ok(order)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum VisitState {
+    NotVisited,
+    Visiting,
+    Visited,
+}
+
+fn visit(
+    i: usize,
+    definitions: &[(String, &Datum)],
+    dependencies: &[Vec<usize>],
+    states: &mut [VisitState],
+    path: &mut Vec<usize>,
+    order: &mut Vec<usize>,
+) -> ParseResult<()> {
+    match states[i] {
+        VisitState::Visited => return Ok(()),
+        VisitState::Visiting => {
+            let cycle_start = path.iter().position(|&j| j == i).unwrap();
+            let cycle: Vec<&str> = path[cycle_start..]
+                .iter()
+                .chain(std::iter::once(&i))
+                .map(|&j| definitions[j].0.as_str())
+                .collect();
+            let (name, value) = &definitions[i];
+            return Err(SyntaxError::syntax(
+                value.span,
+                format!("Cyclic initialisation of {} at {}: {}", name, value.span, cycle.join(" -> ")),
+            ));
+        }
+        VisitState::NotVisited => {}
+    }
+
+    states[i] = VisitState::Visiting;
+    path.push(i);
+    for &dependency in &dependencies[i] {
+        visit(dependency, definitions, dependencies, states, path, order)?;
+    }
+    path.pop();
+    states[i] = VisitState::Visited;
+    order.push(i);
+    Ok(())
+}
+
+// The body of a (lambda (params...) body...) form.
+fn lambda_body(datum: &Datum) -> Option<&[Datum]> {
+    match datum.as_list().map(|items| &items[..]) {
+        Some([lambda, _, body @ ..]) if matches!(lambda.as_symbol(), Some("lambda" | "λ")) => Some(body),
+        _ => None,
+    }
+}
+
+// Finds the definitions that are used while a form is evaluated, including the ones used by any
+// function it calls. Quoted data is never evaluated, so it is skipped. A lambda is assumed to be
+// called while the form is evaluated, as it is when it is passed to map or called straight away.
+fn collect_references(
+    datum: &Datum,
+    names: &HashMap<&str, usize>,
+    bodies: &HashMap<String, &[Datum]>,
+    visited_bodies: &mut HashSet<String>,
+    references: &mut Vec<usize>,
+) {
+    match &datum.kind {
+        DatumKind::Symbol(s) => {
+            if let Some(&i) = names.get(s.as_str()) {
+                references.push(i);
+            }
+            if let Some(body) = bodies.get(s) {
+                if visited_bodies.insert(s.clone()) {
+                    for form in body.iter() {
+                        collect_references(form, names, bodies, visited_bodies, references);
+                    }
+                }
+            }
+        }
+        DatumKind::List(items) => {
+            if items.first().and_then(|head| head.as_symbol()) == Some("quote") {
+                return;
+            }
+            let items = lambda_body(datum).unwrap_or(items);
+            for item in items {
+                collect_references(item, names, bodies, visited_bodies, references);
+            }
+        }
+        _ => {}
+    }
+}
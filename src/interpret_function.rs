@@ -1,4 +1,12 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::error::SyntaxError;
 use crate::interpret::{Expr, FunctionInfo, FunctionMap};
+use crate::interpret_contract::FunctionContract;
+use crate::parser::{definition_annotation, parameter_annotation};
+use crate::reader::Datum;
+use crate::types::{parse_type, Type};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct FunctionExpr {
@@ -7,14 +15,72 @@ pub struct FunctionExpr {
     pub body: Box<Expr>,
 }
 
-pub fn interpret_function_expr(function: &FunctionExpr, function_map: &mut FunctionMap) {
+pub fn interpret_function_expr(
+    function: &FunctionExpr,
+    signature: Option<Type>,
+    module: Option<String>,
+    contract: Option<FunctionContract>,
+    function_map: &mut FunctionMap,
+) {
     function_map.insert(
         function.name.clone(),
-        FunctionInfo {
+        FunctionInfo::Defined {
             parameter_names: function.parameters.clone(),
             // Clone here may be slightly expensive however, it is done only once when
             // bringing the expression from the parser into the interpreter.
             body: *function.body.clone(),
+            signature,
+            module,
+            contract: contract.map(Rc::new),
         },
     );
 }
+
+// The signature a function definition is checked against when it is called, from its (: name Type)
+// declaration or the annotations on its parameters and result. Unannotated parts have type Any.
+pub fn function_signature(
+    datum: &Datum,
+    declaration: Option<&Datum>,
+    type_names: &HashMap<String, Type>,
+) -> Result<Option<Type>, SyntaxError> {
+    let parameters = &datum.as_list().unwrap()[1].as_list().unwrap()[1..];
+    let mut names = type_names.clone();
+    let mut count = 0;
+    let mut fresh = || {
+        count += 1;
+        Type::Var(count)
+    };
+    let mut parse = |ty: &Datum| parse_type(ty, &mut names, &mut fresh);
+
+    if let Some(declaration) = declaration {
+        let signature = parse(declaration)?;
+        return match &signature {
+            Type::Function(types, _) if types.len() == parameters.len() => Ok(Some(signature)),
+            _ => Err(SyntaxError::syntax(
+                declaration.span,
+                format!(
+                    "The type declared at {} is not a function of {} argument(s): {}",
+                    declaration.span,
+                    parameters.len(),
+                    declaration
+                ),
+            )),
+        };
+    }
+
+    let annotations: Vec<Option<&Datum>> = parameters
+        .iter()
+        .map(|parameter| parameter_annotation(parameter).and_then(|(_, ty)| ty))
+        .collect();
+    let result = definition_annotation(datum);
+    if result.is_none() && annotations.iter().all(|ty| ty.is_none()) {
+        return Ok(None);
+    }
+
+    let parameter_types = annotations
+        .into_iter()
+        .map(|ty| ty.map_or(Ok(Type::Any), &mut parse))
+        .collect::<Result<_, _>>()?;
+    let result = result.map_or(Ok(Type::Any), parse)?;
+    Ok(Some(Type::Function(parameter_types, Box::new(result))))
+}
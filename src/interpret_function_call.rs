@@ -1,48 +1,305 @@
-use crate::interpret::{interpret, Expr, FunctionMap, Value, VariableMap};
+use crate::debugger;
+use crate::error::{EvalResult, RacketError};
+use crate::interpret::{interpret, Expr, FunctionInfo, FunctionMap, FunctionValue, Value, VariableMap};
+use crate::interpret_contract::{current_module, enter_module};
+use crate::interpret_struct::apply_struct_function;
+use crate::limits;
+use crate::primitives::{apply_primitive, is_primitive};
+use crate::stack_trace::{attach_backtrace, enter_frame, CallSite};
+use crate::tokenizer::Span;
+use crate::types::{value_has_type, Type};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct FunctionCallExpr {
     pub name: String,
     pub arguments: Vec<Expr>,
+    // Arguments passed as `#:name value`. Only built in functions accept these.
+    pub keyword_arguments: Vec<(String, Expr)>,
+    pub span: Span,
+}
+
+// A call where the function is computed by an expression, e.g. ((make-adder 1) 2).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApplicationExpr {
+    pub function: Box<Expr>,
+    pub arguments: Vec<Expr>,
+    pub span: Span,
 }
 
 pub fn interpret_function_call(
     function_call: &FunctionCallExpr,
     variable_map: &mut VariableMap,
     function_map: &FunctionMap,
-) -> Value {
+) -> EvalResult {
     // 1. Interpret all of the arguments into the function.
-    let argument_values: Vec<Value> = function_call
+    let argument_values = function_call
+        .arguments
+        .iter()
+        .map(|expr| interpret(expr, variable_map, function_map))
+        .collect::<EvalResult<Vec<Value>>>()?;
+    let keyword_argument_values = function_call
+        .keyword_arguments
+        .iter()
+        .map(|(keyword, expr)| Ok((keyword.clone(), interpret(expr, variable_map, function_map)?)))
+        .collect::<EvalResult<Vec<(String, Value)>>>()?;
+
+    // 2. Find the function being called and call it.
+    let function = resolve_function(&function_call.name, variable_map, function_map)?;
+    call_at(
+        &function_call.name,
+        &function,
+        argument_values,
+        &keyword_argument_values,
+        function_call.span,
+        variable_map,
+        function_map,
+    )
+}
+
+pub fn interpret_application_expr(
+    application: &ApplicationExpr,
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult {
+    let function = match interpret(&application.function, variable_map, function_map)? {
+        Value::FunctionValue(function) => function,
+        x => return Err(RacketError::contract(format!("Expected a function to call but got {}", x))),
+    };
+
+    let argument_values = application
         .arguments
         .iter()
         .map(|expr| interpret(expr, variable_map, function_map))
-        .collect();
+        .collect::<EvalResult<Vec<Value>>>()?;
 
-    // 2. Add the parameters to the variable map so the function can access its parameter values.
-    let mut i: usize = 0;
-    for arg in argument_values {
-        let name = &function_map
-            .get(&function_call.name)
-            .expect(&format!("Undefined function: '{}'", function_call.name))
-            .parameter_names[i];
-        variable_map.entry(name.to_string()).or_insert(Vec::new()).push(arg);
-        i += 1;
+    call_at(
+        function.name(),
+        &function,
+        argument_values,
+        &[],
+        application.span,
+        variable_map,
+        function_map,
+    )
+}
+
+// Calls a function from the call site at `span`, which is where backtraces and the debugger say it was
+// called from.
+fn call_at(
+    name: &str,
+    function: &FunctionValue,
+    arguments: Vec<Value>,
+    keyword_arguments: &[(String, Value)],
+    span: Span,
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult {
+    let call_site = CallSite {
+        module: current_module(),
+        span,
+    };
+    debugger::before_call(name, &arguments, call_site.clone(), variable_map, function_map);
+    let result = {
+        let _frame = enter_frame(name, Some(call_site.clone()));
+        apply_function(function, arguments, keyword_arguments, variable_map, function_map).map_err(attach_backtrace)?
+    };
+    debugger::after_call(name, &result, call_site, variable_map, function_map);
+    Ok(result)
+}
+
+// A variable bound to a function takes precedence over top level functions, which take precedence over primitives.
+pub fn resolve_function(name: &str, variable_map: &VariableMap, function_map: &FunctionMap) -> EvalResult<FunctionValue> {
+    if let Some(Value::FunctionValue(function)) = variable_map.get(name).and_then(|values| values.last()) {
+        return Ok(function.clone());
     }
 
-    // 3. Get the body of the function.
-    let function_body = &function_map
-        .get(&function_call.name)
-        .expect(&format!("Undefined function '{}'", &function_call.name))
-        .body;
+    if function_map.contains_key(name) || is_primitive(name) {
+        Ok(FunctionValue::Named(name.to_string()))
+    } else {
+        Err(RacketError::variable(format!("Undefined function '{}'", name)))
+    }
+}
+
+pub fn apply_function(
+    function: &FunctionValue,
+    arguments: Vec<Value>,
+    keyword_arguments: &[(String, Value)],
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult {
+    match function {
+        FunctionValue::Named(name) => match function_map.get(name) {
+            Some(FunctionInfo::Defined {
+                parameter_names,
+                body,
+                signature,
+                module,
+                contract,
+            }) => {
+                reject_keyword_arguments(name, keyword_arguments)?;
+                check_arity(name, parameter_names.len(), arguments.len())?;
+
+                // Annotated functions check their arguments and result against their signature, and
+                // contracts are checked where they apply, blaming whoever broke them.
+                let contract = contract.as_ref().filter(|contract| contract.applies());
+                if let Some(signature) = signature {
+                    check_arguments(name, signature, &arguments)?;
+                }
+                if let Some(contract) = contract {
+                    contract.check_arguments(name, &arguments, variable_map, function_map)?;
+                }
+
+                let result = {
+                    let _module = enter_module(module.clone());
+                    call_with_bindings(name, parameter_names, body, arguments, &[], variable_map, function_map)?
+                };
+
+                if let Some(contract) = contract {
+                    contract.check_result(name, &result, variable_map, function_map)?;
+                }
+                if let Some(signature) = signature {
+                    check_result(name, signature, &result)?;
+                }
+                Ok(result)
+            }
+            Some(FunctionInfo::Struct(function)) => {
+                reject_keyword_arguments(name, keyword_arguments)?;
+                let result = apply_struct_function(name, function, arguments);
+                limits::check_heap()?;
+                result
+            }
+            Some(FunctionInfo::Native(native)) => {
+                reject_keyword_arguments(name, keyword_arguments)?;
+                check_arity(name, native.arity, arguments.len())?;
+                let result = (native.function)(&arguments);
+                limits::check_heap()?;
+                result
+            }
+            None => {
+                // Primitives build lists without going through allocate, so their allocations are checked here.
+                let result = apply_primitive(name, arguments, keyword_arguments, variable_map, function_map);
+                limits::check_heap()?;
+                result
+            }
+        },
+        FunctionValue::Closure(closure) => {
+            reject_keyword_arguments(closure.name(), keyword_arguments)?;
+            call_with_bindings(
+                closure.name(),
+                &closure.parameters,
+                &closure.body,
+                arguments,
+                &closure.environment(),
+                variable_map,
+                function_map,
+            )
+        }
+    }
+}
+
+pub fn check_arity(name: &str, expected: usize, actual: usize) -> EvalResult<()> {
+    if expected != actual {
+        return Err(RacketError::contract(format!(
+            "Function {} expects {} argument(s) but was called with {}",
+            name, expected, actual
+        )));
+    }
+    Ok(())
+}
+
+// Raises a contract violation for the first argument that does not have the type of its parameter
+// in the function type `signature`.
+pub fn check_arguments(name: &str, signature: &Type, arguments: &[Value]) -> EvalResult<()> {
+    let Type::Function(parameters, _) = signature else {
+        return Ok(());
+    };
+    for (i, (parameter, argument)) in parameters.iter().zip(arguments).enumerate() {
+        if !value_has_type(argument, parameter) {
+            return Err(contract_violation(
+                name,
+                parameter,
+                argument,
+                &format!("the {} argument of {}", ordinal(i + 1), signature),
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub fn check_result(name: &str, signature: &Type, result: &Value) -> EvalResult<()> {
+    match signature {
+        Type::Function(_, range) if !value_has_type(result, range) => Err(contract_violation(
+            name,
+            range,
+            result,
+            &format!("the range of {}", signature),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn contract_violation(name: &str, expected: &Type, given: &Value, position: &str) -> RacketError {
+    RacketError::contract(format!(
+        "{}: contract violation\n  expected: {}\n  given: {}\n  in: {}",
+        name, expected, given, position
+    ))
+}
+
+// 1st, 2nd, 3rd, 4th ...
+pub fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+fn reject_keyword_arguments(name: &str, keyword_arguments: &[(String, Value)]) -> EvalResult<()> {
+    match keyword_arguments.first() {
+        Some((keyword, _)) => Err(RacketError::contract(format!(
+            "Function {} does not accept the keyword argument #:{}",
+            name, keyword
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn call_with_bindings(
+    name: &str,
+    parameter_names: &[String],
+    body: &Expr,
+    arguments: Vec<Value>,
+    captured: &[(String, Value)],
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult {
+    check_arity(name, parameter_names.len(), arguments.len())?;
+
+    limits::enter_call()?;
+
+    // 1. Add the captured variables and then the parameters to the variable map so the body can access them.
+    for (parameter_name, value) in captured {
+        variable_map.entry(parameter_name.clone()).or_default().push(value.clone());
+    }
+    for (parameter_name, arg) in parameter_names.iter().zip(arguments) {
+        variable_map.entry(parameter_name.clone()).or_default().push(arg);
+    }
 
-    // 4. Execute the function itself and get the result.
-    let result = interpret(&function_body, variable_map, function_map);
+    // 2. Execute the function itself and get the result.
+    let result = interpret(body, variable_map, function_map);
 
-    // 5. Clean up by removing the parameters from the environment.
-    for j in 0..i {
-        let name = &function_map[&function_call.name].parameter_names[j];
-        variable_map.get_mut(name).expect("Expected variable not found").pop();
+    // 3. Clean up by removing the parameters and captured variables from the environment. This also
+    // happens when the body raised an exception, since it may still be caught further up.
+    for parameter_name in parameter_names.iter().chain(captured.iter().map(|(name, _)| name)) {
+        variable_map
+            .get_mut(parameter_name)
+            .expect("Expected variable not found")
+            .pop();
     }
+    limits::exit_call();
 
     result
 }
@@ -0,0 +1,96 @@
+use crate::error::{EvalResult, RacketError};
+use crate::interpret::{FunctionMap, Value, ValueList, VariableMap, H, N};
+use crate::limits;
+use crate::primitives::call_function_value;
+
+// Native implementations of the hash table functions. They are exposed through primitives.rs.
+// Hash tables are immutable, so every update produces a new table that shares most of its
+// structure with the old one.
+
+fn to_hash(name: &str, hash: &Value) -> EvalResult<H> {
+    match hash {
+        Value::HashValue(h) => Ok(h.clone()),
+        x => Err(RacketError::contract(format!(
+            "Expected a hash as the first argument to {} but got {}",
+            name, x
+        ))),
+    }
+}
+
+// Entries ordered by how their keys print, so that printing and hash-keys do not depend on the
+// order the underlying map happens to store them in.
+pub fn sorted_entries(hash: &H) -> Vec<(Value, Value)> {
+    let mut entries: Vec<(String, (Value, Value))> = hash
+        .iter()
+        .map(|(key, value)| (key.to_string(), (key.clone(), value.clone())))
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    entries.into_iter().map(|(_, entry)| entry).collect()
+}
+
+pub fn hash_to_string(hash: &H) -> String {
+    let mut parts = vec!["hash".to_string()];
+    for (key, value) in sorted_entries(hash) {
+        parts.push(key.to_string());
+        parts.push(value.to_string());
+    }
+    format!("({})", parts.join(" "))
+}
+
+// (hash key value ...)
+pub fn hash_new(arguments: &[Value]) -> EvalResult {
+    if !arguments.len().is_multiple_of(2) {
+        return Err(RacketError::contract(format!(
+            "Expected hash to be given an even number of arguments but got {}",
+            arguments.len()
+        )));
+    }
+
+    limits::allocate(arguments.len() / 2)?;
+    Ok(Value::HashValue(
+        arguments.chunks(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect(),
+    ))
+}
+
+// (hash-ref hash key [default]). A function given as the default is called when the key is missing.
+pub fn hash_ref(
+    hash: &Value,
+    key: &Value,
+    default: Option<&Value>,
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult {
+    match (to_hash("hash-ref", hash)?.get(key), default) {
+        (Some(value), _) => Ok(value.clone()),
+        (None, Some(function @ Value::FunctionValue(_))) => call_function_value(function, vec![], variable_map, function_map),
+        (None, Some(default)) => Ok(default.clone()),
+        (None, None) => Err(RacketError::contract(format!("No value found in hash for key {}", key))),
+    }
+}
+
+pub fn hash_set(hash: &Value, key: &Value, value: &Value) -> EvalResult {
+    limits::allocate(1)?;
+    Ok(Value::HashValue(
+        to_hash("hash-set", hash)?.update(key.clone(), value.clone()),
+    ))
+}
+
+pub fn hash_remove(hash: &Value, key: &Value) -> EvalResult {
+    Ok(Value::HashValue(to_hash("hash-remove", hash)?.without(key)))
+}
+
+pub fn hash_keys(hash: &Value) -> EvalResult {
+    let keys = sorted_entries(&to_hash("hash-keys", hash)?)
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect();
+    Ok(Value::ListValue(ValueList::from_vec(keys)))
+}
+
+pub fn hash_count(hash: &Value) -> EvalResult {
+    Ok(Value::NumValue(to_hash("hash-count", hash)?.len() as N))
+}
+
+pub fn hash_has_key(hash: &Value, key: &Value) -> EvalResult {
+    Ok(Value::BoolValue(to_hash("hash-has-key?", hash)?.contains_key(key)))
+}
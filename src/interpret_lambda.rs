@@ -0,0 +1,93 @@
+use std::borrow::Cow;
+use std::rc::Rc;
+
+use crate::interpret::{Expr, FunctionValue, Value, VariableMap};
+
+// (lambda (params...) body) or (λ (params...) body)
+#[derive(Debug, Clone, PartialEq)]
+pub struct LambdaExpr {
+    pub parameters: Vec<String>,
+    pub body: Rc<Expr>,
+    // The names the body refers to other than the parameters.
+    pub free_variables: Vec<String>,
+}
+
+// An anonymous function together with the values its free variables had when it was created.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Closure {
+    pub parameters: Vec<String>,
+    pub body: Rc<Expr>,
+    pub captured: Vec<(String, Value)>,
+    // For a function defined in a body, its index among the functions defined in that body.
+    pub local: Option<(usize, Rc<LocalFunctions>)>,
+}
+
+// The functions defined in a body by name, in the order they are defined in. Each of them can call
+// itself and the others, also after the body has returned.
+pub type LocalFunctions = Vec<(String, Closure)>;
+
+impl Closure {
+    // The name errors and backtraces use for the function.
+    pub fn name(&self) -> &str {
+        match &self.local {
+            Some((index, functions)) => &functions[*index].0,
+            None => "lambda",
+        }
+    }
+
+    // The variables the body can refer to besides its parameters: the captured ones and the
+    // functions defined together with this one.
+    pub fn environment(&self) -> Cow<'_, [(String, Value)]> {
+        match &self.local {
+            Some((_, functions)) => {
+                let mut environment = self.captured.clone();
+                environment.extend(local_function_values(functions));
+                Cow::Owned(environment)
+            }
+            None => Cow::Borrowed(&self.captured),
+        }
+    }
+}
+
+pub fn interpret_lambda_expr(lambda: &LambdaExpr, variable_map: &VariableMap) -> Value {
+    Value::FunctionValue(FunctionValue::Closure(closure(lambda, variable_map, &[])))
+}
+
+// The functions defined in a body, as the values their names are bound to.
+pub fn interpret_local_functions(functions: &[(String, LambdaExpr)], variable_map: &VariableMap) -> Vec<(String, Value)> {
+    let names: Vec<String> = functions.iter().map(|(name, _)| name.clone()).collect();
+    let functions: LocalFunctions = functions
+        .iter()
+        .map(|(name, lambda)| (name.clone(), closure(lambda, variable_map, &names)))
+        .collect();
+    local_function_values(&Rc::new(functions)).collect()
+}
+
+fn local_function_values(functions: &Rc<LocalFunctions>) -> impl Iterator<Item = (String, Value)> + '_ {
+    functions.iter().enumerate().map(|(index, (name, closure))| {
+        let closure = Closure {
+            local: Some((index, functions.clone())),
+            ..closure.clone()
+        };
+        (name.clone(), Value::FunctionValue(FunctionValue::Closure(closure)))
+    })
+}
+
+// Capture the innermost binding of every free variable so the closure can be called after they go
+// out of scope. Names that are not bound yet, such as constants defined later in the same body, are
+// looked up when the closure is called. Names in `local` are bound by the closure itself.
+fn closure(lambda: &LambdaExpr, variable_map: &VariableMap, local: &[String]) -> Closure {
+    let captured = lambda
+        .free_variables
+        .iter()
+        .filter(|name| !local.contains(name))
+        .filter_map(|name| Some((name.clone(), variable_map.get(name)?.last()?.clone())))
+        .collect();
+
+    Closure {
+        parameters: lambda.parameters.clone(),
+        body: lambda.body.clone(),
+        captured,
+        local: None,
+    }
+}
@@ -1,7 +1,10 @@
 use crate::{
-    interpret::{interpret, Expr, FunctionMap, Value, ValueList, ValueNode, VariableMap, L},
+    error::{EvalResult, RacketError},
+    interpret::{interpret, Expr, FunctionMap, Value, ValueList, ValueNode, VariableMap, L, N},
     interpret_function_call::{interpret_function_call, FunctionCallExpr},
     interpret_variable::{interpret_variable_expr, VariableExpr},
+    limits,
+    primitives::{call_function_value, is_truthy},
 };
 
 // All possible expression types that can result in a ListValue.
@@ -12,6 +15,8 @@ pub enum ListExpr {
     VariableExpr(VariableExpr),
     CarExpr(CarExpr),
     FunctionCallExpr(FunctionCallExpr),
+    // Any other expression, checked to produce a list when it is evaluated.
+    DynamicExpr(Box<Expr>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -36,68 +41,382 @@ pub struct CdrExpr {
     pub list: Box<ListExpr>,
 }
 
-pub fn interpret_list_expr(list: &ListExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> L {
+pub fn interpret_list_expr(list: &ListExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> EvalResult<L> {
     match list {
         ListExpr::ListLiteralExpr(x) => interpret_list_literal_expr(x, variable_map, function_map),
-        ListExpr::CdrExpr(x) => interpret_cdr_expr(&x, variable_map, function_map),
+        ListExpr::CdrExpr(x) => interpret_cdr_expr(x, variable_map, function_map),
         ListExpr::VariableExpr(x) => interpret_variable_list_expr(x, variable_map),
-        ListExpr::FunctionCallExpr(x) => {
-            if let Value::ListValue(y) = interpret_function_call(x, variable_map, function_map) {
-                y
-            } else {
-                panic!(
-                    "Expected call to function {} to return list but returned something else instead",
-                    x.name
-                );
-            }
-        }
-        ListExpr::CarExpr(x) => {
-            if let Value::ListValue(y) = interpret_car_expr(x, variable_map, function_map) {
-                y
-            } else {
-                panic!("Malformed car expression {:#?}", x);
-            }
-        }
+        ListExpr::FunctionCallExpr(x) => match interpret_function_call(x, variable_map, function_map)? {
+            Value::ListValue(y) => Ok(y),
+            y => Err(RacketError::contract(format!(
+                "Expected call to function {} to return list but returned {}",
+                x.name, y
+            ))),
+        },
+        ListExpr::CarExpr(x) => match interpret_car_expr(x, variable_map, function_map)? {
+            Value::ListValue(y) => Ok(y),
+            y => Err(RacketError::contract(format!("Expected car to produce a list but got {}", y))),
+        },
+        ListExpr::DynamicExpr(x) => match interpret(x, variable_map, function_map)? {
+            Value::ListValue(y) => Ok(y),
+            y => Err(RacketError::contract(format!("Expected a list but got {}", y))),
+        },
     }
 }
 
-pub fn interpret_list_literal_expr(list: &ListLiteralExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> L {
+pub fn interpret_list_literal_expr(
+    list: &ListLiteralExpr,
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult<L> {
     match list {
-        ListLiteralExpr::Empty => ValueList::Empty,
+        ListLiteralExpr::Empty => Ok(ValueList::Empty),
         ListLiteralExpr::Node(y) => {
-            let interpreted_data = interpret(&y.data, variable_map, function_map);
-            let interpreted_next = interpret_list_expr(&y.next, variable_map, function_map);
+            let interpreted_data = interpret(&y.data, variable_map, function_map)?;
+            let interpreted_next = interpret_list_expr(&y.next, variable_map, function_map)?;
+            limits::allocate(1)?;
 
-            ValueList::Node(ValueNode {
+            Ok(ValueList::Node(ValueNode {
                 data: Box::new(interpreted_data),
                 next: Box::new(interpreted_next),
-            })
+            }))
         }
     }
 }
 
-pub fn interpret_car_expr(list: &CarExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> Value {
-    let result = interpret_list_expr(&list.list, variable_map, function_map);
+pub fn interpret_car_expr(list: &CarExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> EvalResult {
+    let result = interpret_list_expr(&list.list, variable_map, function_map)?;
     match result {
-        ValueList::Empty => panic!("Argument to car cannot be empty"),
-        ValueList::Node(result) => *result.data,
+        ValueList::Empty => Err(RacketError::contract("Argument to car cannot be empty".to_string())),
+        ValueList::Node(result) => Ok(*result.data),
     }
 }
 
-pub fn interpret_cdr_expr(list: &CdrExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> L {
-    let res = interpret_list_expr(&list.list, variable_map, function_map);
+pub fn interpret_cdr_expr(list: &CdrExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> EvalResult<L> {
+    let res = interpret_list_expr(&list.list, variable_map, function_map)?;
 
     match res {
-        ValueList::Empty => panic!("Cannot take cdr of empty list"),
-        ValueList::Node(x) => *x.next,
+        ValueList::Empty => Err(RacketError::contract("Cannot take cdr of empty list".to_string())),
+        ValueList::Node(x) => Ok(*x.next),
     }
 }
 
-pub fn interpret_variable_list_expr(expr: &VariableExpr, variable_map: &VariableMap) -> L {
-    let res = interpret_variable_expr(expr, variable_map);
+pub fn interpret_variable_list_expr(expr: &VariableExpr, variable_map: &VariableMap) -> EvalResult<L> {
+    match interpret_variable_expr(expr, variable_map)? {
+        Value::ListValue(x) => Ok(x),
+        x => Err(RacketError::contract(format!(
+            "Variable {} does not refer to a list value",
+            x
+        ))),
+    }
+}
 
-    match res {
-        Value::ListValue(x) => x,
-        x => panic!("Variable {} does not refer to a num value", x),
+// Native implementations of the higher order list functions. They are exposed through primitives.rs.
+
+fn list_arguments(name: &str, lists: &[Value]) -> EvalResult<Vec<Vec<Value>>> {
+    let lists = lists
+        .iter()
+        .map(|list| match list {
+            Value::ListValue(list) => Ok(list.to_vec()),
+            x => Err(RacketError::contract(format!(
+                "Expected list arguments to {} but got {}",
+                name, x
+            ))),
+        })
+        .collect::<EvalResult<Vec<Vec<Value>>>>()?;
+
+    if lists.iter().any(|list| list.len() != lists[0].len()) {
+        return Err(RacketError::contract(format!(
+            "All lists passed to {} must have the same length",
+            name
+        )));
+    }
+
+    Ok(lists)
+}
+
+// The i-th element of every list, in order.
+fn nth_elements(lists: &[Vec<Value>], i: usize) -> Vec<Value> {
+    lists.iter().map(|list| list[i].clone()).collect()
+}
+
+fn to_list(name: &str, list: &Value) -> EvalResult<Vec<Value>> {
+    Ok(list_arguments(name, std::slice::from_ref(list))?.pop().unwrap())
+}
+
+pub fn list_map(f: &Value, lists: &[Value], variable_map: &mut VariableMap, function_map: &FunctionMap) -> EvalResult {
+    let lists = list_arguments("map", lists)?;
+    let result = (0..lists[0].len())
+        .map(|i| call_function_value(f, nth_elements(&lists, i), variable_map, function_map))
+        .collect::<EvalResult<Vec<Value>>>()?;
+    Ok(Value::ListValue(ValueList::from_vec(result)))
+}
+
+pub fn list_filter(f: &Value, list: &Value, variable_map: &mut VariableMap, function_map: &FunctionMap) -> EvalResult {
+    let mut result = Vec::new();
+    for x in to_list("filter", list)? {
+        if is_truthy(&call_function_value(f, vec![x.clone()], variable_map, function_map)?) {
+            result.push(x);
+        }
+    }
+    Ok(Value::ListValue(ValueList::from_vec(result)))
+}
+
+// (foldl f init l1 l2 ...) calls (f x1 x2 ... acc) from the front of the lists.
+pub fn list_foldl(
+    f: &Value,
+    init: &Value,
+    lists: &[Value],
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult {
+    let lists = list_arguments("foldl", lists)?;
+    (0..lists[0].len()).try_fold(init.clone(), |acc, i| {
+        let mut arguments = nth_elements(&lists, i);
+        arguments.push(acc);
+        call_function_value(f, arguments, variable_map, function_map)
+    })
+}
+
+// Like foldl, but starting from the back of the lists.
+pub fn list_foldr(
+    f: &Value,
+    init: &Value,
+    lists: &[Value],
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult {
+    let lists = list_arguments("foldr", lists)?;
+    (0..lists[0].len()).rev().try_fold(init.clone(), |acc, i| {
+        let mut arguments = nth_elements(&lists, i);
+        arguments.push(acc);
+        call_function_value(f, arguments, variable_map, function_map)
+    })
+}
+
+// Stops at the first false result. Returns the last result, or true for empty lists.
+pub fn list_andmap(f: &Value, lists: &[Value], variable_map: &mut VariableMap, function_map: &FunctionMap) -> EvalResult {
+    let lists = list_arguments("andmap", lists)?;
+    let mut result = Value::BoolValue(true);
+    for i in 0..lists[0].len() {
+        result = call_function_value(f, nth_elements(&lists, i), variable_map, function_map)?;
+        if !is_truthy(&result) {
+            break;
+        }
     }
+    Ok(result)
+}
+
+// Stops at the first result that is not false and returns it. Returns false otherwise.
+pub fn list_ormap(f: &Value, lists: &[Value], variable_map: &mut VariableMap, function_map: &FunctionMap) -> EvalResult {
+    let lists = list_arguments("ormap", lists)?;
+    for i in 0..lists[0].len() {
+        let result = call_function_value(f, nth_elements(&lists, i), variable_map, function_map)?;
+        if is_truthy(&result) {
+            return Ok(result);
+        }
+    }
+    Ok(Value::BoolValue(false))
+}
+
+// A stable merge sort that only ever asks whether one element is less than another.
+pub fn list_sort(
+    list: &Value,
+    less_than: &Value,
+    key: Option<&Value>,
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult {
+    let mut keyed = Vec::new();
+    for x in to_list("sort", list)? {
+        match key {
+            Some(key) => keyed.push((call_function_value(key, vec![x.clone()], variable_map, function_map)?, x)),
+            None => keyed.push((x.clone(), x)),
+        }
+    }
+
+    let sorted = merge_sort(keyed, less_than, variable_map, function_map)?;
+    Ok(Value::ListValue(ValueList::from_vec(
+        sorted.into_iter().map(|(_, x)| x).collect(),
+    )))
+}
+
+fn merge_sort(
+    mut elements: Vec<(Value, Value)>,
+    less_than: &Value,
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult<Vec<(Value, Value)>> {
+    if elements.len() <= 1 {
+        return Ok(elements);
+    }
+
+    let right = elements.split_off(elements.len() / 2);
+    let mut left = merge_sort(elements, less_than, variable_map, function_map)?
+        .into_iter()
+        .peekable();
+    let mut right = merge_sort(right, less_than, variable_map, function_map)?
+        .into_iter()
+        .peekable();
+
+    let mut result = Vec::new();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // Only take from the right when it is strictly less, so equal elements keep their order.
+        if is_truthy(&call_function_value(
+            less_than,
+            vec![r.0.clone(), l.0.clone()],
+            variable_map,
+            function_map,
+        )?) {
+            result.push(right.next().unwrap());
+        } else {
+            result.push(left.next().unwrap());
+        }
+    }
+    result.extend(left);
+    result.extend(right);
+    Ok(result)
+}
+
+pub fn list_build(n: N, f: &Value, variable_map: &mut VariableMap, function_map: &FunctionMap) -> EvalResult {
+    let result = (0..n)
+        .map(|i| call_function_value(f, vec![Value::NumValue(i)], variable_map, function_map))
+        .collect::<EvalResult<Vec<Value>>>()?;
+    Ok(Value::ListValue(ValueList::from_vec(result)))
+}
+
+fn values_equal(
+    a: &Value,
+    b: &Value,
+    is_equal: Option<&Value>,
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult<bool> {
+    match is_equal {
+        Some(is_equal) => Ok(is_truthy(&call_function_value(
+            is_equal,
+            vec![a.clone(), b.clone()],
+            variable_map,
+            function_map,
+        )?)),
+        None => Ok(a == b),
+    }
+}
+
+// The first element of the list that is itself a list starting with x, or false.
+pub fn list_assoc(
+    x: &Value,
+    list: &Value,
+    is_equal: Option<&Value>,
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult {
+    for element in to_list("assoc", list)? {
+        match &element {
+            Value::ListValue(ValueList::Node(node)) => {
+                if values_equal(x, &node.data, is_equal, variable_map, function_map)? {
+                    return Ok(element);
+                }
+            }
+            y => {
+                return Err(RacketError::contract(format!(
+                    "Expected assoc to be given a list of non-empty lists but found {}",
+                    y
+                )))
+            }
+        }
+    }
+    Ok(Value::BoolValue(false))
+}
+
+// The tail of the list starting at the first element equal to x, or false.
+pub fn list_member(
+    x: &Value,
+    list: &Value,
+    is_equal: Option<&Value>,
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult {
+    let mut current = match list {
+        Value::ListValue(list) => list.clone(),
+        y => {
+            return Err(RacketError::contract(format!(
+                "Expected list arguments to member but got {}",
+                y
+            )))
+        }
+    };
+    while let ValueList::Node(node) = current {
+        if values_equal(x, &node.data, is_equal, variable_map, function_map)? {
+            return Ok(Value::ListValue(ValueList::Node(node)));
+        }
+        current = *node.next;
+    }
+    Ok(Value::BoolValue(false))
+}
+
+// The list without the first element equal to x.
+pub fn list_remove(
+    x: &Value,
+    list: &Value,
+    is_equal: Option<&Value>,
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult {
+    let mut elements = to_list("remove", list)?;
+    for i in 0..elements.len() {
+        if values_equal(x, &elements[i], is_equal, variable_map, function_map)? {
+            elements.remove(i);
+            break;
+        }
+    }
+    Ok(Value::ListValue(ValueList::from_vec(elements)))
+}
+
+// first, second and third.
+pub fn list_nth_element(name: &str, list: &Value, n: usize) -> EvalResult {
+    match to_list(name, list)?.get(n) {
+        Some(x) => Ok(x.clone()),
+        None => Err(RacketError::contract(format!(
+            "Expected {} to be given a list with at least {} element(s) but got {}",
+            name,
+            n + 1,
+            list
+        ))),
+    }
+}
+
+pub fn list_rest(list: &Value) -> EvalResult {
+    match list {
+        Value::ListValue(ValueList::Node(node)) => Ok(Value::ListValue(*node.next.clone())),
+        x => Err(RacketError::contract(format!(
+            "Expected rest to be given a non-empty list but got {}",
+            x
+        ))),
+    }
+}
+
+pub fn list_last(list: &Value) -> EvalResult {
+    match to_list("last", list)?.pop() {
+        Some(x) => Ok(x),
+        None => Err(RacketError::contract(format!(
+            "Expected last to be given a non-empty list but got {}",
+            list
+        ))),
+    }
+}
+
+// (cadr x) is (car (cdr x)), and so on. The letters between c and r are applied from right to left.
+pub fn list_cxr(name: &str, x: &Value) -> EvalResult {
+    name[1..name.len() - 1]
+        .chars()
+        .rev()
+        .try_fold(x.clone(), |x, operation| match (operation, x) {
+            ('a', Value::ListValue(ValueList::Node(node))) => Ok(*node.data),
+            ('d', Value::ListValue(ValueList::Node(node))) => Ok(Value::ListValue(*node.next)),
+            (_, y) => Err(RacketError::contract(format!(
+                "Expected {} to be given a list with the right shape but reached {}",
+                name, y
+            ))),
+        })
 }
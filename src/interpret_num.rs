@@ -1,4 +1,5 @@
-use crate::interpret::{FunctionMap, Value, VariableMap, N};
+use crate::error::{EvalResult, RacketError};
+use crate::interpret::{interpret, Expr, FunctionMap, Value, VariableMap, N};
 use crate::interpret_function_call::{interpret_function_call, FunctionCallExpr};
 use crate::interpret_list::{interpret_car_expr, CarExpr};
 use crate::interpret_variable::{interpret_variable_expr, VariableExpr};
@@ -12,6 +13,18 @@ pub enum BinaryNumOp {
     Mod,
 }
 
+impl BinaryNumOp {
+    fn symbol(&self) -> &'static str {
+        match self {
+            BinaryNumOp::Add => "+",
+            BinaryNumOp::Sub => "-",
+            BinaryNumOp::Mul => "*",
+            BinaryNumOp::Div => "/",
+            BinaryNumOp::Mod => "%",
+        }
+    }
+}
+
 // All possible expression types that can result in a NumValue.
 #[derive(Debug, Clone, PartialEq)]
 pub enum NumExpr {
@@ -20,6 +33,8 @@ pub enum NumExpr {
     BinaryNumExpr(Box<BinaryNumExpr>),
     FunctionCallExpr(FunctionCallExpr),
     CarExpr(CarExpr),
+    // Any other expression, checked to produce a number when it is evaluated.
+    DynamicExpr(Box<Expr>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -29,54 +44,70 @@ pub struct BinaryNumExpr {
     pub right: NumExpr,
 }
 
-pub fn interpret_num_expr(expr: &NumExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> N {
+pub fn interpret_num_expr(expr: &NumExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> EvalResult<N> {
     match expr {
-        NumExpr::LiteralNumExpr(x) => *x,
+        NumExpr::LiteralNumExpr(x) => Ok(*x),
         NumExpr::BinaryNumExpr(x) => interpret_binary_num_expr(x, variable_map, function_map),
         NumExpr::VariableExpr(x) => interpret_variable_num_expr(x, variable_map),
-        NumExpr::FunctionCallExpr(x) => {
-            if let Value::NumValue(y) = interpret_function_call(x, variable_map, function_map) {
-                y
-            } else {
-                panic!(
-                    "Expected call to function {} to return number but returned something else instead",
-                    x.name
-                );
-            }
-        }
-        NumExpr::CarExpr(x) => {
-            if let Value::NumValue(y) = interpret_car_expr(x, variable_map, function_map) {
+        NumExpr::FunctionCallExpr(x) => match interpret_function_call(x, variable_map, function_map)? {
+            Value::NumValue(y) => Ok(y),
+            y => Err(RacketError::contract(format!(
+                "Expected call to function {} to return number but returned {}",
+                x.name, y
+            ))),
+        },
+        NumExpr::CarExpr(x) => match interpret_car_expr(x, variable_map, function_map)? {
+            Value::NumValue(y) => Ok(y),
+            y => Err(RacketError::contract(format!(
+                "Expected car to produce a number but got {}",
                 y
-            } else {
-                panic!()
-            }
-        }
+            ))),
+        },
+        NumExpr::DynamicExpr(x) => match interpret(x, variable_map, function_map)? {
+            Value::NumValue(y) => Ok(y),
+            y => Err(RacketError::contract(format!("Expected a number but got {}", y))),
+        },
     }
 }
 
-fn interpret_binary_num_expr(expr: &BinaryNumExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> N {
-    let left = interpret_num_expr(&expr.left, variable_map, function_map);
-    let right = interpret_num_expr(&expr.right, variable_map, function_map);
+fn interpret_binary_num_expr(expr: &BinaryNumExpr, variable_map: &mut VariableMap, function_map: &FunctionMap) -> EvalResult<N> {
+    let left = interpret_num_expr(&expr.left, variable_map, function_map)?;
+    let right = interpret_num_expr(&expr.right, variable_map, function_map)?;
+    apply_num_op(&expr.op, left, right)
+}
 
+// Numbers are 32 bit, and a result that does not fit raises exn:fail:contract rather than wrapping.
+pub fn apply_num_op(op: &BinaryNumOp, left: N, right: N) -> EvalResult<N> {
     // Division by zero!
-    if (expr.op == BinaryNumOp::Div) && (right == 0) {
-        panic!("Tried to divide by zero: {} / {}", left, right);
+    if (*op == BinaryNumOp::Div || *op == BinaryNumOp::Mod) && (right == 0) {
+        return Err(RacketError::divide_by_zero(format!(
+            "Tried to divide by zero: {} {} {}",
+            left,
+            op.symbol(),
+            right
+        )));
     }
 
-    match expr.op {
-        BinaryNumOp::Add => left + right,
-        BinaryNumOp::Sub => left - right,
-        BinaryNumOp::Mul => left * right,
-        BinaryNumOp::Div => left / right,
-        BinaryNumOp::Mod => left % right,
-    }
+    let result = match op {
+        BinaryNumOp::Add => left.checked_add(right),
+        BinaryNumOp::Sub => left.checked_sub(right),
+        BinaryNumOp::Mul => left.checked_mul(right),
+        BinaryNumOp::Div => left.checked_div(right),
+        BinaryNumOp::Mod => left.checked_rem(right),
+    };
+    result.ok_or_else(|| {
+        RacketError::contract(format!(
+            "Integer overflow: {} {} {} does not fit in a number",
+            left,
+            op.symbol(),
+            right
+        ))
+    })
 }
 
-fn interpret_variable_num_expr(expr: &VariableExpr, variable_map: &VariableMap) -> N {
-    let res = interpret_variable_expr(expr, variable_map);
-
-    match res {
-        Value::NumValue(x) => x,
-        x => panic!("Variable {} does not refer to a num value", x),
+fn interpret_variable_num_expr(expr: &VariableExpr, variable_map: &VariableMap) -> EvalResult<N> {
+    match interpret_variable_expr(expr, variable_map)? {
+        Value::NumValue(x) => Ok(x),
+        x => Err(RacketError::contract(format!("Variable {} does not refer to a num value", x))),
     }
 }
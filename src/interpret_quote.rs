@@ -0,0 +1,76 @@
+use std::rc::Rc;
+
+use crate::error::{EvalResult, RacketError, SyntaxError};
+use crate::interpret::{interpret, Expr, FunctionMap, Value, ValueList, VariableMap};
+use crate::reader::{Datum, DatumKind};
+use crate::symbol_table::Symbol;
+
+// 'datum or (quote datum). The value is computed once when the program is parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuoteExpr {
+    pub value: Value,
+}
+
+// `datum or (quasiquote datum). Parts of the template escape back into evaluation with
+// ,expr (unquote) and ,@expr (unquote-splicing).
+#[derive(Debug, Clone, PartialEq)]
+pub enum QuasiquoteExpr {
+    Literal(Value),
+    Unquote(Box<Expr>),
+    List(Vec<QuasiquoteItem>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QuasiquoteItem {
+    Item(QuasiquoteExpr),
+    Splice(Expr),
+}
+
+// The value of a datum as quoted data.
+pub fn datum_to_value(datum: &Datum) -> Result<Value, SyntaxError> {
+    let values = |items: &[Datum]| items.iter().map(datum_to_value).collect::<Result<Vec<_>, _>>();
+    Ok(match &datum.kind {
+        DatumKind::Number(n) => Value::NumValue(*n),
+        DatumKind::Boolean(b) => Value::BoolValue(*b),
+        DatumKind::String(s) => Value::StringValue(s.clone()),
+        DatumKind::Char(c) => Value::CharValue(*c),
+        DatumKind::Symbol(s) => Value::SymbolValue(Symbol::intern(s)),
+        DatumKind::List(items) => Value::ListValue(ValueList::from_vec(values(items)?)),
+        DatumKind::Vector(items) => Value::VectorValue(Rc::new(values(items)?)),
+        DatumKind::Keyword(_) => {
+            let message = format!("Cannot quote {} at {}", datum, datum.span);
+            return Err(SyntaxError::syntax(datum.span, message));
+        }
+    })
+}
+
+pub fn interpret_quasiquote_expr(
+    expr: &QuasiquoteExpr,
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult {
+    match expr {
+        QuasiquoteExpr::Literal(x) => Ok(x.clone()),
+        QuasiquoteExpr::Unquote(x) => interpret(x, variable_map, function_map),
+        QuasiquoteExpr::List(items) => {
+            let mut values = Vec::new();
+
+            for item in items {
+                match item {
+                    QuasiquoteItem::Item(x) => values.push(interpret_quasiquote_expr(x, variable_map, function_map)?),
+                    QuasiquoteItem::Splice(x) => match interpret(x, variable_map, function_map)? {
+                        Value::ListValue(list) => values.extend(list.to_vec()),
+                        x => {
+                            return Err(RacketError::contract(format!(
+                                "Expected unquote-splicing to produce a list but got {}",
+                                x
+                            )))
+                        }
+                    },
+                }
+            }
+
+            Ok(Value::ListValue(ValueList::from_vec(values)))
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::error::{EvalResult, RacketError, SyntaxError};
+use crate::interpret::{FunctionInfo, FunctionMap, Value, V};
+use crate::interpret_function_call::{check_arguments, check_arity};
+use crate::limits;
+use crate::reader::Datum;
+use crate::types::{parse_type, Type, TypeError};
+
+// An instance of a struct defined with (struct name (fields ...)).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StructValue {
+    pub name: String,
+    pub fields: V,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructType {
+    pub name: String,
+    pub fields: Vec<String>,
+    // Fields without an annotation have type Any.
+    pub field_types: Vec<Type>,
+}
+
+impl StructType {
+    // The type of the constructor, which is also the signature it is checked against.
+    pub fn constructor_type(&self) -> Type {
+        Type::Function(self.field_types.clone(), Box::new(Type::Struct(self.name.clone())))
+    }
+}
+
+// The functions a struct definition introduces: `name` to construct an instance, `name?` to
+// recognise one and `name-field` to read each field.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StructFunction {
+    Constructor(Rc<StructType>),
+    Predicate(String),
+    Accessor { struct_name: String, index: usize },
+}
+
+pub fn struct_to_string(instance: &StructValue) -> String {
+    let mut parts = vec![instance.name.clone()];
+    parts.extend(instance.fields.iter().map(|x| x.to_string()));
+    format!("({})", parts.join(" "))
+}
+
+// (struct name (field ...)) or (struct name ([field : Type] ...)) gives the name and the fields.
+pub fn struct_definition(datum: &Datum) -> Option<(&str, &[Datum])> {
+    match datum.as_list().map(|items| &items[..]) {
+        Some([keyword, name, fields]) if keyword.as_symbol() == Some("struct") => Some((name.as_symbol()?, fields.as_list()?)),
+        // Reported by parse_expr, which struct forms of the wrong shape are left to.
+        _ => None,
+    }
+}
+
+// Reads the fields of a struct definition. `names` holds the struct types in scope.
+pub fn parse_struct_type(name: &str, fields: &[Datum], names: &HashMap<String, Type>) -> Result<StructType, TypeError> {
+    let mut struct_type = StructType {
+        name: name.to_string(),
+        fields: Vec::new(),
+        field_types: Vec::new(),
+    };
+    for field in fields {
+        let (field_name, ty) = match field.as_list().map(|items| &items[..]) {
+            Some([field_name, colon, ty]) if colon.as_symbol() == Some(":") && field_name.as_symbol().is_some() => (
+                field_name.as_symbol().unwrap(),
+                parse_type(ty, &mut names.clone(), &mut || Type::Any)?,
+            ),
+            None if field.as_symbol().is_some() => (field.as_symbol().unwrap(), Type::Any),
+            _ => return Err(TypeError::new(field, format!("Invalid struct field {}", field))),
+        };
+        struct_type.fields.push(field_name.to_string());
+        struct_type.field_types.push(ty);
+    }
+    Ok(struct_type)
+}
+
+// Adds the functions of every struct defined in the program to the function map, returning the struct
+// types by name so that annotations can refer to them.
+pub fn define_structs(datums: &[Datum], function_map: &mut FunctionMap) -> Result<HashMap<String, Type>, SyntaxError> {
+    let definitions: Vec<(&str, &[Datum])> = datums.iter().filter_map(struct_definition).collect();
+    let names: HashMap<String, Type> = definitions
+        .iter()
+        .map(|(name, _)| (name.to_string(), Type::Struct(name.to_string())))
+        .collect();
+
+    for (name, fields) in definitions {
+        let struct_type = parse_struct_type(name, fields, &names)?;
+        for (function_name, function) in struct_functions(struct_type) {
+            function_map.insert(function_name, FunctionInfo::Struct(function));
+        }
+    }
+    Ok(names)
+}
+
+pub fn struct_functions(struct_type: StructType) -> Vec<(String, StructFunction)> {
+    let mut functions = vec![(
+        format!("{}?", struct_type.name),
+        StructFunction::Predicate(struct_type.name.clone()),
+    )];
+    for (index, field) in struct_type.fields.iter().enumerate() {
+        functions.push((
+            format!("{}-{}", struct_type.name, field),
+            StructFunction::Accessor {
+                struct_name: struct_type.name.clone(),
+                index,
+            },
+        ));
+    }
+    functions.push((struct_type.name.clone(), StructFunction::Constructor(Rc::new(struct_type))));
+    functions
+}
+
+pub fn apply_struct_function(name: &str, function: &StructFunction, arguments: Vec<Value>) -> EvalResult {
+    match function {
+        StructFunction::Constructor(struct_type) => {
+            check_arity(name, struct_type.fields.len(), arguments.len())?;
+            check_arguments(name, &struct_type.constructor_type(), &arguments)?;
+            limits::allocate(arguments.len())?;
+            Ok(Value::StructValue(StructValue {
+                name: struct_type.name.clone(),
+                fields: Rc::new(arguments),
+            }))
+        }
+        StructFunction::Predicate(struct_name) => {
+            check_arity(name, 1, arguments.len())?;
+            Ok(Value::BoolValue(
+                matches!(&arguments[0], Value::StructValue(instance) if instance.name == *struct_name),
+            ))
+        }
+        StructFunction::Accessor { struct_name, index } => {
+            check_arity(name, 1, arguments.len())?;
+            match &arguments[0] {
+                Value::StructValue(instance) if instance.name == *struct_name => Ok(instance.fields[*index].clone()),
+                x => Err(RacketError::contract(format!(
+                    "Expected a {} as the argument to {} but got {}",
+                    struct_name, name, x
+                ))),
+            }
+        }
+    }
+}
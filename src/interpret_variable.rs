@@ -1,16 +1,31 @@
-use crate::interpret::{Value, VariableMap};
+use crate::error::{EvalResult, RacketError};
+use crate::interpret::{FunctionMap, FunctionValue, Value, VariableMap};
+use crate::primitives::is_primitive;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct VariableExpr {
     pub name: String,
 }
 
-pub fn interpret_variable_expr(variable: &VariableExpr, variable_map: &VariableMap) -> Value {
-    match variable_map.get(&variable.name) {
-        Some(x) => match &x[..] {
-            [.., val] => val.clone(),
-            _ => panic!("Val not found"),
-        },
-        None => panic!("Undefined variable {}", variable.name),
+pub fn interpret_variable_expr(variable: &VariableExpr, variable_map: &VariableMap) -> EvalResult {
+    match variable_map.get(&variable.name).and_then(|values| values.last()) {
+        Some(value) => Ok(value.clone()),
+        None => Err(RacketError::variable(format!("Undefined variable {}", variable.name))),
     }
 }
+
+// A variable where any kind of value is allowed. If no variable has the name, the name may refer to a
+// function, which is then used as a value.
+pub fn interpret_variable_or_function_expr(
+    variable: &VariableExpr,
+    variable_map: &VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult {
+    let is_bound = variable_map.get(&variable.name).is_some_and(|values| !values.is_empty());
+
+    if !is_bound && (function_map.contains_key(&variable.name) || is_primitive(&variable.name)) {
+        return Ok(Value::FunctionValue(FunctionValue::Named(variable.name.clone())));
+    }
+
+    interpret_variable_expr(variable, variable_map)
+}
@@ -0,0 +1,73 @@
+use std::rc::Rc;
+
+use crate::error::{EvalResult, RacketError};
+use crate::interpret::{Value, ValueList, N, V};
+use crate::limits;
+
+// Native implementations of the vector functions. They are exposed through primitives.rs.
+// Vectors are immutable: vector-set produces a new vector and leaves the original unchanged.
+
+fn to_vector(name: &str, vector: &Value) -> EvalResult<V> {
+    match vector {
+        Value::VectorValue(v) => Ok(v.clone()),
+        x => Err(RacketError::contract(format!(
+            "Expected a vector as the first argument to {} but got {}",
+            name, x
+        ))),
+    }
+}
+
+fn check_index(name: &str, vector: &V, i: N) -> EvalResult<usize> {
+    if i < 0 || i as usize >= vector.len() {
+        return Err(RacketError::contract(format!(
+            "Index {} passed to {} is out of range for a vector of length {}",
+            i,
+            name,
+            vector.len()
+        )));
+    }
+    Ok(i as usize)
+}
+
+pub fn vector_to_string(vector: &V) -> String {
+    let mut parts = vec!["vector".to_string()];
+    parts.extend(vector.iter().map(|x| x.to_string()));
+    format!("({})", parts.join(" "))
+}
+
+// (make-vector size [fill]), where fill defaults to 0.
+pub fn vector_make(size: N, fill: &Value) -> EvalResult {
+    if size < 0 {
+        return Err(RacketError::contract(format!(
+            "Expected a non-negative size for make-vector but got {}",
+            size
+        )));
+    }
+    limits::allocate(size as usize)?;
+    Ok(Value::VectorValue(Rc::new(vec![fill.clone(); size as usize])))
+}
+
+pub fn vector_ref(vector: &Value, i: N) -> EvalResult {
+    let vector = to_vector("vector-ref", vector)?;
+    Ok(vector[check_index("vector-ref", &vector, i)?].clone())
+}
+
+pub fn vector_length(vector: &Value) -> EvalResult {
+    Ok(Value::NumValue(to_vector("vector-length", vector)?.len() as N))
+}
+
+pub fn vector_set(vector: &Value, i: N, x: &Value) -> EvalResult {
+    let vector = to_vector("vector-set", vector)?;
+    let i = check_index("vector-set", &vector, i)?;
+
+    limits::allocate(vector.len())?;
+    let mut result = (*vector).clone();
+    result[i] = x.clone();
+    Ok(Value::VectorValue(Rc::new(result)))
+}
+
+pub fn vector_to_list(vector: &Value) -> EvalResult {
+    Ok(Value::ListValue(ValueList::from_vec(
+        to_vector("vector->list", vector)?.to_vec(),
+    )))
+}
@@ -0,0 +1,48 @@
+use crate::error::{EvalResult, RacketError};
+use crate::interpret::{interpret, Expr, FunctionMap, Value, VariableMap};
+use crate::primitives::{call_function_value, is_truthy};
+
+// (with-handlers ([predicate handler] ...) body). When the body raises a value, it is passed to the
+// handler of the first predicate that accepts it, and the handler's result becomes the result of the
+// whole expression. Values that no predicate accepts keep propagating.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WithHandlersExpr {
+    pub handlers: Vec<(Expr, Expr)>,
+    pub body: Box<Expr>,
+}
+
+pub fn interpret_with_handlers_expr(
+    expr: &WithHandlersExpr,
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult {
+    // The predicates and handlers are evaluated before the body, as in Racket.
+    let handlers = expr
+        .handlers
+        .iter()
+        .map(|(predicate, handler)| {
+            Ok((
+                interpret(predicate, variable_map, function_map)?,
+                interpret(handler, variable_map, function_map)?,
+            ))
+        })
+        .collect::<EvalResult<Vec<(Value, Value)>>>()?;
+
+    let (raised, backtrace) = match interpret(&expr.body, variable_map, function_map) {
+        Err(RacketError::Raise(raised, backtrace)) => (raised, backtrace),
+        result => return result,
+    };
+
+    for (predicate, handler) in &handlers {
+        if is_truthy(&call_function_value(
+            predicate,
+            vec![raised.clone()],
+            variable_map,
+            function_map,
+        )?) {
+            return call_function_value(handler, vec![raised], variable_map, function_map);
+        }
+    }
+
+    Err(RacketError::Raise(raised, backtrace))
+}
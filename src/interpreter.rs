@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use crate::error::EvalResult;
+use crate::interpret::{
+    interpret, parse_top_level, read_lang_line, resolve_includes, FunctionInfo, FunctionMap, Value, VariableMap,
+};
+use crate::interpret_define::define_all;
+use crate::interpret_function_call::{apply_function, resolve_function};
+use crate::limits::{with_limits, EvalLimits};
+use crate::native::{NativeFn, NativeFunction, NativeModule, NativeModules};
+use crate::parser::parse;
+use crate::stack_trace::{attach_backtrace, enter_frame};
+
+// An interpreter that keeps its functions and constants between calls, for embedding in Rust programs.
+// Modules are loaded into a shared environment, so later modules and expressions can use the
+// definitions of earlier ones.
+#[derive(Default)]
+pub struct Interpreter {
+    function_map: FunctionMap,
+    // Holds the top level constants of every loaded module.
+    variable_map: VariableMap,
+    // Modules that loaded modules can include, by name.
+    native_modules: NativeModules,
+    limits: EvalLimits,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Interpreter {
+            function_map: HashMap::new(),
+            variable_map: HashMap::new(),
+            native_modules: HashMap::new(),
+            limits: EvalLimits::default(),
+        }
+    }
+
+    // The limits apply separately to each load, evaluation and call.
+    pub fn set_limits(&mut self, limits: EvalLimits) {
+        self.limits = limits;
+    }
+
+    // Defines a native function that every module can call without including anything.
+    pub fn register_function(&mut self, name: &str, arity: usize, function: NativeFn) {
+        let native = NativeFunction {
+            name: name.to_string(),
+            arity,
+            function,
+        };
+        self.function_map.insert(name.to_string(), FunctionInfo::Native(native));
+    }
+
+    // Makes a native module available to `(include name)` in modules loaded after this.
+    pub fn register_module(&mut self, module: NativeModule) {
+        self.native_modules.insert(module.name.clone(), module);
+    }
+
+    // Defines the functions and constants in the source, which may start with a #lang line and include
+    // other modules. Returns the values of its top level expressions. A function with the same name as
+    // one that is already defined replaces it.
+    pub fn load_module(&mut self, source: &str) -> EvalResult<Vec<Value>> {
+        let (_, program) = read_lang_line(source.to_string())?;
+        let (program, native_functions) = resolve_includes(program, &self.native_modules)?;
+        for native in native_functions {
+            self.function_map.insert(native.name.clone(), FunctionInfo::Native(native));
+        }
+
+        let (function_map, definitions, expressions) = parse_top_level(program)?;
+        self.function_map.extend(function_map);
+
+        with_limits(&self.limits, || {
+            define_all(&definitions, &mut self.variable_map, &self.function_map)?;
+            expressions
+                .iter()
+                .map(|expr| interpret(expr, &mut self.variable_map, &self.function_map))
+                .collect()
+        })
+    }
+
+    // Evaluates a single expression, e.g. "(map add1 (list 1 2))".
+    pub fn eval_str(&mut self, expr: &str) -> EvalResult {
+        let expr = parse(expr.to_string())?;
+        with_limits(&self.limits, || interpret(&expr, &mut self.variable_map, &self.function_map))
+    }
+
+    // Calls a loaded function, or a primitive, by name.
+    pub fn call(&mut self, name: &str, arguments: &[Value]) -> EvalResult {
+        with_limits(&self.limits, || {
+            let function = resolve_function(name, &self.variable_map, &self.function_map)?;
+            let _frame = enter_frame(name, None);
+            apply_function(&function, arguments.to_vec(), &[], &mut self.variable_map, &self.function_map)
+                .map_err(attach_backtrace)
+        })
+    }
+}
@@ -0,0 +1,74 @@
+// Expression and value variants are intentionally named after the type they produce.
+#![allow(clippy::enum_variant_names)]
+
+mod analysis;
+mod convert;
+mod dap;
+mod debug_console;
+mod debugger;
+mod error;
+mod formatter;
+mod interpret;
+mod interpret_bool;
+mod interpret_char;
+mod interpret_cond;
+mod interpret_contract;
+mod interpret_define;
+mod interpret_function;
+mod interpret_function_call;
+mod interpret_hash;
+mod interpret_lambda;
+mod interpret_list;
+mod interpret_num;
+mod interpret_quote;
+mod interpret_struct;
+mod interpret_variable;
+mod interpret_vector;
+mod interpret_with_handlers;
+mod interpreter;
+mod limits;
+mod lint;
+mod lsp;
+mod macro_expander;
+mod native;
+mod parser;
+mod primitives;
+mod protocol;
+mod quickcheck;
+mod racket_printer;
+mod reader;
+mod stack_trace;
+mod symbol_table;
+mod testing;
+mod tokenizer;
+mod type_check;
+mod types;
+
+// The public interface for embedding the interpreter. Everything else is an implementation detail.
+pub use convert::{FromValue, IntoValue};
+pub use error::{EvalResult, Exn, RacketError, SyntaxError};
+pub use interpret::{FunctionValue, Value, ValueList};
+pub use interpreter::Interpreter;
+pub use limits::{EvalLimits, Limit};
+pub use native::{NativeFn, NativeFunction, NativeModule};
+pub use racket_printer::to_racket_string;
+pub use stack_trace::{Backtrace, CallSite, Frame};
+pub use symbol_table::Symbol;
+pub use tokenizer::Span;
+
+// What the command line tool is built from. It is not part of the embedding interface and may change.
+#[doc(hidden)]
+pub mod cli {
+    pub use crate::dap::run_dap_server;
+    pub use crate::debug_console::DebugConsole;
+    pub use crate::debugger::{with_debugger, Resume};
+    pub use crate::formatter::format_program;
+    pub use crate::interpret::{interpret_racket_program, read_lang_line, source_module, try_interpret_program, Language};
+    pub use crate::lint::{lint_program, Lint, RULES as LINT_RULES};
+    pub use crate::lsp::run_lsp_server;
+    pub use crate::testing::{junit_report, run_checks, CheckResult};
+    pub use crate::type_check::check_program;
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,175 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::error::{EvalResult, RacketError};
+
+// Bounds on the resources a program may use, for running code that cannot be trusted to terminate.
+// Limits that are None are not enforced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalLimits {
+    // Number of expressions evaluated.
+    pub max_steps: Option<u64>,
+    // Number of nested function calls. Evaluation grows the native stack as it needs to, so any thread
+    // can run to this depth, but each call still takes about 16KB of memory in debug builds.
+    pub max_depth: Option<usize>,
+    // Number of list nodes, vector slots and hash entries allocated over the whole run.
+    pub max_heap_cells: Option<usize>,
+    // Wall-clock time since evaluation started. The clock is only read every STEPS_PER_CLOCK_CHECK
+    // steps, and not at all while a native function or a primitive such as sort runs, so a program
+    // can go over the timeout by as long as those take.
+    pub timeout: Option<Duration>,
+}
+
+// The call depth allowed when no other limit is given, so that runaway recursion raises an error
+// instead of overflowing the stack.
+pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+
+impl Default for EvalLimits {
+    fn default() -> Self {
+        EvalLimits {
+            max_steps: None,
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            max_heap_cells: None,
+            timeout: None,
+        }
+    }
+}
+
+// The limit a program ran into.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Limit {
+    Steps(u64),
+    Depth(usize),
+    HeapCells(usize),
+    Timeout(Duration),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Steps(n) => write!(f, "Evaluation exceeded the limit of {} steps", n),
+            Limit::Depth(n) => write!(f, "Evaluation exceeded the maximum call depth of {}", n),
+            Limit::HeapCells(n) => write!(f, "Evaluation exceeded the limit of {} heap cells", n),
+            Limit::Timeout(timeout) => write!(f, "Evaluation exceeded the timeout of {:?}", timeout),
+        }
+    }
+}
+
+// How often the clock is read, in steps. Reading it on every step would dominate simple expressions.
+const STEPS_PER_CLOCK_CHECK: u64 = 1024;
+
+struct Budget {
+    limits: EvalLimits,
+    started: Instant,
+    steps: u64,
+    depth: usize,
+    heap_cells: usize,
+}
+
+thread_local! {
+    // The budget of the evaluation running on this thread, if it has limits.
+    static BUDGET: RefCell<Option<Budget>> = const { RefCell::new(None) };
+}
+
+// Runs f with the given limits applied to all evaluation it does on this thread.
+pub fn with_limits<T>(limits: &EvalLimits, f: impl FnOnce() -> T) -> T {
+    // Restores the enclosing budget even if f panics, so a failed run does not leak its limits.
+    struct Restore(Option<Budget>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            BUDGET.with(|budget| *budget.borrow_mut() = self.0.take());
+        }
+    }
+
+    let budget = Budget {
+        limits: limits.clone(),
+        started: Instant::now(),
+        steps: 0,
+        depth: 0,
+        heap_cells: 0,
+    };
+    let _restore = Restore(BUDGET.with(|current| current.borrow_mut().replace(budget)));
+    f()
+}
+
+fn exceeded(limit: Limit) -> EvalResult<()> {
+    Err(RacketError::limit_exceeded(limit))
+}
+
+// Called once for every expression evaluated. Also reports heap limits exceeded by earlier allocations.
+pub fn step() -> EvalResult<()> {
+    BUDGET.with(|budget| {
+        let mut budget = budget.borrow_mut();
+        let Some(budget) = budget.as_mut() else {
+            return Ok(());
+        };
+        let limits = &budget.limits;
+
+        budget.steps += 1;
+        if let Some(max_steps) = limits.max_steps.filter(|max_steps| budget.steps > *max_steps) {
+            return exceeded(Limit::Steps(max_steps));
+        }
+        if let Some(max_heap_cells) = limits.max_heap_cells.filter(|max_cells| budget.heap_cells > *max_cells) {
+            return exceeded(Limit::HeapCells(max_heap_cells));
+        }
+        match limits.timeout {
+            Some(timeout) if budget.steps.is_multiple_of(STEPS_PER_CLOCK_CHECK) && budget.started.elapsed() > timeout => {
+                exceeded(Limit::Timeout(timeout))
+            }
+            _ => Ok(()),
+        }
+    })
+}
+
+// Called when a function body is entered. Every successful call must be paired with exit_call.
+pub fn enter_call() -> EvalResult<()> {
+    BUDGET.with(|budget| {
+        let mut budget = budget.borrow_mut();
+        let Some(budget) = budget.as_mut() else {
+            return Ok(());
+        };
+
+        match budget.limits.max_depth {
+            Some(max_depth) if budget.depth >= max_depth => exceeded(Limit::Depth(max_depth)),
+            _ => {
+                budget.depth += 1;
+                Ok(())
+            }
+        }
+    })
+}
+
+pub fn exit_call() {
+    BUDGET.with(|budget| {
+        if let Some(budget) = budget.borrow_mut().as_mut() {
+            budget.depth -= 1;
+        }
+    })
+}
+
+// Counts cells that are about to be allocated, failing before the allocation if there is no room.
+pub fn allocate(cells: usize) -> EvalResult<()> {
+    record_allocation(cells);
+    check_heap()
+}
+
+// Counts cells allocated somewhere that cannot report an error. The next step or check_heap reports it
+// if this went over the limit.
+pub fn record_allocation(cells: usize) {
+    BUDGET.with(|budget| {
+        if let Some(budget) = budget.borrow_mut().as_mut() {
+            budget.heap_cells = budget.heap_cells.saturating_add(cells);
+        }
+    })
+}
+
+pub fn check_heap() -> EvalResult<()> {
+    BUDGET.with(|budget| match budget.borrow().as_ref() {
+        Some(budget) => match budget.limits.max_heap_cells {
+            Some(max_heap_cells) if budget.heap_cells > max_heap_cells => exceeded(Limit::HeapCells(max_heap_cells)),
+            _ => Ok(()),
+        },
+        None => Ok(()),
+    })
+}
@@ -0,0 +1,346 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use regex::Regex;
+
+use crate::analysis::{included_modules, DefinitionKind};
+use crate::error::SyntaxError;
+use crate::interpret::read_lang_line;
+use crate::macro_expander::expand_macros;
+use crate::parser::{
+    constant_definition, contract_definition, function_definition, include_name, parameter_annotation, provided_contracts,
+};
+use crate::primitives::is_primitive;
+use crate::reader::{read_program, Datum, DatumKind};
+use crate::tokenizer::Span;
+
+// The rules the linter checks, by ID. A rule is turned off for a line by a comment on that line or
+// the line before, such as `; lint: allow non-tail-recursion unused-parameter`.
+pub const RULES: &[(&str, &str)] = &[
+    ("unused-parameter", "A parameter is never used. Parameters that start with _ are not reported."),
+    (
+        "unused-function",
+        "A function is never used. Module functions that do not start with __ are the module's interface, so they are not reported.",
+    ),
+    ("unreachable-clause", "A cond clause comes after a [true ...] or [else ...] clause, so it never runs."),
+    ("missing-else", "A cond has no [true ...] or [else ...] clause, so it raises an error when no clause matches."),
+    ("private-helper", "A __ helper of a standard library module is called from outside the module."),
+    ("shadowed-name", "A parameter or internal definition hides a definition or parameter of the same name."),
+    ("non-tail-recursion", "A function calls itself outside of a tail position, so every call uses more of the stack."),
+];
+
+// Rules that only run when they are enabled, since what they report is usually fine. Most list
+// functions recurse outside of a tail position, for example.
+pub const OPT_IN_RULES: &[&str] = &["non-tail-recursion"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub rule: &'static str,
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} [{}]", self.span, self.message, self.rule)
+    }
+}
+
+// Lints a program, or the standard library module named by `module`, returning the lints in the
+// order they appear. Macros are expanded first, so a use of a macro is linted as the code it expands
+// to. Gives the error that stops the program from being read or expanded instead, if there is one.
+// `enabled` turns on rules from OPT_IN_RULES.
+pub fn lint_program(program: &str, module: Option<&str>, enabled: &[&str]) -> Result<Vec<Lint>, SyntaxError> {
+    let text = read_lang_line(program.to_string())?.1;
+    let datums = read_program(text.clone())?;
+    let modules = included_modules(&datums);
+
+    let mut linter = Linter {
+        lints: Vec::new(),
+        module: module.map(str::to_string),
+        globals: HashMap::new(),
+        references: Vec::new(),
+        top_level: None,
+    };
+    for definition in modules.iter().flat_map(|module| &module.definitions) {
+        let kind = match definition.kind {
+            DefinitionKind::Function => "function",
+            DefinitionKind::Constant => "constant",
+            DefinitionKind::Struct => "struct",
+            DefinitionKind::Macro => "macro",
+        };
+        linter
+            .globals
+            .insert(definition.name.clone(), format!("the {} {}", kind, definition.name));
+    }
+
+    // Macros from included modules can be used in the program, as they can when it runs.
+    let macros = modules
+        .iter()
+        .flat_map(|module| &module.datums)
+        .filter(|datum| datum.as_list().and_then(|items| items.first()?.as_symbol()) == Some("define-syntax"));
+    let forms = datums.iter().filter(|datum| include_name(datum).is_none());
+    let datums = expand_macros(macros.chain(forms).cloned().collect())?;
+    let datums: Vec<Datum> = datums
+        .iter()
+        .map(|datum| contract_definition(datum).map_or(datum.clone(), |(define, _)| define))
+        .collect();
+
+    let mut functions = Vec::new();
+    for datum in &datums {
+        if let Some((name, _)) = function_definition(datum) {
+            linter.globals.insert(name.to_string(), format!("the function {}", name));
+            functions.push((name, datum.as_list().unwrap()[1].as_list().unwrap()[0].span));
+        } else if let Some((name, _)) = constant_definition(datum) {
+            linter.globals.insert(name.to_string(), format!("the constant {}", name));
+        }
+    }
+
+    for datum in &datums {
+        if let Some(provided) = provided_contracts(datum) {
+            // Functions a module provides are used by the programs that include it.
+            for (name, _) in provided {
+                linter.references.push((name.to_string(), None));
+            }
+        } else if let Some((name, body)) = function_definition(datum) {
+            linter.top_level = Some(name.to_string());
+            let parameters = &datum.as_list().unwrap()[1].as_list().unwrap()[1..];
+            linter.function(parameters, body, &mut Vec::new(), Some(name), true);
+            linter.top_level = None;
+        } else if let Some((_, value)) = constant_definition(datum) {
+            linter.walk(value, &mut Vec::new(), None, false);
+        } else if !is_declaration(datum) {
+            linter.walk(datum, &mut Vec::new(), None, false);
+        }
+    }
+
+    for (name, span) in functions {
+        let used = linter
+            .references
+            .iter()
+            .any(|(reference, function)| reference == name && function.as_deref() != Some(name));
+        // Functions of a module without __ are there for the programs that include it.
+        if !used && name != "main" && (module.is_none() || name.starts_with("__")) {
+            linter.lint("unused-function", span, format!("{} is never used", name));
+        }
+    }
+
+    let allowed = allowed_rules(&text);
+    let mut lints: Vec<Lint> = linter
+        .lints
+        .into_iter()
+        .filter(|lint| {
+            let allows = |line: usize| allowed.get(&line).is_some_and(|rules| rules.contains(lint.rule));
+            let opted_out = OPT_IN_RULES.contains(&lint.rule) && !enabled.contains(&lint.rule);
+            !(opted_out || allows(lint.span.line) || allows(lint.span.line - 1))
+        })
+        .collect();
+    lints.sort_by_key(|lint| (lint.span.line, lint.span.column));
+    Ok(lints)
+}
+
+// The rules allowed by `; lint: allow ...` comments, by the line of the comment.
+fn allowed_rules(text: &str) -> HashMap<usize, HashSet<&str>> {
+    let comment = Regex::new(r";\s*lint:\s*allow\s+(.*)$").unwrap();
+    text.lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let rules = comment.captures(line)?.get(1)?.as_str();
+            Some((
+                i + 1,
+                rules
+                    .split(|c: char| c.is_whitespace() || c == ',')
+                    .filter(|rule| !rule.is_empty())
+                    .collect(),
+            ))
+        })
+        .collect()
+}
+
+// Struct definitions and type declarations have no code to lint.
+fn is_declaration(datum: &Datum) -> bool {
+    matches!(
+        datum.as_list().and_then(|items| items.first()?.as_symbol()),
+        Some("struct" | ":")
+    )
+}
+
+fn is_else(test: &Datum) -> bool {
+    test.as_symbol() == Some("else") || test.kind == DatumKind::Boolean(true)
+}
+
+// Whether the datums use the name, outside of quoted data.
+fn mentions(datums: &[Datum], name: &str) -> bool {
+    datums.iter().any(|datum| match &datum.kind {
+        DatumKind::Symbol(symbol) => symbol == name,
+        DatumKind::List(items) if items.first().and_then(|head| head.as_symbol()) == Some("quote") => false,
+        DatumKind::List(items) => mentions(items, name),
+        _ => false,
+    })
+}
+
+struct Linter {
+    lints: Vec<Lint>,
+    module: Option<String>,
+    // Top level definitions of the program and its modules, with how lints refer to them.
+    globals: HashMap<String, String>,
+    // Every name used, with the top level function it is used in.
+    references: Vec<(String, Option<String>)>,
+    // The top level function being linted.
+    top_level: Option<String>,
+}
+
+impl Linter {
+    fn lint(&mut self, rule: &'static str, span: Span, message: String) {
+        self.lints.push(Lint { rule, span, message });
+    }
+
+    // Reports a parameter or internal definition that hides another definition.
+    fn check_shadowing(&mut self, name: &str, span: Span, scope: &[String]) {
+        let hidden = if scope.iter().any(|local| local == name) {
+            format!("a parameter or definition {}", name)
+        } else if let Some(global) = self.globals.get(name) {
+            global.clone()
+        } else if is_primitive(name) {
+            format!("the built in function {}", name)
+        } else {
+            return;
+        };
+        self.lint("shadowed-name", span, format!("{} hides {}", name, hidden));
+    }
+
+    // Lints a function or lambda. `recursive` is the function whose calls to itself are checked, and
+    // `tail` says whether the body's result is that function's result.
+    fn function(&mut self, parameters: &[Datum], body: &[Datum], scope: &mut Vec<String>, recursive: Option<&str>, tail: bool) {
+        let depth = scope.len();
+        for parameter in parameters {
+            let Some((name, _)) = parameter_annotation(parameter) else {
+                continue;
+            };
+            self.check_shadowing(name, parameter.span, scope);
+            if !name.starts_with('_') && !mentions(body, name) {
+                self.lint("unused-parameter", parameter.span, format!("{} is never used", name));
+            }
+            scope.push(name.to_string());
+        }
+        self.body(body, scope, recursive, tail);
+        scope.truncate(depth);
+    }
+
+    // Lints a body: internal definitions followed by an expression.
+    fn body(&mut self, forms: &[Datum], scope: &mut Vec<String>, recursive: Option<&str>, tail: bool) {
+        let depth = scope.len();
+        let Some((result, definitions)) = forms.split_last() else {
+            return;
+        };
+        for definition in definitions {
+            let name = function_definition(definition)
+                .map(|(name, _)| name)
+                .or(constant_definition(definition).map(|(name, _)| name));
+            if let (Some(name), Some(items)) = (name, definition.as_list()) {
+                let name_span = items[1].as_list().map_or(items[1].span, |signature| signature[0].span);
+                self.check_shadowing(name, name_span, scope);
+                scope.push(name.to_string());
+            }
+        }
+        for definition in definitions {
+            if let Some((name, body)) = function_definition(definition) {
+                let parameters = &definition.as_list().unwrap()[1].as_list().unwrap()[1..];
+                self.function(parameters, body, scope, Some(name), true);
+            } else if let Some((_, value)) = constant_definition(definition) {
+                self.walk(value, scope, recursive, false);
+            }
+        }
+        self.walk(result, scope, recursive, tail);
+        scope.truncate(depth);
+    }
+
+    fn walk(&mut self, datum: &Datum, scope: &mut Vec<String>, recursive: Option<&str>, tail: bool) {
+        let items = match &datum.kind {
+            DatumKind::Symbol(name) => {
+                self.references.push((name.clone(), self.top_level.clone()));
+                return;
+            }
+            DatumKind::List(items) => items,
+            _ => return,
+        };
+        match items.first().and_then(|head| head.as_symbol()) {
+            Some("quote") => {}
+            Some("lambda") if items.len() > 2 => {
+                let parameters = items[1].as_list().map_or(&[][..], |parameters| &parameters[..]);
+                // A call from a lambda happens whenever the lambda is called, not as the function's result.
+                self.function(parameters, &items[2..], scope, recursive, false);
+            }
+            Some("cond") => self.cond(datum, &items[1..], scope, recursive, tail),
+            Some("with-handlers") if items.len() > 2 => {
+                for handler in items[1].as_list().into_iter().flatten() {
+                    self.walk(handler, scope, recursive, false);
+                }
+                // The handlers are still installed when the body returns, so its result is not in tail position.
+                self.body(&items[2..], scope, recursive, false);
+            }
+            Some(name) => {
+                let is_local = scope.iter().any(|local| local == name);
+                if recursive == Some(name) && !tail && !is_local {
+                    self.lint(
+                        "non-tail-recursion",
+                        datum.span,
+                        format!("the recursive call to {} is not in tail position", name),
+                    );
+                }
+                if let Some(helper_module) = name
+                    .strip_prefix("__")
+                    .and_then(|name| name.split_once("::"))
+                    .map(|(module, _)| module)
+                {
+                    let helper_module = format!("stdlib::{}", helper_module);
+                    if !is_local && self.module.as_ref() != Some(&helper_module) {
+                        self.lint(
+                            "private-helper",
+                            datum.span,
+                            format!("{} is private to {}", name, helper_module),
+                        );
+                    }
+                }
+                for item in items {
+                    self.walk(item, scope, recursive, false);
+                }
+            }
+            None => {
+                for item in items {
+                    self.walk(item, scope, recursive, false);
+                }
+            }
+        }
+    }
+
+    fn cond(&mut self, datum: &Datum, clauses: &[Datum], scope: &mut Vec<String>, recursive: Option<&str>, tail: bool) {
+        let mut else_clause = None;
+        for clause in clauses {
+            if let Some(else_span) = else_clause {
+                self.lint(
+                    "unreachable-clause",
+                    clause.span,
+                    format!("this clause comes after the clause at {} that always matches", else_span),
+                );
+            }
+            match clause.as_list().map(|items| &items[..]) {
+                Some([test, result]) => {
+                    if is_else(test) && else_clause.is_none() {
+                        else_clause = Some(clause.span);
+                    }
+                    self.walk(test, scope, recursive, false);
+                    self.walk(result, scope, recursive, tail);
+                }
+                _ => self.walk(clause, scope, recursive, false),
+            }
+        }
+        if else_clause.is_none() {
+            self.lint(
+                "missing-else",
+                datum.span,
+                "cond has no [true ...] or [else ...] clause, so it raises an error when no clause matches".to_string(),
+            );
+        }
+    }
+}
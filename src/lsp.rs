@@ -0,0 +1,248 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::io::{BufRead, Write};
+
+use serde_json::{json, Value as Json};
+
+use crate::analysis::{analyze, Analysis, Definition, DefinitionKind};
+use crate::primitives::primitive_names;
+use crate::protocol::{read_message, write_message};
+use crate::tokenizer::Span;
+
+// JSON-RPC error code for requests the server does not implement.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+struct Server {
+    output: Box<dyn Write>,
+    // The analysis of every open document, by URI.
+    documents: HashMap<String, Analysis>,
+}
+
+// Serves the Language Server Protocol until the client sends exit. Documents are synced in full and
+// analyzed whenever they change. Standard library modules are read relative to the working
+// directory, as they are when programs run.
+pub fn run_lsp_server(mut input: impl BufRead, output: impl Write + 'static) {
+    let mut server = Server {
+        output: Box::new(output),
+        documents: HashMap::new(),
+    };
+
+    while let Some(message) = read_message(&mut input) {
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+        let result = match message["method"].as_str().unwrap_or_default() {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": {},
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "rusty-racket" },
+            }),
+            "textDocument/didOpen" => {
+                server.update(uri, params["textDocument"]["text"].as_str().unwrap_or_default());
+                continue;
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array().cloned().unwrap_or_default();
+                if let Some(text) = changes.last().and_then(|change| change["text"].as_str()) {
+                    server.update(uri, text);
+                }
+                continue;
+            }
+            "textDocument/didClose" => {
+                server.documents.remove(&uri);
+                server.notify("textDocument/publishDiagnostics", json!({ "uri": uri, "diagnostics": [] }));
+                continue;
+            }
+            "textDocument/definition" => server.with_word(&uri, params, |analysis, word| {
+                let (module, definition) = analysis.find_definition(word)?;
+                let uri = match module {
+                    Some(module) => format!("file://{}", env::current_dir().ok()?.join(&module.path).display()),
+                    None => uri.clone(),
+                };
+                Some(json!({ "uri": uri, "range": name_range(definition) }))
+            }),
+            "textDocument/hover" => server.with_word(&uri, params, |analysis, word| {
+                let (module, definition) = analysis.find_definition(word)?;
+                let mut code = definition.signature.clone();
+                if let Some(declared_type) = &definition.declared_type {
+                    code = format!("(: {} {})\n{}", definition.name, declared_type, code);
+                }
+                let mut value = format!("```racket\n{}\n```", code);
+                if let Some(module) = module {
+                    value += &format!("\n\nDefined in {}", module.name);
+                }
+                Some(json!({ "contents": { "kind": "markdown", "value": value } }))
+            }),
+            "textDocument/completion" => server.completion(&uri, params),
+            "textDocument/documentSymbol" => match server.documents.get(&uri) {
+                Some(analysis) => Json::Array(
+                    analysis
+                        .definitions
+                        .iter()
+                        .map(|definition| document_symbol(&analysis.text, definition))
+                        .collect(),
+                ),
+                None => Json::Null,
+            },
+            "shutdown" => Json::Null,
+            "exit" => return,
+            method => {
+                // Notifications the server has no use for are ignored.
+                if !message["id"].is_null() {
+                    server.send(json!({
+                        "jsonrpc": "2.0",
+                        "id": message["id"],
+                        "error": { "code": METHOD_NOT_FOUND, "message": format!("Unsupported request {}", method) },
+                    }));
+                }
+                continue;
+            }
+        };
+        server.send(json!({ "jsonrpc": "2.0", "id": message["id"], "result": result }));
+    }
+}
+
+impl Server {
+    fn send(&mut self, message: Json) {
+        // A client that stopped reading is handled when its next message is read.
+        let _ = write_message(&mut self.output, &message);
+    }
+
+    fn notify(&mut self, method: &str, params: Json) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    // Analyzes a new version of a document and publishes its diagnostics.
+    fn update(&mut self, uri: String, text: &str) {
+        let analysis = analyze(text);
+        let diagnostics: Vec<Json> = analysis
+            .diagnostics
+            .iter()
+            .map(|diagnostic| {
+                json!({
+                    "range": range(&analysis.text, diagnostic.span),
+                    "severity": 1,
+                    "source": "rusty-racket",
+                    "message": diagnostic.message,
+                })
+            })
+            .collect();
+        self.notify(
+            "textDocument/publishDiagnostics",
+            json!({ "uri": uri, "diagnostics": diagnostics }),
+        );
+        self.documents.insert(uri, analysis);
+    }
+
+    // Answers a request about the word at the position in params, with null when there is none.
+    fn with_word(&self, uri: &str, params: &Json, answer: impl FnOnce(&Analysis, &str) -> Option<Json>) -> Json {
+        let Some(analysis) = self.documents.get(uri) else {
+            return Json::Null;
+        };
+        let (before, after) = word_at(&analysis.text, &params["position"]);
+        answer(analysis, &(before + &after)).unwrap_or(Json::Null)
+    }
+
+    // Names that start with the part of the word before the cursor: the functions of the program and
+    // its modules, its other definitions and the built in functions.
+    fn completion(&self, uri: &str, params: &Json) -> Json {
+        let Some(analysis) = self.documents.get(uri) else {
+            return Json::Null;
+        };
+        let (prefix, _) = word_at(&analysis.text, &params["position"]);
+
+        let mut items = BTreeMap::new();
+        let names = primitive_names()
+            .map(str::to_string)
+            .chain(analysis.functions.iter().cloned());
+        for name in names {
+            items.insert(name.clone(), json!({ "label": name, "kind": 3 }));
+        }
+        let definitions = analysis.modules.iter().flat_map(|module| &module.definitions);
+        for definition in definitions.chain(&analysis.definitions) {
+            let kind = match definition.kind {
+                DefinitionKind::Function => 3,
+                DefinitionKind::Constant => 21,
+                DefinitionKind::Struct => 22,
+                DefinitionKind::Macro => 14,
+            };
+            items.insert(
+                definition.name.clone(),
+                json!({ "label": definition.name, "kind": kind, "detail": definition.signature }),
+            );
+        }
+        Json::Array(
+            items
+                .into_iter()
+                .filter(|(name, _)| name.starts_with(&prefix))
+                .map(|(_, item)| item)
+                .collect(),
+        )
+    }
+}
+
+// The parts of the word before and after an LSP position. Words end at whitespace, brackets and
+// quotes, as symbols do.
+fn word_at(text: &str, position: &Json) -> (String, String) {
+    let line = text
+        .lines()
+        .nth(position["line"].as_u64().unwrap_or(0) as usize)
+        .unwrap_or_default();
+    let chars: Vec<char> = line.chars().collect();
+    let cursor = (position["character"].as_u64().unwrap_or(0) as usize).min(chars.len());
+    let is_word = |c: &&char| !c.is_whitespace() && !"()[]{}\"'`,;".contains(**c);
+    let before: String = chars[..cursor].iter().rev().take_while(is_word).collect();
+    let after: String = chars[cursor..].iter().take_while(is_word).collect();
+    (before.chars().rev().collect(), after)
+}
+
+// LSP positions count lines and characters from 0.
+fn position(line: usize, column: usize) -> Json {
+    json!({ "line": line - 1, "character": column - 1 })
+}
+
+// The position of a byte offset into the text.
+fn position_at(text: &str, offset: usize) -> Json {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    position(before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+}
+
+// The range of a span in the text. An empty span is the position where it starts.
+fn range(text: &str, span: Span) -> Json {
+    let start = position(span.line, span.column);
+    let end = if span.end > span.start {
+        position_at(text, span.end)
+    } else {
+        start.clone()
+    };
+    json!({ "start": start, "end": end })
+}
+
+// Names are on one line, so their range does not need the text of the module they are in.
+fn name_range(definition: &Definition) -> Json {
+    let span = definition.name_span;
+    json!({
+        "start": position(span.line, span.column),
+        "end": position(span.line, span.column + definition.name.chars().count()),
+    })
+}
+
+fn document_symbol(text: &str, definition: &Definition) -> Json {
+    let kind = match definition.kind {
+        DefinitionKind::Function | DefinitionKind::Macro => 12,
+        DefinitionKind::Constant => 14,
+        DefinitionKind::Struct => 23,
+    };
+    json!({
+        "name": definition.name,
+        "detail": definition.signature,
+        "kind": kind,
+        "range": range(text, definition.span),
+        "selectionRange": name_range(definition),
+    })
+}
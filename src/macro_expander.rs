@@ -0,0 +1,560 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::error::SyntaxError;
+use crate::parser::{collect_symbols, constant_definition, function_definition, parameter_annotation};
+use crate::reader::{Datum, DatumKind};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxRule {
+    pub pattern: Datum,
+    pub template: Datum,
+}
+
+// (syntax-rules (literals...) [pattern template] ...)
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxRules {
+    pub literals: Vec<String>,
+    pub rules: Vec<SyntaxRule>,
+}
+
+pub type MacroMap = HashMap<String, SyntaxRules>;
+
+// What a pattern variable matched. Variables under an ellipsis match a sequence, one entry per repetition.
+#[derive(Debug, Clone)]
+enum MatchTree {
+    One(Datum),
+    Many(Vec<MatchTree>),
+}
+
+type Bindings = HashMap<String, MatchTree>;
+
+type ExpandResult<T> = Result<T, SyntaxError>;
+
+// An error in a macro definition or use, reported at the form.
+fn malformed<T>(datum: &Datum, message: String) -> ExpandResult<T> {
+    Err(SyntaxError::syntax(datum.span, message))
+}
+
+// Collects every top level `define-syntax` and expands all macro uses in the remaining forms.
+pub fn expand_macros(datums: Vec<Datum>) -> ExpandResult<Vec<Datum>> {
+    let mut macro_map = MacroMap::new();
+    let mut forms = Vec::new();
+
+    for datum in datums {
+        match parse_define_syntax(&datum)? {
+            Some((name, rules)) => {
+                macro_map.insert(name, rules);
+            }
+            None => forms.push(datum),
+        }
+    }
+
+    let mut expander = Expander {
+        template_names: template_names(&macro_map),
+        macro_map,
+        expansion_count: 0,
+    };
+    forms.into_iter().map(|form| expander.expand(form, &Renames::new())).collect()
+}
+
+// The local variables in scope that have been renamed, by their name in the source.
+type Renames = HashMap<String, String>;
+
+struct Expander {
+    macro_map: MacroMap,
+    // Identifiers that some template uses without binding them. These refer to the top level
+    // definitions where the macro is defined, so local variables with these names are renamed to keep
+    // them from capturing the identifiers in an expansion.
+    template_names: HashSet<String>,
+    // Numbers each expansion and renamed local variable, to make their names unique.
+    expansion_count: usize,
+}
+
+impl Expander {
+    fn expand(&mut self, datum: Datum, renames: &Renames) -> ExpandResult<Datum> {
+        let macro_name = match datum
+            .as_list()
+            .and_then(|items| items.first())
+            .and_then(|head| head.as_symbol())
+        {
+            // Quoted data is not code, so macro uses in it stay as they are.
+            Some("quote") => return Ok(datum),
+            Some("quasiquote") => return self.expand_quasiquoted(datum, 0, renames),
+            // A local variable with the name of a macro shadows it.
+            Some(head) if self.macro_map.contains_key(head) && !renames.contains_key(head) => head.to_string(),
+            _ => return self.expand_items(datum, renames),
+        };
+
+        // Local variables in the macro use are renamed before the template is instantiated, so that the
+        // renaming does not reach the identifiers the template introduces.
+        let datum = rename(datum, renames, 0);
+        self.expansion_count += 1;
+        let expanded = apply_syntax_rules(&macro_name, &self.macro_map[&macro_name], &datum, self.expansion_count)?;
+        // The expansion may itself contain macro uses.
+        self.expand(expanded, &Renames::new())
+    }
+
+    fn expand_items(&mut self, datum: Datum, renames: &Renames) -> ExpandResult<Datum> {
+        let items = match datum.kind {
+            DatumKind::List(items) => items,
+            DatumKind::Symbol(s) => {
+                let s = renames.get(&s).cloned().unwrap_or(s);
+                return Ok(Datum::new(DatumKind::Symbol(s), datum.span));
+            }
+            _ => return Ok(datum),
+        };
+
+        // Parameters are in scope in the whole form, and internal definitions in the body.
+        let (parameters, body_start) = scope(&items);
+        let definitions = items.get(body_start..).map_or_else(Vec::new, body_definitions);
+        let inner = self.bind(parameters, renames);
+        let body = self.bind(definitions, &inner);
+
+        let items = items
+            .into_iter()
+            .enumerate()
+            .map(|(i, item)| match i {
+                0 => self.expand(item, renames),
+                _ if i < body_start => self.expand(item, &inner),
+                _ => self.expand(item, &body),
+            })
+            .collect::<ExpandResult<_>>()?;
+        Ok(Datum::new(DatumKind::List(items), datum.span))
+    }
+
+    // Adds a rename for each local variable that could capture an identifier introduced by a macro.
+    fn bind(&mut self, names: Vec<String>, renames: &Renames) -> Renames {
+        let mut renames = renames.clone();
+        for name in names {
+            if self.template_names.contains(&name) {
+                self.expansion_count += 1;
+                let renamed = format!("{}%{}", name, self.expansion_count);
+                renames.insert(name, renamed);
+            }
+        }
+        renames
+    }
+
+    // Expands only the parts of a quasiquoted datum that are unquoted, where depth is the number of
+    // quasiquotes the datum is inside of that have not been unquoted.
+    fn expand_quasiquoted(&mut self, datum: Datum, depth: usize, renames: &Renames) -> ExpandResult<Datum> {
+        let depth = match datum
+            .as_list()
+            .and_then(|items| items.first())
+            .and_then(|head| head.as_symbol())
+        {
+            Some("quasiquote") => depth + 1,
+            Some("unquote" | "unquote-splicing") if depth == 1 => return self.expand_items(datum, renames),
+            Some("unquote" | "unquote-splicing") => depth - 1,
+            _ => depth,
+        };
+        Ok(match datum.kind {
+            DatumKind::List(items) => Datum::new(
+                DatumKind::List(
+                    items
+                        .into_iter()
+                        .map(|item| self.expand_quasiquoted(item, depth, renames))
+                        .collect::<ExpandResult<_>>()?,
+                ),
+                datum.span,
+            ),
+            _ => datum,
+        })
+    }
+}
+
+// Renames the local variables in a datum that is not expanded yet, leaving quoted data alone. depth
+// is as in expand_quasiquoted.
+fn rename(datum: Datum, renames: &Renames, depth: usize) -> Datum {
+    if renames.is_empty() {
+        return datum;
+    }
+    let depth = match datum
+        .as_list()
+        .and_then(|items| items.first())
+        .and_then(|head| head.as_symbol())
+    {
+        Some("quote") if depth == 0 => return datum,
+        Some("quasiquote") => depth + 1,
+        Some("unquote" | "unquote-splicing") => depth.saturating_sub(1),
+        _ => depth,
+    };
+    match datum.kind {
+        DatumKind::Symbol(s) if depth == 0 => {
+            let s = renames.get(&s).cloned().unwrap_or(s);
+            Datum::new(DatumKind::Symbol(s), datum.span)
+        }
+        DatumKind::List(items) => Datum::new(
+            DatumKind::List(items.into_iter().map(|item| rename(item, renames, depth)).collect()),
+            datum.span,
+        ),
+        kind => Datum::new(kind, datum.span),
+    }
+}
+
+// The parameters a form binds and the index its body starts at, for (lambda (params...) body...),
+// (define (name params...) body...) and (with-handlers (clauses...) body...). Other forms have no body.
+fn scope(items: &[Datum]) -> (Vec<String>, usize) {
+    let parameters = |parameters: &[Datum]| -> Vec<String> {
+        parameters
+            .iter()
+            .filter_map(|parameter| parameter_annotation(parameter).map(|(name, _)| name.to_string()))
+            .collect()
+    };
+    match (
+        items.first().and_then(|head| head.as_symbol()),
+        items.get(1).and_then(|item| item.as_list()),
+    ) {
+        (Some("lambda" | "λ"), Some(list)) => (parameters(list), 2),
+        (Some("define"), Some(signature)) => (parameters(signature.get(1..).unwrap_or_default()), 2),
+        (Some("with-handlers"), _) => (Vec::new(), 2),
+        _ => (Vec::new(), items.len()),
+    }
+}
+
+// The names of the internal definitions in a body.
+fn body_definitions(body: &[Datum]) -> Vec<String> {
+    body.iter()
+        .filter_map(|form| match function_definition(form) {
+            Some((name, _)) => Some(name.to_string()),
+            None => constant_definition(form).map(|(name, _)| name.to_string()),
+        })
+        .collect()
+}
+
+// Identifiers in the templates of the macros that are not pattern variables.
+fn template_names(macro_map: &MacroMap) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for syntax_rules in macro_map.values() {
+        for rule in &syntax_rules.rules {
+            let mut pattern_variables = Vec::new();
+            collect_pattern_variables(&rule.pattern, &syntax_rules.literals, &mut pattern_variables);
+            let mut symbols = HashSet::new();
+            collect_symbols(&rule.template, &mut symbols);
+            names.extend(symbols.into_iter().filter(|symbol| !pattern_variables.contains(symbol)));
+        }
+    }
+    names
+}
+
+// (define-syntax name (syntax-rules (literals...) [pattern template] ...))
+fn parse_define_syntax(datum: &Datum) -> ExpandResult<Option<(String, SyntaxRules)>> {
+    let items = match datum.as_list() {
+        Some(items) if is_symbol(items.first(), "define-syntax") => items,
+        _ => return Ok(None),
+    };
+
+    let name = match items.get(1).and_then(|name| name.as_symbol()) {
+        Some(name) => name.to_string(),
+        None => {
+            return malformed(
+                datum,
+                format!("Malformed define-syntax at {}: expected a macro name", datum.span),
+            )
+        }
+    };
+
+    let rules_items = match items.get(2).and_then(|rules| rules.as_list()) {
+        Some(rules_items) if items.len() == 3 && is_symbol(rules_items.first(), "syntax-rules") => rules_items,
+        _ => {
+            return malformed(
+                datum,
+                format!(
+                    "Malformed define-syntax for {} at {}: expected (syntax-rules ...)",
+                    name, datum.span
+                ),
+            )
+        }
+    };
+
+    let literals = match rules_items.get(1).and_then(|literals| literals.as_list()) {
+        Some(literals) => literals
+            .iter()
+            .map(|literal| match literal.as_symbol() {
+                Some(literal) => Ok(literal.to_string()),
+                None => malformed(
+                    literal,
+                    format!("Malformed syntax-rules literals for macro {} at {}", name, literal.span),
+                ),
+            })
+            .collect::<ExpandResult<_>>()?,
+        None => {
+            return malformed(
+                datum,
+                format!(
+                    "Malformed syntax-rules for macro {} at {}: expected a list of literals",
+                    name, datum.span
+                ),
+            )
+        }
+    };
+
+    let rules = rules_items[2..]
+        .iter()
+        .map(|rule| match rule.as_list().map(|parts| &parts[..]) {
+            Some([pattern, template]) if pattern.as_list().is_some() => Ok(SyntaxRule {
+                pattern: pattern.clone(),
+                template: template.clone(),
+            }),
+            _ => malformed(
+                rule,
+                format!(
+                    "Malformed syntax-rules clause for macro {} at {}: expected [pattern template]",
+                    name, rule.span
+                ),
+            ),
+        })
+        .collect::<ExpandResult<_>>()?;
+
+    Ok(Some((name, SyntaxRules { literals, rules })))
+}
+
+fn apply_syntax_rules(name: &str, syntax_rules: &SyntaxRules, form: &Datum, expansion_id: usize) -> ExpandResult<Datum> {
+    let form_items = form.as_list().unwrap();
+
+    for rule in &syntax_rules.rules {
+        let pattern_items = rule.pattern.as_list().unwrap();
+
+        // The first element of the pattern is the macro keyword and is ignored.
+        let mut bindings = Bindings::new();
+        if pattern_items.is_empty() || !match_list(&pattern_items[1..], &form_items[1..], &syntax_rules.literals, &mut bindings) {
+            continue;
+        }
+
+        let mut binders = HashSet::new();
+        collect_binders(&rule.template, &bindings, &mut binders);
+
+        return instantiate(&rule.template, &bindings, &binders, expansion_id);
+    }
+
+    malformed(
+        form,
+        format!("No syntax-rules pattern matched the use of macro {} at {}", name, form.span),
+    )
+}
+
+fn is_symbol(datum: Option<&Datum>, text: &str) -> bool {
+    datum.and_then(|datum| datum.as_symbol()) == Some(text)
+}
+
+fn is_ellipsis(datum: Option<&Datum>) -> bool {
+    is_symbol(datum, "...")
+}
+
+fn match_pattern(pattern: &Datum, form: &Datum, literals: &[String], bindings: &mut Bindings) -> bool {
+    match (&pattern.kind, &form.kind) {
+        (DatumKind::Symbol(p), _) => {
+            if p == "_" {
+                true
+            } else if literals.contains(p) {
+                form.as_symbol() == Some(p)
+            } else {
+                bindings.insert(p.clone(), MatchTree::One(form.clone()));
+                true
+            }
+        }
+        (DatumKind::List(patterns), DatumKind::List(forms)) => match_list(patterns, forms, literals, bindings),
+        (DatumKind::List(_), _) | (DatumKind::Vector(_), _) => false,
+        // Numbers, booleans and strings have to appear exactly as written.
+        (p, f) => p == f,
+    }
+}
+
+fn match_list(patterns: &[Datum], forms: &[Datum], literals: &[String], bindings: &mut Bindings) -> bool {
+    let ellipsis_index = (0..patterns.len()).find(|&i| is_ellipsis(patterns.get(i + 1)));
+
+    let repeated_index = match ellipsis_index {
+        Some(i) => i,
+        None => {
+            return patterns.len() == forms.len()
+                && patterns
+                    .iter()
+                    .zip(forms)
+                    .all(|(pattern, form)| match_pattern(pattern, form, literals, bindings));
+        }
+    };
+
+    // Patterns before and after `x ...` must match exactly, the ellipsis takes whatever is left over.
+    let before = &patterns[..repeated_index];
+    let after = &patterns[repeated_index + 2..];
+    if forms.len() < before.len() + after.len() {
+        return false;
+    }
+    let repeated_forms = &forms[before.len()..forms.len() - after.len()];
+
+    if !match_list(before, &forms[..before.len()], literals, bindings)
+        || !match_list(after, &forms[forms.len() - after.len()..], literals, bindings)
+    {
+        return false;
+    }
+
+    let repeated_pattern = &patterns[repeated_index];
+    let mut repetitions = Vec::new();
+    for form in repeated_forms {
+        let mut repetition = Bindings::new();
+        if !match_pattern(repeated_pattern, form, literals, &mut repetition) {
+            return false;
+        }
+        repetitions.push(repetition);
+    }
+
+    let mut variables = Vec::new();
+    collect_pattern_variables(repeated_pattern, literals, &mut variables);
+    for variable in variables {
+        let sequence = repetitions
+            .iter_mut()
+            .map(|repetition| repetition.remove(&variable).unwrap())
+            .collect();
+        bindings.insert(variable, MatchTree::Many(sequence));
+    }
+
+    true
+}
+
+fn collect_pattern_variables(pattern: &Datum, literals: &[String], variables: &mut Vec<String>) {
+    match &pattern.kind {
+        DatumKind::Symbol(s) if s != "_" && s != "..." && !literals.contains(s) => variables.push(s.clone()),
+        DatumKind::List(items) | DatumKind::Vector(items) => {
+            for item in items {
+                collect_pattern_variables(item, literals, variables);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Identifiers that the template itself introduces in a binding position. These are renamed on every
+// expansion so that they can never capture (or be captured by) identifiers written by the macro user.
+fn collect_binders(template: &Datum, bindings: &Bindings, binders: &mut HashSet<String>) {
+    let items = match template.as_list() {
+        Some(items) => items,
+        None => return,
+    };
+
+    // (define (name params...) body), (lambda (params...) body) and (let ([name value] ...) body), which
+    // a program can define as a macro itself. Parameters may be annotated as [name : Type].
+    let head = items.first().and_then(|head| head.as_symbol());
+    let (name, parameters) = match (head, items.get(1).map(|item| (item.as_symbol(), item.as_list()))) {
+        (Some("define"), Some((_, Some(signature)))) => (None, signature.get(1..).unwrap_or_default()),
+        (Some("lambda" | "λ"), Some((_, Some(parameters)))) => (None, &parameters[..]),
+        (Some("let" | "let*" | "letrec"), Some((_, Some(bindings)))) => (None, &bindings[..]),
+        // A named let also binds its name.
+        (Some("let" | "let*" | "letrec"), Some((Some(name), _))) => (
+            Some(name),
+            items
+                .get(2)
+                .and_then(|bindings| bindings.as_list())
+                .map_or(&[][..], |bindings| &bindings[..]),
+        ),
+        _ => (None, &[][..]),
+    };
+    let names = parameters.iter().filter_map(|parameter| match parameter.as_list() {
+        Some(items) => items.first()?.as_symbol(),
+        None => parameter.as_symbol(),
+    });
+    for binder in name.into_iter().chain(names) {
+        if !bindings.contains_key(binder) {
+            binders.insert(binder.to_string());
+        }
+    }
+
+    for item in items {
+        collect_binders(item, bindings, binders);
+    }
+}
+
+fn instantiate(template: &Datum, bindings: &Bindings, binders: &HashSet<String>, expansion_id: usize) -> ExpandResult<Datum> {
+    Ok(match &template.kind {
+        DatumKind::Symbol(s) => match bindings.get(s) {
+            Some(MatchTree::One(datum)) => datum.clone(),
+            Some(MatchTree::Many(_)) => {
+                return malformed(
+                    template,
+                    format!(
+                        "Pattern variable {} must be followed by an ellipsis in the template at {}",
+                        s, template.span
+                    ),
+                )
+            }
+            None if binders.contains(s) => Datum::new(DatumKind::Symbol(format!("{}%{}", s, expansion_id)), template.span),
+            None => template.clone(),
+        },
+        DatumKind::List(items) => Datum::new(
+            DatumKind::List(instantiate_items(items, bindings, binders, expansion_id)?),
+            template.span,
+        ),
+        DatumKind::Vector(items) => Datum::new(
+            DatumKind::Vector(instantiate_items(items, bindings, binders, expansion_id)?),
+            template.span,
+        ),
+        _ => template.clone(),
+    })
+}
+
+fn instantiate_items(
+    items: &[Datum],
+    bindings: &Bindings,
+    binders: &HashSet<String>,
+    expansion_id: usize,
+) -> ExpandResult<Vec<Datum>> {
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < items.len() {
+        if is_ellipsis(items.get(i + 1)) {
+            for repetition in ellipsis_repetitions(&items[i], bindings)? {
+                result.push(instantiate(&items[i], &repetition, binders, expansion_id)?);
+            }
+            i += 2;
+        } else {
+            result.push(instantiate(&items[i], bindings, binders, expansion_id)?);
+            i += 1;
+        }
+    }
+    Ok(result)
+}
+
+// One set of bindings per repetition of `template ...`, where every sequence variable used in the
+// template is bound to a single element of its sequence.
+fn ellipsis_repetitions(template: &Datum, bindings: &Bindings) -> ExpandResult<Vec<Bindings>> {
+    let mut identifiers = Vec::new();
+    collect_pattern_variables(template, &[], &mut identifiers);
+
+    let sequences: Vec<(&String, &Vec<MatchTree>)> = identifiers
+        .iter()
+        .filter_map(|identifier| match bindings.get(identifier) {
+            Some(MatchTree::Many(sequence)) => Some((identifier, sequence)),
+            _ => None,
+        })
+        .collect();
+
+    if sequences.is_empty() {
+        return malformed(
+            template,
+            format!(
+                "Ellipsis in template at {} does not follow any pattern variable bound by an ellipsis",
+                template.span
+            ),
+        );
+    }
+
+    let length = sequences[0].1.len();
+    if sequences.iter().any(|(_, sequence)| sequence.len() != length) {
+        return malformed(
+            template,
+            format!(
+                "Pattern variables under the same ellipsis at {} matched a different number of forms",
+                template.span
+            ),
+        );
+    }
+
+    Ok((0..length)
+        .map(|i| {
+            let mut repetition = bindings.clone();
+            for (identifier, sequence) in &sequences {
+                repetition.insert(identifier.to_string(), sequence[i].clone());
+            }
+            repetition
+        })
+        .collect())
+}
@@ -1,33 +1,191 @@
-// #![allow(dead_code)]
+use std::{env, fs, io};
 
-mod interpret;
-mod interpret_bool;
-mod interpret_cond;
-mod interpret_function;
-mod interpret_function_call;
-mod interpret_list;
-mod interpret_num;
-mod interpret_variable;
-mod parser;
-mod tokenizer;
-
-use std::{env, fs};
-
-use interpret::interpret_program;
+use rusty_racket::cli::{
+    check_program, format_program, interpret_racket_program, junit_report, lint_program, read_lang_line, run_checks,
+    run_dap_server, run_lsp_server, source_module, try_interpret_program, with_debugger, CheckResult, DebugConsole, Language,
+    Resume,
+};
+use rusty_racket::{to_racket_string, RacketError};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() < 2 {
-        eprintln!("Usage: {} <file>", args[0]);
+    // dap serves the Debug Adapter Protocol and lsp the Language Server Protocol over stdin and stdout
+    // for editors.
+    if args[1..] == ["dap"] {
+        run_dap_server(io::stdin().lock(), io::stdout());
+        return;
+    }
+    if args[1..] == ["lsp"] {
+        run_lsp_server(io::stdin().lock(), io::stdout());
+        return;
+    }
+
+    // fmt formats programs in place, or with --check lists the ones that are not formatted.
+    if args.get(1).map(String::as_str) == Some("fmt") {
+        format_files(&args[2..]);
+        return;
+    }
+
+    // lint reports likely mistakes in programs, with --enable turning on rules that are off by default.
+    if args.get(1).map(String::as_str) == Some("lint") {
+        lint_files(&args[2..]);
+        return;
+    }
+
+    // test runs the check forms in programs, optionally writing a JUnit report with --junit.
+    if args.get(1).map(String::as_str) == Some("test") {
+        test_files(&args[2..]);
+        return;
+    }
+
+    // --check only type checks the program, printing the type of each definition. --debug runs it in
+    // the debugger, paused at its first call.
+    let (flag, file_path) = match &args[1..] {
+        [flag, file_path] if flag == "--check" || flag == "--debug" => (Some(flag.as_str()), file_path),
+        [file_path] => (None, file_path),
+        _ => {
+            eprintln!(
+                "Usage: {0} [--check | --debug] <file>\n       {0} fmt [--check] <file>...\n       {0} lint [--enable <rule>]... <file>...\n       {0} test [--junit <report>] <file>...\n       {0} dap | lsp",
+                args[0]
+            );
+            std::process::exit(1);
+        }
+    };
+    let program = fs::read_to_string(file_path).unwrap_or_else(|_| panic!("Unable to read file {}", file_path));
+
+    if flag == Some("--debug") {
+        println!("Debugging {}. Type help for a list of commands.", file_path);
+        let console = DebugConsole::new(io::stdin().lock(), io::stdout(), file_path);
+        with_debugger(Box::new(console), Vec::new(), Resume::StepIn, || run(program));
+        return;
+    }
+
+    if flag == Some("--check") {
+        match check_program(program) {
+            Ok(definitions) => {
+                for (name, scheme) in definitions {
+                    println!("{} : {}", name, scheme);
+                }
+            }
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("{}", error);
+                }
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    run(program);
+}
+
+fn format_files(args: &[String]) {
+    let (check, file_paths) = match args {
+        [flag, file_paths @ ..] if flag == "--check" => (true, file_paths),
+        file_paths => (false, file_paths),
+    };
+    let mut unformatted = false;
+    for file_path in file_paths {
+        let program = fs::read_to_string(file_path).unwrap_or_else(|_| panic!("Unable to read file {}", file_path));
+        let formatted = format_program(&program).unwrap_or_else(|error| exit_with_error(error.into()));
+        if formatted == program {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", file_path);
+            unformatted = true;
+        } else {
+            fs::write(file_path, formatted).unwrap_or_else(|_| panic!("Unable to write file {}", file_path));
+        }
+    }
+    if unformatted {
         std::process::exit(1);
     }
+}
+
+fn lint_files(args: &[String]) {
+    // Each --enable <rule> turns on a rule that is off by default.
+    let mut enabled = Vec::new();
+    let mut file_paths = args;
+    while let [flag, rule, rest @ ..] = file_paths {
+        if flag != "--enable" {
+            break;
+        }
+        enabled.push(rule.as_str());
+        file_paths = rest;
+    }
+
+    let mut linted = false;
+    for file_path in file_paths {
+        let program = fs::read_to_string(file_path).unwrap_or_else(|_| panic!("Unable to read file {}", file_path));
+        let lints = lint_program(&program, source_module(file_path).as_deref(), &enabled);
+        for lint in lints.unwrap_or_else(|error| exit_with_error(error.into())) {
+            println!("{}:{}", file_path, lint);
+            linted = true;
+        }
+    }
+    if linted {
+        std::process::exit(1);
+    }
+}
+
+fn test_files(args: &[String]) {
+    let (report_path, file_paths) = match args {
+        [flag, report_path, file_paths @ ..] if flag == "--junit" => (Some(report_path), file_paths),
+        file_paths => (None, file_paths),
+    };
+    let mut files = Vec::new();
+    for file_path in file_paths {
+        let program = fs::read_to_string(file_path).unwrap_or_else(|_| panic!("Unable to read file {}", file_path));
+        let results = run_checks(program);
+        for result in results.iter().filter(|result| !result.passed()) {
+            println!("{}:{}", file_path, result);
+        }
+        files.push((file_path.clone(), results));
+    }
 
-    let file_path = &args[1];
-    let program = fs::read_to_string(file_path).expect(&format!("Unable to read file {}", file_path));
+    let results: Vec<&CheckResult> = files.iter().flat_map(|(_, results)| results).collect();
+    let passed = results.iter().filter(|result| result.passed()).count();
+    println!(
+        "{} checks: {} passed, {} failed",
+        results.len(),
+        passed,
+        results.len() - passed
+    );
+    if let Some(report_path) = report_path {
+        fs::write(report_path, junit_report(&files)).unwrap_or_else(|_| panic!("Unable to write file {}", report_path));
+    }
+    if passed < results.len() {
+        std::process::exit(1);
+    }
+}
 
-    println!("{}", interpret_program(program.to_string()));
+fn run(program: String) {
+    let language = read_lang_line(program.clone())
+        .unwrap_or_else(|error| exit_with_error(error.into()))
+        .0;
+    match language {
+        Language::RustyRacket => match try_interpret_program(program) {
+            Ok(value) => println!("{}", value),
+            Err(error) => exit_with_error(error),
+        },
+        Language::Racket => {
+            for result in interpret_racket_program(program) {
+                match result {
+                    Ok(value) => println!("{}", to_racket_string(&value)),
+                    Err(error) => exit_with_error(error),
+                }
+            }
+        }
+    }
 }
 
-#[cfg(test)]
-mod test;
+fn exit_with_error(error: RacketError) -> ! {
+    eprintln!("{}", error);
+    if !error.backtrace().frames.is_empty() {
+        eprintln!("{}", error.backtrace());
+    }
+    std::process::exit(1);
+}
@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+
+use crate::error::EvalResult;
+use crate::interpret::Value;
+
+// A function implemented in Rust by the program embedding the interpreter.
+pub type NativeFn = fn(&[Value]) -> EvalResult;
+
+#[derive(Debug, Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    // The number of arguments the function must be called with. Calls with any other number are
+    // rejected before the function runs.
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+// A named group of native functions, made available to a program by `(include name)`.
+#[derive(Debug, Clone)]
+pub struct NativeModule {
+    pub name: String,
+    pub functions: Vec<NativeFunction>,
+}
+
+pub type NativeModules = HashMap<String, NativeModule>;
+
+impl NativeModule {
+    pub fn new(name: &str) -> Self {
+        NativeModule {
+            name: name.to_string(),
+            functions: Vec::new(),
+        }
+    }
+
+    // Adds a function to the module. Its name is used as is, so modules usually prefix their
+    // functions the way the standard library does, as in geometry::area.
+    pub fn function(mut self, name: &str, arity: usize, function: NativeFn) -> Self {
+        self.functions.push(NativeFunction {
+            name: name.to_string(),
+            arity,
+            function,
+        });
+        self
+    }
+}
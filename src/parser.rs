@@ -1,279 +1,679 @@
-use itertools::peek_nth;
-use itertools::PeekNth;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
+use crate::error::SyntaxError;
 use crate::interpret::*;
 use crate::interpret_bool::*;
 use crate::interpret_cond::*;
+use crate::interpret_define::{initialization_order, BodyExpr, Definition};
 use crate::interpret_function::FunctionExpr;
-use crate::interpret_function_call::FunctionCallExpr;
+use crate::interpret_function_call::{ApplicationExpr, FunctionCallExpr};
+use crate::interpret_lambda::LambdaExpr;
 use crate::interpret_list::CarExpr;
 use crate::interpret_list::CdrExpr;
 use crate::interpret_list::ListLiteralExpr;
 use crate::interpret_list::{ListExpr, Node};
 use crate::interpret_num::*;
+use crate::interpret_quote::{datum_to_value, QuasiquoteExpr, QuasiquoteItem, QuoteExpr};
 use crate::interpret_variable::*;
-use crate::tokenizer::*;
+use crate::interpret_with_handlers::WithHandlersExpr;
+use crate::reader::{read, Datum, DatumKind};
+
+pub type ParseResult<T> = Result<T, SyntaxError>;
+
+// Symbols with built in meaning. Any other symbol at the head of a list is a function call.
+const KEYWORDS: &[&str] = &[
+    "define",
+    "cond",
+    "list",
+    "cons",
+    "empty",
+    "null",
+    "car",
+    "cdr",
+    "empty?",
+    "list?",
+    "quote",
+    "lambda",
+    "λ",
+    "with-handlers",
+    "quasiquote",
+    "unquote",
+    "unquote-splicing",
+    "+",
+    "-",
+    "/",
+    "*",
+    "%",
+    "&",
+    "|",
+    "!",
+    "<",
+    "=",
+    ">",
+];
+
+pub fn parse(program: String) -> ParseResult<Expr> {
+    parse_expr(&read(program)?)
+}
 
-pub fn parse(program: String) -> Expr {
-    let tokens = string_to_tokens(program);
-    let mut token_iterator = peek_nth(TokenIter::new(&tokens));
+// An error in the form, reported at its location.
+fn malformed<T>(datum: &Datum, message: String) -> ParseResult<T> {
+    Err(SyntaxError::syntax(datum.span, message))
+}
 
-    let parsed = parse_expr(&mut token_iterator);
+pub fn symbol_to_binary_num_op(symbol: &str) -> Option<BinaryNumOp> {
+    match symbol {
+        "+" => Some(BinaryNumOp::Add),
+        "-" => Some(BinaryNumOp::Sub),
+        "/" => Some(BinaryNumOp::Div),
+        "*" => Some(BinaryNumOp::Mul),
+        "%" => Some(BinaryNumOp::Mod),
+        _ => None,
+    }
+}
 
-    if token_iterator.peek().is_some() {
-        panic!(
-            "Malformed program, more tokens after program completion {}",
-            token_iterator
-                .map(|token| format!("{}", token))
-                .collect::<Vec<_>>()
-                .join(", ")
-        );
+pub fn symbol_to_binary_bool_op(symbol: &str) -> Option<BinaryBoolOp> {
+    match symbol {
+        "&" => Some(BinaryBoolOp::And),
+        "|" => Some(BinaryBoolOp::Or),
+        _ => None,
+    }
+}
+
+pub fn symbol_to_unary_bool_op(symbol: &str) -> Option<UnaryBoolOp> {
+    match symbol {
+        "!" => Some(UnaryBoolOp::Not),
+        _ => None,
+    }
+}
+
+pub fn symbol_to_cmp_bool_op(symbol: &str) -> Option<CmpBoolOp> {
+    match symbol {
+        "<" => Some(CmpBoolOp::Lt),
+        "=" => Some(CmpBoolOp::Eq),
+        ">" => Some(CmpBoolOp::Gt),
+        _ => None,
     }
+}
+
+fn is_keyword(symbol: &str) -> bool {
+    KEYWORDS.contains(&symbol)
+}
 
-    parsed
+// The symbol at the head of a list, e.g. `cond` for `(cond ...)`.
+fn head_symbol(datum: &Datum) -> Option<&str> {
+    datum.as_list()?.first()?.as_symbol()
 }
 
-pub fn parse_expr(tokens: &mut PeekNth<TokenIter<'_>>) -> Expr {
-    match tokens.peek().unwrap().kind {
-        TokenKind::Identifier => Expr::VariableExpr(parse_variable_expr(tokens)),
-        TokenKind::Minus => Expr::NumExpr(parse_num_expr(tokens)),
-        TokenKind::Number => Expr::NumExpr(NumExpr::LiteralNumExpr(tokens.next().unwrap().text.parse::<i32>().unwrap())),
-        TokenKind::Boolean => Expr::BoolExpr(BoolExpr::LiteralBoolExpr(
-            tokens.next().unwrap().text.parse::<bool>().unwrap(),
-        )),
-        TokenKind::Empty => {
-            tokens.next();
-            Expr::EmptyExpr(ListExpr::ListLiteralExpr(ListLiteralExpr::Empty))
+fn is_function_call(datum: &Datum) -> bool {
+    head_symbol(datum).is_some_and(|symbol| !is_keyword(symbol))
+}
+
+pub fn parse_expr(datum: &Datum) -> ParseResult<Expr> {
+    Ok(match &datum.kind {
+        DatumKind::Number(n) => Expr::NumExpr(NumExpr::LiteralNumExpr(*n)),
+        DatumKind::Boolean(b) => Expr::BoolExpr(BoolExpr::LiteralBoolExpr(*b)),
+        DatumKind::String(s) => Expr::StringExpr(s.clone()),
+        DatumKind::Char(c) => Expr::CharExpr(*c),
+        DatumKind::Symbol(s) if s == "empty" || s == "null" => Expr::EmptyExpr(ListExpr::ListLiteralExpr(ListLiteralExpr::Empty)),
+        DatumKind::Symbol(_) => Expr::VariableExpr(parse_variable_expr(datum)),
+        // Vector literals are self quoting, their elements are not evaluated.
+        DatumKind::Vector(_) => Expr::QuoteExpr(QuoteExpr {
+            value: datum_to_value(datum)?,
+        }),
+        DatumKind::Keyword(_) => {
+            return malformed(
+                datum,
+                format!("Keywords are only allowed as function arguments at {}: {}", datum.span, datum),
+            )
         }
-        TokenKind::OpenParen => match tokens.peek_nth(1).unwrap().kind {
-            TokenKind::Define => Expr::FunctionExpr(parse_function_expr(tokens)),
-            TokenKind::Cond => Expr::CondExpr(parse_cond_expr(tokens)),
-            TokenKind::Car => Expr::CarExpr(parse_car_expr(tokens)),
-            TokenKind::Identifier => Expr::FunctionCallExpr(parse_function_call(tokens)),
-            TokenKind::List | TokenKind::Cons | TokenKind::Cdr => Expr::ListExpr(parse_list_expr(tokens)),
-            TokenKind::Plus | TokenKind::Minus | TokenKind::Slash | TokenKind::Star | TokenKind::Percent => {
-                Expr::NumExpr(parse_num_expr(tokens))
+        DatumKind::List(_) => match head_symbol(datum) {
+            // Well formed definitions are taken out of the top level and of bodies before their
+            // expressions are parsed, so any that are left are misplaced.
+            Some("define") if constant_definition(datum).is_some() || function_definition(datum).is_some() => {
+                return malformed(
+                    datum,
+                    format!(
+                        "Definitions are only allowed at the top level or at the start of a body at {}: {}",
+                        datum.span, datum
+                    ),
+                )
+            }
+            Some("define") => return Err(malformed_definition(datum)),
+            Some("define/contract") => {
+                return malformed(
+                    datum,
+                    format!(
+                        "Expected a function signature, a contract and a body for define/contract at {}: {}",
+                        datum.span, datum
+                    ),
+                )
+            }
+            Some("provide") if provided_contracts(datum).is_none() => return Err(parse_provide(datum).unwrap_err()),
+            Some("struct") => return malformed(datum, format!("Invalid struct definition at {}: {}", datum.span, datum)),
+            Some("cond") => Expr::CondExpr(parse_cond_expr(datum)?),
+            Some("car") => Expr::CarExpr(parse_car_expr(datum)?),
+            Some("list" | "cons" | "cdr") => Expr::ListExpr(parse_list_expr(datum)?),
+            Some("+" | "-" | "/" | "*" | "%") => Expr::NumExpr(parse_num_expr(datum)?),
+            Some("&" | "|" | "!" | "<" | "=" | ">" | "empty?" | "list?") => Expr::BoolExpr(parse_bool_expr(datum)?),
+            Some("quote") => Expr::QuoteExpr(parse_quote_expr(datum)?),
+            Some("quasiquote") => Expr::QuasiquoteExpr(parse_quasiquote_expr(datum)?),
+            Some("lambda" | "λ") => Expr::LambdaExpr(parse_lambda_expr(datum)?),
+            Some("with-handlers") => Expr::WithHandlersExpr(parse_with_handlers_expr(datum)?),
+            Some(symbol) if !is_keyword(symbol) => Expr::FunctionCallExpr(parse_function_call(datum)?),
+            None if datum.as_list().unwrap().first().is_some_and(|head| head.as_list().is_some()) => {
+                Expr::ApplicationExpr(parse_application_expr(datum)?)
+            }
+            _ => {
+                return malformed(
+                    datum,
+                    format!(
+                        "Invalid expression starting with an open parenthesis '(' at {}: {}",
+                        datum.span, datum
+                    ),
+                )
             }
-            TokenKind::Ampersand
-            | TokenKind::Pipe
-            | TokenKind::Bang
-            | TokenKind::LessThan
-            | TokenKind::Equal
-            | TokenKind::GreaterThan
-            | TokenKind::EmptyHuh
-            | TokenKind::ListHuh => Expr::BoolExpr(parse_bool_expr(tokens)),
-            _ => panic!(
-                "Invalid expression starting with an open parenthesis '(': {}",
-                tokens.peek_nth(1).unwrap()
-            ),
         },
-        _ => panic!(
-            "Malformed expression, expression begins with an illegal character {}.",
-            tokens.peek().unwrap()
-        ),
-    }
+    })
 }
 
-pub fn parse_num_expr(tokens: &mut PeekNth<TokenIter<'_>>) -> NumExpr {
-    match tokens.peek().unwrap().kind {
-        TokenKind::Number => NumExpr::LiteralNumExpr(tokens.next().unwrap().text.parse::<N>().unwrap()),
-        TokenKind::Identifier => NumExpr::VariableExpr(VariableExpr {
-            name: tokens.next().unwrap().text.parse::<String>().unwrap(),
-        }),
-        // Do nth(1) because the negative sign needs to be consumed.
-        TokenKind::Minus => NumExpr::LiteralNumExpr(-tokens.nth(1).unwrap().text.parse::<N>().unwrap()),
-        _ => match tokens.peek_nth(1).unwrap().kind {
-            TokenKind::Identifier => NumExpr::FunctionCallExpr(parse_function_call(tokens)),
-            TokenKind::Car => NumExpr::CarExpr(parse_car_expr(tokens)),
-            TokenKind::Plus | TokenKind::Minus | TokenKind::Star | TokenKind::Slash | TokenKind::Percent => {
-                consume_open_paren(tokens);
-                let op_token = tokens.next().unwrap();
-                let left = parse_num_expr(tokens);
-                let right = parse_num_expr(tokens);
-                consume_close_paren(tokens);
+// What is wrong with a define form that is neither a function nor a constant definition.
+fn malformed_definition(datum: &Datum) -> SyntaxError {
+    let items = datum.as_list().unwrap();
+    let message = match items.get(1) {
+        Some(name) if name.as_symbol().is_some() => {
+            format!("Expected exactly one value for the definition at {}: {}", datum.span, datum)
+        }
+        Some(signature) if signature.as_list().is_some() && items.len() >= 3 => {
+            format!("Invalid function signature at {}: {}", signature.span, signature)
+        }
+        Some(signature) if signature.as_list().is_some() => {
+            format!("Expected a body for the function defined at {}: {}", datum.span, datum)
+        }
+        _ => format!("Invalid definition at {}: {}", datum.span, datum),
+    };
+    SyntaxError::syntax(datum.span, message)
+}
+
+pub fn parse_num_expr(datum: &Datum) -> ParseResult<NumExpr> {
+    Ok(match &datum.kind {
+        DatumKind::Number(n) => NumExpr::LiteralNumExpr(*n),
+        DatumKind::Symbol(s) if !is_keyword(s) => NumExpr::VariableExpr(parse_variable_expr(datum)),
+        _ if is_function_call(datum) => NumExpr::FunctionCallExpr(parse_function_call(datum)?),
+        _ => match head_symbol(datum) {
+            Some("car") => NumExpr::CarExpr(parse_car_expr(datum)?),
+            Some(symbol) if symbol_to_binary_num_op(symbol).is_some() => {
+                let [left, right] = parse_operands(datum)?;
 
                 NumExpr::BinaryNumExpr(Box::new(BinaryNumExpr {
-                    op: token_kind_to_binary_num_op(&op_token.kind),
-                    left,
-                    right,
+                    op: symbol_to_binary_num_op(symbol).unwrap(),
+                    left: parse_num_expr(left)?,
+                    right: parse_num_expr(right)?,
                 }))
             }
-            _ => panic!(
-                "Invalid num expr {:?}",
-                tokens.map(|token| format!("{}", token)).collect::<Vec<_>>().join(", ")
-            ),
+            _ => NumExpr::DynamicExpr(Box::new(parse_expr(datum)?)),
         },
-    }
+    })
 }
 
-fn parse_bool_expr(tokens: &mut PeekNth<TokenIter<'_>>) -> BoolExpr {
-    match tokens.peek().unwrap().kind {
-        TokenKind::Boolean => BoolExpr::LiteralBoolExpr(tokens.next().unwrap().text.parse::<B>().unwrap()),
-        TokenKind::Identifier => BoolExpr::VariableExpr(VariableExpr {
-            name: tokens.next().unwrap().text.parse::<String>().unwrap(),
-        }),
-        TokenKind::OpenParen => match tokens.peek_nth(1).unwrap().kind {
-            TokenKind::Ampersand | TokenKind::Pipe => parse_binary_bool_expr(tokens),
-            TokenKind::Bang => parse_unary_bool_expr(tokens),
-            TokenKind::LessThan | TokenKind::Equal | TokenKind::GreaterThan => parse_cmp_bool_expr(tokens),
-            TokenKind::Identifier => BoolExpr::FunctionCallExpr(parse_function_call(tokens)),
-            TokenKind::EmptyHuh => BoolExpr::EmptyHuhExpr(parse_empty_huh_expr(tokens)),
-            TokenKind::ListHuh => BoolExpr::ListHuhExpr(parse_list_huh_expr(tokens)),
-            TokenKind::Car => BoolExpr::CarExpr(parse_car_expr(tokens)),
-            _ => panic!(
-                "Invalid expression starting with an open parenthesis '(': {}",
-                tokens.peek_nth(1).unwrap()
-            ),
+fn parse_bool_expr(datum: &Datum) -> ParseResult<BoolExpr> {
+    Ok(match &datum.kind {
+        DatumKind::Boolean(b) => BoolExpr::LiteralBoolExpr(*b),
+        DatumKind::Symbol(s) if !is_keyword(s) => BoolExpr::VariableExpr(parse_variable_expr(datum)),
+        DatumKind::List(_) if is_function_call(datum) => BoolExpr::FunctionCallExpr(parse_function_call(datum)?),
+        DatumKind::List(_) => match head_symbol(datum) {
+            Some("&" | "|") => parse_binary_bool_expr(datum)?,
+            Some("!") => parse_unary_bool_expr(datum)?,
+            Some("<" | "=" | ">") => parse_cmp_bool_expr(datum)?,
+            Some("empty?") => BoolExpr::EmptyHuhExpr(parse_empty_huh_expr(datum)?),
+            Some("list?") => BoolExpr::ListHuhExpr(parse_list_huh_expr(datum)?),
+            Some("car") => BoolExpr::CarExpr(parse_car_expr(datum)?),
+            _ => BoolExpr::DynamicExpr(Box::new(parse_expr(datum)?)),
         },
-        _ => panic!("Invalid start token to bool expr {}", tokens.peek().unwrap()),
-    }
+        _ => BoolExpr::DynamicExpr(Box::new(parse_expr(datum)?)),
+    })
 }
 
-fn parse_binary_bool_expr(tokens: &mut PeekNth<TokenIter<'_>>) -> BoolExpr {
-    consume_open_paren(tokens);
-    let op = token_kind_to_binary_bool_op(&tokens.next().unwrap().kind);
-    let left = parse_bool_expr(tokens);
-    let right = parse_bool_expr(tokens);
-    consume_close_paren(tokens);
+fn parse_binary_bool_expr(datum: &Datum) -> ParseResult<BoolExpr> {
+    let op = symbol_to_binary_bool_op(head_symbol(datum).unwrap()).unwrap();
+    let [left, right] = parse_operands(datum)?;
 
-    BoolExpr::BinaryBoolExpr(Box::new(BinaryBoolExpr { op, left, right }))
+    Ok(BoolExpr::BinaryBoolExpr(Box::new(BinaryBoolExpr {
+        op,
+        left: parse_bool_expr(left)?,
+        right: parse_bool_expr(right)?,
+    })))
 }
 
-fn parse_unary_bool_expr(tokens: &mut PeekNth<TokenIter<'_>>) -> BoolExpr {
-    consume_open_paren(tokens);
-    let op = token_kind_to_unary_bool_op(&tokens.next().unwrap().kind);
-    let value = parse_bool_expr(tokens);
-    consume_close_paren(tokens);
+fn parse_unary_bool_expr(datum: &Datum) -> ParseResult<BoolExpr> {
+    let op = symbol_to_unary_bool_op(head_symbol(datum).unwrap()).unwrap();
+    let [value] = parse_operands(datum)?;
 
-    BoolExpr::UnaryBoolExpr(Box::new(UnaryBoolExpr { op, value }))
+    Ok(BoolExpr::UnaryBoolExpr(Box::new(UnaryBoolExpr {
+        op,
+        value: parse_bool_expr(value)?,
+    })))
 }
 
-fn parse_cmp_bool_expr(tokens: &mut PeekNth<TokenIter<'_>>) -> BoolExpr {
-    consume_open_paren(tokens);
-    let op = token_kind_to_cmp_bool_op(&tokens.next().unwrap().kind);
-    let left = parse_num_expr(tokens);
-    let right = parse_num_expr(tokens);
-    consume_close_paren(tokens);
+fn parse_cmp_bool_expr(datum: &Datum) -> ParseResult<BoolExpr> {
+    let op = symbol_to_cmp_bool_op(head_symbol(datum).unwrap()).unwrap();
+    let [left, right] = parse_operands(datum)?;
 
-    BoolExpr::CmpExpr(Box::new(CmpBoolExpr { op, left, right }))
+    Ok(BoolExpr::CmpExpr(Box::new(CmpBoolExpr {
+        op,
+        left: parse_num_expr(left)?,
+        right: parse_num_expr(right)?,
+    })))
 }
 
 // (cond (case 1) (case 2))
-fn parse_cond_expr(tokens: &mut PeekNth<TokenIter<'_>>) -> CondExpr {
-    consume_open_paren(tokens);
-    tokens.next(); // 'cond'
+fn parse_cond_expr(datum: &Datum) -> ParseResult<CondExpr> {
+    let items = datum.as_list().unwrap();
 
     // Does not contain a case.
-    if tokens.peek().unwrap().kind == TokenKind::CloseParen {
-        panic!("No case in cond.");
-    }
-
-    let mut cases: Vec<CondCase> = Vec::new();
-    while tokens.peek().unwrap().kind != TokenKind::CloseParen {
-        cases.push(parse_cond_case(tokens));
+    if items.len() == 1 {
+        return malformed(datum, format!("No case in cond at {}", datum.span));
     }
 
-    consume_close_paren(tokens);
+    let cases = items[1..].iter().map(parse_cond_case).collect::<ParseResult<_>>()?;
 
-    CondExpr { cases }
+    Ok(CondExpr { cases })
 }
 
-fn parse_cond_case(tokens: &mut PeekNth<TokenIter<'_>>) -> CondCase {
-    consume_open_paren(tokens);
-    let condition = parse_bool_expr(tokens);
-    let result = parse_expr(tokens);
-    consume_close_paren(tokens);
-
-    CondCase { condition, result }
+fn parse_cond_case(datum: &Datum) -> ParseResult<CondCase> {
+    match datum.as_list().map(|items| &items[..]) {
+        // [else result] is the same as [true result].
+        Some([condition, result]) if condition.as_symbol() == Some("else") => Ok(CondCase {
+            condition: BoolExpr::LiteralBoolExpr(true),
+            result: parse_expr(result)?,
+        }),
+        Some([condition, result]) => Ok(CondCase {
+            condition: parse_bool_expr(condition)?,
+            result: parse_expr(result)?,
+        }),
+        _ => malformed(datum, format!("Malformed cond case at {}: {}", datum.span, datum)),
+    }
 }
 
-fn parse_variable_expr(tokens: &mut PeekNth<TokenIter<'_>>) -> VariableExpr {
+fn parse_variable_expr(datum: &Datum) -> VariableExpr {
     VariableExpr {
-        name: tokens.next().unwrap().text.to_string(),
+        name: datum.as_symbol().unwrap().to_string(),
     }
 }
 
 /*
 (define (add a b) (+ a b))
 */
-fn parse_function_expr(tokens: &mut PeekNth<TokenIter<'_>>) -> FunctionExpr {
-    consume_open_paren(tokens);
-    tokens.next(); // 'define'
-    consume_open_paren(tokens);
-    let function_name = &tokens.next().unwrap().text;
-
-    let mut function_parameters = Vec::new();
-    while tokens.peek().unwrap().kind == TokenKind::Identifier {
-        function_parameters.push(tokens.next().unwrap().text.to_string());
+pub fn parse_function_expr(datum: &Datum) -> ParseResult<FunctionExpr> {
+    let items = datum.as_list().unwrap();
+    if items.len() < 3 {
+        return malformed(
+            datum,
+            format!("Expected a body for the function defined at {}: {}", datum.span, datum),
+        );
     }
 
-    consume_close_paren(tokens);
-
-    let function_body = parse_expr(tokens);
-
-    consume_close_paren(tokens);
+    let signature = &items[1];
+    let (function_name, function_parameters) = match signature.as_list().map(|items| &items[..]) {
+        Some([name, parameters @ ..]) if name.as_symbol().is_some() => (name.as_symbol().unwrap(), parse_parameters(parameters)?),
+        _ => {
+            return malformed(
+                signature,
+                format!("Invalid function signature at {}: {}", signature.span, signature),
+            )
+        }
+    };
 
-    FunctionExpr {
+    Ok(FunctionExpr {
         name: function_name.to_string(),
         parameters: function_parameters,
-        body: Box::new(function_body),
+        body: Box::new(parse_body(function_definition(datum).unwrap().1, datum)?),
+    })
+}
+
+// (define (name params...) body...) gives the name and the body forms. The body does not include a
+// result type annotation, as in (define (name params...) : Type body...).
+pub fn function_definition(datum: &Datum) -> Option<(&str, &[Datum])> {
+    match datum.as_list().map(|items| &items[..]) {
+        Some([define, signature, body @ ..]) if define.as_symbol() == Some("define") => {
+            let body = match body {
+                [colon, _, body @ ..] if colon.as_symbol() == Some(":") => body,
+                _ => body,
+            };
+            signature.as_list()?.first()?.as_symbol().map(|name| (name, body))
+        }
+        _ => None,
     }
 }
 
-// (add 1 2) or (main)
-fn parse_function_call(tokens: &mut PeekNth<TokenIter<'_>>) -> FunctionCallExpr {
-    consume_open_paren(tokens);
+// (define name value) or (define name : Type value) gives the name and the value form.
+pub fn constant_definition(datum: &Datum) -> Option<(&str, &Datum)> {
+    match datum.as_list().map(|items| &items[..]) {
+        Some([define, name, rest @ ..]) if define.as_symbol() == Some("define") && name.as_symbol().is_some() => match rest {
+            [value] => Some((name.as_symbol().unwrap(), value)),
+            [colon, _, value] if colon.as_symbol() == Some(":") => Some((name.as_symbol().unwrap(), value)),
+            // Reported by parse_expr, as definitions of the wrong shape are not taken out of the program.
+            _ => None,
+        },
+        _ => None,
+    }
+}
 
-    let name = &tokens.next().unwrap().text;
+// The type annotation in a definition: the result type of a function or the type of a constant.
+pub fn definition_annotation(datum: &Datum) -> Option<&Datum> {
+    match datum.as_list().map(|items| &items[..]) {
+        Some([define, _, colon, ty, _, ..]) if define.as_symbol() == Some("define") && colon.as_symbol() == Some(":") => Some(ty),
+        _ => None,
+    }
+}
 
-    let mut arguments: Vec<Expr> = Vec::new();
-    while tokens.peek().unwrap().kind != TokenKind::CloseParen {
-        arguments.push(parse_expr(tokens));
+// A parameter `name` or `[name : Type]` gives the name and the type, if there is one.
+pub fn parameter_annotation(parameter: &Datum) -> Option<(&str, Option<&Datum>)> {
+    match parameter.as_list().map(|items| &items[..]) {
+        Some([name, colon, ty]) if colon.as_symbol() == Some(":") => Some((name.as_symbol()?, Some(ty))),
+        None => Some((parameter.as_symbol()?, None)),
+        _ => None,
     }
+}
 
-    tokens.next();
+// (define/contract (name params...) contract body...) gives the equivalent define and the contract.
+pub fn contract_definition(datum: &Datum) -> Option<(Datum, &Datum)> {
+    match datum.as_list().map(|items| &items[..]) {
+        Some([define, signature, contract, body @ ..])
+            if define.as_symbol() == Some("define/contract") && signature.as_list().is_some() =>
+        {
+            let define = Datum::new(DatumKind::Symbol("define".to_string()), define.span);
+            let items = [define, signature.clone()].into_iter().chain(body.iter().cloned()).collect();
+            Some((Datum::new(DatumKind::List(items), datum.span), contract))
+        }
+        _ => None,
+    }
+}
 
-    FunctionCallExpr {
-        name: name.to_string(),
-        arguments,
+// (provide name ... (contract-out [name contract] ...)) gives the names provided with a contract.
+// Every definition is visible to the modules that include it, so plain names have no effect.
+// Malformed provide forms give None, and parse_expr reports them.
+pub fn provided_contracts(datum: &Datum) -> Option<Vec<(&str, &Datum)>> {
+    if head_symbol(datum) != Some("provide") {
+        return None;
     }
+    parse_provide(datum).ok()
 }
 
-fn parse_list_expr(tokens: &mut PeekNth<TokenIter<'_>>) -> ListExpr {
-    match tokens.peek().unwrap().kind {
-        TokenKind::Identifier => ListExpr::VariableExpr(VariableExpr {
-            name: tokens.next().unwrap().text.parse::<String>().unwrap(),
-        }),
-        TokenKind::Empty => {
-            tokens.next();
-            ListExpr::ListLiteralExpr(ListLiteralExpr::Empty)
+fn parse_provide(datum: &Datum) -> ParseResult<Vec<(&str, &Datum)>> {
+    let mut contracts = Vec::new();
+    for spec in &datum.as_list().unwrap()[1..] {
+        match spec.as_list().map(|items| &items[..]) {
+            None if spec.as_symbol().is_some() => {}
+            Some([contract_out, entries @ ..]) if contract_out.as_symbol() == Some("contract-out") => {
+                for entry in entries {
+                    match entry.as_list().map(|items| &items[..]) {
+                        Some([name, contract]) if name.as_symbol().is_some() => {
+                            contracts.push((name.as_symbol().unwrap(), contract))
+                        }
+                        _ => return malformed(entry, format!("Invalid contract-out entry at {}: {}", entry.span, entry)),
+                    }
+                }
+            }
+            _ => return malformed(spec, format!("Invalid provide spec at {}: {}", spec.span, spec)),
         }
-        TokenKind::OpenParen => match tokens.peek_nth(1).unwrap().kind {
-            TokenKind::Identifier => ListExpr::FunctionCallExpr(parse_function_call(tokens)),
-            TokenKind::List => ListExpr::ListLiteralExpr(parse_list_literal_expr(tokens)),
-            TokenKind::Cons => ListExpr::ListLiteralExpr(parse_cons_expr(tokens)),
-            TokenKind::Car => ListExpr::CarExpr(parse_car_expr(tokens)),
-            TokenKind::Cdr => ListExpr::CdrExpr(parse_cdr_expr(tokens)),
-            _ => panic!(
-                "Invalid expression starting with an open parenthesis '(': {}",
-                tokens.peek_nth(1).unwrap()
+    }
+    Ok(contracts)
+}
+
+// (include stdlib::name) gives the name of the included module.
+pub fn include_name(datum: &Datum) -> Option<&str> {
+    match datum.as_list().map(|items| &items[..]) {
+        Some([include, name]) if include.as_symbol() == Some("include") => name.as_symbol(),
+        _ => None,
+    }
+}
+
+// (: name Type) declares the type of a definition.
+pub fn type_declaration(datum: &Datum) -> Option<(&str, &Datum)> {
+    match datum.as_list().map(|items| &items[..]) {
+        Some([colon, name, ty]) if colon.as_symbol() == Some(":") => Some((name.as_symbol()?, ty)),
+        _ => None,
+    }
+}
+
+// Parses constant definitions in the order they have to be evaluated in. `functions` are the bodies
+// of the functions that may be called while the values are computed.
+pub fn parse_definitions(constants: &[(&str, &Datum)], functions: &HashMap<String, &[Datum]>) -> ParseResult<Vec<Definition>> {
+    let named: Vec<(String, &Datum)> = constants.iter().map(|(name, value)| (name.to_string(), *value)).collect();
+
+    initialization_order(&named, functions)?
+        .into_iter()
+        .map(|i| {
+            Ok(Definition {
+                name: named[i].0.clone(),
+                value: parse_expr(named[i].1)?,
+            })
+        })
+        .collect()
+}
+
+// The body of a function or lambda: any number of internal definitions followed by one expression.
+fn parse_body(forms: &[Datum], context: &Datum) -> ParseResult<Expr> {
+    let (result, definitions) = match forms.split_last() {
+        Some((result, definitions)) if function_definition(result).is_none() && constant_definition(result).is_none() => {
+            (result, definitions)
+        }
+        _ => {
+            return malformed(
+                context,
+                format!("Expected the body at {} to end with an expression: {}", context.span, context),
+            )
+        }
+    };
+
+    if definitions.is_empty() {
+        return parse_expr(result);
+    }
+
+    // Internal functions, including constants whose value is a lambda, are bound before the other
+    // definitions and can call each other.
+    let mut local_functions = Vec::new();
+    let mut local_function_bodies = HashMap::new();
+    let mut constants = Vec::new();
+    for definition in definitions {
+        if let Some((name, body)) = function_definition(definition) {
+            let function = parse_function_expr(definition)?;
+            local_functions.push((
+                name.to_string(),
+                LambdaExpr {
+                    free_variables: free_variables(body, &function.parameters),
+                    parameters: function.parameters,
+                    body: Rc::new(*function.body),
+                },
+            ));
+            local_function_bodies.insert(name.to_string(), body);
+        } else if let Some((name, value)) = constant_definition(definition) {
+            if head_symbol(value).is_some_and(|head| head == "lambda" || head == "λ") {
+                local_functions.push((name.to_string(), parse_lambda_expr(value)?));
+                local_function_bodies.insert(name.to_string(), value.as_list().unwrap().get(2..).unwrap_or_default());
+            } else {
+                constants.push((name, value));
+            }
+        } else if type_declaration(definition).is_some() {
+            // Types are only checked statically inside bodies.
+        } else if head_symbol(definition) == Some("define") {
+            return Err(malformed_definition(definition));
+        } else {
+            return malformed(
+                definition,
+                format!(
+                    "Expected only definitions before the last expression of the body at {}: {}",
+                    definition.span, definition
+                ),
+            );
+        }
+    }
+
+    Ok(Expr::BodyExpr(BodyExpr {
+        functions: local_functions,
+        definitions: parse_definitions(&constants, &local_function_bodies)?,
+        result: Box::new(parse_expr(result)?),
+    }))
+}
+
+fn parse_parameters(parameters: &[Datum]) -> ParseResult<Vec<String>> {
+    parameters
+        .iter()
+        .map(|parameter| match parameter_annotation(parameter) {
+            Some((parameter, _)) => Ok(parameter.to_string()),
+            None => malformed(
+                parameter,
+                format!("Invalid function parameter at {}: {}", parameter.span, parameter),
             ),
-        },
-        _ => panic!("Invalid start token to bool expr {}", tokens.peek().unwrap()),
+        })
+        .collect()
+}
+
+// (lambda (x y) (+ x y))
+fn parse_lambda_expr(datum: &Datum) -> ParseResult<LambdaExpr> {
+    let items = datum.as_list().unwrap();
+    if items.len() < 3 {
+        return malformed(
+            datum,
+            format!("Expected parameters and a body for the lambda at {}: {}", datum.span, datum),
+        );
+    }
+
+    let parameters = &items[1];
+    match parameters.as_list() {
+        Some(parameters) => {
+            let parameters = parse_parameters(parameters)?;
+            Ok(LambdaExpr {
+                free_variables: free_variables(&items[2..], &parameters),
+                parameters,
+                body: Rc::new(parse_body(&items[2..], datum)?),
+            })
+        }
+        None => malformed(
+            parameters,
+            format!("Invalid lambda parameters at {}: {}", parameters.span, parameters),
+        ),
     }
 }
 
-fn parse_list_literal_expr(tokens: &mut PeekNth<TokenIter<'_>>) -> ListLiteralExpr {
-    consume_open_paren(tokens);
-    assert_eq!(tokens.next().unwrap().kind, TokenKind::List);
+// The variables a lambda body can refer to other than its parameters, which are the only ones its
+// closure needs to capture. Names bound inside the body are included as well, which only costs a
+// capture that is never used.
+fn free_variables(body: &[Datum], parameters: &[String]) -> Vec<String> {
+    let mut symbols = HashSet::new();
+    body.iter().for_each(|form| collect_symbols(form, &mut symbols));
+    let mut free_variables: Vec<String> = symbols.into_iter().filter(|symbol| !parameters.contains(symbol)).collect();
+    free_variables.sort();
+    free_variables
+}
 
-    let mut list_items = Vec::new();
+// Every symbol the datum could refer to. Quoted data is skipped.
+pub fn collect_symbols(datum: &Datum, symbols: &mut HashSet<String>) {
+    match &datum.kind {
+        DatumKind::Symbol(s) => {
+            symbols.insert(s.clone());
+        }
+        DatumKind::List(items) if head_symbol(datum) != Some("quote") => {
+            items.iter().for_each(|item| collect_symbols(item, symbols));
+        }
+        _ => {}
+    }
+}
 
-    while tokens.peek().unwrap().kind != TokenKind::CloseParen {
-        list_items.push(parse_expr(tokens));
+// (with-handlers ([exn:fail? (lambda (e) 0)]) (/ 1 0))
+fn parse_with_handlers_expr(datum: &Datum) -> ParseResult<WithHandlersExpr> {
+    let items = datum.as_list().unwrap();
+    let clauses = match items.get(1).and_then(|clauses| clauses.as_list()) {
+        Some(clauses) if items.len() >= 3 => clauses,
+        _ => {
+            return malformed(
+                datum,
+                format!("Expected handlers and a body for with-handlers at {}: {}", datum.span, datum),
+            )
+        }
+    };
+
+    let handlers = clauses
+        .iter()
+        .map(|clause| match clause.as_list().map(|parts| &parts[..]) {
+            Some([predicate, handler]) => Ok((parse_expr(predicate)?, parse_expr(handler)?)),
+            _ => malformed(
+                clause,
+                format!("Malformed with-handlers clause at {}: {}", clause.span, clause),
+            ),
+        })
+        .collect::<ParseResult<_>>()?;
+
+    Ok(WithHandlersExpr {
+        handlers,
+        body: Box::new(parse_body(&items[2..], datum)?),
+    })
+}
+
+// (add 1 2) or (main) or (sort lst < #:key car)
+fn parse_function_call(datum: &Datum) -> ParseResult<FunctionCallExpr> {
+    let items = datum.as_list().unwrap();
+
+    let mut arguments = Vec::new();
+    let mut keyword_arguments = Vec::new();
+    let mut rest = items[1..].iter();
+    while let Some(argument) = rest.next() {
+        match &argument.kind {
+            DatumKind::Keyword(keyword) => match rest.next() {
+                Some(value) => keyword_arguments.push((keyword.clone(), parse_expr(value)?)),
+                None => {
+                    return malformed(
+                        argument,
+                        format!("Missing value for keyword argument at {}: {}", argument.span, argument),
+                    )
+                }
+            },
+            _ => arguments.push(parse_expr(argument)?),
+        }
     }
 
-    consume_close_paren(tokens);
+    Ok(FunctionCallExpr {
+        name: head_symbol(datum).unwrap().to_string(),
+        arguments,
+        keyword_arguments,
+        span: datum.span,
+    })
+}
+
+// ((make-adder 1) 2)
+fn parse_application_expr(datum: &Datum) -> ParseResult<ApplicationExpr> {
+    let items = datum.as_list().unwrap();
+
+    Ok(ApplicationExpr {
+        function: Box::new(parse_expr(&items[0])?),
+        arguments: items[1..].iter().map(parse_expr).collect::<ParseResult<_>>()?,
+        span: datum.span,
+    })
+}
+
+fn parse_list_expr(datum: &Datum) -> ParseResult<ListExpr> {
+    Ok(match &datum.kind {
+        DatumKind::Symbol(s) if s == "empty" || s == "null" => ListExpr::ListLiteralExpr(ListLiteralExpr::Empty),
+        DatumKind::Symbol(s) if !is_keyword(s) => ListExpr::VariableExpr(parse_variable_expr(datum)),
+        DatumKind::List(_) if is_function_call(datum) => ListExpr::FunctionCallExpr(parse_function_call(datum)?),
+        DatumKind::List(_) => match head_symbol(datum) {
+            Some("list") => ListExpr::ListLiteralExpr(parse_list_literal_expr(datum)?),
+            Some("cons") => ListExpr::ListLiteralExpr(parse_cons_expr(datum)?),
+            Some("car") => ListExpr::CarExpr(parse_car_expr(datum)?),
+            Some("cdr") => ListExpr::CdrExpr(parse_cdr_expr(datum)?),
+            _ => ListExpr::DynamicExpr(Box::new(parse_expr(datum)?)),
+        },
+        _ => ListExpr::DynamicExpr(Box::new(parse_expr(datum)?)),
+    })
+}
+
+fn parse_list_literal_expr(datum: &Datum) -> ParseResult<ListLiteralExpr> {
+    let list_items: Vec<Expr> = datum.as_list().unwrap()[1..]
+        .iter()
+        .map(parse_expr)
+        .collect::<ParseResult<_>>()?;
 
     // Turn vec of list items to a `List`
     let mut result = ListLiteralExpr::Empty;
@@ -285,109 +685,141 @@ fn parse_list_literal_expr(tokens: &mut PeekNth<TokenIter<'_>>) -> ListLiteralEx
         });
     }
 
-    result
+    Ok(result)
 }
 
 // (cons 1 (cons 2 empty)) or (cons 1 empty)
-fn parse_cons_expr(tokens: &mut PeekNth<TokenIter<'_>>) -> ListLiteralExpr {
-    let first_token = tokens.next().unwrap();
-
-    if let TokenKind::Empty = first_token.kind {
-        ListLiteralExpr::Empty
-    } else {
-        let next = tokens.next().unwrap();
-        assert_eq!(next.kind, TokenKind::Cons, "{:#?}", next);
-
-        let first = parse_expr(tokens);
-        let rest = parse_list_expr(tokens);
-
-        consume_close_paren(tokens);
+fn parse_cons_expr(datum: &Datum) -> ParseResult<ListLiteralExpr> {
+    let [first, rest] = parse_operands(datum)?;
 
-        ListLiteralExpr::Node(Node {
-            data: Box::new(first),
-            next: Box::new(rest),
-        })
-    }
+    Ok(ListLiteralExpr::Node(Node {
+        data: Box::new(parse_expr(first)?),
+        next: Box::new(parse_list_expr(rest)?),
+    }))
 }
 
 // (car <some list>)
-fn parse_car_expr(tokens: &mut PeekNth<TokenIter<'_>>) -> CarExpr {
-    consume_open_paren(tokens);
-    assert_eq!(tokens.next().unwrap().kind, TokenKind::Car);
-
-    let list = parse_expr(tokens);
+fn parse_car_expr(datum: &Datum) -> ParseResult<CarExpr> {
+    let [list] = parse_operands(datum)?;
 
-    consume_close_paren(tokens);
-
-    match list {
-        Expr::ListExpr(x) | Expr::EmptyExpr(x) => CarExpr { list: Box::new(x) },
-        Expr::VariableExpr(x) => CarExpr {
-            list: Box::new(ListExpr::VariableExpr(x)),
-        },
-        _ => panic!("Malformed car expr: argument is not a list"),
-    }
+    Ok(CarExpr {
+        list: Box::new(parse_list_expr(list)?),
+    })
 }
 
 // (cdr <some list>)
-fn parse_cdr_expr(tokens: &mut PeekNth<TokenIter<'_>>) -> CdrExpr {
-    consume_open_paren(tokens);
-    assert_eq!(tokens.next().unwrap().kind, TokenKind::Cdr);
-
-    let list = parse_list_expr(tokens);
+fn parse_cdr_expr(datum: &Datum) -> ParseResult<CdrExpr> {
+    let [list] = parse_operands(datum)?;
 
-    consume_close_paren(tokens);
-
-    CdrExpr { list: Box::new(list) }
+    Ok(CdrExpr {
+        list: Box::new(parse_list_expr(list)?),
+    })
 }
 
 // (empty? <some list>)
-fn parse_empty_huh_expr(tokens: &mut PeekNth<TokenIter<'_>>) -> EmptyHuhExpr {
-    consume_open_paren(tokens);
-    assert_eq!(tokens.next().unwrap().kind, TokenKind::EmptyHuh);
+fn parse_empty_huh_expr(datum: &Datum) -> ParseResult<EmptyHuhExpr> {
+    let [list] = parse_operands(datum)?;
 
-    let list = parse_expr(tokens);
+    Ok(EmptyHuhExpr {
+        list: Box::new(parse_list_expr(list)?),
+    })
+}
 
-    consume_close_paren(tokens);
+// (list? expr)
+fn parse_list_huh_expr(datum: &Datum) -> ParseResult<ListHuhExpr> {
+    let [expr] = parse_operands(datum)?;
 
-    match list {
-        Expr::ListExpr(x) | Expr::EmptyExpr(x) => EmptyHuhExpr { list: Box::new(x) },
-        Expr::VariableExpr(x) => EmptyHuhExpr {
-            list: Box::new(ListExpr::VariableExpr(x)),
-        },
-        x => panic!("Malformed empty? expr: argument is not a list: {:#?}", x),
-    }
+    Ok(ListHuhExpr {
+        expr: Box::new(parse_expr(expr)?),
+    })
 }
 
-// (list? expr)
-fn parse_list_huh_expr(tokens: &mut PeekNth<TokenIter<'_>>) -> ListHuhExpr {
-    consume_open_paren(tokens);
-    assert_eq!(tokens.next().unwrap().kind, TokenKind::ListHuh);
+// (quote datum) or 'datum
+fn parse_quote_expr(datum: &Datum) -> ParseResult<QuoteExpr> {
+    let [quoted] = parse_operands(datum)?;
 
-    let expr = parse_expr(tokens);
+    Ok(QuoteExpr {
+        value: datum_to_value(quoted)?,
+    })
+}
 
-    consume_close_paren(tokens);
+// (quasiquote datum) or `datum
+fn parse_quasiquote_expr(datum: &Datum) -> ParseResult<QuasiquoteExpr> {
+    let [template] = parse_operands(datum)?;
 
-    ListHuhExpr { expr: Box::new(expr) }
+    parse_quasiquote_template(template, 0)
 }
 
-fn consume_open_paren(tokens: &mut PeekNth<TokenIter<'_>>) {
-    let next_token = tokens.next().unwrap();
-    assert_eq!(
-        next_token.kind,
-        TokenKind::OpenParen,
-        "Open paren not found. Got {} instead. Rest of tokens are: {}",
-        next_token.text,
-        tokens.map(|token| format!("{}", token)).collect::<Vec<_>>().join(", ")
-    );
+// `depth` counts how many quasiquotes the template is nested in beyond the outermost one. Only
+// unquotes at depth 0 are evaluated, deeper ones are kept as data.
+fn parse_quasiquote_template(datum: &Datum, depth: usize) -> ParseResult<QuasiquoteExpr> {
+    let items = match datum.as_list() {
+        Some(items) => items,
+        None => return Ok(QuasiquoteExpr::Literal(datum_to_value(datum)?)),
+    };
+
+    match head_symbol(datum) {
+        Some("unquote") if depth == 0 => {
+            let [expr] = parse_operands(datum)?;
+            return Ok(QuasiquoteExpr::Unquote(Box::new(parse_expr(expr)?)));
+        }
+        Some("unquote-splicing") if depth == 0 => {
+            return malformed(
+                datum,
+                format!("unquote-splicing is only allowed inside a list at {}: {}", datum.span, datum),
+            )
+        }
+        _ => {}
+    }
+
+    let item_depth = match head_symbol(datum) {
+        Some("quasiquote") => depth + 1,
+        Some("unquote" | "unquote-splicing") => depth - 1,
+        _ => depth,
+    };
+
+    let template_items: Vec<QuasiquoteItem> = items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| match head_symbol(item) {
+            Some("unquote-splicing") if depth == 0 => {
+                let [expr] = parse_operands(item)?;
+                Ok(QuasiquoteItem::Splice(parse_expr(expr)?))
+            }
+            // The head of a nested (quasiquote x) or (unquote x) is kept at the current depth.
+            _ if i == 0 => Ok(QuasiquoteItem::Item(parse_quasiquote_template(item, depth)?)),
+            _ => Ok(QuasiquoteItem::Item(parse_quasiquote_template(item, item_depth)?)),
+        })
+        .collect::<ParseResult<_>>()?;
+
+    // Templates without any unquotes are just quoted data.
+    if template_items
+        .iter()
+        .all(|item| matches!(item, QuasiquoteItem::Item(QuasiquoteExpr::Literal(_))))
+    {
+        return Ok(QuasiquoteExpr::Literal(datum_to_value(datum)?));
+    }
+
+    Ok(QuasiquoteExpr::List(template_items))
 }
 
-fn consume_close_paren(tokens: &mut PeekNth<TokenIter<'_>>) {
-    let next_token = tokens.next().unwrap();
-    assert_eq!(
-        next_token.kind,
-        TokenKind::CloseParen,
-        "Close paren not found. Got {} instead. Rest of tokens are: {}",
-        next_token.text,
-        tokens.map(|token| format!("{}", token)).collect::<Vec<_>>().join(", ")
-    );
+// The operands of a built in form such as `(+ a b)`, checking that there are exactly `COUNT` of them.
+fn parse_operands<const COUNT: usize>(datum: &Datum) -> ParseResult<[&Datum; COUNT]> {
+    let items = datum.as_list().unwrap();
+
+    if items.len() != COUNT + 1 {
+        return malformed(
+            datum,
+            format!(
+                "Expected {} operand(s) to {} at {} but found {}: {}",
+                COUNT,
+                head_symbol(datum).unwrap(),
+                datum.span,
+                items.len() - 1,
+                datum
+            ),
+        );
+    }
+
+    Ok(std::array::from_fn(|i| &items[i + 1]))
 }
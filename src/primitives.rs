@@ -0,0 +1,352 @@
+use std::rc::Rc;
+
+use crate::error::{EvalResult, RacketError};
+use crate::interpret::{FunctionMap, Value, ValueList, ValueNode, VariableMap, N};
+use crate::interpret_char::*;
+use crate::interpret_function_call::apply_function;
+use crate::interpret_hash::*;
+use crate::interpret_list::*;
+use crate::interpret_num::{apply_num_op, BinaryNumOp};
+use crate::interpret_quote::datum_to_value;
+use crate::interpret_vector::*;
+use crate::limits;
+use crate::reader::read;
+use crate::symbol_table::Symbol;
+
+// Functions that are built into the interpreter. They are called exactly like user defined
+// functions, but a user defined function with the same name takes precedence.
+const PRIMITIVES: &[&str] = &[
+    // Built in operations, so that they can also be passed around as functions.
+    "+",
+    "-",
+    "*",
+    "/",
+    "%",
+    "<",
+    "=",
+    ">",
+    "&",
+    "|",
+    "!",
+    "car",
+    "cdr",
+    "cons",
+    "list",
+    "empty?",
+    "list?",
+    "null?",
+    "cons?",
+    "pair?",
+    "first",
+    "second",
+    "third",
+    "rest",
+    "last",
+    // Data.
+    "read",
+    "symbol?",
+    "symbol->string",
+    "string->symbol",
+    "symbol=?",
+    "equal?",
+    "procedure?",
+    "number?",
+    "integer?",
+    "string?",
+    "boolean?",
+    // Higher order list functions.
+    "map",
+    "filter",
+    "foldl",
+    "foldr",
+    "andmap",
+    "ormap",
+    "sort",
+    "build-list",
+    "assoc",
+    "member",
+    "remove",
+    // Hash tables.
+    "hash",
+    "hash?",
+    "hash-ref",
+    "hash-set",
+    "hash-remove",
+    "hash-keys",
+    "hash-count",
+    "hash-has-key?",
+    // Vectors.
+    "vector",
+    "vector?",
+    "make-vector",
+    "vector-ref",
+    "vector-length",
+    "vector-set",
+    "vector->list",
+    "list->vector",
+    // Characters and strings.
+    "char?",
+    "char->integer",
+    "integer->char",
+    "char-alphabetic?",
+    "char-numeric?",
+    "char-whitespace?",
+    "char-upcase",
+    "char-downcase",
+    "string->list",
+    "list->string",
+    // Exceptions.
+    "raise",
+    "error",
+    "exn-message",
+];
+
+// Predicates for the kinds of exceptions raised by built in operations.
+const EXN_PREDICATES: &[&str] = &[
+    "exn?",
+    "exn:fail?",
+    "exn:fail:contract?",
+    "exn:fail:contract:divide-by-zero?",
+    "exn:fail:contract:variable?",
+    "exn:fail:read?",
+    "exn:fail:syntax?",
+];
+
+pub fn is_primitive(name: &str) -> bool {
+    PRIMITIVES.contains(&name) || EXN_PREDICATES.contains(&name) || is_cxr(name)
+}
+
+// The names of the built in functions, apart from the c[ad]+r functions.
+pub fn primitive_names() -> impl Iterator<Item = &'static str> {
+    PRIMITIVES.iter().chain(EXN_PREDICATES).copied()
+}
+
+// caar, cadr, cdar, cddr and so on, up to four levels deep.
+fn is_cxr(name: &str) -> bool {
+    name.strip_prefix('c')
+        .and_then(|name| name.strip_suffix('r'))
+        .is_some_and(|path| (2..=4).contains(&path.len()) && path.chars().all(|c| c == 'a' || c == 'd'))
+}
+
+pub fn apply_primitive(
+    name: &str,
+    arguments: Vec<Value>,
+    keyword_arguments: &[(String, Value)],
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult {
+    if let Some((keyword, _)) = keyword_arguments
+        .iter()
+        .find(|(keyword, _)| !(name == "sort" && keyword == "key"))
+    {
+        return Err(RacketError::contract(format!(
+            "Function {} does not accept the keyword argument #:{}",
+            name, keyword
+        )));
+    }
+
+    match (name, &arguments[..]) {
+        ("+", _) => num_arguments(name, &arguments)?
+            .iter()
+            .try_fold(0, |sum, n| apply_num_op(&BinaryNumOp::Add, sum, *n))
+            .map(Value::NumValue),
+        ("*", _) => num_arguments(name, &arguments)?
+            .iter()
+            .try_fold(1, |product, n| apply_num_op(&BinaryNumOp::Mul, product, *n))
+            .map(Value::NumValue),
+        ("-", [Value::NumValue(a), Value::NumValue(b)]) => apply_num_op(&BinaryNumOp::Sub, *a, *b).map(Value::NumValue),
+        ("/", [Value::NumValue(a), Value::NumValue(b)]) => apply_num_op(&BinaryNumOp::Div, *a, *b).map(Value::NumValue),
+        ("%", [Value::NumValue(a), Value::NumValue(b)]) => apply_num_op(&BinaryNumOp::Mod, *a, *b).map(Value::NumValue),
+        ("<", [Value::NumValue(a), Value::NumValue(b)]) => Ok(Value::BoolValue(a < b)),
+        ("=", [Value::NumValue(a), Value::NumValue(b)]) => Ok(Value::BoolValue(a == b)),
+        (">", [Value::NumValue(a), Value::NumValue(b)]) => Ok(Value::BoolValue(a > b)),
+        ("&", [Value::BoolValue(a), Value::BoolValue(b)]) => Ok(Value::BoolValue(*a && *b)),
+        ("|", [Value::BoolValue(a), Value::BoolValue(b)]) => Ok(Value::BoolValue(*a || *b)),
+        ("!", [Value::BoolValue(a)]) => Ok(Value::BoolValue(!a)),
+        ("car", [Value::ListValue(ValueList::Node(node))]) => Ok(*node.data.clone()),
+        ("cdr", [Value::ListValue(ValueList::Node(node))]) => Ok(Value::ListValue(*node.next.clone())),
+        ("cons", [x, Value::ListValue(list)]) => {
+            limits::allocate(1)?;
+            Ok(Value::ListValue(ValueList::Node(ValueNode {
+                data: Box::new(x.clone()),
+                next: Box::new(list.clone()),
+            })))
+        }
+        ("list", _) => Ok(Value::ListValue(ValueList::from_vec(arguments))),
+        ("empty?", [Value::ListValue(list)]) => Ok(Value::BoolValue(*list == ValueList::Empty)),
+        ("list?", [x]) => Ok(Value::BoolValue(matches!(x, Value::ListValue(_)))),
+        ("null?", [x]) => Ok(Value::BoolValue(*x == Value::ListValue(ValueList::Empty))),
+        ("cons?" | "pair?", [x]) => Ok(Value::BoolValue(matches!(x, Value::ListValue(ValueList::Node(_))))),
+        ("first", [list]) => list_nth_element(name, list, 0),
+        ("second", [list]) => list_nth_element(name, list, 1),
+        ("third", [list]) => list_nth_element(name, list, 2),
+        ("rest", [list]) => list_rest(list),
+        ("last", [list]) => list_last(list),
+        (_, [x]) if is_cxr(name) => list_cxr(name, x),
+        ("read", [Value::StringValue(s)]) => Ok(datum_to_value(&read(s.clone())?)?),
+        ("symbol?", [x]) => Ok(Value::BoolValue(matches!(x, Value::SymbolValue(_)))),
+        ("symbol->string", [Value::SymbolValue(s)]) => Ok(Value::StringValue(s.name())),
+        ("string->symbol", [Value::StringValue(s)]) => Ok(Value::SymbolValue(Symbol::intern(s))),
+        ("symbol=?", [Value::SymbolValue(a), Value::SymbolValue(b)]) => Ok(Value::BoolValue(a == b)),
+        ("equal?", [a, b]) => Ok(Value::BoolValue(a == b)),
+        ("procedure?", [x]) => Ok(Value::BoolValue(matches!(x, Value::FunctionValue(_)))),
+        ("number?" | "integer?", [x]) => Ok(Value::BoolValue(matches!(x, Value::NumValue(_)))),
+        ("string?", [x]) => Ok(Value::BoolValue(matches!(x, Value::StringValue(_)))),
+        ("boolean?", [x]) => Ok(Value::BoolValue(matches!(x, Value::BoolValue(_)))),
+        ("map", [f, lists @ ..]) if !lists.is_empty() => list_map(f, lists, variable_map, function_map),
+        ("filter", [f, list]) => list_filter(f, list, variable_map, function_map),
+        ("foldl", [f, init, lists @ ..]) if !lists.is_empty() => list_foldl(f, init, lists, variable_map, function_map),
+        ("foldr", [f, init, lists @ ..]) if !lists.is_empty() => list_foldr(f, init, lists, variable_map, function_map),
+        ("andmap", [f, lists @ ..]) if !lists.is_empty() => list_andmap(f, lists, variable_map, function_map),
+        ("ormap", [f, lists @ ..]) if !lists.is_empty() => list_ormap(f, lists, variable_map, function_map),
+        ("sort", [list, less_than]) => {
+            let key = keyword_arguments
+                .iter()
+                .find(|(keyword, _)| keyword == "key")
+                .map(|(_, key)| key);
+            list_sort(list, less_than, key, variable_map, function_map)
+        }
+        ("build-list", [Value::NumValue(n), f]) if *n >= 0 => list_build(*n, f, variable_map, function_map),
+        ("assoc", [x, list]) => list_assoc(x, list, None, variable_map, function_map),
+        ("assoc", [x, list, is_equal]) => list_assoc(x, list, Some(is_equal), variable_map, function_map),
+        ("member", [x, list]) => list_member(x, list, None, variable_map, function_map),
+        ("member", [x, list, is_equal]) => list_member(x, list, Some(is_equal), variable_map, function_map),
+        ("remove", [x, list]) => list_remove(x, list, None, variable_map, function_map),
+        ("remove", [x, list, is_equal]) => list_remove(x, list, Some(is_equal), variable_map, function_map),
+        ("hash", _) => hash_new(&arguments),
+        ("hash?", [x]) => Ok(Value::BoolValue(matches!(x, Value::HashValue(_)))),
+        ("hash-ref", [hash, key]) => hash_ref(hash, key, None, variable_map, function_map),
+        ("hash-ref", [hash, key, default]) => hash_ref(hash, key, Some(default), variable_map, function_map),
+        ("hash-set", [hash, key, value]) => hash_set(hash, key, value),
+        ("hash-remove", [hash, key]) => hash_remove(hash, key),
+        ("hash-keys", [hash]) => hash_keys(hash),
+        ("hash-count", [hash]) => hash_count(hash),
+        ("hash-has-key?", [hash, key]) => hash_has_key(hash, key),
+        ("vector", _) => {
+            limits::allocate(arguments.len())?;
+            Ok(Value::VectorValue(Rc::new(arguments.clone())))
+        }
+        ("vector?", [x]) => Ok(Value::BoolValue(matches!(x, Value::VectorValue(_)))),
+        ("make-vector", [Value::NumValue(n)]) => vector_make(*n, &Value::NumValue(0)),
+        ("make-vector", [Value::NumValue(n), fill]) => vector_make(*n, fill),
+        ("vector-ref", [vector, Value::NumValue(i)]) => vector_ref(vector, *i),
+        ("vector-length", [vector]) => vector_length(vector),
+        ("vector-set", [vector, Value::NumValue(i), x]) => vector_set(vector, *i, x),
+        ("vector->list", [vector]) => vector_to_list(vector),
+        ("list->vector", [Value::ListValue(list)]) => {
+            let elements = list.to_vec();
+            limits::allocate(elements.len())?;
+            Ok(Value::VectorValue(Rc::new(elements)))
+        }
+        ("char?", [x]) => Ok(Value::BoolValue(matches!(x, Value::CharValue(_)))),
+        ("char->integer", [Value::CharValue(c)]) => Ok(Value::NumValue(*c as N)),
+        ("integer->char", [Value::NumValue(n)]) => integer_to_char(*n),
+        ("char-alphabetic?", [Value::CharValue(c)]) => Ok(Value::BoolValue(c.is_alphabetic())),
+        ("char-numeric?", [Value::CharValue(c)]) => Ok(Value::BoolValue(c.is_numeric())),
+        ("char-whitespace?", [Value::CharValue(c)]) => Ok(Value::BoolValue(c.is_whitespace())),
+        ("char-upcase", [Value::CharValue(c)]) => Ok(Value::CharValue(char_upcase(*c))),
+        ("char-downcase", [Value::CharValue(c)]) => Ok(Value::CharValue(char_downcase(*c))),
+        ("string->list", [Value::StringValue(s)]) => Ok(string_to_list(s)),
+        ("list->string", [Value::ListValue(list)]) => list_to_string(list),
+        ("raise", [x]) => Err(RacketError::raise(x.clone())),
+        ("error", [message, rest @ ..]) => Err(error_from_arguments(message, rest)?),
+        ("exn-message", [Value::ExnValue(exn)]) => Ok(Value::StringValue(exn.message.clone())),
+        (_, [x]) if EXN_PREDICATES.contains(&name) => Ok(Value::BoolValue(match x {
+            Value::ExnValue(exn) => exn.is_a(name.strip_suffix('?').unwrap()),
+            _ => false,
+        })),
+        _ => Err(RacketError::contract(format!(
+            "Invalid arguments to primitive {}: {}",
+            name,
+            arguments
+                .iter()
+                .map(|argument| argument.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+fn num_arguments(name: &str, arguments: &[Value]) -> EvalResult<Vec<N>> {
+    arguments
+        .iter()
+        .map(|argument| match argument {
+            Value::NumValue(n) => Ok(*n),
+            x => Err(RacketError::contract(format!(
+                "Expected number arguments to {} but got {}",
+                name, x
+            ))),
+        })
+        .collect()
+}
+
+// (error 'name "format string ~a" values...) or (error "message" values...) or (error 'name).
+fn error_from_arguments(message: &Value, rest: &[Value]) -> EvalResult<RacketError> {
+    let message = match (message, rest) {
+        (Value::SymbolValue(name), []) => name.name(),
+        (Value::SymbolValue(name), [Value::StringValue(format), values @ ..]) => {
+            format!("{}: {}", name, format_message(format, values)?)
+        }
+        (Value::StringValue(message), values) => std::iter::once(message.clone())
+            .chain(values.iter().map(|value| value.to_string()))
+            .collect::<Vec<_>>()
+            .join(" "),
+        _ => {
+            return Err(RacketError::contract(format!(
+                "Expected error to be given a symbol or a message but got {}",
+                message
+            )))
+        }
+    };
+
+    Ok(RacketError::fail(message))
+}
+
+// Replaces each ~a, ~s or ~v in the format string with the next value, and ~~ with ~.
+fn format_message(format: &str, values: &[Value]) -> EvalResult<String> {
+    let mut result = String::new();
+    let mut values = values.iter();
+    let mut chars = format.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '~' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('~') => result.push('~'),
+            Some(directive @ ('a' | 's' | 'v')) => match (directive, values.next()) {
+                ('a', Some(Value::StringValue(s))) => result.push_str(s),
+                ('a', Some(Value::SymbolValue(s))) => result.push_str(&s.name()),
+                (_, Some(value)) => result.push_str(&value.to_string()),
+                (_, None) => {
+                    return Err(RacketError::contract(format!(
+                        "Format string {:?} expects more values than were given",
+                        format
+                    )))
+                }
+            },
+            _ => return Err(RacketError::contract(format!("Invalid format string {:?}", format))),
+        }
+    }
+
+    Ok(result)
+}
+
+// Calls a function value from inside a primitive.
+pub fn call_function_value(
+    function: &Value,
+    arguments: Vec<Value>,
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult {
+    match function {
+        Value::FunctionValue(function) => apply_function(function, arguments, &[], variable_map, function_map),
+        x => Err(RacketError::contract(format!("Expected a function but got {}", x))),
+    }
+}
+
+// Every value other than false counts as true, as in Racket.
+pub fn is_truthy(value: &Value) -> bool {
+    *value != Value::BoolValue(false)
+}
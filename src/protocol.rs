@@ -0,0 +1,45 @@
+use std::io::{self, BufRead, Write};
+
+use serde_json::Value as Json;
+
+// The debug adapter and language servers exchange JSON messages, each preceded by a Content-Length
+// header. Returns the next message, or None once the client has gone away. A message without a valid
+// Content-Length, or whose content is not JSON, is skipped so that one bad message does not end the
+// session.
+pub fn read_message(input: &mut dyn BufRead) -> Option<Json> {
+    loop {
+        let Some(length) = read_headers(input)? else {
+            continue;
+        };
+        let mut content = vec![0; length];
+        input.read_exact(&mut content).ok()?;
+        if let Ok(message) = serde_json::from_slice(&content) {
+            return Some(message);
+        }
+    }
+}
+
+// Reads the headers of a message up to the empty line after them, giving the Content-Length if there
+// is a valid one. Returns None at the end of the input.
+fn read_headers(input: &mut dyn BufRead) -> Option<Option<usize>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            return Some(length);
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+}
+
+pub fn write_message(output: &mut dyn Write, message: &Json) -> io::Result<()> {
+    let content = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", content.len(), content)?;
+    output.flush()
+}
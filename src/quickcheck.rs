@@ -0,0 +1,296 @@
+use crate::error::{EvalResult, RacketError};
+use crate::interpret::{interpret, Expr, FunctionMap, Value, ValueList, VariableMap};
+use crate::interpret_struct::StructValue;
+
+// Property based testing for check-property. A property is checked with values made by generators,
+// which are the quickcheck::generator structs built by the functions of stdlib::quickcheck. Values
+// come from a seeded random number generator, so a property sees the same values on every run, and
+// values that falsify it are shrunk until no smaller value does.
+
+pub const DEFAULT_SEED: u64 = 0;
+pub const DEFAULT_TRIALS: usize = 100;
+
+// Values grow with the number of the trial up to this size, so that small values are tried first.
+// Numbers stay within it and lists are no longer, when their ranges allow, which keeps recursive
+// functions well within the stack.
+const MAX_SIZE: i32 = 30;
+// Shrinking stops after this many steps, even if smaller values might still falsify the property.
+const MAX_SHRINKS: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Property {
+    // Each name is bound to values made by the generator its expression evaluates to.
+    pub bindings: Vec<(String, Expr)>,
+    pub seed: u64,
+    pub trials: usize,
+}
+
+// Values for which the property returned false or raised an exception, after shrinking.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Counterexample {
+    pub values: Vec<(String, Value)>,
+    // The exception the property raised for the values, if it raised one.
+    pub error: Option<RacketError>,
+    // How many trials ran before the property was falsified, and how many times the values shrank.
+    pub trials: usize,
+    pub shrinks: usize,
+    pub seed: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Generator {
+    Number { low: i32, high: i32 },
+    Boolean,
+    List { element: Box<Generator>, low: i32, high: i32 },
+    // Lists whose items are elements or nested lists.
+    NestedList { element: Box<Generator>, low: i32, high: i32 },
+}
+
+// Checks that the body holds for the property, returning the smallest counterexample found, or None
+// if it held for every trial. Errors that stop the check, such as a generator expression that does
+// not give a generator or a property that returns something other than a boolean, are returned as
+// errors.
+pub fn check_property(
+    property: &Property,
+    body: &Expr,
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult<Option<Counterexample>> {
+    let mut generators = Vec::new();
+    for (_, expr) in &property.bindings {
+        generators.push(generator(&interpret(expr, variable_map, function_map)?)?);
+    }
+    let mut run = |values: &[Value]| falsifies(property, body, values, variable_map, function_map);
+
+    let mut random = Random(property.seed);
+    for trial in 0..property.trials {
+        let size = (trial as i32).min(MAX_SIZE);
+        let mut values: Vec<Value> = generators
+            .iter()
+            .map(|generator| generate(generator, &mut random, size))
+            .collect();
+        let Some(mut error) = run(&values)? else {
+            continue;
+        };
+
+        let mut shrinks = 0;
+        'shrinking: while shrinks < MAX_SHRINKS {
+            for (i, generator) in generators.iter().enumerate() {
+                for candidate in shrink(generator, &values[i]) {
+                    let mut smaller = values.clone();
+                    smaller[i] = candidate;
+                    if let Some(smaller_error) = run(&smaller)? {
+                        values = smaller;
+                        error = smaller_error;
+                        shrinks += 1;
+                        continue 'shrinking;
+                    }
+                }
+            }
+            break;
+        }
+
+        let names = property.bindings.iter().map(|(name, _)| name.clone());
+        return Ok(Some(Counterexample {
+            values: names.zip(values).collect(),
+            error,
+            trials: trial + 1,
+            shrinks,
+            seed: property.seed,
+        }));
+    }
+    Ok(None)
+}
+
+// Runs the body with the names of the property bound to the values. Returns None if it holds, and
+// otherwise Some with the exception it raised, if any.
+fn falsifies(
+    property: &Property,
+    body: &Expr,
+    values: &[Value],
+    variable_map: &mut VariableMap,
+    function_map: &FunctionMap,
+) -> EvalResult<Option<Option<RacketError>>> {
+    for ((name, _), value) in property.bindings.iter().zip(values) {
+        variable_map.entry(name.clone()).or_default().push(value.clone());
+    }
+    let result = interpret(body, variable_map, function_map);
+    for (name, _) in &property.bindings {
+        variable_map.get_mut(name).unwrap().pop();
+    }
+
+    match result {
+        Ok(Value::BoolValue(holds)) => Ok((!holds).then_some(None)),
+        Ok(value) => Err(RacketError::contract(format!(
+            "check-property expects the property to return a boolean but it returned {}",
+            value
+        ))),
+        // Running out of a limit is not a property of the values, so it stops the check.
+        Err(error @ RacketError::LimitExceeded(..)) => Err(error),
+        Err(error) => Ok(Some(Some(error))),
+    }
+}
+
+fn generator(value: &Value) -> EvalResult<Generator> {
+    let invalid = || {
+        RacketError::contract(format!(
+            "check-property expects a quickcheck::generator but was given {}",
+            value
+        ))
+    };
+    let Value::StructValue(StructValue { name, fields }) = value else {
+        return Err(invalid());
+    };
+    if name != "quickcheck::generator" || fields.len() != 4 {
+        return Err(invalid());
+    }
+    let number = |value: &Value| match value {
+        Value::NumValue(n) => Ok(*n),
+        _ => Err(invalid()),
+    };
+    let (low, high) = (number(&fields[1])?, number(&fields[2])?);
+    if low > high {
+        return Err(invalid());
+    }
+    let Value::SymbolValue(kind) = &fields[0] else {
+        return Err(invalid());
+    };
+    Ok(match kind.name().as_str() {
+        "number" => Generator::Number { low, high },
+        "boolean" => Generator::Boolean,
+        "list" => Generator::List {
+            element: Box::new(generator(&fields[3])?),
+            low,
+            high,
+        },
+        "nested-list" => Generator::NestedList {
+            element: Box::new(generator(&fields[3])?),
+            low,
+            high,
+        },
+        _ => return Err(invalid()),
+    })
+}
+
+// The splitmix64 generator, which is small and good enough for choosing test values.
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // A number from low to high, inclusive.
+    fn between(&mut self, low: i32, high: i32) -> i32 {
+        let range = (high as i64 - low as i64 + 1) as u64;
+        (low as i64 + (self.next() % range) as i64) as i32
+    }
+}
+
+fn generate(generator: &Generator, random: &mut Random, size: i32) -> Value {
+    match generator {
+        // Numbers stay within the size of the trial when the range allows it.
+        Generator::Number { low, high } => {
+            let (small_low, small_high) = ((*low).max(-size), (*high).min(size));
+            match small_low <= small_high {
+                true => Value::NumValue(random.between(small_low, small_high)),
+                false => Value::NumValue(random.between(*low, *high)),
+            }
+        }
+        Generator::Boolean => Value::BoolValue(random.between(0, 1) == 1),
+        Generator::List { element, low, high } => {
+            let length = random.between(*low, size.clamp(*low, *high));
+            let items = (0..length).map(|_| generate(element, random, size)).collect();
+            Value::ListValue(ValueList::from_vec(items))
+        }
+        Generator::NestedList { element, low, high } => {
+            let length = random.between(*low, size.clamp(*low, *high));
+            let items = (0..length)
+                .map(|_| match size > 1 && random.between(0, 3) == 0 {
+                    true => generate(generator, random, size / 2),
+                    false => generate(element, random, size),
+                })
+                .collect();
+            Value::ListValue(ValueList::from_vec(items))
+        }
+    }
+}
+
+// Values smaller than the value that the generator could have made, the most promising first.
+fn shrink(generator: &Generator, value: &Value) -> Vec<Value> {
+    match (generator, value) {
+        // Numbers shrink towards 0, or the end of their range nearest to it.
+        (Generator::Number { low, high }, Value::NumValue(n)) => {
+            let target = 0.clamp(*low, *high);
+            let mut candidates = Vec::new();
+            for candidate in [target, target + (n - target) / 2, n - (n - target).signum()] {
+                if candidate != *n && !candidates.contains(&candidate) {
+                    candidates.push(candidate);
+                }
+            }
+            candidates.into_iter().map(Value::NumValue).collect()
+        }
+        (Generator::Boolean, Value::BoolValue(true)) => vec![Value::BoolValue(false)],
+        (Generator::List { element, low, high }, Value::ListValue(list))
+        | (Generator::NestedList { element, low, high }, Value::ListValue(list)) => {
+            let items = list.to_vec();
+            let nested = matches!(generator, Generator::NestedList { .. });
+            let mut candidates = Vec::new();
+
+            // Remove all the items, then halves, quarters, and so on down to single items.
+            if !items.is_empty() && *low == 0 {
+                candidates.push(Vec::new());
+            }
+            let mut chunk = items.len() / 2;
+            while chunk > 0 {
+                if items.len() - chunk >= *low as usize {
+                    for start in (0..=items.len() - chunk).step_by(chunk) {
+                        let mut smaller = items.clone();
+                        smaller.drain(start..start + chunk);
+                        candidates.push(smaller);
+                    }
+                }
+                chunk /= 2;
+            }
+
+            // Shrink each item in place. A nested list can also be replaced by its own items.
+            for (i, item) in items.iter().enumerate() {
+                match item {
+                    Value::ListValue(sublist) if nested && !makes_lists(element) => {
+                        let sublist = sublist.to_vec();
+                        if items.len() - 1 + sublist.len() <= *high as usize {
+                            let mut spliced = items.clone();
+                            spliced.splice(i..i + 1, sublist);
+                            candidates.push(spliced);
+                        }
+                        for smaller in shrink(generator, item) {
+                            let mut replaced = items.clone();
+                            replaced[i] = smaller;
+                            candidates.push(replaced);
+                        }
+                    }
+                    _ => {
+                        for smaller in shrink(element, item) {
+                            let mut replaced = items.clone();
+                            replaced[i] = smaller;
+                            candidates.push(replaced);
+                        }
+                    }
+                }
+            }
+            candidates
+                .into_iter()
+                .map(|items| Value::ListValue(ValueList::from_vec(items)))
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn makes_lists(generator: &Generator) -> bool {
+    matches!(generator, Generator::List { .. } | Generator::NestedList { .. })
+}
@@ -0,0 +1,86 @@
+use crate::interpret::{FunctionValue, Value, ValueList};
+use crate::interpret_char::char_to_string;
+use crate::interpret_hash::sorted_entries;
+
+// Prints values the way Racket's REPL does, e.g. '(1 2 3) instead of (list 1 2 3). Used when
+// running programs in Racket compatibility mode.
+pub fn to_racket_string(value: &Value) -> String {
+    if is_self_quoting(value) || !is_quotable(value) {
+        print_expression(value)
+    } else {
+        format!("'{}", print_datum(value))
+    }
+}
+
+// Values that print the same whether or not they are quoted.
+fn is_self_quoting(value: &Value) -> bool {
+    matches!(
+        value,
+        Value::NumValue(_)
+            | Value::BoolValue(_)
+            | Value::StringValue(_)
+            | Value::CharValue(_)
+            | Value::FunctionValue(_)
+            | Value::ExnValue(_)
+    )
+}
+
+// Functions have no quoted form, so data containing one has to be printed as an expression.
+fn is_quotable(value: &Value) -> bool {
+    match value {
+        Value::FunctionValue(_) | Value::ExnValue(_) | Value::StructValue(_) => false,
+        Value::ListValue(list) => list.to_vec().iter().all(is_quotable),
+        Value::VectorValue(vector) => vector.iter().all(is_quotable),
+        Value::HashValue(hash) => hash.iter().all(|(key, value)| is_quotable(key) && is_quotable(value)),
+        _ => true,
+    }
+}
+
+// A value that cannot be quoted, printed as the expression that constructs it.
+fn print_expression(value: &Value) -> String {
+    let constructor = |name: &str, items: Vec<String>| format!("({})", [vec![name.to_string()], items].concat().join(" "));
+
+    match value {
+        Value::ListValue(list) => constructor("list", list.to_vec().iter().map(to_racket_string).collect()),
+        Value::VectorValue(vector) => constructor("vector", vector.iter().map(to_racket_string).collect()),
+        Value::HashValue(hash) => constructor(
+            "hash",
+            sorted_entries(hash)
+                .iter()
+                .flat_map(|(key, value)| [to_racket_string(key), to_racket_string(value)])
+                .collect(),
+        ),
+        Value::FunctionValue(FunctionValue::Named(name)) => format!("#<procedure:{}>", name),
+        Value::FunctionValue(FunctionValue::Closure(_)) => "#<procedure>".to_string(),
+        Value::ExnValue(exn) => format!("#<{}: {}>", exn.kind, exn.message),
+        Value::StructValue(instance) => constructor(&instance.name, instance.fields.iter().map(to_racket_string).collect()),
+        _ => print_datum(value),
+    }
+}
+
+// The written form of a value, as it would appear inside a quote.
+fn print_datum(value: &Value) -> String {
+    let join = |items: Vec<String>| items.join(" ");
+
+    match value {
+        Value::NumValue(n) => n.to_string(),
+        Value::BoolValue(true) => "#t".to_string(),
+        Value::BoolValue(false) => "#f".to_string(),
+        Value::StringValue(s) => format!("{:?}", s),
+        Value::CharValue(c) => char_to_string(*c),
+        Value::SymbolValue(s) => s.to_string(),
+        Value::ListValue(ValueList::Empty) => "()".to_string(),
+        Value::ListValue(list) => format!("({})", join(list.to_vec().iter().map(print_datum).collect())),
+        Value::VectorValue(vector) => format!("#({})", join(vector.iter().map(print_datum).collect())),
+        Value::HashValue(hash) => format!(
+            "#hash({})",
+            join(
+                sorted_entries(hash)
+                    .iter()
+                    .map(|(key, value)| format!("({} . {})", print_datum(key), print_datum(value)))
+                    .collect()
+            )
+        ),
+        Value::FunctionValue(_) | Value::ExnValue(_) | Value::StructValue(_) => print_expression(value),
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt;
+
+use crate::error::SyntaxError;
+use crate::interpret::N;
+use crate::interpret_char::char_to_string;
+use crate::tokenizer::{string_to_tokens, Span, Token, TokenKind};
+
+// Generic s-expression data produced by reading program text. The parser builds expressions from
+// these, and macros are expanded over them before that happens.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Datum {
+    pub kind: DatumKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DatumKind {
+    List(Vec<Datum>),
+    Vector(Vec<Datum>),
+    Symbol(String),
+    // #:name, used for keyword arguments.
+    Keyword(String),
+    Number(N),
+    Boolean(bool),
+    String(String),
+    Char(char),
+}
+
+impl Datum {
+    pub fn new(kind: DatumKind, span: Span) -> Self {
+        Datum { kind, span }
+    }
+
+    pub fn as_symbol(&self) -> Option<&str> {
+        match &self.kind {
+            DatumKind::Symbol(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&Vec<Datum>> {
+        match &self.kind {
+            DatumKind::List(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Datum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            DatumKind::List(items) => write!(f, "({})", join_datums(items)),
+            DatumKind::Vector(items) => write!(f, "#({})", join_datums(items)),
+            DatumKind::Symbol(s) => write!(f, "{}", s),
+            DatumKind::Keyword(s) => write!(f, "#:{}", s),
+            DatumKind::Number(n) => write!(f, "{}", n),
+            DatumKind::Boolean(b) => write!(f, "{}", b),
+            DatumKind::String(s) => write!(f, "{:?}", s),
+            DatumKind::Char(c) => write!(f, "{}", char_to_string(*c)),
+        }
+    }
+}
+
+fn join_datums(items: &[Datum]) -> String {
+    items.iter().map(|item| item.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+// Reads every datum in the program text.
+pub fn read_program(program: String) -> Result<Vec<Datum>, SyntaxError> {
+    let tokens = string_to_tokens(program)?;
+    let mut tokens = tokens.iter().peekable();
+
+    let mut result = Vec::new();
+    while tokens.peek().is_some() {
+        result.push(read_datum(&mut tokens)?);
+    }
+
+    Ok(result)
+}
+
+// Reads text that contains exactly one datum.
+pub fn read(program: String) -> Result<Datum, SyntaxError> {
+    let mut datums = read_program(program)?;
+
+    if datums.len() != 1 {
+        let span = datums.get(1).map(|datum| datum.span).unwrap_or_default();
+        return Err(SyntaxError::read(
+            span,
+            format!(
+                "Malformed program, expected exactly one datum but found {}: {}",
+                datums.len(),
+                join_datums(&datums)
+            ),
+        ));
+    }
+
+    Ok(datums.pop().unwrap())
+}
+
+fn read_datum<'a>(tokens: &mut std::iter::Peekable<impl Iterator<Item = &'a Token>>) -> Result<Datum, SyntaxError> {
+    let Some(token) = tokens.next() else {
+        return Err(SyntaxError::read(
+            Span::default(),
+            "Unexpected end of program while reading.".to_string(),
+        ));
+    };
+
+    let kind = match token.kind {
+        TokenKind::Number => match token.text.parse::<N>() {
+            Ok(n) => DatumKind::Number(n),
+            Err(_) => {
+                let message = format!("Number out of range at {}: {}", token.span, token.text);
+                return Err(SyntaxError::read(token.span, message));
+            }
+        },
+        TokenKind::Boolean => DatumKind::Boolean(token.text == "true"),
+        TokenKind::String => DatumKind::String(token.text.clone()),
+        TokenKind::Char => DatumKind::Char(token.text.chars().next().unwrap()),
+        TokenKind::Symbol => DatumKind::Symbol(token.text.clone()),
+        TokenKind::Keyword => DatumKind::Keyword(token.text.clone()),
+        TokenKind::OpenParen | TokenKind::OpenVector => {
+            let mut items = Vec::new();
+            loop {
+                match tokens.peek() {
+                    Some(next) if next.kind == TokenKind::CloseParen => break,
+                    Some(_) => items.push(read_datum(tokens)?),
+                    None => {
+                        let message = format!("Unmatched open paren at {}", token.span);
+                        return Err(SyntaxError::read(token.span, message));
+                    }
+                }
+            }
+            let close = tokens.next().unwrap();
+
+            let span = Span {
+                end: close.span.end,
+                ..token.span
+            };
+            let kind = if token.kind == TokenKind::OpenParen {
+                DatumKind::List(items)
+            } else {
+                DatumKind::Vector(items)
+            };
+            return Ok(Datum::new(kind, span));
+        }
+        TokenKind::CloseParen => {
+            let message = format!("Unmatched close paren at {}", token.span);
+            return Err(SyntaxError::read(token.span, message));
+        }
+        // 'x is read as (quote x), and likewise for the other quoting shorthands.
+        TokenKind::Quote | TokenKind::Quasiquote | TokenKind::Unquote | TokenKind::UnquoteSplicing => {
+            let name = match token.kind {
+                TokenKind::Quote => "quote",
+                TokenKind::Quasiquote => "quasiquote",
+                TokenKind::Unquote => "unquote",
+                _ => "unquote-splicing",
+            };
+            if tokens.peek().is_none() {
+                let message = format!("Expected a datum after {} at {}", token.text, token.span);
+                return Err(SyntaxError::read(token.span, message));
+            }
+            let quoted = read_datum(tokens)?;
+            let span = Span {
+                end: quoted.span.end,
+                ..token.span
+            };
+            return Ok(Datum::new(
+                DatumKind::List(vec![Datum::new(DatumKind::Symbol(name.to_string()), token.span), quoted]),
+                span,
+            ));
+        }
+    };
+
+    Ok(Datum::new(kind, token.span))
+}
@@ -0,0 +1,117 @@
+use std::cell::RefCell;
+use std::fmt;
+
+use crate::error::RacketError;
+use crate::tokenizer::Span;
+
+// A call that was running: the function called and where it was called from. Calls made by the
+// interpreter itself, such as the call to main, have no call site.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub function: String,
+    pub call_site: Option<CallSite>,
+}
+
+// A location in the program, or in the module named by `module`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallSite {
+    pub module: Option<String>,
+    pub span: Span,
+}
+
+impl fmt::Display for CallSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.module {
+            Some(module) => write!(f, "{}:{}", module, self.span),
+            None => write!(f, "{}", self.span),
+        }
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.call_site {
+            Some(call_site) => write!(f, "{} at {}", self.function, call_site),
+            None => write!(f, "{}", self.function),
+        }
+    }
+}
+
+// The calls that were running when an error was raised, innermost first.
+#[derive(Debug, Clone, Default)]
+pub struct Backtrace {
+    pub frames: Vec<Frame>,
+}
+
+// Errors are the same error wherever they were raised, so backtraces never make them unequal.
+impl PartialEq for Backtrace {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+// Printed like Racket's context, with a run of the same recursive call collapsed into one line:
+//   context...:
+//    list::nth at stdlib::list:47:11 [repeats 2 more times]
+//    main
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "context...:")?;
+        let mut frames = self.frames.iter().peekable();
+        while let Some(frame) = frames.next() {
+            let mut repeats = 0;
+            while frames.next_if(|next| *next == frame).is_some() {
+                repeats += 1;
+            }
+            write!(f, "\n   {}", frame)?;
+            if repeats > 0 {
+                write!(f, " [repeats {} more time{}]", repeats, if repeats == 1 { "" } else { "s" })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    // The calls running on this thread, innermost last.
+    static STACK: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+}
+
+// Pushes a call onto the stack until the returned guard is dropped.
+pub fn enter_frame(function: &str, call_site: Option<CallSite>) -> FrameGuard {
+    let frame = Frame {
+        function: function.to_string(),
+        call_site,
+    };
+    STACK.with(|stack| stack.borrow_mut().push(frame));
+    FrameGuard
+}
+
+pub struct FrameGuard;
+
+impl Drop for FrameGuard {
+    fn drop(&mut self) {
+        STACK.with(|stack| stack.borrow_mut().pop());
+    }
+}
+
+// The number of calls running.
+pub fn depth() -> usize {
+    STACK.with(|stack| stack.borrow().len())
+}
+
+pub fn backtrace() -> Backtrace {
+    STACK.with(|stack| Backtrace {
+        frames: stack.borrow().iter().rev().cloned().collect(),
+    })
+}
+
+// Gives an error the backtrace of the calls running now, unless it already has the backtrace of where
+// it was raised. Called as the error leaves each call, so the innermost call sets it.
+pub fn attach_backtrace(error: RacketError) -> RacketError {
+    match error {
+        RacketError::Raise(value, trace) if trace.frames.is_empty() => RacketError::Raise(value, backtrace()),
+        RacketError::LimitExceeded(limit, trace) if trace.frames.is_empty() => RacketError::LimitExceeded(limit, backtrace()),
+        error => error,
+    }
+}
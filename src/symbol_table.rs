@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+
+// An interned symbol. Two symbols with the same name are always the same `Symbol`, so comparing
+// symbols is as cheap as comparing numbers.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(usize);
+
+#[derive(Default)]
+struct SymbolTable {
+    names: Vec<String>,
+    ids: HashMap<String, usize>,
+}
+
+fn symbol_table() -> &'static Mutex<SymbolTable> {
+    static SYMBOL_TABLE: OnceLock<Mutex<SymbolTable>> = OnceLock::new();
+    SYMBOL_TABLE.get_or_init(|| Mutex::new(SymbolTable::default()))
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        let mut table = symbol_table().lock().unwrap();
+
+        if let Some(id) = table.ids.get(name) {
+            return Symbol(*id);
+        }
+
+        let id = table.names.len();
+        table.names.push(name.to_string());
+        table.ids.insert(name.to_string(), id);
+        Symbol(id)
+    }
+
+    pub fn name(&self) -> String {
+        symbol_table().lock().unwrap().names[self.0].clone()
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Symbol({})", self.name())
+    }
+}
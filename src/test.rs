@@ -1,11 +1,28 @@
-use std::{collections::HashMap, env, fs, path::PathBuf};
-
-use itertools::peek_nth;
+use std::{cell::RefCell, collections::HashMap, env, fs, io::Write, path::PathBuf, rc::Rc, time::Duration};
 
 use crate::{
+    analysis::analyze,
+    convert::{FromValue, IntoValue},
+    dap::run_dap_server,
+    debug_console::DebugConsole,
+    debugger::{with_debugger, Resume},
+    error::{EvalResult, RacketError},
+    formatter::format_program,
     interpret::*,
+    interpret_lambda::Closure,
+    interpreter::Interpreter,
+    limits::{EvalLimits, Limit, DEFAULT_MAX_DEPTH},
+    lint::lint_program,
+    lsp::run_lsp_server,
+    macro_expander::expand_macros,
+    native::NativeModule,
     parser::{parse, parse_num_expr},
-    tokenizer::{string_to_tokens, TokenIter},
+    racket_printer::to_racket_string,
+    reader::{read, read_program, Datum, DatumKind},
+    symbol_table::Symbol,
+    testing::{junit_report, run_checks, CheckOutcome},
+    tokenizer::Span,
+    type_check::check_program,
 };
 
 fn get_example_program(file: &str) -> String {
@@ -13,11 +30,16 @@ fn get_example_program(file: &str) -> String {
     let mut path = PathBuf::from(&curr_dir);
     path.push(format!("examples/{}", file));
 
-    let program = fs::read_to_string(&path).expect(format!("Failed to read file at {}", path.to_str().unwrap()).as_str());
+    let program = fs::read_to_string(&path).unwrap_or_else(|_| panic!("Failed to read file at {}", path.to_str().unwrap()));
 
     program
 }
 
+// Runs the definitions with a main function that evaluates `main`.
+fn run_with_main(definitions: &str, main: &str) -> EvalResult {
+    try_interpret_program(format!("{}\n(define (main) {})", definitions, main))
+}
+
 #[test]
 fn number_literal() {
     assert_eq!(interpret_program_snippet("1010101".to_string()), Value::NumValue(1010101));
@@ -137,9 +159,7 @@ fn interpret_function() {
     ";
     assert_eq!(interpret_program(program3.to_string()), Value::NumValue(15));
 
-    let tokens = string_to_tokens("(a 1)".to_string());
-    let mut token_iterator = peek_nth(TokenIter::new(&tokens));
-    parse_num_expr(&mut token_iterator);
+    parse_num_expr(&read("(a 1)".to_string()).unwrap()).unwrap();
 
     let program4 = "
     (define (identity a) a)
@@ -157,12 +177,12 @@ fn interpret_variable() {
     let function_map = HashMap::new();
 
     assert_eq!(
-        interpret(&parse("a".to_string()), &mut variable_map, &function_map),
-        Value::NumValue(10)
+        interpret(&parse("a".to_string()).unwrap(), &mut variable_map, &function_map),
+        Ok(Value::NumValue(10))
     );
     assert_eq!(
-        interpret(&parse("(+ a b)".to_string()), &mut variable_map, &function_map),
-        Value::NumValue(15)
+        interpret(&parse("(+ a b)".to_string()).unwrap(), &mut variable_map, &function_map),
+        Ok(Value::NumValue(15))
     );
     let cond = "
     (cond
@@ -170,8 +190,8 @@ fn interpret_variable() {
         ((= b 5) 2)
     )";
     assert_eq!(
-        interpret(&parse(cond.to_string()), &mut variable_map, &function_map),
-        Value::NumValue(2)
+        interpret(&parse(cond.to_string()).unwrap(), &mut variable_map, &function_map),
+        Ok(Value::NumValue(2))
     );
 }
 
@@ -300,6 +320,7 @@ fn test_factorial() {
 }
 
 #[test]
+#[allow(clippy::identity_op)]
 fn test_fibonacci() {
     let program = get_example_program("fibonacci.rkt");
     assert_eq!(interpret_program(program), Value::NumValue(0 + 1 + 5 + 55));
@@ -559,3 +580,2245 @@ fn test_list_sort() {
         interpret_program_snippet("(list 2 3 3 3 5 9 9 9 13 13 13 14 15 17 17 17 19 20 20 20)".to_string())
     );
 }
+
+#[test]
+fn test_macros() {
+    assert_eq!(interpret_program(get_example_program("macros.rkt")), Value::NumValue(11));
+}
+
+#[test]
+fn test_macro_ellipsis() {
+    let program = "
+    (define-syntax sum
+        (syntax-rules ()
+            [(_) 0]
+            [(_ first rest ...) (+ first (sum rest ...))]))
+    (define-syntax my-list
+        (syntax-rules ()
+            [(_ (a b) ...) (list (+ a b) ...)]))
+    (define (main) (cons (sum 1 2 3 4) (my-list (1 2) (3 4))))
+    ";
+    assert_eq!(
+        interpret_program(program.to_string()),
+        interpret_program_snippet("(list 10 3 7)".to_string())
+    );
+}
+
+#[test]
+fn test_macro_literals() {
+    let program = "
+    (define-syntax choose
+        (syntax-rules (else)
+            [(_ else e) e]
+            [(_ c e) (cond [c e] [true 0])]))
+    (define (main) (list (choose else 1) (choose false 10)))
+    ";
+    assert_eq!(
+        interpret_program(program.to_string()),
+        interpret_program_snippet("(list 1 0)".to_string())
+    );
+}
+
+#[test]
+fn test_macro_from_stdlib() {
+    let program = "
+    (include stdlib::bool)
+    (define (main) (list (bool::and true true false) (bool::or false false true) (bool::and)))
+    ";
+    assert_eq!(
+        interpret_program(program.to_string()),
+        interpret_program_snippet("(list false true true)".to_string())
+    );
+}
+
+#[test]
+fn test_macro_hygiene() {
+    let program = "
+    (define-syntax define-adder
+        (syntax-rules ()
+            [(_ name amount) (define (name x) (+ x amount))]))
+    (define-adder add-x x)
+    ";
+    let expanded = expand_macros(read_program(program.to_string()).unwrap()).unwrap();
+
+    // The parameter introduced by the macro is renamed, the `x` written by the user is not.
+    assert_eq!(expanded.len(), 1);
+    assert_eq!(expanded[0].to_string(), "(define (add-x x%1) (+ x%1 x))");
+
+    // Lambda parameters, including annotated ones, and let bindings are renamed as well.
+    let program = "
+    (define-syntax adder
+        (syntax-rules ()
+            [(_ n) (lambda ([y : Integer]) (+ y n))]))
+    (define-syntax swap-args
+        (syntax-rules ()
+            [(_ f a b) (let loop ([tmp a]) (f b tmp))]))
+    (adder y)
+    (swap-args tmp loop 1)
+    ";
+    let expanded = expand_macros(read_program(program.to_string()).unwrap()).unwrap();
+    let expanded: Vec<String> = expanded.iter().map(|datum| datum.to_string()).collect();
+    assert_eq!(
+        expanded,
+        vec![
+            "(lambda ((y%1 : Integer)) (+ y%1 y))",
+            "(let loop%2 ((tmp%2 loop)) (tmp 1 tmp%2))"
+        ]
+    );
+}
+
+#[test]
+fn test_macro_hygiene_with_let() {
+    // let is not built in, but a program can define it as a macro, and macros that use it stay hygienic.
+    let program = "
+    (define-syntax let
+        (syntax-rules ()
+            [(_ ([name value] ...) body) ((lambda (name ...) body) value ...)]))
+    (define-syntax my-or
+        (syntax-rules ()
+            [(_ a b) (let ([t a]) (cond [t t] [else b]))]))
+    (define (main) (let ([t 5]) (my-or false t)))
+    ";
+    assert_eq!(interpret_program(program.to_string()), Value::NumValue(5));
+}
+
+#[test]
+fn test_macro_references_are_not_captured() {
+    // The helper in the template is the top level function, even where the macro is used inside a
+    // parameter or an internal definition with the same name.
+    let program = "
+    (define (helper y) y)
+    (define-syntax h (syntax-rules () [(_ a) (helper a)]))
+    (define (shadowed)
+        (define (helper z) (+ z 1))
+        (helper (h 6)))
+    (define (main) (list ((lambda (helper) (h 5)) (lambda (z) 99)) (shadowed)))
+    ";
+    assert_eq!(
+        interpret_program(program.to_string()),
+        interpret_program_snippet("(list 5 7)".to_string())
+    );
+}
+
+#[test]
+fn test_macro_uses_in_quoted_data() {
+    // Quoted data is left alone, and only the unquoted parts of a quasiquote are expanded.
+    let program = "
+    (define-syntax m (syntax-rules () [(_ x) (list x x)]))
+    (define (main) (list '(m 1) `(m ,(m 2) (unquote (m 3)) `(m ,(m 4)))))
+    ";
+    assert_eq!(
+        interpret_program(program.to_string()),
+        interpret_program_snippet("(list '(m 1) '(m (2 2) (3 3) `(m ,(m 4))))".to_string())
+    );
+}
+
+#[test]
+#[should_panic(expected = "No syntax-rules pattern matched the use of macro pair")]
+fn test_macro_no_matching_rule() {
+    let program = "
+    (define-syntax pair (syntax-rules () [(_ a b) (list a b)]))
+    (define (main) (pair 1))
+    ";
+    interpret_program(program.to_string());
+}
+
+#[test]
+fn test_reader() {
+    let datums = read_program("(define (f x) [+ x -1]) #(1 \"a\\\"b\" true) ; comment\nsym".to_string()).unwrap();
+    assert_eq!(datums.len(), 3);
+    assert_eq!(datums[0].to_string(), "(define (f x) (+ x -1))");
+    assert_eq!(
+        datums[1].kind,
+        DatumKind::Vector(vec![
+            Datum::new(
+                DatumKind::Number(1),
+                Span {
+                    start: 26,
+                    end: 27,
+                    line: 1,
+                    column: 27
+                }
+            ),
+            Datum::new(
+                DatumKind::String("a\"b".to_string()),
+                Span {
+                    start: 28,
+                    end: 34,
+                    line: 1,
+                    column: 29
+                }
+            ),
+            Datum::new(
+                DatumKind::Boolean(true),
+                Span {
+                    start: 35,
+                    end: 39,
+                    line: 1,
+                    column: 36
+                }
+            ),
+        ])
+    );
+    assert_eq!(
+        datums[1].span,
+        Span {
+            start: 24,
+            end: 40,
+            line: 1,
+            column: 25
+        }
+    );
+    assert_eq!(
+        datums[2],
+        Datum::new(
+            DatumKind::Symbol("sym".to_string()),
+            Span {
+                start: 51,
+                end: 54,
+                line: 2,
+                column: 1
+            }
+        )
+    );
+}
+
+#[test]
+fn test_reader_unmatched_paren() {
+    let error = read_program("(define (main) 1".to_string()).unwrap_err();
+    assert_eq!(error.message, "Unmatched open paren at 1:1");
+    assert_eq!(
+        error.span,
+        Span {
+            start: 0,
+            end: 1,
+            line: 1,
+            column: 1
+        }
+    );
+}
+
+#[test]
+fn test_read_primitive() {
+    assert_eq!(
+        interpret_program_snippet("(read \"(1 (2 3) true)\")".to_string()),
+        interpret_program_snippet("(list 1 (list 2 3) true)".to_string())
+    );
+    assert_eq!(
+        interpret_program_snippet("(read \"\\\"text\\\"\")".to_string()),
+        Value::StringValue("text".to_string())
+    );
+    assert_eq!(
+        interpret_program_snippet("(with-handlers ([exn:fail:read? exn-message]) (read \"(1\"))".to_string()),
+        Value::StringValue("Unmatched open paren at 1:1".to_string())
+    );
+    assert_eq!(
+        interpret_program_snippet("(with-handlers ([exn:fail? (lambda (e) 'malformed)]) (read \"1 2\"))".to_string()),
+        Value::SymbolValue(Symbol::intern("malformed"))
+    );
+}
+
+#[test]
+fn test_quote() {
+    assert_eq!(
+        interpret_program_snippet("'sym".to_string()),
+        Value::SymbolValue(Symbol::intern("sym"))
+    );
+    assert_eq!(
+        interpret_program_snippet("'(1 2 (3 4))".to_string()),
+        interpret_program_snippet("(list 1 2 (list 3 4))".to_string())
+    );
+    assert_eq!(
+        interpret_program_snippet("(quote (a \"b\" true))".to_string()),
+        Value::ListValue(ValueList::from_vec(vec![
+            Value::SymbolValue(Symbol::intern("a")),
+            Value::StringValue("b".to_string()),
+            Value::BoolValue(true),
+        ]))
+    );
+    assert_eq!(interpret_program_snippet("''a".to_string()).to_string(), "(list 'quote 'a)");
+    assert_eq!(interpret_program_snippet("(car '(1 2))".to_string()), Value::NumValue(1));
+    assert_eq!(interpret_program_snippet("(+ 1 (car '(2)))".to_string()), Value::NumValue(3));
+}
+
+#[test]
+fn test_quasiquote() {
+    let program = "
+    (define (numbers) (list 2 3))
+    (define (main) `(1 ,(+ 1 1) ,@(numbers) (nested ,(car (numbers))) end))
+    ";
+    assert_eq!(
+        interpret_program(program.to_string()),
+        interpret_program_snippet("(list 1 2 2 3 (list 'nested 2) 'end)".to_string())
+    );
+
+    // Unquotes inside a nested quasiquote stay quoted.
+    assert_eq!(
+        interpret_program_snippet("`(1 `(2 ,(3 ,(+ 1 3))))".to_string()),
+        interpret_program_snippet("'(1 (quasiquote (2 (unquote (3 4)))))".to_string())
+    );
+}
+
+#[test]
+fn test_symbols() {
+    assert_eq!(Symbol::intern("abc"), Symbol::intern("abc"));
+    assert_ne!(Symbol::intern("abc"), Symbol::intern("abd"));
+
+    assert_eq!(interpret_program_snippet("(symbol? 'a)".to_string()), Value::BoolValue(true));
+    assert_eq!(
+        interpret_program_snippet("(symbol? \"a\")".to_string()),
+        Value::BoolValue(false)
+    );
+    assert_eq!(
+        interpret_program_snippet("(symbol->string 'hello-world)".to_string()),
+        Value::StringValue("hello-world".to_string())
+    );
+    assert_eq!(
+        interpret_program_snippet("(symbol=? (string->symbol \"x\") 'x)".to_string()),
+        Value::BoolValue(true)
+    );
+    assert_eq!(
+        interpret_program_snippet("(equal? '(a (b)) (list 'a (list 'b)))".to_string()),
+        Value::BoolValue(true)
+    );
+    assert_eq!(
+        interpret_program_snippet("(read \"(define x)\")".to_string()).to_string(),
+        "(list 'define 'x)"
+    );
+}
+
+#[test]
+fn test_lambda() {
+    let program = "
+    (define (make-adder n) (lambda (x) (+ x n)))
+    (define (twice f x) (f (f x)))
+    (define (main) (list ((make-adder 1) 2) (twice (make-adder 10) 1) ((λ (a b) (* a b)) 3 4)))
+    ";
+    assert_eq!(
+        interpret_program(program.to_string()),
+        interpret_program_snippet("(list 3 21 12)".to_string())
+    );
+}
+
+#[test]
+fn test_closures_capture_free_variables() {
+    let program = "
+    (define offset 100)
+    (define (make-adder n unused)
+        (define scale 2)
+        (define ignored 3)
+        (lambda (x) (+ (* x scale) (+ n offset))))
+    (define (main) (make-adder 1 0))
+    ";
+    let Value::FunctionValue(FunctionValue::Closure(Closure { captured, .. })) = interpret_program(program.to_string()) else {
+        panic!("Expected a closure");
+    };
+    assert_eq!(
+        captured,
+        vec![
+            ("n".to_string(), Value::NumValue(1)),
+            ("offset".to_string(), Value::NumValue(100)),
+            ("scale".to_string(), Value::NumValue(2)),
+        ]
+    );
+}
+
+#[test]
+fn test_functions_as_values() {
+    let program = "
+    (define (square x) (* x x))
+    (define (main) (list (map square (list 1 2 3)) (procedure? square) (procedure? 1) (foldl + 0 (list 1 2 3))))
+    ";
+    assert_eq!(
+        interpret_program(program.to_string()),
+        interpret_program_snippet("(list (list 1 4 9) true false 6)".to_string())
+    );
+    assert_eq!(
+        interpret_program_snippet("(map car (list (list 1 2) (list 3)))".to_string()).to_string(),
+        "(list 1 3)"
+    );
+}
+
+#[test]
+#[should_panic(expected = "Function lambda expects 1 argument(s) but was called with 2")]
+fn test_lambda_arity() {
+    interpret_program_snippet("((lambda (x) x) 1 2)".to_string());
+}
+
+#[test]
+fn test_higher_order_list_functions() {
+    let check = |program: &str, expected: &str| {
+        assert_eq!(
+            interpret_program_snippet(program.to_string()),
+            interpret_program_snippet(expected.to_string())
+        );
+    };
+
+    check("(map (lambda (a b) (+ a b)) (list 1 2) (list 10 20))", "(list 11 22)");
+    check("(filter (lambda (x) (> x 1)) (list 3 1 2))", "(list 3 2)");
+    check("(foldl cons empty (list 1 2 3))", "(list 3 2 1)");
+    check("(foldr cons empty (list 1 2 3))", "(list 1 2 3)");
+    check("(foldl (lambda (a b acc) (+ acc (* a b))) 0 (list 1 2) (list 3 4))", "11");
+    check("(andmap (lambda (x) (> x 0)) (list 1 2))", "true");
+    check("(andmap (lambda (x) (> x 1)) (list 1 2))", "false");
+    check("(andmap (lambda (x) (> x 1)) empty)", "true");
+    check("(ormap (lambda (x) (> x 1)) (list 1 2))", "true");
+    check("(ormap (lambda (x) (> x 1)) empty)", "false");
+    check("(sort (list 3 1 2) <)", "(list 1 2 3)");
+    check("(sort (list 3 1 2) >)", "(list 3 2 1)");
+    check(
+        "(sort (list (list 2 'a) (list 1 'b) (list 2 'c) (list 1 'd)) < #:key car)",
+        "(list (list 1 'b) (list 1 'd) (list 2 'a) (list 2 'c))",
+    );
+    check("(build-list 4 (lambda (i) (* i i)))", "(list 0 1 4 9)");
+    check("(assoc 2 (list (list 1 'one) (list 2 'two)))", "(list 2 'two)");
+    check("(assoc 3 (list (list 1 'one) (list 2 'two)))", "false");
+    check("(member 2 (list 1 2 3))", "(list 2 3)");
+    check("(member 4 (list 1 2 3))", "false");
+    check("(remove 2 (list 1 2 3 2))", "(list 1 3 2)");
+}
+
+#[test]
+#[should_panic(expected = "All lists passed to map must have the same length")]
+fn test_map_different_lengths() {
+    interpret_program_snippet("(map + (list 1 2) (list 1))".to_string());
+}
+
+#[test]
+fn test_hash() {
+    let check = |program: &str, expected: &str| {
+        assert_eq!(
+            interpret_program_snippet(program.to_string()),
+            interpret_program_snippet(expected.to_string())
+        );
+    };
+
+    check("(hash-ref (hash 'a 1 'b 2) 'b)", "2");
+    check("(hash-ref (hash 'a 1) 'b 0)", "0");
+    check("(hash-ref (hash 'a 1) 'b (lambda () 5))", "5");
+    check("(hash-ref (hash-set (hash 'a 1) 'a 3) 'a)", "3");
+    check("(hash-has-key? (hash-remove (hash 'a 1 'b 2) 'a) 'a)", "false");
+    check("(hash-count (hash-set (hash 'a 1) 'b 2))", "2");
+    check("(hash-keys (hash 'c 1 'a 2 'b 3))", "(list 'a 'b 'c)");
+    check("(equal? (hash 'a 1 'b 2) (hash 'b 2 'a 1))", "true");
+    check("(hash? (hash))", "true");
+
+    // Keys are compared structurally.
+    check("(hash-ref (hash (list 1 2) 'pair) (cons 1 (list 2)))", "'pair");
+    check("(hash-ref (hash \"key\" 1) \"key\")", "1");
+}
+
+#[test]
+fn test_hash_is_immutable() {
+    let program = "
+    (define (with-b h) (hash-set h 'b 2))
+    (define (main) (list (hash-count (hash 'a 1)) (hash-count (with-b (hash 'a 1)))))
+    ";
+    assert_eq!(
+        interpret_program(program.to_string()),
+        interpret_program_snippet("(list 1 2)".to_string())
+    );
+}
+
+#[test]
+fn test_hash_printing() {
+    assert_eq!(
+        interpret_program_snippet("(hash 'b 2 'a (list 1) \"c\" 3)".to_string()).to_string(),
+        "(hash \"c\" 3 'a (list 1) 'b 2)"
+    );
+    assert_eq!(interpret_program_snippet("(hash)".to_string()).to_string(), "(hash)");
+}
+
+#[test]
+#[should_panic(expected = "No value found in hash for key 'b")]
+fn test_hash_ref_missing_key() {
+    interpret_program_snippet("(hash-ref (hash 'a 1) 'b)".to_string());
+}
+
+#[test]
+fn test_vector() {
+    let check = |program: &str, expected: &str| {
+        assert_eq!(
+            interpret_program_snippet(program.to_string()),
+            interpret_program_snippet(expected.to_string())
+        );
+    };
+
+    check("(vector-ref (vector 1 2 3) 1)", "2");
+    check("(vector-ref #(1 (2 3) x) 2)", "'x");
+    check("(vector-length #())", "0");
+    check("(vector->list (make-vector 3 'a))", "(list 'a 'a 'a)");
+    check("(vector->list (make-vector 2))", "(list 0 0)");
+    check("(list->vector (list 1 2))", "#(1 2)");
+    check("(vector-set #(1 2 3) 0 'x)", "(vector 'x 2 3)");
+    check("(vector? #(1))", "true");
+    check("(vector? (list 1))", "false");
+    check("(equal? #(1 (2)) (vector 1 (list 2)))", "true");
+    assert_eq!(
+        interpret_program_snippet("#(1 a \"b\")".to_string()).to_string(),
+        "(vector 1 'a \"b\")"
+    );
+}
+
+#[test]
+fn test_vector_set_is_functional() {
+    let program = "
+    (define (update v) (vector-set v 0 10))
+    (define (main) (list (update #(1 2)) #(1 2)))
+    ";
+    assert_eq!(
+        interpret_program(program.to_string()),
+        interpret_program_snippet("(list #(10 2) #(1 2))".to_string())
+    );
+}
+
+#[test]
+#[should_panic(expected = "Index 3 passed to vector-ref is out of range for a vector of length 3")]
+fn test_vector_ref_out_of_range() {
+    interpret_program_snippet("(vector-ref #(1 2 3) 3)".to_string());
+}
+
+#[test]
+fn test_bst_from_list() {
+    let program = "
+    (include stdlib::bst)
+    (define (main) (bst::from-list (list 5 3 1 4 2 6 7)))
+    ";
+    assert_eq!(
+        interpret_program(program.to_string()).to_string(),
+        "(list 4 (list 2 (list 1 empty empty) (list 3 empty empty)) (list 6 (list 5 empty empty) (list 7 empty empty)))"
+    );
+}
+
+#[test]
+fn test_chars() {
+    let check = |program: &str, expected: &str| {
+        assert_eq!(
+            interpret_program_snippet(program.to_string()),
+            interpret_program_snippet(expected.to_string())
+        );
+    };
+
+    check("(char->integer #\\a)", "97");
+    check("(char->integer #\\space)", "32");
+    check("(char->integer #\\newline)", "10");
+    check("(char->integer #\\λ)", "955");
+    check("(integer->char 955)", "#\\u03BB");
+    check("(char-alphabetic? #\\λ)", "true");
+    check("(char-alphabetic? #\\1)", "false");
+    check("(char-upcase #\\a)", "#\\A");
+    check("(char-upcase #\\ß)", "#\\ß");
+    check("(string->list \"ab)\")", "(list #\\a #\\b #\\))");
+    check("(list->string (list #\\h #\\i #\\space #\\())", "\"hi (\"");
+    check("(equal? '(#\\a) (list #\\a))", "true");
+}
+
+#[test]
+fn test_char_printing() {
+    assert_eq!(
+        interpret_program_snippet("(list #\\a #\\space #\\newline #\\λ #\\( #\\;)".to_string()).to_string(),
+        "(list #\\a #\\space #\\newline #\\λ #\\( #\\;)"
+    );
+}
+
+#[test]
+#[should_panic(expected = "Unknown character name at 1:1: #\\spaces")]
+fn test_unknown_char_name() {
+    interpret_program_snippet("#\\spaces".to_string());
+}
+
+#[test]
+fn test_racket_literals() {
+    let check = |program: &str, expected: &str| {
+        assert_eq!(
+            interpret_program_snippet(program.to_string()),
+            interpret_program_snippet(expected.to_string())
+        );
+    };
+
+    check("(list #t #f #true #false)", "(list true false true false)");
+    check("'()", "empty");
+    check("null", "empty");
+    check("(list (null? '()) (null? (list 1)) (null? 1))", "(list true false false)");
+    check("(list (cons? (list 1)) (pair? '()) (cons? 'a))", "(list true false false)");
+    check(
+        "(list (first '(1 2 3)) (second '(1 2 3)) (third '(1 2 3)) (last '(1 2 3)))",
+        "(list 1 2 3 3)",
+    );
+    check("(rest '(1 2 3))", "(list 2 3)");
+    check(
+        "(list (cadr '(1 2 3)) (cddr '(1 2 3)) (caar '((1) 2)) (caddr '(1 2 3)))",
+        "(list 2 (list 3) 1 3)",
+    );
+    check("(map first '((1 2) (3 4)))", "(list 1 3)");
+}
+
+#[test]
+fn test_textbook_program() {
+    let program = "
+    ; sum : list-of-numbers -> number
+    (define (sum alon)
+      (cond
+        [(empty? alon) 0]
+        [else (+ (first alon) (sum (rest alon)))]))
+
+    (define (all-positive? alon)
+      (cond
+        [(null? alon) #t]
+        [(> (first alon) 0) (all-positive? (rest alon))]
+        [else #f]))
+
+    (define (main) (list (sum (cons 1 (cons 2 '()))) (all-positive? '(1 2)) (all-positive? '(1 -2))))
+    ";
+    assert_eq!(
+        interpret_program(program.to_string()),
+        interpret_program_snippet("(list 3 #t #f)".to_string())
+    );
+}
+
+#[test]
+#[should_panic(expected = "Expected second to be given a list with at least 2 element(s) but got (list 1)")]
+fn test_second_of_short_list() {
+    interpret_program_snippet("(second '(1))".to_string());
+}
+
+#[test]
+fn test_lang_line() {
+    assert_eq!(
+        interpret_program("#lang rusty-racket\n(define (main) (+ 1 2))".to_string()),
+        Value::NumValue(3)
+    );
+    assert_eq!(
+        read_lang_line("\n#lang racket\n(f)".to_string()),
+        Ok((Language::Racket, "\n\n(f)".to_string()))
+    );
+    assert_eq!(
+        read_lang_line("(define (main) 1)".to_string()),
+        Ok((Language::RustyRacket, "(define (main) 1)".to_string()))
+    );
+}
+
+#[test]
+fn test_unsupported_lang_line() {
+    let error = read_lang_line("\n#lang typed/racket\n".to_string()).unwrap_err();
+    assert_eq!(error.message, "Unsupported language in #lang line: typed/racket");
+    assert_eq!(
+        error.span,
+        Span {
+            start: 1,
+            end: 19,
+            line: 2,
+            column: 1
+        }
+    );
+}
+
+#[test]
+fn test_racket_compatibility_mode() {
+    let output: Vec<String> = interpret_racket_program(get_example_program("racket_compat.rkt"))
+        .iter()
+        .map(|result| to_racket_string(result.as_ref().unwrap()))
+        .collect();
+    assert_eq!(
+        output,
+        vec![
+            "16",
+            "'(1 4 9)",
+            "'(a \"b\" #\\c #t)",
+            "(list #<procedure:square> 1)",
+            "'#(1 x)",
+            "'#hash((a . 1) (b . 2))",
+            "'()",
+        ]
+    );
+}
+
+#[test]
+fn test_top_level_definitions() {
+    let program = "
+    (define area (* pi (square radius)))
+    (define (square x) (* x x))
+    (define radius 2)
+    (define pi 3)
+    (define double (lambda (x) (* 2 x)))
+    (define (main) (list area (double pi)))
+    ";
+    assert_eq!(
+        interpret_program(program.to_string()),
+        interpret_program_snippet("(list 12 6)".to_string())
+    );
+}
+
+#[test]
+fn test_definition_used_through_function() {
+    // limit is only used inside the function that computes capped, so it has to be defined first.
+    let program = "
+    (define capped (cap 100))
+    (define (cap x) (cond [(> x limit) limit] [else x]))
+    (define limit 10)
+    (define (main) capped)
+    ";
+    assert_eq!(interpret_program(program.to_string()), Value::NumValue(10));
+}
+
+#[test]
+fn test_internal_definitions() {
+    let program = "
+    (define (hypotenuse-squared a b)
+        (define c-squared (+ a-squared b-squared))
+        (define a-squared (square a))
+        (define b-squared (square b))
+        (define (square x) (* x x))
+        c-squared)
+    (define (count-down n)
+        (define (loop i acc) (cond [(= i 0) acc] [else (loop (- i 1) (cons i acc))]))
+        (loop n empty))
+    (define (main) (list (hypotenuse-squared 3 4) (count-down 3) ((lambda (x) (define y (+ x 1)) y) 1)))
+    ";
+    assert_eq!(
+        interpret_program(program.to_string()),
+        interpret_program_snippet("(list 25 (list 1 2 3) 2)".to_string())
+    );
+}
+
+#[test]
+fn test_internal_functions_are_recursive() {
+    // Internal functions can call themselves and each other after the body that defines them returns.
+    let program = "
+    (define (make-counter)
+        (define (count n) (cond [(= n 0) 0] [else (+ 1 (count (- n 1)))]))
+        count)
+    (define (make-odd?)
+        (define (even? n) (cond [(= n 0) true] [else (odd? (- n 1))]))
+        (define odd? (lambda (n) (cond [(= n 0) false] [else (even? (- n 1))])))
+        odd?)
+    (define (main) (list ((make-counter) 3) ((make-odd?) 7)))
+    ";
+    assert_eq!(
+        interpret_program(program.to_string()),
+        interpret_program_snippet("(list 3 true)".to_string())
+    );
+
+    // Backtraces name them as well.
+    let program = "
+    (define (make-fail) (define (fail n) (cond [(= n 0) (car empty)] [else (fail (- n 1))])) fail)
+    (define (main) ((make-fail) 2))
+    ";
+    assert_eq!(
+        try_interpret_program(program.to_string())
+            .unwrap_err()
+            .backtrace()
+            .to_string(),
+        "context...:\n   fail at 2:76 [repeats 1 more time]\n   fail at 3:20\n   main"
+    );
+}
+
+#[test]
+fn test_cyclic_definitions() {
+    let program = "
+    (define a (+ b 1))
+    (define b (get-a))
+    (define (get-a) a)
+    (define (main) a)
+    ";
+    assert_eq!(
+        try_interpret_program(program.to_string()),
+        Err(RacketError::exn(
+            "exn:fail:syntax",
+            "Cyclic initialisation of a at 2:15: a -> b -> a".to_string()
+        ))
+    );
+
+    // A lambda is assumed to be called where it is created.
+    let program = "
+    (define a (map (lambda (x) (+ x b)) (list 1)))
+    (define b (car a))
+    (define (main) a)
+    ";
+    assert_eq!(
+        try_interpret_program(program.to_string()),
+        Err(RacketError::exn(
+            "exn:fail:syntax",
+            "Cyclic initialisation of a at 2:15: a -> b -> a".to_string()
+        ))
+    );
+}
+
+#[test]
+fn test_cyclic_internal_definitions() {
+    let program = "
+    (define (f)
+        (define x y)
+        (define y x)
+        x)
+    (define (main) (f))
+    ";
+    assert_eq!(
+        try_interpret_program(program.to_string()),
+        Err(RacketError::exn(
+            "exn:fail:syntax",
+            "Cyclic initialisation of x at 3:19: x -> y -> x".to_string()
+        ))
+    );
+}
+
+#[test]
+fn test_definitions_used_in_lambdas() {
+    let program = "
+    (define scaled (map (lambda (x) (* x factor)) (list 1 2)))
+    (define total ((lambda () (+ (car scaled) offset))))
+    (define factor 3)
+    (define offset 10)
+    (define (main) (list scaled total))
+    ";
+    assert_eq!(
+        interpret_program(program.to_string()),
+        interpret_program_snippet("(list (list 3 6) 13)".to_string())
+    );
+
+    // The body of a lambda that is the value of a definition runs when it is called, so lambdas can
+    // call each other, and the definitions that call them come after everything they use.
+    let program = "
+    (define result (even 4))
+    (define even (lambda (n) (cond [(= n 0) true] [else (odd (- n 1))])))
+    (define odd (lambda (n) (cond [(= n 0) false] [else (even (- n 1))])))
+    (define (main) result)
+    ";
+    assert_eq!(interpret_program(program.to_string()), Value::BoolValue(true));
+}
+
+#[test]
+fn test_with_handlers() {
+    let check = |program: &str, expected: &str| {
+        assert_eq!(
+            interpret_program_snippet(program.to_string()),
+            interpret_program_snippet(expected.to_string())
+        );
+    };
+
+    check(
+        "(with-handlers ([exn:fail:contract? (lambda (e) (exn-message e))]) (car empty))",
+        "\"Argument to car cannot be empty\"",
+    );
+    check(
+        "(with-handlers ([exn:fail:contract:divide-by-zero? (lambda (e) 0)]) (+ 1 (/ 1 0)))",
+        "0",
+    );
+    check(
+        "(with-handlers ([exn:fail? exn-message]) (error 'parse \"bad token ~a at ~s\" 'x \"end\"))",
+        "\"parse: bad token x at \\\"end\\\"\"",
+    );
+    check(
+        "(with-handlers ([symbol? (lambda (s) (list s 1))]) (raise 'oops))",
+        "(list 'oops 1)",
+    );
+    check(
+        "(with-handlers ([string? (lambda (s) 1)] [number? (lambda (n) (* n 2))]) (raise 21))",
+        "42",
+    );
+    check("(with-handlers ([exn:fail? (lambda (e) 0)]) (cond [false 1]))", "0");
+    check("(with-handlers ([exn:fail? (lambda (e) 0)]) 5)", "5");
+    check(
+        "(with-handlers ([number? (lambda (n) 'outer)]) (with-handlers ([string? (lambda (s) 'inner)]) (raise 1)))",
+        "'outer",
+    );
+}
+
+#[test]
+fn test_integer_overflow() {
+    let check = |program: &str, message: &str| {
+        assert_eq!(
+            interpret_program_snippet(format!("(with-handlers ([exn:fail:contract? exn-message]) {})", program)),
+            Value::StringValue(message.to_string())
+        );
+    };
+
+    check(
+        "(+ 1 2147483647)",
+        "Integer overflow: 1 + 2147483647 does not fit in a number",
+    );
+    check(
+        "(foldl + 0 (list 2147483647 1))",
+        "Integer overflow: 1 + 2147483647 does not fit in a number",
+    );
+    check(
+        "(foldl * 1 (list 65536 65536))",
+        "Integer overflow: 65536 * 65536 does not fit in a number",
+    );
+    check(
+        "(- -2147483647 2)",
+        "Integer overflow: -2147483647 - 2 does not fit in a number",
+    );
+    check("(* 65536 65536)", "Integer overflow: 65536 * 65536 does not fit in a number");
+    check(
+        "(/ (- -2147483647 1) -1)",
+        "Integer overflow: -2147483648 / -1 does not fit in a number",
+    );
+    assert_eq!(
+        interpret_program_snippet("(* 2 (+ 1073741823 0))".to_string()),
+        Value::NumValue(2147483646)
+    );
+}
+
+#[test]
+fn test_with_handlers_restores_bindings() {
+    let program = "
+    (define (fail-inside x) (car empty))
+    (define (safe x) (with-handlers ([exn? (lambda (e) x)]) (fail-inside (+ x 1))))
+    (define (main) (list (safe 1) (safe 2)))
+    ";
+    assert_eq!(
+        interpret_program(program.to_string()),
+        interpret_program_snippet("(list 1 2)".to_string())
+    );
+}
+
+#[test]
+#[should_panic(expected = "Argument to car cannot be empty")]
+fn test_unmatched_handler_reraises() {
+    interpret_program_snippet("(with-handlers ([string? (lambda (s) s)]) (car empty))".to_string());
+}
+
+#[test]
+#[should_panic(expected = "uncaught exception: 'oops")]
+fn test_uncaught_raise() {
+    interpret_program_snippet("(raise 'oops)".to_string());
+}
+
+#[test]
+fn test_step_limit() {
+    let limits = EvalLimits {
+        max_steps: Some(1000),
+        ..EvalLimits::default()
+    };
+    let program = "(define (main) (build-list 2000000000 (lambda (i) i)))";
+    assert_eq!(
+        try_interpret_program_with_limits(program.to_string(), &limits),
+        Err(RacketError::limit_exceeded(Limit::Steps(1000)))
+    );
+
+    // Programs that finish within their limits are unaffected.
+    let program = "(define (main) (foldl + 0 (list 1 2 3)))";
+    assert_eq!(
+        try_interpret_program_with_limits(program.to_string(), &limits),
+        Ok(Value::NumValue(6))
+    );
+}
+
+#[test]
+fn test_depth_limit() {
+    let limits = EvalLimits {
+        max_depth: Some(50),
+        ..EvalLimits::default()
+    };
+    let infinite_recursion = "
+    (define (loop n) (cond [(< n 0) n] [else (+ 1 (loop (+ n 1)))]))
+    (define (main) (loop 0))
+    ";
+    assert_eq!(
+        try_interpret_program_with_limits(infinite_recursion.to_string(), &limits),
+        Err(RacketError::limit_exceeded(Limit::Depth(50)))
+    );
+
+    let program = "
+    (define (count-down n) (cond [(= n 0) 'done] [else (count-down (- n 1))]))
+    (define (main) (count-down 40))
+    ";
+    assert_eq!(
+        try_interpret_program_with_limits(program.to_string(), &limits),
+        Ok(Value::SymbolValue(Symbol::intern("done")))
+    );
+}
+
+#[test]
+fn test_default_depth_limit() {
+    let infinite_recursion = "
+    (define (loop n) (cond [(< n 0) n] [else (+ 1 (loop (+ n 1)))]))
+    (define (main) (loop 0))
+    ";
+    assert_eq!(
+        try_interpret_program(infinite_recursion.to_string()),
+        Err(RacketError::limit_exceeded(Limit::Depth(DEFAULT_MAX_DEPTH)))
+    );
+}
+
+#[test]
+fn test_heap_cell_limit() {
+    let limits = EvalLimits {
+        max_heap_cells: Some(100),
+        ..EvalLimits::default()
+    };
+    let check = |program: &str| {
+        assert_eq!(
+            try_interpret_program_with_limits(format!("(define (main) {})", program), &limits),
+            Err(RacketError::limit_exceeded(Limit::HeapCells(100)))
+        );
+    };
+
+    check("(make-vector 1000000000 0)");
+    check("(foldl cons empty (build-list 500 (lambda (i) i)))");
+    check("(list? (build-list 101 (lambda (i) i)))");
+}
+
+#[test]
+fn test_timeout() {
+    let limits = EvalLimits {
+        timeout: Some(Duration::from_millis(50)),
+        ..EvalLimits::default()
+    };
+    let program = "(define (main) (build-list 2000000000 (lambda (i) i)))";
+    assert_eq!(
+        try_interpret_program_with_limits(program.to_string(), &limits),
+        Err(RacketError::limit_exceeded(Limit::Timeout(Duration::from_millis(50))))
+    );
+}
+
+#[test]
+fn test_limits_cannot_be_caught() {
+    let limits = EvalLimits {
+        max_steps: Some(1000),
+        ..EvalLimits::default()
+    };
+    let program = "
+    (define (main) (with-handlers ([(lambda (e) true) (lambda (e) 'caught)]) (build-list 2000000000 (lambda (i) i))))
+    ";
+    let result = try_interpret_program_with_limits(program.to_string(), &limits);
+    assert_eq!(result, Err(RacketError::limit_exceeded(Limit::Steps(1000))));
+    assert_eq!(result.unwrap_err().to_string(), "Evaluation exceeded the limit of 1000 steps");
+}
+
+#[test]
+fn test_interpreter() {
+    let mut interpreter = Interpreter::new();
+    let results = interpreter
+        .load_module(
+            "
+            (include stdlib::list)
+            (define scale 10)
+            (define (size xs) (list::length xs))
+            (define (scale-all xs) (map (lambda (x) (* x scale)) xs))
+            (scale-all (list 1 2))
+            ",
+        )
+        .unwrap();
+    assert_eq!(results, vec![vec![10i64, 20].into_value().unwrap()]);
+    assert_eq!(interpreter.eval_str("(size (list 1 2 3))"), Ok(Value::NumValue(3)));
+
+    // Later modules and expressions see earlier definitions.
+    interpreter
+        .load_module("(define (total xs) (foldl + 0 (scale-all xs)))")
+        .unwrap();
+    assert_eq!(interpreter.eval_str("(total (list 1 2 3))"), Ok(Value::NumValue(60)));
+
+    let arguments = [vec![4i64, 5].into_value().unwrap()];
+    let result = interpreter.call("scale-all", &arguments).unwrap();
+    assert_eq!(Vec::<i64>::from_value(&result), Ok(vec![40, 50]));
+    assert_eq!(
+        bool::from_value(
+            &interpreter
+                .call("empty?", &[Vec::<i64>::new().into_value().unwrap()])
+                .unwrap()
+        ),
+        Ok(true)
+    );
+
+    assert_eq!(
+        interpreter.call("missing", &[]),
+        Err(RacketError::variable("Undefined function 'missing'".to_string()))
+    );
+}
+
+#[test]
+fn test_interpreter_syntax_errors() {
+    let mut interpreter = Interpreter::new();
+    assert_eq!(
+        interpreter.load_module("\n(include stdlib::missing)"),
+        Err(RacketError::exn(
+            "exn:fail:syntax",
+            "Unknown module name stdlib::missing at 2:1".to_string()
+        ))
+    );
+    assert_eq!(
+        interpreter.load_module("(define (f) (cond))"),
+        Err(RacketError::exn("exn:fail:syntax", "No case in cond at 1:13".to_string()))
+    );
+    assert_eq!(
+        interpreter.eval_str("(+ 1"),
+        Err(RacketError::exn("exn:fail:read", "Unmatched open paren at 1:1".to_string()))
+    );
+    // Nothing from a module that fails to parse is defined.
+    assert_eq!(
+        interpreter.eval_str("(f)"),
+        Err(RacketError::variable("Undefined function 'f'".to_string()))
+    );
+}
+
+#[test]
+fn test_value_conversions() {
+    assert_eq!(true.into_value(), Ok(Value::BoolValue(true)));
+    assert_eq!("hi".into_value(), Ok(Value::StringValue("hi".to_string())));
+    assert_eq!(i64::from_value(&Value::NumValue(-3)), Ok(-3));
+    assert_eq!(
+        vec![vec![true], vec![]].into_value(),
+        Ok(interpret_program_snippet("(list (list true) empty)".to_string()))
+    );
+    assert_eq!(
+        i64::MAX.into_value().unwrap_err().to_string(),
+        "Integer 9223372036854775807 is too large to be a number value"
+    );
+    assert_eq!(
+        Vec::<bool>::from_value(&interpret_program_snippet("(list true 1)".to_string()))
+            .unwrap_err()
+            .to_string(),
+        "Expected a boolean but got 1"
+    );
+}
+
+#[test]
+fn test_unreadable_module() {
+    let span = Span {
+        start: 0,
+        end: 25,
+        line: 1,
+        column: 1,
+    };
+    let error = get_module_content("stdlib::missing", span).unwrap_err();
+    assert_eq!(error.span, span);
+    assert!(error.message.starts_with(&format!(
+        "Failed to read module stdlib::missing from {}",
+        module_path("stdlib::missing").display()
+    )));
+
+    // The standard library does not depend on the working directory.
+    assert!(module_path("stdlib::list").is_absolute());
+    assert!(module_path("stdlib::list").exists());
+}
+
+#[test]
+fn test_interpreter_limits() {
+    let mut interpreter = Interpreter::new();
+    interpreter.load_module("(define (spin n) (spin n))").unwrap();
+    interpreter.set_limits(EvalLimits {
+        max_depth: Some(20),
+        ..EvalLimits::default()
+    });
+    assert_eq!(
+        interpreter.call("spin", &[Value::NumValue(0)]),
+        Err(RacketError::limit_exceeded(Limit::Depth(20)))
+    );
+    // Each call gets a fresh budget.
+    assert_eq!(interpreter.eval_str("(+ 1 2)"), Ok(Value::NumValue(3)));
+}
+
+#[test]
+fn test_interpreter_deep_recursion() {
+    // Runs on the test's own thread, which has far less stack than the default depth would need
+    // without growing it.
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .load_module("(define (f n) (cond [(= n 0) 0] [else (+ 1 (f (- n 1)))]))")
+        .unwrap();
+    assert_eq!(interpreter.call("f", &[Value::NumValue(1000)]), Ok(Value::NumValue(1000)));
+    assert_eq!(interpreter.call("f", &[Value::NumValue(9000)]), Ok(Value::NumValue(9000)));
+    assert_eq!(interpreter.eval_str("(f 5000)"), Ok(Value::NumValue(5000)));
+}
+
+fn native_area(arguments: &[Value]) -> EvalResult {
+    match arguments {
+        [Value::NumValue(w), Value::NumValue(h)] if *w >= 0 && *h >= 0 => Ok(Value::NumValue(w * h)),
+        _ => Err(RacketError::contract(
+            "geometry::area expects two non-negative numbers".to_string(),
+        )),
+    }
+}
+
+fn native_origin(_: &[Value]) -> EvalResult {
+    vec![0i64, 0].into_value()
+}
+
+#[test]
+fn test_native_modules() {
+    let mut interpreter = Interpreter::new();
+    interpreter.register_module(
+        NativeModule::new("host::geometry")
+            .function("geometry::area", 2, native_area)
+            .function("geometry::origin", 0, native_origin),
+    );
+    interpreter.register_function("double", 1, |arguments| match arguments {
+        [Value::NumValue(n)] => Ok(Value::NumValue(n * 2)),
+        _ => Err(RacketError::contract("double expects a number".to_string())),
+    });
+
+    // Native functions are only visible to modules that include them.
+    assert_eq!(
+        interpreter.eval_str("(geometry::area 2 3)"),
+        Err(RacketError::variable("Undefined function 'geometry::area'".to_string()))
+    );
+
+    interpreter
+        .load_module(
+            "
+            (include host::geometry)
+            (define (areas sizes) (map (lambda (size) (geometry::area (car size) (cadr size))) sizes))
+            ",
+        )
+        .unwrap();
+    let check = |interpreter: &mut Interpreter, expr: &str, expected: &str| {
+        assert_eq!(
+            interpreter.eval_str(expr),
+            Ok(interpret_program_snippet(expected.to_string()))
+        );
+    };
+    check(&mut interpreter, "(areas (list (list 2 3) (list 4 5)))", "(list 6 20)");
+    check(&mut interpreter, "(geometry::origin)", "(list 0 0)");
+    check(&mut interpreter, "(map double (list 1 2))", "(list 2 4)");
+    check(
+        &mut interpreter,
+        "(with-handlers ([exn:fail:contract? exn-message]) (geometry::area -1 2))",
+        "\"geometry::area expects two non-negative numbers\"",
+    );
+    assert_eq!(
+        interpreter.call("geometry::area", &[Value::NumValue(1)]),
+        Err(RacketError::contract(
+            "Function geometry::area expects 2 argument(s) but was called with 1".to_string()
+        ))
+    );
+}
+
+fn check_types(program: &str) -> Vec<String> {
+    check_program(program.to_string())
+        .unwrap_or_else(|errors| panic!("Unexpected type errors: {:?}", errors))
+        .into_iter()
+        .map(|(name, scheme)| format!("{} : {}", name, scheme))
+        .collect()
+}
+
+fn check_type_errors(program: &str) -> Vec<String> {
+    check_program(program.to_string())
+        .expect_err("Expected type errors")
+        .into_iter()
+        .map(|error| error.to_string())
+        .collect()
+}
+
+#[test]
+fn test_type_check_infers_types() {
+    assert_eq!(
+        check_types(
+            "
+            (include stdlib::list)
+            (define limit 10)
+            (define (count lst) (foldl (lambda (x acc) (+ acc 1)) 0 lst))
+            (define (pairs xs ys) (map (lambda (x y) (list x y)) xs ys))
+            (define (lookup table key) (hash-ref table key 0))
+            (define (main) (list (count (list 'a 'b)) (count (list #\\a)) (list::length (list limit))))
+            "
+        ),
+        vec![
+            "limit : Integer",
+            "count : (All (a) (-> (Listof a) Integer))",
+            "pairs : (All (a) (-> (Listof a) (Listof a) (Listof (Listof a))))",
+            "lookup : (All (a b) (-> (HashTable a b) a b))",
+            "main : (-> (Listof Integer))",
+        ]
+    );
+
+    // Mutually recursive functions and internal definitions.
+    assert_eq!(
+        check_types(
+            "
+            (define (my-even? n) (cond [(= n 0) true] [else (my-odd? (- n 1))]))
+            (define (my-odd? n) (cond [(= n 0) false] [else (my-even? (- n 1))]))
+            (define (swap-all lst)
+                (define (swap p) (list (cadr p) (car p)))
+                (map swap lst))
+            "
+        ),
+        vec![
+            "my-even? : (-> Integer Boolean)",
+            "my-odd? : (-> Integer Boolean)",
+            "swap-all : (All (a) (-> (Listof (Listof a)) (Listof (Listof a))))",
+        ]
+    );
+}
+
+#[test]
+fn test_type_check_reports_errors() {
+    assert_eq!(
+        check_type_errors("(define (f x) (+ x 1))\n(define (main)\n  (f \"a\"))"),
+        vec!["Type error at 3:6: expected Integer but got String: \"a\""]
+    );
+    assert_eq!(
+        check_type_errors("(define (sign n) (cond [(< n 0) 'negative] [else 1]))"),
+        vec!["Type error at 1:50: expected Symbol but got Integer: 1"]
+    );
+    assert_eq!(
+        check_type_errors("(define (main) (cond [1 2] [else (undefined-function 3)]))"),
+        vec![
+            "Type error at 1:23: expected Boolean but got Integer: 1",
+            "Type error at 1:34: Undefined function undefined-function",
+        ]
+    );
+    assert_eq!(
+        check_type_errors("(define (main) (car 1 2))"),
+        vec!["Type error at 1:16: Function car cannot be called with 2 argument(s)"]
+    );
+}
+
+#[test]
+fn test_type_check_examples() {
+    for example in [
+        "factorial.rkt",
+        "fibonacci.rkt",
+        "list_filter_even.rkt",
+        "list_sum.rkt",
+        "macros.rkt",
+        "median.rkt",
+        "primes.rkt",
+        "stdlib_test.rkt",
+    ] {
+        assert!(
+            check_program(get_example_program(example)).is_ok(),
+            "{} failed to type check",
+            example
+        );
+    }
+
+    // Lists have a single element type, so the examples that build lists of mixed types do not type check.
+    let errors = |example: &str| -> Vec<String> {
+        let errors = check_program(get_example_program(example)).unwrap_err();
+        errors.iter().map(|error| error.to_string()).collect()
+    };
+    assert_eq!(
+        errors("list_flatten.rkt"),
+        vec![
+            "Type error at 3:1: list-flatten is used as (-> a (Listof a)) but defined as (-> (Listof a) (Listof a))",
+            "Type error at 6:77: expected a but got (Listof a): (cdr lst)",
+            "Type error at 7:41: expected a but got (Listof a): (cdr lst)",
+            "Type error at 9:46: expected Integer but got (Listof Integer): (list 3 (list 4 5) 6 7)",
+            "Type error at 9:54: expected Integer but got (Listof Integer): (list 4 5)",
+            "Type error at 9:71: expected (Listof Integer) but got Integer: 8",
+            "Type error at 9:73: expected (Listof Integer) but got Integer: 9",
+        ]
+    );
+    assert_eq!(
+        errors("racket_compat.rkt"),
+        vec![
+            "Type error at 7:10: expected Symbol but got String: \"b\"",
+            "Type error at 7:14: expected Symbol but got Char: #\\c",
+            "Type error at 7:18: expected Symbol but got Boolean: true",
+            "Type error at 8:14: expected (-> Integer Integer) but got Integer: 1",
+            "Type error at 9:11: expected Integer but got Symbol: (quote x)",
+        ]
+    );
+}
+
+#[test]
+fn test_type_annotations_are_enforced_at_function_boundaries() {
+    let definitions = "
+        (struct point ([x : Integer] [y : Integer]))
+        (struct tagged (tag value))
+        (: fib (-> Integer Integer))
+        (define (fib n) (cond [(< n 2) n] [else (+ (fib (- n 1)) (fib (- n 2)))]))
+        (define (norm [p : point]) : Integer (+ (point-x p) (point-y p)))
+        (define (describe [x : (U Integer String)]) : String (cond [(string? x) x] [else \"number\"]))
+        (define (broken [x : Integer]) : (Listof Integer) x)";
+
+    assert_eq!(run_with_main(definitions, "(fib 10)"), Ok(Value::NumValue(55)));
+    assert_eq!(run_with_main(definitions, "(norm (point 3 4))"), Ok(Value::NumValue(7)));
+    assert_eq!(
+        run_with_main(definitions, "(list (describe 1) (describe \"a\"))"),
+        Ok(interpret_program_snippet("(list \"number\" \"a\")".to_string()))
+    );
+    assert_eq!(
+        run_with_main(
+            definitions,
+            "(list (point? (point 1 2)) (point? (tagged 'a 1)) (tagged-value (tagged 'a \"b\")))"
+        )
+        .map(|value| value.to_string()),
+        Ok("(list true false \"b\")".to_string())
+    );
+    assert_eq!(
+        run_with_main(definitions, "(point 1 2)").map(|value| to_racket_string(&value)),
+        Ok("(point 1 2)".to_string())
+    );
+
+    assert_eq!(
+        run_with_main(definitions, "(fib \"ten\")"),
+        Err(RacketError::contract(
+            "fib: contract violation\n  expected: Integer\n  given: \"ten\"\n  in: the 1st argument of (-> Integer Integer)"
+                .to_string()
+        ))
+    );
+    assert_eq!(
+        run_with_main(definitions, "(point 1 'a)"),
+        Err(RacketError::contract(
+            "point: contract violation\n  expected: Integer\n  given: 'a\n  in: the 2nd argument of (-> Integer Integer point)"
+                .to_string()
+        ))
+    );
+    assert_eq!(
+        run_with_main(definitions, "(describe 'a)"),
+        Err(RacketError::contract(
+            "describe: contract violation\n  expected: (U Integer String)\n  given: 'a\n  in: the 1st argument of (-> (U Integer String) String)"
+                .to_string()
+        ))
+    );
+    assert_eq!(
+        run_with_main(definitions, "(broken 1)"),
+        Err(RacketError::contract(
+            "broken: contract violation\n  expected: (Listof Integer)\n  given: 1\n  in: the range of (-> Integer (Listof Integer))"
+                .to_string()
+        ))
+    );
+    assert!(run_with_main(definitions, "(norm (tagged 1 2))").is_err());
+}
+
+#[test]
+fn test_type_annotations_are_checked_statically() {
+    assert_eq!(
+        check_types(
+            "
+            (struct point ([x : Integer] [y : Integer]))
+            (: first-or (All (a) (-> (Listof a) a a)))
+            (define (first-or lst default) (cond [(empty? lst) default] [else (car lst)]))
+            (define (norm [p : point]) : Integer (+ (point-x p) (point-y p)))
+            (define (describe [x : (U Integer String)]) : String \"value\")
+            (define origin : point (point 0 0))
+            (define (main) (list (norm origin) (first-or (list 1) 2) (char->integer (car (string->list (describe 1))))))
+            "
+        ),
+        vec![
+            "first-or : (All (a) (-> (Listof a) a a))",
+            "norm : (-> point Integer)",
+            "describe : (-> (U Integer String) String)",
+            "origin : point",
+            "main : (-> (Listof Integer))",
+        ]
+    );
+
+    assert_eq!(
+        check_type_errors(
+            "(: fib (-> Integer Integer))
+(define (fib n) n)
+(define (main) (fib \"ten\"))"
+        ),
+        vec!["Type error at 3:21: expected Integer but got String: \"ten\""]
+    );
+    assert_eq!(
+        check_type_errors("(: id (-> a a))\n(define (id x) (+ x 1))"),
+        vec!["Type error at 2:1: id is declared as (-> a a) but its definition has type (-> Integer Integer)"]
+    );
+    assert_eq!(
+        check_type_errors("(define (f [s : String]) : Integer (+ s 1))"),
+        vec!["Type error at 1:39: expected Integer but got String: s"]
+    );
+    assert_eq!(
+        check_type_errors("(define (f [x : (U Integer String)]) x)\n(define (main) (f 'a))"),
+        vec!["Type error at 2:19: expected (U Integer String) but got Symbol: (quote a)"]
+    );
+    assert_eq!(
+        check_type_errors("(define (f [x : Integr]) x)"),
+        vec!["Type error at 1:17: Unknown type Integr"]
+    );
+}
+
+#[test]
+fn test_define_contract_blames_the_caller_or_the_function() {
+    let definitions = "
+        (define (positive? n) (> n 0))
+        (define/contract (area w h) (-> positive? positive? integer?) (* w h))
+        (define/contract (label n) (-> integer? string?) (cond [(< n 0) n] [else \"ok\"]))
+        (define/contract (total xs) (-> (listof integer?) (or/c integer? 'none))
+            (cond [(empty? xs) 'none] [else (foldl + 0 xs)]))";
+
+    assert_eq!(run_with_main(definitions, "(area 2 3)"), Ok(Value::NumValue(6)));
+    assert_eq!(
+        run_with_main(definitions, "(list (total (list 1 2)) (total empty))").map(|value| value.to_string()),
+        Ok("(list 3 'none)".to_string())
+    );
+    assert_eq!(
+        run_with_main(definitions, "(area 2 0)"),
+        Err(RacketError::contract(
+            "area: contract violation\n  expected: positive?\n  given: 0\n  in: the 2nd argument of (-> positive? positive? integer?)\n  contract from: (function area)\n  blaming: top-level"
+                .to_string()
+        ))
+    );
+    assert_eq!(
+        run_with_main(definitions, "(label -1)"),
+        Err(RacketError::contract(
+            "label: contract violation\n  expected: string?\n  given: -1\n  in: the range of (-> integer? string?)\n  contract from: (function label)\n  blaming: (function label)"
+                .to_string()
+        ))
+    );
+    assert_eq!(
+        run_with_main(definitions, "(total (list 1 \"2\"))"),
+        Err(RacketError::contract(
+            "total: contract violation\n  expected: (listof integer?)\n  given: (list 1 \"2\")\n  in: the 1st argument of (-> (listof integer?) (or/c integer? (quote none)))\n  contract from: (function total)\n  blaming: top-level"
+                .to_string()
+        ))
+    );
+}
+
+#[test]
+fn test_contract_out_blames_the_calling_module() {
+    let definitions = "(include stdlib::list)";
+
+    assert_eq!(
+        run_with_main(definitions, "(list::nth (list 1 2 3) 2)"),
+        Ok(Value::NumValue(3))
+    );
+    assert_eq!(
+        run_with_main(definitions, "(list::sort (list 3 1 2))").map(|value| value.to_string()),
+        Ok("(list 1 2 3)".to_string())
+    );
+    assert_eq!(
+        run_with_main(definitions, "(list::nth (list 1 2 3) -1)"),
+        Err(RacketError::contract(
+            "list::nth: contract violation\n  expected: list::index?\n  given: -1\n  in: the 2nd argument of (-> list? list::index? any/c)\n  contract from: stdlib::list\n  blaming: top-level"
+                .to_string()
+        ))
+    );
+    assert_eq!(
+        run_with_main(definitions, "(list::length 5)"),
+        Err(RacketError::contract(
+            "list::length: contract violation\n  expected: list?\n  given: 5\n  in: the 1st argument of (-> list? integer?)\n  contract from: stdlib::list\n  blaming: top-level"
+                .to_string()
+        ))
+    );
+    assert_eq!(
+        check_types("(include stdlib::list) (define/contract (f x) (-> integer? integer?) (list::nth (list x) 0))"),
+        vec!["f : (All (a) (-> a a))"]
+    );
+}
+
+#[test]
+fn test_runtime_errors_have_backtraces() {
+    let backtrace = |program: &str| {
+        try_interpret_program(program.to_string())
+            .unwrap_err()
+            .backtrace()
+            .to_string()
+    };
+
+    assert_eq!(
+        backtrace(
+            "(include stdlib::list)
+
+            (define (first-of lst) (car lst))
+            (define (count-down n)
+              (cond [(= n 0) (first-of (list::take (list 1) n))]
+                    [else (count-down (- n 1))]))
+
+            (define (main) (count-down 3))"
+        ),
+        "context...:\n   first-of at 5:30\n   count-down at 6:27 [repeats 2 more times]\n   count-down at 8:28\n   main"
+    );
+    assert_eq!(
+        backtrace("(include stdlib::list)\n(define (main) (list::nth (list 1 2) 5))"),
+        "context...:\n   list::nth at stdlib::list:47:11 [repeats 1 more time]\n   list::nth at 2:16\n   main"
+    );
+    assert_eq!(
+        backtrace("(define (main) ((lambda (x) (raise x)) 'oops))"),
+        "context...:\n   raise at 1:29\n   lambda at 1:16\n   main"
+    );
+
+    // An exception no handler accepts keeps the backtrace of where it was raised.
+    assert_eq!(
+        backtrace(
+            "(define (fail) (error \"failed\"))
+            (define (main) (with-handlers ([string? (lambda (e) e)]) (fail)))"
+        ),
+        "context...:\n   error at 1:16\n   fail at 2:70\n   main"
+    );
+    assert_eq!(
+        try_interpret_program_with_limits(
+            "(define (loop n) (loop (+ n 1))) (define (main) (loop 0))".to_string(),
+            &EvalLimits {
+                max_depth: Some(3),
+                ..EvalLimits::default()
+            }
+        )
+        .unwrap_err()
+        .backtrace()
+        .to_string(),
+        "context...:\n   loop at 1:18 [repeats 1 more time]\n   loop at 1:49\n   main"
+    );
+}
+
+// Output that the test can read after handing a writer to the debugger.
+#[derive(Clone, Default)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn debug_session(program: &str, commands: &'static str) -> (EvalResult, String) {
+    let output = SharedOutput::default();
+    let console = DebugConsole::new(commands.as_bytes(), output.clone(), "program.rkt");
+    let result = with_debugger(Box::new(console), Vec::new(), Resume::StepIn, || {
+        try_interpret_program(program.to_string())
+    });
+    let transcript = String::from_utf8(output.0.borrow().clone()).unwrap();
+    (result, transcript)
+}
+
+#[test]
+fn test_debugger_steps_through_calls() {
+    let program = "(include stdlib::list)
+        (define (square x) (* x x))
+        (define (sum-squares xs)
+          (cond [(empty? xs) 0]
+                [else (+ (square (car xs)) (sum-squares (cdr xs)))]))
+        (define (main) (sum-squares (list 1 2)))";
+
+    let (result, transcript) = debug_session(
+        program,
+        "step\nbindings\nnext\nbacktrace\nprint (list xs (square 3))\nbreak program.rkt:5\nbreak nope.rkt:5\ncontinue\ndelete\nout\ncontinue\n",
+    );
+    assert_eq!(result, Ok(Value::NumValue(5)));
+    assert_eq!(
+        transcript,
+        "-> 6:24: (sum-squares (list 1 2))
+(debug) -> 5:26: (square 1)
+(debug) xs = (list 1 2)
+(debug) <- square returned 1
+(debug) context...:
+   sum-squares at 6:24
+   main
+(debug) (list (list 1 2) 9)
+(debug) Breakpoint at line 5
+(debug) Unknown file nope.rkt
+(debug) -> 5:44: (sum-squares (list 2))
+(debug) (debug) <- sum-squares returned 5
+(debug) "
+    );
+
+    // Breakpoints on functions and on lines of the standard library.
+    let (result, transcript) = debug_session(
+        "(include stdlib::list) (define (main) (list::nth (list 1 2 3) 2))",
+        "break list::take\nbreak stdlib::list:47\ncontinue\ncontinue\nbindings\nquit\n",
+    );
+    assert_eq!(result, Ok(Value::NumValue(3)));
+    assert_eq!(
+        transcript,
+        "-> 1:39: (list::nth (list 1 2 3) 2)
+(debug) Breakpoint at list::take
+(debug) Breakpoint at stdlib::list:47
+(debug) -> stdlib::list:47:11: (list::nth (list 2 3) 1)
+(debug) -> stdlib::list:47:11: (list::nth (list 3) 0)
+(debug) lst = (list 2 3)
+n = 1
+(debug) "
+    );
+}
+
+// Frames messages with Content-Length headers, as a client sends them.
+fn frame_messages(messages: &[serde_json::Value]) -> Vec<u8> {
+    let mut input = Vec::new();
+    for message in messages {
+        let content = message.to_string();
+        input.extend(format!("Content-Length: {}\r\n\r\n{}", content.len(), content).into_bytes());
+    }
+    input
+}
+
+// The messages a server wrote to the output.
+fn read_framed_messages(output: SharedOutput) -> Vec<serde_json::Value> {
+    let output = String::from_utf8(output.0.borrow().clone()).unwrap();
+    output
+        .split("Content-Length: ")
+        .skip(1)
+        .map(|message| serde_json::from_str(message.split_once("\r\n\r\n").unwrap().1).unwrap())
+        .collect()
+}
+
+// Sends the requests to a DAP server in one go, as a client that does not wait for responses, and
+// returns the messages the server sent back.
+fn dap_session(requests: &[serde_json::Value]) -> Vec<serde_json::Value> {
+    let requests: Vec<serde_json::Value> = requests
+        .iter()
+        .enumerate()
+        .map(|(seq, request)| {
+            let mut request = request.clone();
+            request["seq"] = serde_json::json!(seq + 1);
+            request["type"] = serde_json::json!("request");
+            request
+        })
+        .collect();
+
+    let output = SharedOutput::default();
+    run_dap_server(std::io::Cursor::new(frame_messages(&requests)), output.clone());
+    read_framed_messages(output)
+}
+
+#[test]
+fn test_dap_server_debugs_a_program() {
+    use serde_json::json;
+
+    let path = env::temp_dir().join(format!("rusty-racket-dap-{}.rkt", std::process::id()));
+    fs::write(
+        &path,
+        "(define (square x) (* x x))
+(define (sum-squares xs)
+  (cond [(empty? xs) 0]
+        [else (+ (square (car xs)) (sum-squares (cdr xs)))]))
+(define (main) (sum-squares (list 1 2)))",
+    )
+    .unwrap();
+    let path = path.to_string_lossy().to_string();
+
+    let messages = dap_session(&[
+        json!({ "command": "initialize", "arguments": { "adapterID": "rusty-racket" } }),
+        json!({ "command": "launch", "arguments": { "program": path } }),
+        json!({ "command": "setBreakpoints", "arguments": { "source": { "path": path }, "breakpoints": [{ "line": 4 }] } }),
+        json!({ "command": "configurationDone" }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "scopes", "arguments": { "frameId": 0 } }),
+        json!({ "command": "variables", "arguments": { "variablesReference": 1 } }),
+        json!({ "command": "variables", "arguments": { "variablesReference": 2 } }),
+        json!({ "command": "evaluate", "arguments": { "expression": "(square (car xs))", "frameId": 0 } }),
+        json!({ "command": "evaluate", "arguments": { "expression": "(car empty)", "frameId": 0 } }),
+        json!({ "command": "next", "arguments": { "threadId": 1 } }),
+        json!({ "command": "setBreakpoints", "arguments": { "source": { "path": path }, "breakpoints": [] } }),
+        json!({ "command": "stepOut", "arguments": { "threadId": 1 } }),
+        json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+        json!({ "command": "disconnect" }),
+    ]);
+    fs::remove_file(&path).unwrap();
+
+    let response = |command: &str| {
+        messages
+            .iter()
+            .find(|message| message["type"] == "response" && message["command"] == command)
+            .unwrap_or_else(|| panic!("No response to {}", command))
+            .clone()
+    };
+    let events: Vec<(String, String)> = messages
+        .iter()
+        .filter(|message| message["type"] == "event")
+        .map(|message| {
+            let detail = &message["body"]["description"];
+            let detail = detail.as_str().or(message["body"]["output"].as_str()).unwrap_or_default();
+            (message["event"].as_str().unwrap().to_string(), detail.to_string())
+        })
+        .collect();
+    let event = |name: &str, detail: &str| (name.to_string(), detail.to_string());
+    assert_eq!(
+        events,
+        vec![
+            event("initialized", ""),
+            event("stopped", "4:18: (square 1)"),
+            event("stopped", "square returned 1"),
+            event("stopped", "sum-squares returned 5"),
+            event("output", "5\n"),
+            event("exited", ""),
+            event("terminated", ""),
+        ]
+    );
+
+    let frames = &response("stackTrace")["body"]["stackFrames"];
+    let frame = |i: usize| {
+        (
+            frames[i]["name"].as_str().unwrap().to_string(),
+            frames[i]["line"].as_u64().unwrap(),
+            frames[i]["column"].as_u64().unwrap(),
+        )
+    };
+    assert_eq!(frames.as_array().unwrap().len(), 2);
+    assert_eq!(frame(0), ("sum-squares".to_string(), 4, 18));
+    assert_eq!(frame(1), ("main".to_string(), 5, 16));
+    assert_eq!(frames[0]["source"]["path"], json!(path));
+
+    assert_eq!(
+        response("scopes")["body"]["scopes"],
+        json!([{ "name": "Locals", "variablesReference": 1, "expensive": false }])
+    );
+    let variables: Vec<serde_json::Value> = messages
+        .iter()
+        .filter(|message| message["command"] == "variables")
+        .map(|message| message["body"]["variables"].clone())
+        .collect();
+    assert_eq!(
+        variables,
+        vec![
+            json!([{ "name": "xs", "value": "(list 1 2)", "variablesReference": 2 }]),
+            json!([
+                { "name": "[0]", "value": "1", "variablesReference": 0 },
+                { "name": "[1]", "value": "2", "variablesReference": 0 },
+            ]),
+        ]
+    );
+
+    let evaluations: Vec<serde_json::Value> = messages
+        .iter()
+        .filter(|message| message["command"] == "evaluate")
+        .map(|message| json!([message["success"], message["body"]["result"], message["message"]]))
+        .collect();
+    assert_eq!(
+        evaluations,
+        vec![
+            json!([true, "1", null]),
+            json!([false, null, "Argument to car cannot be empty"]),
+        ]
+    );
+    assert_eq!(response("disconnect")["success"], json!(true));
+}
+
+// Sends the messages to a language server in one go and returns the messages it sent back.
+fn lsp_session(messages: &[serde_json::Value]) -> Vec<serde_json::Value> {
+    let output = SharedOutput::default();
+    run_lsp_server(std::io::Cursor::new(frame_messages(messages)), output.clone());
+    read_framed_messages(output)
+}
+
+#[test]
+fn test_lsp_server_skips_malformed_messages() {
+    use serde_json::json;
+
+    let mut input = b"Content-Length: 10\r\n\r\n{not json}Content-Length: x\r\n\r\n".to_vec();
+    input.extend(frame_messages(&[
+        json!({ "jsonrpc": "2.0", "id": 1, "method": "shutdown" }),
+        json!({ "jsonrpc": "2.0", "method": "exit" }),
+    ]));
+    let output = SharedOutput::default();
+    run_lsp_server(std::io::Cursor::new(input), output.clone());
+    assert_eq!(
+        read_framed_messages(output),
+        vec![json!({ "jsonrpc": "2.0", "id": 1, "result": null })]
+    );
+}
+
+#[test]
+fn test_lsp_server_answers_requests() {
+    use serde_json::json;
+
+    let uri = "file:///program.rkt";
+    let program = "#lang rusty-racket
+(include stdlib::list)
+(: square (-> Integer Integer))
+(define (square x) (* x x))
+(define (main)
+  (list::nth (list (square 1 2)) 0))";
+    let at = |id: i64, method: &str, line: i64, character: i64| {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": { "textDocument": { "uri": uri }, "position": { "line": line, "character": character } },
+        })
+    };
+    let messages = lsp_session(&[
+        json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": uri, "languageId": "racket", "version": 1, "text": program } },
+        }),
+        at(2, "textDocument/definition", 5, 5),
+        at(3, "textDocument/definition", 5, 22),
+        at(4, "textDocument/hover", 5, 22),
+        at(5, "textDocument/completion", 5, 11),
+        json!({ "jsonrpc": "2.0", "id": 6, "method": "textDocument/documentSymbol", "params": { "textDocument": { "uri": uri } } }),
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": { "textDocument": { "uri": uri, "version": 2 }, "contentChanges": [{ "text": "(define (f x)\n  (+ x 1)))" }] },
+        }),
+        json!({ "jsonrpc": "2.0", "id": 7, "method": "workspace/symbol", "params": {} }),
+        json!({ "jsonrpc": "2.0", "id": 8, "method": "shutdown" }),
+        json!({ "jsonrpc": "2.0", "method": "exit" }),
+    ]);
+
+    let response = |id: i64| {
+        messages
+            .iter()
+            .find(|message| message["id"] == json!(id))
+            .unwrap_or_else(|| panic!("No response to {}", id))
+            .clone()
+    };
+    let range = |start_line: i64, start: i64, end_line: i64, end: i64| json!({ "start": { "line": start_line, "character": start }, "end": { "line": end_line, "character": end } });
+    assert_eq!(response(1)["result"]["capabilities"]["hoverProvider"], json!(true));
+
+    let diagnostics: Vec<serde_json::Value> = messages
+        .iter()
+        .filter(|message| message["method"] == "textDocument/publishDiagnostics")
+        .map(|message| message["params"]["diagnostics"].clone())
+        .collect();
+    assert_eq!(
+        diagnostics,
+        vec![
+            json!([{
+                "range": range(5, 19, 5, 31),
+                "severity": 1,
+                "source": "rusty-racket",
+                "message": "Function square expects 1 argument(s) but was called with 2",
+            }]),
+            json!([{
+                "range": range(1, 10, 1, 11),
+                "severity": 1,
+                "source": "rusty-racket",
+                "message": "Unmatched close paren at 2:11",
+            }]),
+        ]
+    );
+
+    let list_module = env::current_dir().unwrap().join("examples/stdlib/list.rkt");
+    assert_eq!(
+        response(2)["result"]["uri"],
+        json!(format!("file://{}", list_module.display()))
+    );
+    assert_eq!(response(3)["result"], json!({ "uri": uri, "range": range(3, 9, 3, 15) }));
+    assert_eq!(
+        response(4)["result"]["contents"]["value"],
+        json!("```racket\n(: square (-> Integer Integer))\n(square x)\n```")
+    );
+
+    let completions = response(5)["result"].as_array().unwrap().clone();
+    assert!(completions.contains(&json!({ "label": "list::nth", "kind": 3, "detail": "(list::nth lst n)" })));
+    assert!(completions
+        .iter()
+        .all(|item| item["label"].as_str().unwrap().starts_with("list::")));
+
+    let symbols: Vec<(serde_json::Value, serde_json::Value)> = response(6)["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|symbol| (symbol["name"].clone(), symbol["range"].clone()))
+        .collect();
+    assert_eq!(
+        symbols,
+        vec![(json!("square"), range(3, 0, 3, 27)), (json!("main"), range(4, 0, 5, 36)),]
+    );
+
+    assert_eq!(response(7)["error"]["code"], json!(-32601));
+    assert_eq!(response(8)["result"], json!(null));
+}
+
+#[test]
+fn test_examples_have_no_diagnostics() {
+    for entry in fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|extension| extension == "rkt") {
+            let diagnostics = analyze(&fs::read_to_string(&path).unwrap()).diagnostics;
+            assert_eq!(diagnostics, vec![], "{}", path.display());
+        }
+    }
+}
+
+#[test]
+fn test_formatter_keeps_comments_and_indents_racket_style() {
+    let program = "#lang racket
+; Sums a list.
+(define   (sum lst)   ; the recursive case is last
+    (cond [(empty? lst) 0]
+      [else (+ (car lst)
+  (sum (cdr lst)))]))
+
+
+(define chars (list #\\( #\\space \"a \\\"quoted\\\" (string)\"))
+(define data '(1 [2 3] #(4 5) `(a ,b ,@c)))
+(define (long-function-name first-argument second-argument third-argument fourth-argument fifth-argument)
+  (list first-argument second-argument third-argument fourth-argument fifth-argument first-argument))
+(sum (list 1 2 ; two
+  3))";
+    let formatted = "#lang racket
+; Sums a list.
+(define (sum lst) ; the recursive case is last
+  (cond
+    [(empty? lst) 0]
+    [else (+ (car lst) (sum (cdr lst)))]))
+
+(define chars (list #\\( #\\space \"a \\\"quoted\\\" (string)\"))
+(define data '(1 [2 3] #(4 5) `(a ,b ,@c)))
+(define (long-function-name first-argument
+                            second-argument
+                            third-argument
+                            fourth-argument
+                            fifth-argument)
+  (list first-argument second-argument third-argument fourth-argument fifth-argument first-argument))
+(sum
+ (list 1
+       2 ; two
+       3))
+";
+    assert_eq!(format_program(program).unwrap(), formatted);
+    assert_eq!(format_program(formatted).unwrap(), formatted);
+}
+
+#[test]
+fn test_examples_are_formatted() {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for directory in ["examples", "examples/stdlib"] {
+        paths.extend(fs::read_dir(directory).unwrap().map(|entry| entry.unwrap().path()));
+    }
+    for path in paths
+        .iter()
+        .filter(|path| path.extension().is_some_and(|extension| extension == "rkt"))
+    {
+        let program = fs::read_to_string(path).unwrap();
+        let formatted = format_program(&program).unwrap();
+        assert_eq!(formatted, program, "{} is not formatted", path.display());
+        // Formatting a program squashed onto one line gives the same result, apart from empty lines,
+        // since only whitespace matters. Comments keep their own lines.
+        let lang_line_end = if program.starts_with("#lang") {
+            program.find('\n').unwrap()
+        } else {
+            0
+        };
+        let (lang_line, rest) = program.split_at(lang_line_end);
+        let squashed = rest.lines().fold(format!("{}\n", lang_line), |squashed, line| {
+            let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+            match line.find(';') {
+                Some(0) => squashed + "\n" + &line + "\n",
+                Some(_) => squashed + &line + "\n",
+                None => squashed + &line + " ",
+            }
+        });
+        assert_eq!(
+            format_program(&squashed).unwrap().replace("\n\n", "\n"),
+            formatted.replace("\n\n", "\n")
+        );
+    }
+}
+
+#[test]
+fn test_linter_reports_rules_unless_allowed() {
+    let program = "(include stdlib::list)
+
+(define (helper x unused) (* x 2))
+
+(define (never-called) 1)
+
+(define (count lst list::length)
+  (cond
+    [(empty? lst) 0]
+    [true (+ 1 (count (cdr lst) list::length))]
+    [(= 1 1) 2]))
+
+(define (sign n)
+  (cond
+    [(< n 0) -1]
+    [(> n 0) 1]))
+
+(define (loop n acc) ; lint: allow unused-parameter
+  (cond
+    [(= n 0) 0]
+    ; lint: allow non-tail-recursion
+    [else (+ 1 (loop (- n 1) acc))]))
+
+(define (main)
+  (__list::list-reverse-helper (list (helper 1 2) (sign 1) (loop 1 0) (count empty list::length)) empty))";
+    let lints = |enabled: &[&str]| -> Vec<String> {
+        let lints = lint_program(program, None, enabled).unwrap();
+        lints.iter().map(|lint| lint.to_string()).collect()
+    };
+    assert_eq!(
+        lints(&[]),
+        vec![
+            "3:19: unused is never used [unused-parameter]",
+            "5:10: never-called is never used [unused-function]",
+            "7:20: list::length hides the function list::length [shadowed-name]",
+            "11:5: this clause comes after the clause at 10:5 that always matches [unreachable-clause]",
+            "14:3: cond has no [true ...] or [else ...] clause, so it raises an error when no clause matches [missing-else]",
+            "25:3: __list::list-reverse-helper is private to stdlib::list [private-helper]",
+        ]
+    );
+    // non-tail-recursion is only reported when it is enabled.
+    assert!(lints(&["non-tail-recursion"])
+        .contains(&"10:16: the recursive call to count is not in tail position [non-tail-recursion]".to_string()));
+    assert_eq!(lints(&["non-tail-recursion"]).len(), 7);
+
+    // Modules can call their own helpers, and only report unused functions that are private.
+    let module = fs::read_to_string(module_path("stdlib::list")).unwrap();
+    assert_eq!(lint_program(&module, Some("stdlib::list"), &[]), Ok(Vec::new()));
+    assert!(lint_program(&module, Some("stdlib::list"), &["non-tail-recursion"])
+        .unwrap()
+        .iter()
+        .all(|lint| lint.rule == "non-tail-recursion"));
+
+    // The examples and the standard library have nothing to report.
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for directory in ["examples", "examples/stdlib"] {
+        paths.extend(fs::read_dir(directory).unwrap().map(|entry| entry.unwrap().path()));
+    }
+    for path in paths
+        .iter()
+        .filter(|path| path.extension().is_some_and(|extension| extension == "rkt"))
+    {
+        let program = fs::read_to_string(path).unwrap();
+        let module = source_module(path.to_str().unwrap());
+        assert_eq!(
+            lint_program(&program, module.as_deref(), &[]),
+            Ok(Vec::new()),
+            "{}",
+            path.display()
+        );
+    }
+}
+
+#[test]
+fn test_checks_report_expected_and_actual_values() {
+    let program = "#lang racket
+(define (double x) (* 2 x))
+(define nested (list 1 (list 2 3)))
+
+(check-expect (double 2) 4)
+(check-equal? nested (list 1 (list 2 4)) \"nested lists\")
+(check-within (double 5) 11 1)
+(check-within (list 9 (double 5)) (list 10 13) 1)
+(check-error (car empty))
+(check-error (raise 'oops) \"uncaught exception: 'oops\")
+(check-error (double 1))
+(check-expect (double 1 2) 2)
+
+(double 3)";
+    let list = |values: Vec<Value>| Value::ListValue(ValueList::from_vec(values));
+    let results = run_checks(program.to_string());
+    let outcomes: Vec<&CheckOutcome> = results.iter().map(|result| &result.outcome).collect();
+    assert_eq!(
+        outcomes[..7],
+        [
+            &CheckOutcome::Passed,
+            &CheckOutcome::Failed {
+                expected: list(vec![Value::NumValue(1), list(vec![Value::NumValue(2), Value::NumValue(4)])]),
+                actual: list(vec![Value::NumValue(1), list(vec![Value::NumValue(2), Value::NumValue(3)])]),
+            },
+            &CheckOutcome::Passed,
+            &CheckOutcome::Failed {
+                expected: list(vec![Value::NumValue(10), Value::NumValue(13)]),
+                actual: list(vec![Value::NumValue(9), Value::NumValue(10)]),
+            },
+            &CheckOutcome::Passed,
+            &CheckOutcome::Passed,
+            &CheckOutcome::Failed {
+                expected: Value::StringValue("an exception".to_string()),
+                actual: Value::NumValue(2),
+            },
+        ]
+    );
+    assert_eq!(
+        results[1].to_string(),
+        "6:1: FAIL (check-equal? nested (list 1 (list 2 4)) \"nested lists\")
+  message:  nested lists
+  expected: (list 1 (list 2 4))
+  actual:   (list 1 (list 2 3))
+  first difference at index 1, index 1: expected 4, actual 3"
+    );
+    assert_eq!(
+        results[7].to_string(),
+        "12:1: ERROR (check-expect (double 1 2) 2)
+  raised:   Function double expects 1 argument(s) but was called with 2"
+    );
+
+    let report = junit_report(&[("checks.rkt".to_string(), results)]);
+    assert!(report.contains("<testsuite name=\"checks.rkt\" tests=\"8\" failures=\"3\" errors=\"1\">"));
+    assert!(report.contains("<testcase name=\"5:1 (check-expect (double 2) 4)\" classname=\"checks.rkt\"/>"));
+    assert!(report.contains("<failure message=\"expected (list 10 13) but got (list 9 10)\">"));
+
+    // A program runs as usual, without its checks.
+    assert_eq!(interpret_racket_program(program.to_string()), vec![Ok(Value::NumValue(6))]);
+}
+
+#[test]
+fn test_malformed_checks_are_errors() {
+    let program = "(check-expect (+ 1 1) 2)
+(check-expect 1)
+(check-property ([x 1]) true #:seed)
+(check-expect (cond) 1)";
+    let results = run_checks(program.to_string());
+    let outcomes: Vec<CheckOutcome> = results.into_iter().map(|result| result.outcome).collect();
+    let syntax_error = |message: &str| CheckOutcome::Error(RacketError::exn("exn:fail:syntax", message.to_string()));
+    assert_eq!(
+        outcomes,
+        vec![
+            CheckOutcome::Passed,
+            syntax_error("Malformed check-expect at 2:1: (check-expect 1)"),
+            syntax_error("Malformed check-property at 3:1: (check-property ((x 1)) true #:seed)"),
+            syntax_error("No case in cond at 4:15"),
+        ]
+    );
+
+    // Editors are told about malformed checks too.
+    let diagnostics: Vec<String> = analyze(program)
+        .diagnostics
+        .iter()
+        .map(|diagnostic| format!("{}: {}", diagnostic.span, diagnostic.message))
+        .collect();
+    assert_eq!(
+        diagnostics,
+        vec![
+            "2:1: Malformed check-expect at 2:1: (check-expect 1)",
+            "3:1: Malformed check-property at 3:1: (check-property ((x 1)) true #:seed)",
+            "4:15: No case in cond at 4:15",
+        ]
+    );
+}
+
+#[test]
+fn test_stdlib_checks_pass() {
+    let results = run_checks(get_example_program("stdlib_test.rkt"));
+    assert!(results.len() >= 10);
+    for result in results {
+        assert!(result.passed(), "{}", result);
+    }
+}
+
+#[test]
+fn test_properties_are_shrunk_to_minimal_counterexamples() {
+    let program = "(include stdlib::list)
+(include stdlib::quickcheck)
+
+(check-property ([lst (quickcheck::list (quickcheck::integer))]) (equal? (list::reverse lst) lst))
+(check-property ([n (quickcheck::number 5 50)] [b (quickcheck::boolean)]) (| b (< n 20)))
+(check-property ([lst (quickcheck::nested-list (quickcheck::natural))]) (< (list::length lst) 3) #:seed 7)
+(check-property ([n (quickcheck::integer)]) (/ 1 n))
+(check-property ([n (list 1 2)]) true)
+(check-property ([n (quickcheck::number -3 3)]) (& (< n 4) (> n -4)) #:trials 500)";
+    let list = |values: Vec<Value>| Value::ListValue(ValueList::from_vec(values));
+    let counterexample = |outcome: &CheckOutcome| match outcome {
+        CheckOutcome::Falsified(counterexample) => counterexample.values.clone(),
+        outcome => panic!("Expected a counterexample but got {:?}", outcome),
+    };
+    let results = run_checks(program.to_string());
+    // Seeding makes every run find the same counterexamples.
+    assert_eq!(results, run_checks(program.to_string()));
+
+    assert_eq!(
+        counterexample(&results[0].outcome),
+        [("lst".to_string(), list(vec![Value::NumValue(0), Value::NumValue(-1)]))]
+    );
+    assert_eq!(
+        counterexample(&results[1].outcome),
+        [
+            ("n".to_string(), Value::NumValue(20)),
+            ("b".to_string(), Value::BoolValue(false))
+        ]
+    );
+    assert_eq!(
+        counterexample(&results[2].outcome),
+        [("lst".to_string(), list(vec![Value::NumValue(0); 3]))]
+    );
+    assert_eq!(
+        results[3].to_string(),
+        "7:1: FAIL (check-property ((n (quickcheck::integer))) (/ 1 n))
+  falsified after 1 trial(s) with seed 0, shrunk 0 time(s):
+  n = 0
+  raised:   Tried to divide by zero: 1 / 0"
+    );
+    assert_eq!(
+        results[4].outcome,
+        CheckOutcome::Error(RacketError::contract(
+            "check-property expects a quickcheck::generator but was given (list 1 2)".to_string()
+        ))
+    );
+    assert!(results[5].passed());
+}
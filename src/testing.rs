@@ -0,0 +1,439 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::error::{EvalResult, RacketError, SyntaxError};
+use crate::interpret::{interpret, parse_program, read_lang_line, resolve_includes, Expr, FunctionMap, Value, VariableMap};
+use crate::interpret_define::define_all;
+use crate::limits::{with_limits, EvalLimits};
+use crate::parser::{parse_expr, ParseResult};
+use crate::quickcheck::{check_property, Counterexample, Property, DEFAULT_SEED, DEFAULT_TRIALS};
+use crate::reader::{Datum, DatumKind};
+use crate::tokenizer::Span;
+
+// Unit tests written in the language itself. A program's top level check forms are skipped when it
+// runs, and are evaluated one after another by `rusty-racket test`, after its constants are defined.
+//
+//   (check-expect actual expected)
+//   (check-equal? actual expected [message])
+//   (check-within actual expected tolerance)
+//   (check-error expr [message])
+//   (check-property ([name generator] ...) body [#:seed seed] [#:trials trials])
+
+pub const CHECK_FORMS: &[&str] = &[
+    "check-expect",
+    "check-equal?",
+    "check-within",
+    "check-error",
+    "check-property",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckKind {
+    // check-expect and check-equal?: the values are equal?.
+    Equal,
+    // check-within: the values are equal, except that numbers in the same position may differ by up
+    // to the tolerance.
+    Within(Expr),
+    // check-error: evaluating the expression raises an exception. The expected expression, if there
+    // is one, is the message the exception has to have.
+    Error,
+    // check-property: the expression is true for every value of the generators. See quickcheck.rs.
+    Property(Property),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    pub kind: CheckKind,
+    pub actual: Expr,
+    pub expected: Option<Expr>,
+    // The message check-equal? shows when it fails.
+    pub message: Option<Expr>,
+    // The form as written, which names the check in reports.
+    pub form: String,
+    pub span: Span,
+}
+
+// A check form that cannot be parsed. Its error is the result of the check, so the other checks in
+// the program still run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MalformedCheck {
+    pub form: String,
+    pub span: Span,
+    pub error: SyntaxError,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckOutcome {
+    Passed,
+    // The check ran, but the value it got is not the one it expected. For check-error, the values
+    // are the messages of the exceptions, or the value that was returned instead of raising one.
+    Failed { expected: Value, actual: Value },
+    // check-property found values for which its property does not hold.
+    Falsified(Counterexample),
+    // The check raised an exception it did not expect, or ran out of one of its limits.
+    Error(RacketError),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckResult {
+    pub form: String,
+    pub span: Span,
+    pub message: Option<String>,
+    pub outcome: CheckOutcome,
+}
+
+impl CheckResult {
+    pub fn passed(&self) -> bool {
+        self.outcome == CheckOutcome::Passed
+    }
+}
+
+// "L:C: FAIL form", with the expected and actual values underneath when the check did not pass.
+impl fmt::Display for CheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self.outcome {
+            CheckOutcome::Passed => "PASS",
+            CheckOutcome::Failed { .. } | CheckOutcome::Falsified(_) => "FAIL",
+            CheckOutcome::Error(_) => "ERROR",
+        };
+        write!(f, "{}: {} {}", self.span, status, self.form)?;
+        if let Some(message) = &self.message {
+            write!(f, "\n  message:  {}", message)?;
+        }
+        match &self.outcome {
+            CheckOutcome::Passed => Ok(()),
+            CheckOutcome::Failed { expected, actual } => {
+                write!(f, "\n  expected: {}\n  actual:   {}", expected, actual)?;
+                match first_difference(expected, actual) {
+                    Some((path, expected, actual)) if !path.is_empty() => write!(
+                        f,
+                        "\n  first difference at {}: expected {}, actual {}",
+                        path, expected, actual
+                    ),
+                    _ => Ok(()),
+                }
+            }
+            CheckOutcome::Falsified(counterexample) => {
+                write!(
+                    f,
+                    "\n  falsified after {} trial(s) with seed {}, shrunk {} time(s):",
+                    counterexample.trials, counterexample.seed, counterexample.shrinks
+                )?;
+                for (name, value) in &counterexample.values {
+                    write!(f, "\n  {} = {}", name, value)?;
+                }
+                match &counterexample.error {
+                    Some(error) => write!(f, "\n  raised:   {}", error),
+                    None => Ok(()),
+                }
+            }
+            CheckOutcome::Error(error) => write!(f, "\n  raised:   {}", error),
+        }
+    }
+}
+
+// Parses a check form, or returns None for any other datum.
+pub fn parse_check(datum: &Datum) -> Option<Result<Check, MalformedCheck>> {
+    let items = datum.as_list()?;
+    let name = items.first()?.as_symbol()?;
+    if !CHECK_FORMS.contains(&name) {
+        return None;
+    }
+    Some(parse_check_form(name, items, datum).map_err(|error| MalformedCheck {
+        form: datum.to_string(),
+        span: datum.span,
+        error,
+    }))
+}
+
+fn parse_check_form(name: &str, items: &[Datum], datum: &Datum) -> ParseResult<Check> {
+    let parse = |datum: Option<&Datum>| datum.map(parse_expr).transpose();
+    let (kind, expected, message) = match (name, &items[1..]) {
+        ("check-expect", [_, expected]) => (CheckKind::Equal, Some(expected), None),
+        ("check-equal?", [_, expected, message @ ..]) if message.len() <= 1 => {
+            (CheckKind::Equal, Some(expected), message.first())
+        }
+        ("check-within", [_, expected, tolerance]) => (CheckKind::Within(parse_expr(tolerance)?), Some(expected), None),
+        ("check-error", [_, message @ ..]) if message.len() <= 1 => (CheckKind::Error, message.first(), None),
+        ("check-property", [bindings, _, options @ ..]) if bindings.as_list().is_some() => {
+            (CheckKind::Property(parse_property(bindings, options, datum)?), None, None)
+        }
+        (name, _) => {
+            return Err(SyntaxError::syntax(
+                datum.span,
+                format!("Malformed {} at {}: {}", name, datum.span, datum),
+            ))
+        }
+    };
+    // The property is what check-property checks.
+    let actual = match kind {
+        CheckKind::Property(_) => &items[2],
+        _ => &items[1],
+    };
+    Ok(Check {
+        kind,
+        actual: parse_expr(actual)?,
+        expected: parse(expected)?,
+        message: parse(message)?,
+        form: datum.to_string(),
+        span: datum.span,
+    })
+}
+
+// ([name generator] ...) followed by #:seed and #:trials, which must be literal numbers.
+fn parse_property(bindings: &Datum, options: &[Datum], datum: &Datum) -> ParseResult<Property> {
+    let malformed = || SyntaxError::syntax(datum.span, format!("Malformed check-property at {}: {}", datum.span, datum));
+    let bindings = bindings
+        .as_list()
+        .unwrap()
+        .iter()
+        .map(|binding| match binding.as_list().map(|items| &items[..]) {
+            Some([name, generator]) if name.as_symbol().is_some() => {
+                Ok((name.as_symbol().unwrap().to_string(), parse_expr(generator)?))
+            }
+            _ => Err(malformed()),
+        });
+    let mut property = Property {
+        bindings: bindings.collect::<ParseResult<_>>()?,
+        seed: DEFAULT_SEED,
+        trials: DEFAULT_TRIALS,
+    };
+    for option in options.chunks(2) {
+        match (&option[0].kind, option.get(1).map(|value| &value.kind)) {
+            (DatumKind::Keyword(keyword), Some(DatumKind::Number(n))) if *n >= 0 => match keyword.as_str() {
+                "seed" => property.seed = *n as u64,
+                "trials" => property.trials = *n as usize,
+                _ => return Err(malformed()),
+            },
+            _ => return Err(malformed()),
+        }
+    }
+    Ok(property)
+}
+
+// Runs the checks in a program, in order. An exception while defining the program's constants is
+// the result of every check, since none of them can run without the constants. A malformed check
+// gives an error as its own result, while a program that cannot be parsed has a single result, the
+// error for the whole program.
+pub fn run_checks(program: String) -> Vec<CheckResult> {
+    run_checks_with_limits(program, &EvalLimits::default())
+}
+
+// The limits apply to each check separately, so one check that runs away does not stop the others.
+pub fn run_checks_with_limits(program: String, limits: &EvalLimits) -> Vec<CheckResult> {
+    let parsed = read_lang_line(program)
+        .and_then(|(_, program)| resolve_includes(program, &HashMap::new()))
+        .and_then(|(program, _)| parse_program(program));
+    let (function_map, definitions, _, checks) = match parsed {
+        Ok(parsed) => parsed,
+        Err(error) => {
+            return vec![CheckResult {
+                form: "program".to_string(),
+                span: error.span,
+                message: None,
+                outcome: CheckOutcome::Error(error.into()),
+            }]
+        }
+    };
+
+    let mut variable_map = HashMap::new();
+    let defined = with_limits(limits, || define_all(&definitions, &mut variable_map, &function_map));
+    checks
+        .iter()
+        .map(|check| {
+            let check = match check {
+                Ok(check) => check,
+                Err(malformed) => {
+                    return CheckResult {
+                        form: malformed.form.clone(),
+                        span: malformed.span,
+                        message: None,
+                        outcome: CheckOutcome::Error(malformed.error.clone().into()),
+                    }
+                }
+            };
+            let outcome = match &defined {
+                Ok(()) => with_limits(limits, || run_check(check, &mut variable_map, &function_map)),
+                Err(error) => CheckOutcome::Error(error.clone()),
+            };
+            let message = check.message.as_ref().filter(|_| outcome != CheckOutcome::Passed);
+            let message = message.and_then(|message| {
+                let message = interpret(message, &mut variable_map, &function_map).ok()?;
+                Some(match message {
+                    Value::StringValue(message) => message,
+                    message => message.to_string(),
+                })
+            });
+            CheckResult {
+                form: check.form.clone(),
+                span: check.span,
+                message,
+                outcome,
+            }
+        })
+        .collect()
+}
+
+fn run_check(check: &Check, variable_map: &mut VariableMap, function_map: &FunctionMap) -> CheckOutcome {
+    if let CheckKind::Property(property) = &check.kind {
+        return match check_property(property, &check.actual, variable_map, function_map) {
+            Ok(None) => CheckOutcome::Passed,
+            Ok(Some(counterexample)) => CheckOutcome::Falsified(counterexample),
+            Err(error) => CheckOutcome::Error(error),
+        };
+    }
+
+    let mut evaluate = |expr: &Expr| interpret(expr, variable_map, function_map);
+    let outcome = |result: EvalResult<CheckOutcome>| result.unwrap_or_else(CheckOutcome::Error);
+    let actual = evaluate(&check.actual);
+    match &check.kind {
+        CheckKind::Error => {
+            let raised = match actual {
+                Err(RacketError::Raise(value, _)) => value,
+                Err(error) => return CheckOutcome::Error(error),
+                Ok(actual) => {
+                    return CheckOutcome::Failed {
+                        expected: Value::StringValue("an exception".to_string()),
+                        actual,
+                    }
+                }
+            };
+            let Some(expected) = &check.expected else {
+                return CheckOutcome::Passed;
+            };
+            outcome(evaluate(expected).and_then(|expected| {
+                let actual = Value::StringValue(RacketError::raise(raised).to_string());
+                Ok(match expected {
+                    Value::StringValue(_) if expected == actual => CheckOutcome::Passed,
+                    Value::StringValue(_) => CheckOutcome::Failed { expected, actual },
+                    expected => {
+                        return Err(RacketError::contract(format!(
+                            "check-error expects a string message but was given {}",
+                            expected
+                        )))
+                    }
+                })
+            }))
+        }
+        kind => outcome(actual.and_then(|actual| {
+            let expected = evaluate(check.expected.as_ref().unwrap())?;
+            let equal = match kind {
+                CheckKind::Within(tolerance) => match evaluate(tolerance)? {
+                    Value::NumValue(tolerance) => within(&expected, &actual, tolerance),
+                    tolerance => {
+                        return Err(RacketError::contract(format!(
+                            "check-within expects a number tolerance but was given {}",
+                            tolerance
+                        )))
+                    }
+                },
+                _ => expected == actual,
+            };
+            Ok(match equal {
+                true => CheckOutcome::Passed,
+                false => CheckOutcome::Failed { expected, actual },
+            })
+        })),
+    }
+}
+
+fn within(expected: &Value, actual: &Value, tolerance: i32) -> bool {
+    match (expected, actual) {
+        (Value::NumValue(expected), Value::NumValue(actual)) => expected.abs_diff(*actual) <= tolerance.unsigned_abs(),
+        (Value::ListValue(expected), Value::ListValue(actual)) => {
+            let (expected, actual) = (expected.to_vec(), actual.to_vec());
+            expected.len() == actual.len()
+                && expected
+                    .iter()
+                    .zip(&actual)
+                    .all(|(expected, actual)| within(expected, actual, tolerance))
+        }
+        _ => expected == actual,
+    }
+}
+
+// Where two values first differ, as a path of list indices such as "index 2, index 0", with the
+// parts that differ. The path is empty when the values differ as a whole.
+fn first_difference(expected: &Value, actual: &Value) -> Option<(String, Value, Value)> {
+    if expected == actual {
+        return None;
+    }
+    if let (Value::ListValue(expected), Value::ListValue(actual)) = (expected, actual) {
+        let (expected, actual) = (expected.to_vec(), actual.to_vec());
+        if expected.len() == actual.len() {
+            return expected.iter().zip(&actual).enumerate().find_map(|(i, (expected, actual))| {
+                let (path, expected, actual) = first_difference(expected, actual)?;
+                let path = match path.is_empty() {
+                    true => format!("index {}", i),
+                    false => format!("index {}, {}", i, path),
+                };
+                Some((path, expected, actual))
+            });
+        }
+    }
+    Some((String::new(), expected.clone(), actual.clone()))
+}
+
+// The results of the checks in each file, as JUnit XML for CI systems. Each file is a test suite
+// and each check a test case.
+pub fn junit_report(files: &[(String, Vec<CheckResult>)]) -> String {
+    let count = |results: &[CheckResult], failed: fn(&CheckOutcome) -> bool| {
+        results.iter().filter(|result| failed(&result.outcome)).count()
+    };
+    let failures = |outcome: &CheckOutcome| matches!(outcome, CheckOutcome::Failed { .. } | CheckOutcome::Falsified(_));
+    let errors = |outcome: &CheckOutcome| matches!(outcome, CheckOutcome::Error(_));
+
+    let all: Vec<CheckResult> = files.iter().flat_map(|(_, results)| results.clone()).collect();
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites tests=\"{}\" failures=\"{}\" errors=\"{}\">\n",
+        all.len(),
+        count(&all, failures),
+        count(&all, errors)
+    );
+    for (path, results) in files {
+        xml += &format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\">\n",
+            escape_xml(path),
+            results.len(),
+            count(results, failures),
+            count(results, errors)
+        );
+        for result in results {
+            let name = escape_xml(&format!("{} {}", result.span, result.form));
+            let (element, summary) = match &result.outcome {
+                CheckOutcome::Passed => {
+                    xml += &format!("    <testcase name=\"{}\" classname=\"{}\"/>\n", name, escape_xml(path));
+                    continue;
+                }
+                CheckOutcome::Failed { expected, actual } => ("failure", format!("expected {} but got {}", expected, actual)),
+                CheckOutcome::Falsified(counterexample) => {
+                    let values: Vec<String> = counterexample
+                        .values
+                        .iter()
+                        .map(|(name, value)| format!("{} = {}", name, value))
+                        .collect();
+                    ("failure", format!("falsified by {}", values.join(", ")))
+                }
+                CheckOutcome::Error(error) => ("error", error.to_string()),
+            };
+            xml += &format!(
+                "    <testcase name=\"{}\" classname=\"{}\">\n      <{} message=\"{}\">{}</{}>\n    </testcase>\n",
+                name,
+                escape_xml(path),
+                element,
+                escape_xml(&summary),
+                escape_xml(&result.to_string()),
+                element
+            );
+        }
+        xml += "  </testsuite>\n";
+    }
+    xml + "</testsuites>\n"
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -1,46 +1,42 @@
 use std::fmt;
 use std::iter::Peekable;
-use std::str::Chars;
+use std::str::CharIndices;
 
-use crate::interpret_bool::*;
-use crate::interpret_num::*;
+use crate::error::SyntaxError;
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum TokenKind {
     Number,
     Boolean,
-
-    // Keywords
-    Cond,
-    Define,
-    List,
-    Cons,
-    Empty,
-    Car,
-    Cdr,
-    EmptyHuh,
-    ListHuh,
-
-    Identifier,
+    String,
+    Char,
+    Symbol,
+    Keyword,
 
     // Special characters
     OpenParen,
     CloseParen,
+    OpenVector,
+    Quote,
+    Quasiquote,
+    Unquote,
+    UnquoteSplicing,
+}
 
-    // Operators
-    Plus,
-    Minus,
-    Slash,
-    Star,
-    Percent,
-
-    Ampersand,
-    Pipe,
-    Bang,
+// Location of a piece of source text. Offsets are byte offsets into the program, line and column
+// are 1-based and refer to the start of the span.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
 
-    LessThan,
-    Equal,
-    GreaterThan,
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
 }
 
 impl fmt::Display for Token {
@@ -49,227 +45,273 @@ impl fmt::Display for Token {
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub struct Token {
     pub kind: TokenKind,
     pub text: String,
+    pub span: Span,
 }
 
-#[derive(Debug)]
-pub struct TokenIter<'a> {
-    tokens: &'a Vec<Token>,
-    index: usize,
+struct CharStream<'a> {
+    chars: Peekable<CharIndices<'a>>,
+    len: usize,
+    line: usize,
+    column: usize,
 }
 
-impl<'a> TokenIter<'a> {
-    pub fn new(tokens: &'a Vec<Token>) -> Self {
-        TokenIter { tokens, index: 0 }
+impl<'a> CharStream<'a> {
+    fn new(s: &'a str) -> Self {
+        CharStream {
+            chars: s.char_indices().peekable(),
+            len: s.len(),
+            line: 1,
+            column: 1,
+        }
     }
-}
 
-impl<'a> Iterator for TokenIter<'a> {
-    type Item = &'a Token;
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|(_, c)| *c)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.index < self.tokens.len() {
-            let token = &self.tokens[self.index];
-            self.index += 1;
-            Some(token)
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map(|(i, _)| *i).unwrap_or(self.len)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let (_, c) = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
         } else {
-            None
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn span_from(&mut self, start: usize, line: usize, column: usize) -> Span {
+        Span {
+            start,
+            end: self.offset(),
+            line,
+            column,
         }
     }
+
+    // An error in the token that started at the line and column.
+    fn error(&mut self, start: usize, line: usize, column: usize, message: String) -> SyntaxError {
+        SyntaxError::read(self.span_from(start, line, column), message)
+    }
 }
 
-pub fn string_to_tokens(s: String) -> Vec<Token> {
+pub fn string_to_tokens(s: String) -> Result<Vec<Token>, SyntaxError> {
     let mut result = Vec::new();
-    let mut s_iterator = s.chars().into_iter().peekable();
+    let mut s_iterator = CharStream::new(&s);
 
     consume_whitespace(&mut s_iterator);
     while s_iterator.peek().is_some() {
-        let token = token_from_position(&mut s_iterator);
+        let token = token_from_position(&mut s_iterator)?;
         result.push(token);
         consume_whitespace(&mut s_iterator);
     }
 
-    result
+    Ok(result)
 }
 
-pub fn token_kind_to_binary_num_op(kind: &TokenKind) -> BinaryNumOp {
-    match kind {
-        TokenKind::Plus => BinaryNumOp::Add,
-        TokenKind::Minus => BinaryNumOp::Sub,
-        TokenKind::Slash => BinaryNumOp::Div,
-        TokenKind::Star => BinaryNumOp::Mul,
-        TokenKind::Percent => BinaryNumOp::Mod,
-        _ => panic!("Could not parse token kind to binary num op"),
-    }
-}
+// Characters that are written by name, e.g. #\space. The first name for a character is the one used when printing it.
+pub const CHAR_NAMES: &[(&str, char)] = &[
+    ("space", ' '),
+    ("newline", '\n'),
+    ("linefeed", '\n'),
+    ("tab", '\t'),
+    ("return", '\r'),
+    ("nul", '\0'),
+    ("null", '\0'),
+    ("backspace", '\u{8}'),
+    ("vtab", '\u{b}'),
+    ("page", '\u{c}'),
+    ("delete", '\u{7f}'),
+    ("rubout", '\u{7f}'),
+];
 
-pub fn token_kind_to_binary_bool_op(kind: &TokenKind) -> BinaryBoolOp {
-    match kind {
-        TokenKind::Ampersand => BinaryBoolOp::And,
-        TokenKind::Pipe => BinaryBoolOp::Or,
-        _ => {
-            panic!("Could not parse token kind to binary bool op");
-        }
-    }
+// Characters that end a symbol or number.
+pub fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | '{' | '}' | '"' | ';' | '\'' | '`' | ',')
 }
 
-pub fn token_kind_to_unary_bool_op(kind: &TokenKind) -> UnaryBoolOp {
-    match kind {
-        TokenKind::Bang => UnaryBoolOp::Not,
-        _ => panic!("Could not parse token kind to unary bool op"),
-    }
+fn is_number(text: &str) -> bool {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
 }
 
-pub fn token_kind_to_cmp_bool_op(kind: &TokenKind) -> CmpBoolOp {
-    match kind {
-        TokenKind::LessThan => CmpBoolOp::Lt,
-        TokenKind::Equal => CmpBoolOp::Eq,
-        TokenKind::GreaterThan => CmpBoolOp::Gt,
-        _ => panic!("Could not parse token kind to cmp bool op"),
-    }
-}
+fn token_from_position(s: &mut CharStream) -> Result<Token, SyntaxError> {
+    let (start, line, column) = (s.offset(), s.line, s.column);
 
-fn is_valid_starting_string_token_char(c: &char) -> bool {
-    c.is_ascii_alphabetic() || *c == '_'
-}
+    let (kind, text) = match s.peek().expect("Index out of range while parsing tokens.") {
+        '(' | '[' => {
+            s.next();
+            (TokenKind::OpenParen, "(".to_string())
+        }
+        ')' | ']' => {
+            s.next();
+            (TokenKind::CloseParen, ")".to_string())
+        }
+        '"' => (TokenKind::String, read_string(s)?),
+        '\'' => {
+            s.next();
+            (TokenKind::Quote, "'".to_string())
+        }
+        '`' => {
+            s.next();
+            (TokenKind::Quasiquote, "`".to_string())
+        }
+        ',' => {
+            s.next();
+            if s.peek() == Some('@') {
+                s.next();
+                (TokenKind::UnquoteSplicing, ",@".to_string())
+            } else {
+                (TokenKind::Unquote, ",".to_string())
+            }
+        }
+        '#' => {
+            s.next();
+            match s.next() {
+                Some('(') => (TokenKind::OpenVector, "#(".to_string()),
+                Some('\\') => (TokenKind::Char, read_char(s, line, column)?.to_string()),
+                Some(c) if c == 't' || c == 'f' => {
+                    let mut buff = format!("#{}", c);
+                    while s.peek().is_some_and(|c| !is_delimiter(c)) {
+                        buff.push(s.next().unwrap());
+                    }
+                    match buff.as_str() {
+                        "#t" | "#true" => (TokenKind::Boolean, "true".to_string()),
+                        "#f" | "#false" => (TokenKind::Boolean, "false".to_string()),
+                        _ => {
+                            let message = format!("Unhandled # syntax at {}:{}: {}", line, column, buff);
+                            return Err(s.error(start, line, column, message));
+                        }
+                    }
+                }
+                Some(':') => {
+                    let mut buff = String::new();
+                    while s.peek().is_some_and(|c| !is_delimiter(c)) {
+                        buff.push(s.next().unwrap());
+                    }
+                    if buff.is_empty() {
+                        let message = format!("Expected a keyword name after #: at {}:{}", line, column);
+                        return Err(s.error(start, line, column, message));
+                    }
+                    (TokenKind::Keyword, buff)
+                }
+                x => {
+                    let message = format!("Unhandled # syntax at {}:{}: #{}", line, column, x.unwrap_or(' '));
+                    return Err(s.error(start, line, column, message));
+                }
+            }
+        }
+        _ => {
+            let mut buff = String::new();
+            while s.peek().is_some_and(|c| !is_delimiter(c)) {
+                buff.push(s.next().unwrap());
+            }
+
+            if buff.is_empty() {
+                let message = format!("Unhandled character at {}:{}: {}", line, column, s.peek().unwrap());
+                return Err(s.error(start, line, column, message));
+            } else if is_number(&buff) {
+                (TokenKind::Number, buff)
+            } else if buff == "true" || buff == "false" {
+                (TokenKind::Boolean, buff)
+            } else {
+                (TokenKind::Symbol, buff)
+            }
+        }
+    };
 
-fn is_valid_string_token_char(c: &char) -> bool {
-    is_valid_starting_string_token_char(c) || c.is_ascii_alphanumeric() || *c == '?' || *c == '!' || *c == '-' || *c == ':'
+    Ok(Token {
+        kind,
+        text,
+        span: s.span_from(start, line, column),
+    })
 }
 
-fn token_from_position(s: &mut std::iter::Peekable<std::str::Chars>) -> Token {
-    if s.peek().is_none() {
-        panic!("Index out of range while parsing tokens.")
-    }
+// Reads the character after #\. Any single character can follow, including delimiters, and longer
+// runs are either a character name or a unicode escape such as #\u03BB.
+fn read_char(s: &mut CharStream, line: usize, column: usize) -> Result<char, SyntaxError> {
+    let start = s.offset() - 2;
+    let Some(first) = s.next() else {
+        let message = format!("Expected a character after #\\ at {}:{}", line, column);
+        return Err(s.error(start, line, column, message));
+    };
 
-    if s.peek().unwrap().is_numeric() {
-        let mut number_buff = String::new();
-        while s.peek().is_some() && s.peek().unwrap().is_numeric() {
-            number_buff.push(s.next().unwrap());
+    let mut buff = first.to_string();
+    if !is_delimiter(first) {
+        while s.peek().is_some_and(|c| !is_delimiter(c)) {
+            buff.push(s.next().unwrap());
         }
+    }
+    if buff.chars().count() == 1 {
+        return Ok(first);
+    }
 
-        return Token {
-            kind: TokenKind::Number,
-            text: number_buff,
-        };
+    if let Some((_, c)) = CHAR_NAMES.iter().find(|(name, _)| *name == buff) {
+        return Ok(*c);
     }
+    let escaped = buff
+        .strip_prefix('u')
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .and_then(char::from_u32);
+    escaped.ok_or_else(|| {
+        let message = format!("Unknown character name at {}:{}: #\\{}", line, column, buff);
+        s.error(start, line, column, message)
+    })
+}
 
-    if is_valid_starting_string_token_char(s.peek().unwrap()) {
-        let mut buff = String::new();
-        while s.peek().is_some() && (is_valid_string_token_char(s.peek().unwrap())) {
-            buff.push(s.next().unwrap());
-        }
+// Reads a string literal and returns its contents with escape sequences resolved.
+fn read_string(s: &mut CharStream) -> Result<String, SyntaxError> {
+    let (start, line, column) = (s.offset(), s.line, s.column);
+    s.next(); // '"'
 
-        return match buff.as_str() {
-            "true" | "false" => Token {
-                kind: TokenKind::Boolean,
-                text: buff,
+    let mut buff = String::new();
+    loop {
+        match s.next() {
+            Some('"') => return Ok(buff),
+            Some('\\') => match s.next() {
+                Some('n') => buff.push('\n'),
+                Some('t') => buff.push('\t'),
+                Some(c @ ('"' | '\\')) => buff.push(c),
+                x => {
+                    let message = format!(
+                        "Unknown escape sequence in string at {}:{}: \\{}",
+                        line,
+                        column,
+                        x.unwrap_or(' ')
+                    );
+                    return Err(s.error(start, line, column, message));
+                }
             },
-            "cond" => Token {
-                kind: TokenKind::Cond,
-                text: buff,
-            },
-            "define" => Token {
-                kind: TokenKind::Define,
-                text: buff,
-            },
-            "list" => Token {
-                kind: TokenKind::List,
-                text: buff,
-            },
-            "cons" => Token {
-                kind: TokenKind::Cons,
-                text: buff,
-            },
-            "empty" => Token {
-                kind: TokenKind::Empty,
-                text: buff,
-            },
-            "car" => Token {
-                kind: TokenKind::Car,
-                text: buff,
-            },
-            "cdr" => Token {
-                kind: TokenKind::Cdr,
-                text: buff,
-            },
-            "empty?" => Token {
-                kind: TokenKind::EmptyHuh,
-                text: buff,
-            },
-            "list?" => Token {
-                kind: TokenKind::ListHuh,
-                text: buff,
-            },
-            _ => Token {
-                kind: TokenKind::Identifier,
-                text: buff,
-            },
-        };
-    }
-
-    match s.next().unwrap() {
-        '(' | '[' => Token {
-            kind: TokenKind::OpenParen,
-            text: "(".to_string(),
-        },
-        ')' | ']' => Token {
-            kind: TokenKind::CloseParen,
-            text: ")".to_string(),
-        },
-        '+' => Token {
-            kind: TokenKind::Plus,
-            text: "+".to_string(),
-        },
-        '-' => Token {
-            kind: TokenKind::Minus,
-            text: "-".to_string(),
-        },
-        '/' => Token {
-            kind: TokenKind::Slash,
-            text: "/".to_string(),
-        },
-        '*' => Token {
-            kind: TokenKind::Star,
-            text: "*".to_string(),
-        },
-        '%' => Token {
-            kind: TokenKind::Percent,
-            text: "%".to_string(),
-        },
-        '&' => Token {
-            kind: TokenKind::Ampersand,
-            text: "&".to_string(),
-        },
-        '|' => Token {
-            kind: TokenKind::Pipe,
-            text: "|".to_string(),
-        },
-        '!' => Token {
-            kind: TokenKind::Bang,
-            text: "!".to_string(),
-        },
-        '<' => Token {
-            kind: TokenKind::LessThan,
-            text: "<".to_string(),
-        },
-        '=' => Token {
-            kind: TokenKind::Equal,
-            text: "=".to_string(),
-        },
-        '>' => Token {
-            kind: TokenKind::GreaterThan,
-            text: ">".to_string(),
-        },
-        x => todo!("Unhandled case in token_from_position {}", x),
+            Some(c) => buff.push(c),
+            None => {
+                let message = format!("Unterminated string starting at {}:{}", line, column);
+                return Err(s.error(start, line, column, message));
+            }
+        }
     }
 }
 
-fn consume_whitespace(it: &mut Peekable<Chars<'_>>) {
-    while it.peek().is_some_and(|x| x.is_whitespace()) {
-        it.next();
+// Whitespace and `;` line comments are skipped between tokens.
+fn consume_whitespace(it: &mut CharStream) {
+    loop {
+        match it.peek() {
+            Some(c) if c.is_whitespace() => {
+                it.next();
+            }
+            Some(';') => {
+                while it.peek().is_some_and(|c| c != '\n') {
+                    it.next();
+                }
+            }
+            _ => return,
+        }
     }
 }
@@ -0,0 +1,1117 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::error::SyntaxError;
+use crate::interpret::{get_module_content, read_lang_line, STDLIB_MODULES};
+use crate::interpret_struct::{parse_struct_type, struct_definition, struct_functions, StructFunction};
+use crate::macro_expander::expand_macros;
+use crate::parser::{
+    collect_symbols, constant_definition, contract_definition, definition_annotation, function_definition, include_name,
+    parameter_annotation, provided_contracts, type_declaration,
+};
+use crate::primitives::is_primitive;
+use crate::reader::{read, read_program, Datum, DatumKind};
+use crate::testing::CHECK_FORMS;
+use crate::tokenizer::Span;
+use crate::types::{display_types, parse_type, Scheme, Type, TypeError};
+
+// Hindley–Milner type inference over a whole program. Lists, vectors and hashes are homogeneous, and
+// top level and internal definitions are generalised, so a function like list::length can be used
+// on lists of any type. The checker works on the macro expanded s-expressions rather than on the
+// parsed program so that it can point at the source of every mismatch.
+
+// Types of the built in functions, in Typed Racket syntax. A function with several entries accepts
+// several numbers of arguments, and `t *` stands for any number of arguments of type t. Functions
+// whose result is either a value or false, such as assoc, have an unconstrained result.
+const PRIMITIVE_TYPES: &[(&str, &str)] = &[
+    ("+", "(-> Integer * Integer)"),
+    ("-", "(-> Integer * Integer)"),
+    ("*", "(-> Integer * Integer)"),
+    ("/", "(-> Integer Integer Integer)"),
+    ("%", "(-> Integer Integer Integer)"),
+    ("<", "(-> Integer Integer Boolean)"),
+    ("=", "(-> Integer Integer Boolean)"),
+    (">", "(-> Integer Integer Boolean)"),
+    ("&", "(-> Boolean Boolean Boolean)"),
+    ("|", "(-> Boolean Boolean Boolean)"),
+    ("!", "(-> Boolean Boolean)"),
+    ("car", "(-> (Listof a) a)"),
+    ("cdr", "(-> (Listof a) (Listof a))"),
+    ("cons", "(-> a (Listof a) (Listof a))"),
+    ("list", "(-> a * (Listof a))"),
+    ("empty?", "(-> (Listof a) Boolean)"),
+    ("list?", "(-> a Boolean)"),
+    ("null?", "(-> a Boolean)"),
+    ("cons?", "(-> a Boolean)"),
+    ("pair?", "(-> a Boolean)"),
+    ("first", "(-> (Listof a) a)"),
+    ("second", "(-> (Listof a) a)"),
+    ("third", "(-> (Listof a) a)"),
+    ("rest", "(-> (Listof a) (Listof a))"),
+    ("last", "(-> (Listof a) a)"),
+    ("read", "(-> String a)"),
+    ("symbol?", "(-> a Boolean)"),
+    ("symbol->string", "(-> Symbol String)"),
+    ("string->symbol", "(-> String Symbol)"),
+    ("symbol=?", "(-> Symbol Symbol Boolean)"),
+    ("equal?", "(-> a a Boolean)"),
+    ("procedure?", "(-> a Boolean)"),
+    ("number?", "(-> a Boolean)"),
+    ("integer?", "(-> a Boolean)"),
+    ("string?", "(-> a Boolean)"),
+    ("boolean?", "(-> a Boolean)"),
+    ("map", "(-> (-> a b) (Listof a) (Listof b))"),
+    ("map", "(-> (-> a b c) (Listof a) (Listof b) (Listof c))"),
+    ("filter", "(-> (-> a Boolean) (Listof a) (Listof a))"),
+    ("foldl", "(-> (-> a b b) b (Listof a) b)"),
+    ("foldl", "(-> (-> a b c c) c (Listof a) (Listof b) c)"),
+    ("foldr", "(-> (-> a b b) b (Listof a) b)"),
+    ("foldr", "(-> (-> a b c c) c (Listof a) (Listof b) c)"),
+    ("andmap", "(-> (-> a Boolean) (Listof a) Boolean)"),
+    ("andmap", "(-> (-> a b Boolean) (Listof a) (Listof b) Boolean)"),
+    ("ormap", "(-> (-> a Boolean) (Listof a) Boolean)"),
+    ("ormap", "(-> (-> a b Boolean) (Listof a) (Listof b) Boolean)"),
+    ("sort", "(-> (Listof a) (-> a a Boolean) (Listof a))"),
+    // sort with a #:key function, which is passed as a third argument.
+    ("sort #:key", "(-> (Listof a) (-> b b Boolean) (-> a b) (Listof a))"),
+    ("build-list", "(-> Integer (-> Integer a) (Listof a))"),
+    ("assoc", "(-> a (Listof (Listof a)) b)"),
+    ("assoc", "(-> a (Listof (Listof a)) (-> a a Boolean) b)"),
+    ("member", "(-> a (Listof a) b)"),
+    ("member", "(-> a (Listof a) (-> a a Boolean) b)"),
+    ("remove", "(-> a (Listof a) (Listof a))"),
+    ("remove", "(-> a (Listof a) (-> a a Boolean) (Listof a))"),
+    ("hash?", "(-> a Boolean)"),
+    ("hash-ref", "(-> (HashTable k v) k v)"),
+    ("hash-ref", "(-> (HashTable k v) k d v)"),
+    ("hash-set", "(-> (HashTable k v) k v (HashTable k v))"),
+    ("hash-remove", "(-> (HashTable k v) k (HashTable k v))"),
+    ("hash-keys", "(-> (HashTable k v) (Listof k))"),
+    ("hash-count", "(-> (HashTable k v) Integer)"),
+    ("hash-has-key?", "(-> (HashTable k v) k Boolean)"),
+    ("vector", "(-> a * (Vectorof a))"),
+    ("vector?", "(-> a Boolean)"),
+    ("make-vector", "(-> Integer (Vectorof Integer))"),
+    ("make-vector", "(-> Integer a (Vectorof a))"),
+    ("vector-ref", "(-> (Vectorof a) Integer a)"),
+    ("vector-length", "(-> (Vectorof a) Integer)"),
+    ("vector-set", "(-> (Vectorof a) Integer a (Vectorof a))"),
+    ("vector->list", "(-> (Vectorof a) (Listof a))"),
+    ("list->vector", "(-> (Listof a) (Vectorof a))"),
+    ("char?", "(-> a Boolean)"),
+    ("char->integer", "(-> Char Integer)"),
+    ("integer->char", "(-> Integer Char)"),
+    ("char-alphabetic?", "(-> Char Boolean)"),
+    ("char-numeric?", "(-> Char Boolean)"),
+    ("char-whitespace?", "(-> Char Boolean)"),
+    ("char-upcase", "(-> Char Char)"),
+    ("char-downcase", "(-> Char Char)"),
+    ("string->list", "(-> String (Listof Char))"),
+    ("list->string", "(-> (Listof Char) String)"),
+    ("raise", "(-> a b)"),
+    ("exn-message", "(-> Exn String)"),
+];
+
+// Infers the type of every top level definition of the program, returning them in the order they are
+// defined, or every type error found. Definitions from included modules are checked but not listed.
+pub fn check_program(program: String) -> Result<Vec<(String, Scheme)>, Vec<TypeError>> {
+    let error = |error: SyntaxError| {
+        vec![TypeError {
+            span: error.span,
+            message: error.message,
+        }]
+    };
+    let (_, program) = read_lang_line(program).map_err(error)?;
+    let mut checker = Checker::default();
+
+    // Modules are read separately so that the locations in the program itself stay accurate.
+    let mut sources = vec![(true, read_program(program).map_err(error)?)];
+    let mut included = HashSet::new();
+    let mut i = 0;
+    while i < sources.len() {
+        for datum in &sources[i].1.clone() {
+            let Some(module_name) = include_name(datum) else {
+                continue;
+            };
+            if !STDLIB_MODULES.contains(&module_name) {
+                checker.error(datum.span, format!("Unknown module name {}", module_name));
+            } else if included.insert(module_name.to_string()) {
+                sources.push((
+                    false,
+                    get_module_content(module_name, datum.span)
+                        .and_then(read_program)
+                        .map_err(error)?,
+                ));
+            }
+        }
+        i += 1;
+    }
+
+    // Macros from every source are available everywhere, as they are when the program runs.
+    let macros: Vec<Datum> = sources
+        .iter()
+        .flat_map(|(_, datums)| datums.iter().filter(|datum| head_symbol(datum) == Some("define-syntax")))
+        .cloned()
+        .collect();
+    let sources: Vec<(bool, Vec<Datum>)> = sources
+        .into_iter()
+        .map(|(from_program, datums)| {
+            let forms = datums.into_iter().filter(|datum| include_name(datum).is_none());
+            let datums = expand_macros(macros.iter().cloned().chain(forms).collect()).map_err(error)?;
+            // Contracts are only checked at runtime, so define/contract is checked as a define.
+            let datums = datums
+                .into_iter()
+                .filter(|datum| provided_contracts(datum).is_none())
+                .map(|datum| contract_definition(&datum).map_or(datum.clone(), |(define, _)| define))
+                .collect();
+            Ok((from_program, datums))
+        })
+        .collect::<Result<_, Vec<TypeError>>>()?;
+
+    let datums: Vec<&Datum> = sources.iter().flat_map(|(_, datums)| datums).collect();
+    checker.define_structs(&datums);
+    let declarations: HashMap<&str, &Datum> = datums.iter().filter_map(|datum| type_declaration(datum)).collect();
+
+    let mut definitions = Vec::new();
+    let mut expressions = Vec::new();
+    for (from_program, datums) in &sources {
+        for datum in datums {
+            if struct_definition(datum).is_some() || type_declaration(datum).is_some() {
+                continue;
+            }
+            match definition(datum, *from_program, &declarations) {
+                Some(definition) => definitions.push(definition),
+                None if *from_program => expressions.push(datum),
+                None => {}
+            }
+        }
+    }
+    for (name, declaration) in &declarations {
+        if !definitions.iter().any(|definition| definition.name == *name) {
+            checker.error(declaration.span, format!("Type declared for {} but it is not defined", name));
+        }
+    }
+
+    checker.infer_top_level(&definitions);
+    for expr in expressions {
+        // Checks compare values of any type, so only the expressions in them are checked. The names a
+        // property binds can have any type too.
+        match expr.as_list() {
+            Some(items) if head_symbol(expr) == Some("check-property") && items.len() > 2 => {
+                let mut env = Vec::new();
+                for binding in items[1].as_list().unwrap() {
+                    if let Some([name, generator]) = binding.as_list().map(|items| &items[..]) {
+                        checker.infer(generator, &mut Vec::new());
+                        let ty = checker.fresh();
+                        env.push((name.to_string(), Scheme::monomorphic(ty)));
+                    }
+                }
+                checker.infer(&items[2], &mut env);
+            }
+            Some(items) if head_symbol(expr).is_some_and(|head| CHECK_FORMS.contains(&head)) => {
+                for item in &items[1..] {
+                    checker.infer(item, &mut Vec::new());
+                }
+            }
+            _ => {
+                checker.infer(expr, &mut Vec::new());
+            }
+        }
+    }
+
+    if !checker.errors.is_empty() {
+        let mut errors = checker.errors;
+        errors.sort_by_key(|error| error.span.start);
+        return Err(errors);
+    }
+
+    Ok(definitions
+        .iter()
+        .filter(|definition| definition.from_program)
+        .map(|definition| (definition.name.clone(), checker.globals[&definition.name].clone()))
+        .collect())
+}
+
+fn head_symbol(datum: &Datum) -> Option<&str> {
+    datum.as_list()?.first()?.as_symbol()
+}
+
+struct Definition<'a> {
+    name: String,
+    kind: DefinitionKind<'a>,
+    span: Span,
+    // The type given by a (: name Type) declaration.
+    declared: Option<&'a Datum>,
+    // The result type of a function or the type of a constant, written in the definition itself.
+    annotation: Option<&'a Datum>,
+    // Definitions from included modules are trusted, see Checker::infer_top_level.
+    from_program: bool,
+}
+
+enum DefinitionKind<'a> {
+    Function { parameters: &'a [Datum], body: &'a [Datum] },
+    Constant(&'a Datum),
+}
+
+// Variables in scope, innermost last.
+type Env = Vec<(String, Scheme)>;
+
+#[derive(Default)]
+struct Checker {
+    // What each type variable has been unified with, if anything.
+    bindings: Vec<Option<Type>>,
+    // The let nesting level each type variable was created at. Variables created deeper than the
+    // current level can be generalised.
+    levels: Vec<usize>,
+    level: usize,
+    globals: HashMap<String, Scheme>,
+    // Struct types by name.
+    type_names: HashMap<String, Type>,
+    errors: Vec<TypeError>,
+}
+
+impl Checker {
+    fn error(&mut self, span: Span, message: String) {
+        self.errors.push(TypeError { span, message });
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.bindings.push(None);
+        self.levels.push(self.level);
+        Type::Var(self.bindings.len() - 1)
+    }
+
+    // Follows the bindings of a type variable until reaching a type that is not a bound variable.
+    fn resolve(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Var(var) => match &self.bindings[*var] {
+                Some(bound) => self.resolve(bound),
+                None => ty.clone(),
+            },
+            _ => ty.clone(),
+        }
+    }
+
+    // Replaces every bound variable in the type with what it is bound to.
+    fn zonk(&self, ty: &Type) -> Type {
+        match self.resolve(ty) {
+            Type::List(element) => Type::List(Box::new(self.zonk(&element))),
+            Type::Vector(element) => Type::Vector(Box::new(self.zonk(&element))),
+            Type::Hash(key, value) => Type::Hash(Box::new(self.zonk(&key)), Box::new(self.zonk(&value))),
+            Type::Function(parameters, result) => Type::Function(
+                parameters.iter().map(|parameter| self.zonk(parameter)).collect(),
+                Box::new(self.zonk(&result)),
+            ),
+            Type::Union(members) => Type::Union(members.iter().map(|member| self.zonk(member)).collect()),
+            ty => ty,
+        }
+    }
+
+    fn unify(&mut self, a: &Type, b: &Type) -> bool {
+        match (self.resolve(a), self.resolve(b)) {
+            // Any is checked when the program runs, so it is consistent with every type.
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Var(x), Type::Var(y)) if x == y => true,
+            (Type::Var(var), ty) | (ty, Type::Var(var)) => self.bind(var, &ty),
+            (Type::Union(x), Type::Union(y)) if x.len() == y.len() => x.iter().zip(&y).all(|(x, y)| self.unify(x, y)),
+            // A union meets a concrete type when one of its members could be that type. Which member it
+            // is, is checked when the program runs.
+            (Type::Union(members), ty) | (ty, Type::Union(members)) => members.iter().any(|member| self.consistent(member, &ty)),
+            (Type::List(x), Type::List(y)) | (Type::Vector(x), Type::Vector(y)) => self.unify(&x, &y),
+            (Type::Hash(k1, v1), Type::Hash(k2, v2)) => self.unify(&k1, &k2) && self.unify(&v1, &v2),
+            (Type::Function(p1, r1), Type::Function(p2, r2)) if p1.len() == p2.len() => {
+                p1.iter().zip(&p2).all(|(x, y)| self.unify(x, y)) && self.unify(&r1, &r2)
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    // Whether two types could be unified, without unifying them.
+    fn consistent(&self, a: &Type, b: &Type) -> bool {
+        match (self.resolve(a), self.resolve(b)) {
+            (Type::Any | Type::Var(_), _) | (_, Type::Any | Type::Var(_)) => true,
+            (Type::List(x), Type::List(y)) | (Type::Vector(x), Type::Vector(y)) => self.consistent(&x, &y),
+            (Type::Hash(k1, v1), Type::Hash(k2, v2)) => self.consistent(&k1, &k2) && self.consistent(&v1, &v2),
+            (Type::Function(p1, r1), Type::Function(p2, r2)) => {
+                p1.len() == p2.len() && p1.iter().zip(&p2).all(|(x, y)| self.consistent(x, y)) && self.consistent(&r1, &r2)
+            }
+            (Type::Union(members), ty) | (ty, Type::Union(members)) => members.iter().any(|member| self.consistent(member, &ty)),
+            (a, b) => a == b,
+        }
+    }
+
+    fn bind(&mut self, var: usize, ty: &Type) -> bool {
+        if !self.occurs_check(var, self.levels[var], ty) {
+            return false;
+        }
+        self.bindings[var] = Some(ty.clone());
+        true
+    }
+
+    // Fails if the variable occurs in the type, which would make the type infinite. Variables in the
+    // type are moved out to the variable's level, since they now escape wherever it does.
+    fn occurs_check(&mut self, var: usize, level: usize, ty: &Type) -> bool {
+        match self.resolve(ty) {
+            Type::Var(other) if other == var => false,
+            Type::Var(other) => {
+                self.levels[other] = self.levels[other].min(level);
+                true
+            }
+            Type::List(element) | Type::Vector(element) => self.occurs_check(var, level, &element),
+            Type::Hash(key, value) => self.occurs_check(var, level, &key) && self.occurs_check(var, level, &value),
+            Type::Function(parameters, result) => {
+                parameters.iter().all(|parameter| self.occurs_check(var, level, parameter))
+                    && self.occurs_check(var, level, &result)
+            }
+            Type::Union(members) => members.iter().all(|member| self.occurs_check(var, level, member)),
+            _ => true,
+        }
+    }
+
+    // Unifies the type the context expects with the type of the datum, reporting a mismatch at the datum.
+    fn expect(&mut self, expected: &Type, actual: &Type, datum: &Datum) {
+        if !self.unify(expected, actual) {
+            let types = display_types(&[&self.zonk(expected), &self.zonk(actual)]);
+            self.error(datum.span, format!("expected {} but got {}: {}", types[0], types[1], datum));
+        }
+    }
+
+    fn generalize(&self, ty: &Type) -> Scheme {
+        fn collect(checker: &Checker, ty: &Type, generics: &mut Vec<usize>) {
+            match ty {
+                Type::Var(var) if checker.levels[*var] > checker.level && !generics.contains(var) => generics.push(*var),
+                Type::List(element) | Type::Vector(element) => collect(checker, element, generics),
+                Type::Hash(key, value) => {
+                    collect(checker, key, generics);
+                    collect(checker, value, generics);
+                }
+                Type::Function(parameters, result) => {
+                    parameters.iter().for_each(|parameter| collect(checker, parameter, generics));
+                    collect(checker, result, generics);
+                }
+                Type::Union(members) => members.iter().for_each(|member| collect(checker, member, generics)),
+                _ => {}
+            }
+        }
+
+        let ty = self.zonk(ty);
+        let mut generics = Vec::new();
+        collect(self, &ty, &mut generics);
+        Scheme { generics, ty }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let substitution: HashMap<usize, Type> = scheme.generics.iter().map(|var| (*var, self.fresh())).collect();
+        self.substitute(&scheme.ty, &substitution)
+    }
+
+    fn substitute(&self, ty: &Type, substitution: &HashMap<usize, Type>) -> Type {
+        match self.resolve(ty) {
+            Type::Var(var) => substitution.get(&var).cloned().unwrap_or(Type::Var(var)),
+            Type::List(element) => Type::List(Box::new(self.substitute(&element, substitution))),
+            Type::Vector(element) => Type::Vector(Box::new(self.substitute(&element, substitution))),
+            Type::Hash(key, value) => Type::Hash(
+                Box::new(self.substitute(&key, substitution)),
+                Box::new(self.substitute(&value, substitution)),
+            ),
+            Type::Function(parameters, result) => Type::Function(
+                parameters
+                    .iter()
+                    .map(|parameter| self.substitute(parameter, substitution))
+                    .collect(),
+                Box::new(self.substitute(&result, substitution)),
+            ),
+            Type::Union(members) => Type::Union(members.iter().map(|member| self.substitute(member, substitution)).collect()),
+            ty => ty,
+        }
+    }
+
+    // Struct types can be used in annotations anywhere, and their functions are globals.
+    fn define_structs(&mut self, datums: &[&Datum]) {
+        let definitions: Vec<(&str, &[Datum])> = datums.iter().filter_map(|datum| struct_definition(datum)).collect();
+        for (name, _) in &definitions {
+            self.type_names.insert(name.to_string(), Type::Struct(name.to_string()));
+        }
+
+        for (name, fields) in definitions {
+            let struct_type = match parse_struct_type(name, fields, &self.type_names) {
+                Ok(struct_type) => struct_type,
+                Err(error) => {
+                    self.errors.push(error);
+                    continue;
+                }
+            };
+
+            let field_types = struct_type.field_types.clone();
+            for (function_name, function) in struct_functions(struct_type) {
+                let ty = match function {
+                    StructFunction::Constructor(struct_type) => struct_type.constructor_type(),
+                    StructFunction::Predicate(_) => Type::Function(vec![Type::Any], Box::new(Type::Boolean)),
+                    StructFunction::Accessor { index, .. } => {
+                        Type::Function(vec![Type::Struct(name.to_string())], Box::new(field_types[index].clone()))
+                    }
+                };
+                self.globals.insert(function_name, Scheme::monomorphic(ty));
+            }
+        }
+    }
+
+    // Top level definitions become globals. Groups of module definitions that do not type check, such
+    // as the binary tree functions that keep a value and its subtrees in one list, are given a type
+    // that accepts anything instead of reporting errors in code the program cannot change.
+    fn infer_top_level(&mut self, definitions: &[Definition]) {
+        for group in dependency_groups(definitions) {
+            let from_module = group.iter().all(|i| !definitions[*i].from_program);
+            let errors = std::mem::take(&mut self.errors);
+
+            let schemes = self.infer_group(definitions, &group, &mut Vec::new());
+            let failed = !self.errors.is_empty();
+            let group_errors = std::mem::replace(&mut self.errors, errors);
+
+            for (name, scheme) in schemes {
+                let scheme = match self.fresh() {
+                    Type::Var(var) if from_module && failed => Scheme {
+                        generics: vec![var],
+                        ty: Type::Var(var),
+                    },
+                    _ => scheme,
+                };
+                self.globals.insert(name, scheme);
+            }
+            if !from_module {
+                self.errors.extend(group_errors);
+            }
+        }
+    }
+
+    // Infers the types of a group of mutually recursive definitions, returning their generalised types.
+    fn infer_group(&mut self, definitions: &[Definition], group: &[usize], env: &mut Env) -> Vec<(String, Scheme)> {
+        self.level += 1;
+        let types: Vec<Type> = group.iter().map(|_| self.fresh()).collect();
+        let mark = env.len();
+        for (i, ty) in group.iter().zip(&types) {
+            env.push((definitions[*i].name.clone(), Scheme::monomorphic(ty.clone())));
+        }
+
+        // Declared types are known before any definition is inferred, so that a mismatch is reported
+        // where it happens rather than where the definition ends.
+        let mut declared_variables = Vec::new();
+        for (i, ty) in group.iter().zip(&types) {
+            let mut variables = HashMap::new();
+            if let Some(declared) = definitions[*i]
+                .declared
+                .and_then(|declared| self.annotation_type(declared, &mut variables))
+            {
+                self.unify(ty, &declared);
+            }
+            declared_variables.push(variables);
+        }
+
+        for (i, ty) in group.iter().zip(&types) {
+            let definition = &definitions[*i];
+            match &definition.kind {
+                DefinitionKind::Function { parameters, body } => {
+                    let actual = self.infer_function(parameters, body, definition.annotation, definition.span, env, Some(ty));
+                    if !self.unify(ty, &actual) {
+                        let types = display_types(&[&self.zonk(ty), &self.zonk(&actual)]);
+                        self.error(
+                            definition.span,
+                            format!("{} is used as {} but defined as {}", definition.name, types[0], types[1]),
+                        );
+                    }
+                }
+                DefinitionKind::Constant(value) => {
+                    let actual = self.infer(value, env);
+                    if let Some(annotation) = definition.annotation {
+                        if let Some(annotated) = self.annotation_type(annotation, &mut HashMap::new()) {
+                            self.expect(&annotated, &actual, value);
+                        }
+                    }
+                    self.expect(ty, &actual, value);
+                }
+            }
+        }
+
+        // The type variables of a declaration must stay distinct and unconstrained, or the definition
+        // is less general than its declaration claims.
+        for ((i, ty), variables) in group.iter().zip(&types).zip(declared_variables) {
+            let resolved: HashSet<usize> = variables
+                .values()
+                .filter_map(|variable| match self.resolve(variable) {
+                    Type::Var(var) => Some(var),
+                    _ => None,
+                })
+                .collect();
+            if resolved.len() != variables.len() {
+                let definition = &definitions[*i];
+                let inferred = display_types(&[&self.zonk(ty)]).remove(0);
+                self.error(
+                    definition.span,
+                    format!(
+                        "{} is declared as {} but its definition has type {}",
+                        definition.name,
+                        definition.declared.unwrap(),
+                        inferred
+                    ),
+                );
+            }
+        }
+
+        env.truncate(mark);
+        self.level -= 1;
+        group
+            .iter()
+            .zip(&types)
+            .map(|(i, ty)| (definitions[*i].name.clone(), self.generalize(ty)))
+            .collect()
+    }
+
+    // The type written in an annotation. `variables` holds the type variables of the annotations in
+    // the same definition, so that they refer to the same types.
+    fn annotation_type(&mut self, annotation: &Datum, variables: &mut HashMap<String, Type>) -> Option<Type> {
+        let mut names = self.type_names.clone();
+        names.extend(variables.iter().map(|(name, ty)| (name.clone(), ty.clone())));
+        match parse_type(annotation, &mut names, &mut || self.fresh()) {
+            Ok(ty) => {
+                variables.extend(names.into_iter().filter(|(name, _)| !self.type_names.contains_key(name)));
+                Some(ty)
+            }
+            Err(error) => {
+                self.errors.push(error);
+                None
+            }
+        }
+    }
+
+    // `expected` is the type the function is known to have, if any, such as a declared type.
+    fn infer_function(
+        &mut self,
+        parameters: &[Datum],
+        body: &[Datum],
+        annotation: Option<&Datum>,
+        span: Span,
+        env: &mut Env,
+        expected: Option<&Type>,
+    ) -> Type {
+        let (parameter_types, result) = match expected.map(|ty| self.resolve(ty)) {
+            Some(Type::Function(parameter_types, result)) if parameter_types.len() == parameters.len() => {
+                (parameter_types, *result)
+            }
+            _ => (parameters.iter().map(|_| self.fresh()).collect(), self.fresh()),
+        };
+
+        let mark = env.len();
+        let mut variables = HashMap::new();
+        for (parameter, ty) in parameters.iter().zip(&parameter_types) {
+            match parameter_annotation(parameter) {
+                Some((name, annotation)) => {
+                    if let Some(annotated) = annotation.and_then(|annotation| self.annotation_type(annotation, &mut variables)) {
+                        self.expect(&annotated, ty, parameter);
+                    }
+                    env.push((name.to_string(), Scheme::monomorphic(ty.clone())));
+                }
+                None => self.error(parameter.span, format!("Invalid function parameter: {}", parameter)),
+            }
+        }
+        if let Some(annotation) = annotation {
+            if let Some(annotated) = self.annotation_type(annotation, &mut variables) {
+                self.expect(&annotated, &result, annotation);
+            }
+        }
+
+        let body_type = self.infer_body(body, span, env);
+        if let Some(last) = body.last() {
+            self.expect(&result, &body_type, last);
+        }
+        env.truncate(mark);
+        Type::Function(parameter_types, Box::new(result))
+    }
+
+    // Internal definitions followed by one expression.
+    fn infer_body(&mut self, forms: &[Datum], span: Span, env: &mut Env) -> Type {
+        let Some((result, forms)) = forms.split_last() else {
+            self.error(span, "Expected a body".to_string());
+            return self.fresh();
+        };
+
+        let declarations: HashMap<&str, &Datum> = forms.iter().filter_map(type_declaration).collect();
+        let mut definitions = Vec::new();
+        for form in forms {
+            match definition(form, true, &declarations) {
+                Some(definition) => definitions.push(definition),
+                None if type_declaration(form).is_some() => {}
+                None => self.error(form.span, format!("Expected a definition: {}", form)),
+            }
+        }
+
+        let mark = env.len();
+        for group in dependency_groups(&definitions) {
+            let schemes = self.infer_group(&definitions, &group, env);
+            env.extend(schemes);
+        }
+        let ty = self.infer(result, env);
+        env.truncate(mark);
+        ty
+    }
+
+    fn infer(&mut self, datum: &Datum, env: &mut Env) -> Type {
+        match &datum.kind {
+            DatumKind::Number(_) => Type::Integer,
+            DatumKind::Boolean(_) => Type::Boolean,
+            DatumKind::String(_) => Type::String,
+            DatumKind::Char(_) => Type::Char,
+            DatumKind::Vector(_) => self.quoted_type(datum),
+            DatumKind::Symbol(s) if s == "empty" || s == "null" => Type::List(Box::new(self.fresh())),
+            DatumKind::Symbol(name) => self.variable_type(name, datum, env),
+            DatumKind::Keyword(_) => {
+                self.error(
+                    datum.span,
+                    format!("Keywords are only allowed as function arguments: {}", datum),
+                );
+                self.fresh()
+            }
+            DatumKind::List(items) => match head_symbol(datum) {
+                Some("define") => {
+                    self.error(
+                        datum.span,
+                        format!("Definitions are only allowed at the start of a body: {}", datum),
+                    );
+                    self.fresh()
+                }
+                Some("cond") => self.infer_cond(&items[1..], env),
+                Some("quote") if items.len() == 2 => self.quoted_type(&items[1]),
+                Some("quasiquote") if items.len() == 2 => self.quasiquoted_type(&items[1], 0, env),
+                Some("lambda" | "λ") if items.len() >= 3 => match items[1].as_list() {
+                    Some(parameters) => self.infer_function(parameters, &items[2..], None, datum.span, env, None),
+                    None => {
+                        self.error(items[1].span, format!("Invalid lambda parameters: {}", items[1]));
+                        self.fresh()
+                    }
+                },
+                Some("with-handlers") if items.len() >= 3 => self.infer_with_handlers(datum, env),
+                Some(name) => self.infer_call(name, datum, env),
+                None if !items.is_empty() => {
+                    let function = self.infer(&items[0], env);
+                    let arguments: Vec<Type> = items[1..].iter().map(|argument| self.infer(argument, env)).collect();
+                    self.apply(function, &arguments, &items[1..], datum)
+                }
+                None => {
+                    self.error(datum.span, "Invalid empty expression ()".to_string());
+                    self.fresh()
+                }
+            },
+        }
+    }
+
+    fn variable_type(&mut self, name: &str, datum: &Datum, env: &Env) -> Type {
+        if let Some(scheme) = self.lookup(name, env) {
+            return self.instantiate(&scheme);
+        }
+
+        if is_primitive(name) {
+            return self.primitive_value_type(name);
+        }
+
+        self.error(datum.span, format!("Undefined variable {}", name));
+        self.fresh()
+    }
+
+    fn lookup(&self, name: &str, env: &Env) -> Option<Scheme> {
+        env.iter()
+            .rev()
+            .find(|(bound, _)| bound == name)
+            .map(|(_, scheme)| scheme.clone())
+            .or_else(|| self.globals.get(name).cloned())
+    }
+
+    // (name arguments ...), where arguments may include keyword arguments.
+    fn infer_call(&mut self, name: &str, datum: &Datum, env: &mut Env) -> Type {
+        let items = datum.as_list().unwrap();
+        let mut arguments = Vec::new();
+        let mut keywords = Vec::new();
+        let mut rest = items[1..].iter();
+        while let Some(argument) = rest.next() {
+            match &argument.kind {
+                DatumKind::Keyword(keyword) => match rest.next() {
+                    Some(value) => keywords.push((keyword.as_str(), value)),
+                    None => self.error(argument.span, format!("Missing value for keyword argument: {}", argument)),
+                },
+                _ => arguments.push(argument),
+            }
+        }
+
+        let function = match self.lookup(name, env) {
+            Some(scheme) => {
+                self.reject_keywords(name, &keywords, datum);
+                self.instantiate(&scheme)
+            }
+            None if name == "sort" && !keywords.is_empty() && keywords.iter().all(|(keyword, _)| *keyword == "key") => {
+                arguments.extend(keywords.iter().map(|(_, value)| *value));
+                self.primitive_call_type("sort #:key", arguments.len())
+                    .unwrap_or_else(|| self.fresh())
+            }
+            None if is_primitive(name) => {
+                self.reject_keywords(name, &keywords, datum);
+                match self.primitive_call_type(name, arguments.len()) {
+                    Some(function) => function,
+                    None => {
+                        self.error(
+                            datum.span,
+                            format!("Function {} cannot be called with {} argument(s)", name, arguments.len()),
+                        );
+                        self.fresh()
+                    }
+                }
+            }
+            None => {
+                self.error(datum.span, format!("Undefined function {}", name));
+                self.fresh()
+            }
+        };
+
+        let types: Vec<Type> = arguments.iter().map(|argument| self.infer(argument, env)).collect();
+        let arguments: Vec<Datum> = arguments.into_iter().cloned().collect();
+        self.apply(function, &types, &arguments, datum)
+    }
+
+    fn reject_keywords(&mut self, name: &str, keywords: &[(&str, &Datum)], datum: &Datum) {
+        if let Some((keyword, _)) = keywords.first() {
+            self.error(
+                datum.span,
+                format!("Function {} does not accept the keyword argument #:{}", name, keyword),
+            );
+        }
+    }
+
+    // The result of calling a function of the given type, checking each argument against its parameter.
+    fn apply(&mut self, function: Type, arguments: &[Type], argument_datums: &[Datum], call: &Datum) -> Type {
+        match self.resolve(&function) {
+            Type::Function(parameters, result) if parameters.len() == arguments.len() => {
+                for ((parameter, argument), datum) in parameters.iter().zip(arguments).zip(argument_datums) {
+                    self.expect(parameter, argument, datum);
+                }
+                *result
+            }
+            Type::Function(parameters, result) => {
+                self.error(
+                    call.span,
+                    format!(
+                        "Function expects {} argument(s) but was called with {}: {}",
+                        parameters.len(),
+                        arguments.len(),
+                        call
+                    ),
+                );
+                *result
+            }
+            function => {
+                let result = self.fresh();
+                let expected = Type::Function(arguments.to_vec(), Box::new(result.clone()));
+                if !self.unify(&function, &expected) {
+                    let function = display_types(&[&self.zonk(&function)]).remove(0);
+                    self.error(call.span, format!("expected a function but got {}: {}", function, call));
+                }
+                result
+            }
+        }
+    }
+
+    // (cond [test result] ...), where every test is a boolean and every result has the same type.
+    fn infer_cond(&mut self, cases: &[Datum], env: &mut Env) -> Type {
+        let result = self.fresh();
+        for case in cases {
+            match case.as_list().map(|items| &items[..]) {
+                Some([test, value]) => {
+                    if test.as_symbol() != Some("else") {
+                        let ty = self.infer(test, env);
+                        self.expect(&Type::Boolean, &ty, test);
+                    }
+                    let ty = self.infer(value, env);
+                    self.expect(&result, &ty, value);
+                }
+                _ => self.error(case.span, format!("Malformed cond case: {}", case)),
+            }
+        }
+        result
+    }
+
+    // Any value can be raised, so each clause checks its predicate and handler against its own type
+    // of raised value. Handlers produce the same type as the body.
+    fn infer_with_handlers(&mut self, datum: &Datum, env: &mut Env) -> Type {
+        let items = datum.as_list().unwrap();
+        let result = self.infer_body(&items[2..], datum.span, env);
+
+        for clause in items[1].as_list().map(|clauses| &clauses[..]).unwrap_or_default() {
+            match clause.as_list().map(|parts| &parts[..]) {
+                Some([predicate, handler]) => {
+                    let raised = self.fresh();
+                    let ty = self.infer(predicate, env);
+                    self.expect(&Type::Function(vec![raised.clone()], Box::new(Type::Boolean)), &ty, predicate);
+                    let ty = self.infer(handler, env);
+                    self.expect(&Type::Function(vec![raised], Box::new(result.clone())), &ty, handler);
+                }
+                _ => self.error(clause.span, format!("Malformed with-handlers clause: {}", clause)),
+            }
+        }
+        result
+    }
+
+    fn quoted_type(&mut self, datum: &Datum) -> Type {
+        match &datum.kind {
+            DatumKind::Number(_) => Type::Integer,
+            DatumKind::Boolean(_) => Type::Boolean,
+            DatumKind::String(_) => Type::String,
+            DatumKind::Char(_) => Type::Char,
+            DatumKind::Symbol(_) | DatumKind::Keyword(_) => Type::Symbol,
+            DatumKind::List(items) => Type::List(Box::new(self.element_type(items))),
+            DatumKind::Vector(items) => Type::Vector(Box::new(self.element_type(items))),
+        }
+    }
+
+    fn element_type(&mut self, items: &[Datum]) -> Type {
+        let element = self.fresh();
+        for item in items {
+            let ty = self.quoted_type(item);
+            self.expect(&element, &ty, item);
+        }
+        element
+    }
+
+    // Like a quoted datum, except that unquoted expressions are inferred. Only unquotes outside any
+    // nested quasiquote are evaluated.
+    fn quasiquoted_type(&mut self, datum: &Datum, depth: usize, env: &mut Env) -> Type {
+        let Some(items) = datum.as_list() else {
+            return self.quoted_type(datum);
+        };
+        match (head_symbol(datum), &items[..]) {
+            (Some("unquote"), [_, expr]) if depth == 0 => return self.infer(expr, env),
+            (Some("quasiquote"), [_, template]) => return self.quasiquoted_type(template, depth + 1, env),
+            (Some("unquote" | "unquote-splicing"), [_, template]) if depth > 0 => {
+                return self.quasiquoted_type(template, depth - 1, env)
+            }
+            _ => {}
+        }
+
+        let element = self.fresh();
+        for item in items {
+            match item.as_list().map(|parts| &parts[..]) {
+                Some([head, expr]) if depth == 0 && head.as_symbol() == Some("unquote-splicing") => {
+                    let ty = self.infer(expr, env);
+                    self.expect(&Type::List(Box::new(element.clone())), &ty, expr);
+                }
+                _ => {
+                    let ty = self.quasiquoted_type(item, depth, env);
+                    self.expect(&element, &ty, item);
+                }
+            }
+        }
+        Type::List(Box::new(element))
+    }
+
+    fn signatures(&mut self, name: &str) -> Vec<(Vec<Type>, Option<Type>, Type)> {
+        PRIMITIVE_TYPES
+            .iter()
+            .filter(|(primitive, _)| *primitive == name)
+            .map(|(_, signature)| self.parse_signature(signature))
+            .collect()
+    }
+
+    // (-> parameter ... result), where the last parameter may be followed by * to accept any number.
+    fn parse_signature(&mut self, signature: &str) -> (Vec<Type>, Option<Type>, Type) {
+        let datum = read(signature.to_string()).unwrap();
+        let items = datum.as_list().unwrap();
+        let mut variables = HashMap::new();
+        let mut types = Vec::new();
+        let mut rest = None;
+        for item in &items[1..] {
+            if item.as_symbol() == Some("*") {
+                rest = types.pop();
+                continue;
+            }
+            let ty = parse_type(item, &mut variables, &mut || self.fresh()).unwrap();
+            types.push(ty);
+        }
+        let result = types.pop().unwrap();
+        (types, rest, result)
+    }
+
+    // The type of a primitive when it is called with `count` arguments, if it can be.
+    fn primitive_call_type(&mut self, name: &str, count: usize) -> Option<Type> {
+        if let Some(path) = cxr_path(name) {
+            return (count == 1).then(|| self.cxr_type(path));
+        }
+        match name {
+            _ if name.starts_with("exn") && name.ends_with('?') => {
+                return (count == 1).then(|| Type::Function(vec![self.fresh()], Box::new(Type::Boolean)));
+            }
+            // (error 'name "format" values ...) accepts values of any type.
+            "error" => {
+                let parameters = (0..count).map(|_| self.fresh()).collect();
+                return (count >= 1).then(|| Type::Function(parameters, Box::new(self.fresh())));
+            }
+            // (hash key value ...) with keys of one type and values of another.
+            "hash" => {
+                let (key, value) = (self.fresh(), self.fresh());
+                let parameters = (0..count)
+                    .map(|i| if i % 2 == 0 { key.clone() } else { value.clone() })
+                    .collect();
+                return count
+                    .is_multiple_of(2)
+                    .then(|| Type::Function(parameters, Box::new(Type::Hash(Box::new(key), Box::new(value)))));
+            }
+            _ => {}
+        }
+
+        self.signatures(name)
+            .into_iter()
+            .find_map(|(mut parameters, rest, result)| match rest {
+                None if parameters.len() == count => Some(Type::Function(parameters, Box::new(result))),
+                Some(rest) if parameters.len() <= count => {
+                    parameters.resize(count, rest);
+                    Some(Type::Function(parameters, Box::new(result)))
+                }
+                _ => None,
+            })
+    }
+
+    // A primitive used as a value takes its first number of arguments. Arithmetic takes two, as it
+    // does when it is folded over a list, and other functions of any number of arguments are not checked.
+    fn primitive_value_type(&mut self, name: &str) -> Type {
+        if let Some(function) = self
+            .primitive_call_type(name, 1)
+            .filter(|_| cxr_path(name).is_some() || name.starts_with("exn"))
+        {
+            return function;
+        }
+        let count = match self.signatures(name).first() {
+            Some((parameters, None, _)) => parameters.len(),
+            Some(_) if matches!(name, "+" | "-" | "*") => 2,
+            _ => return self.fresh(),
+        };
+        self.primitive_call_type(name, count).unwrap_or_else(|| self.fresh())
+    }
+
+    // cadr and friends apply car and cdr from right to left.
+    fn cxr_type(&mut self, path: &str) -> Type {
+        let result = self.fresh();
+        let mut ty = result.clone();
+        for op in path.chars() {
+            ty = match op {
+                'a' => Type::List(Box::new(ty)),
+                _ => {
+                    let element = self.fresh();
+                    self.unify(&ty, &Type::List(Box::new(element)));
+                    ty
+                }
+            };
+        }
+        Type::Function(vec![ty], Box::new(result))
+    }
+}
+
+fn cxr_path(name: &str) -> Option<&str> {
+    name.strip_prefix('c')
+        .and_then(|name| name.strip_suffix('r'))
+        .filter(|path| (2..=4).contains(&path.len()) && path.chars().all(|c| c == 'a' || c == 'd'))
+}
+
+// Splits definitions into groups of mutually recursive definitions, ordered so that each group comes
+// after the groups it refers to (Tarjan's algorithm).
+// Recognises (define (name parameters ...) body ...) and (define name value), with their annotations.
+fn definition<'a>(datum: &'a Datum, from_program: bool, declarations: &HashMap<&str, &'a Datum>) -> Option<Definition<'a>> {
+    let (name, kind) = if let Some((name, body)) = function_definition(datum) {
+        let parameters = &datum.as_list().unwrap()[1].as_list().unwrap()[1..];
+        (name, DefinitionKind::Function { parameters, body })
+    } else {
+        let (name, value) = constant_definition(datum)?;
+        (name, DefinitionKind::Constant(value))
+    };
+
+    Some(Definition {
+        name: name.to_string(),
+        kind,
+        span: datum.span,
+        declared: declarations.get(name).copied(),
+        annotation: definition_annotation(datum),
+        from_program,
+    })
+}
+
+fn dependency_groups(definitions: &[Definition]) -> Vec<Vec<usize>> {
+    let indices: HashMap<&str, usize> = definitions.iter().enumerate().map(|(i, d)| (d.name.as_str(), i)).collect();
+    let edges: Vec<Vec<usize>> = definitions
+        .iter()
+        .map(|definition| {
+            let mut references = HashSet::new();
+            match &definition.kind {
+                DefinitionKind::Function { body, .. } => body.iter().for_each(|form| collect_symbols(form, &mut references)),
+                DefinitionKind::Constant(value) => collect_symbols(value, &mut references),
+            }
+            let mut edges: Vec<usize> = references
+                .iter()
+                .filter_map(|name| indices.get(name.as_str()).copied())
+                .collect();
+            edges.sort();
+            edges
+        })
+        .collect();
+
+    struct Tarjan<'a> {
+        edges: &'a [Vec<usize>],
+        index: Vec<Option<usize>>,
+        low: Vec<usize>,
+        stack: Vec<usize>,
+        on_stack: Vec<bool>,
+        next: usize,
+        groups: Vec<Vec<usize>>,
+    }
+
+    impl Tarjan<'_> {
+        fn visit(&mut self, v: usize) {
+            self.index[v] = Some(self.next);
+            self.low[v] = self.next;
+            self.next += 1;
+            self.stack.push(v);
+            self.on_stack[v] = true;
+
+            for &w in &self.edges[v] {
+                match self.index[w] {
+                    None => {
+                        self.visit(w);
+                        self.low[v] = self.low[v].min(self.low[w]);
+                    }
+                    Some(index) if self.on_stack[w] => self.low[v] = self.low[v].min(index),
+                    _ => {}
+                }
+            }
+
+            if Some(self.low[v]) == self.index[v] {
+                let mut group = Vec::new();
+                while let Some(w) = self.stack.pop() {
+                    self.on_stack[w] = false;
+                    group.push(w);
+                    if w == v {
+                        break;
+                    }
+                }
+                group.sort();
+                self.groups.push(group);
+            }
+        }
+    }
+
+    let mut tarjan = Tarjan {
+        edges: &edges,
+        index: vec![None; definitions.len()],
+        low: vec![0; definitions.len()],
+        stack: Vec::new(),
+        on_stack: vec![false; definitions.len()],
+        next: 0,
+        groups: Vec::new(),
+    };
+    for v in 0..definitions.len() {
+        if tarjan.index[v].is_none() {
+            tarjan.visit(v);
+        }
+    }
+    tarjan.groups
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::error::SyntaxError;
+use crate::interpret::Value;
+use crate::reader::{Datum, DatumKind};
+use crate::tokenizer::Span;
+
+// Static types of values, as inferred by the type checker. They are written the way Typed Racket
+// writes them, e.g. (-> (Listof Integer) Boolean).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Integer,
+    Boolean,
+    String,
+    Char,
+    Symbol,
+    Exn,
+    List(Box<Type>),
+    Vector(Box<Type>),
+    Hash(Box<Type>, Box<Type>),
+    Function(Vec<Type>, Box<Type>),
+    // A struct defined by the program, by name.
+    Struct(String),
+    // Values of any of the types. Unions and Any are only checked statically where they meet a
+    // concrete type, and are otherwise enforced when the program runs.
+    Union(Vec<Type>),
+    Any,
+    // Stands for a type that inference has not determined yet, or for any type in a polymorphic scheme.
+    Var(usize),
+}
+
+// A type that is used wrongly or written wrongly, at the location of the offending datum.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl TypeError {
+    pub fn new(datum: &Datum, message: String) -> Self {
+        TypeError {
+            span: datum.span,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Type error at {}: {}", self.span, self.message)
+    }
+}
+
+// Annotations that cannot be read stop the program from being parsed.
+impl From<TypeError> for SyntaxError {
+    fn from(error: TypeError) -> Self {
+        SyntaxError::syntax(error.span, error.to_string())
+    }
+}
+
+// A type that may be polymorphic in some of its variables, such as (All (a) (-> (Listof a) Integer)).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scheme {
+    pub generics: Vec<usize>,
+    pub ty: Type,
+}
+
+impl Scheme {
+    pub fn monomorphic(ty: Type) -> Self {
+        Scheme {
+            generics: Vec::new(),
+            ty,
+        }
+    }
+}
+
+// Names type variables a, b, c ... in the order they are first printed, so that the types printed
+// by one namer refer to the same variables by the same names.
+#[derive(Default)]
+struct VariableNamer {
+    names: HashMap<usize, String>,
+}
+
+impl VariableNamer {
+    fn name(&mut self, var: usize) -> String {
+        let count = self.names.len();
+        self.names.entry(var).or_insert_with(|| variable_name(count)).clone()
+    }
+
+    fn show(&mut self, ty: &Type) -> String {
+        match ty {
+            Type::Integer => "Integer".to_string(),
+            Type::Boolean => "Boolean".to_string(),
+            Type::String => "String".to_string(),
+            Type::Char => "Char".to_string(),
+            Type::Symbol => "Symbol".to_string(),
+            Type::Exn => "Exn".to_string(),
+            Type::List(element) => format!("(Listof {})", self.show(element)),
+            Type::Vector(element) => format!("(Vectorof {})", self.show(element)),
+            Type::Hash(key, value) => format!("(HashTable {} {})", self.show(key), self.show(value)),
+            Type::Function(parameters, result) => {
+                let mut parts = vec!["->".to_string()];
+                parts.extend(parameters.iter().map(|parameter| self.show(parameter)));
+                parts.push(self.show(result));
+                format!("({})", parts.join(" "))
+            }
+            Type::Struct(name) => name.clone(),
+            Type::Union(members) => {
+                let members: Vec<String> = members.iter().map(|member| self.show(member)).collect();
+                format!("(U {})", members.join(" "))
+            }
+            Type::Any => "Any".to_string(),
+            Type::Var(var) => self.name(*var),
+        }
+    }
+}
+
+fn variable_name(i: usize) -> String {
+    let letter = (b'a' + (i % 26) as u8) as char;
+    match i / 26 {
+        0 => letter.to_string(),
+        n => format!("{}{}", letter, n),
+    }
+}
+
+// Prints types that appear together, such as the two sides of a mismatch, with consistent variable names.
+pub fn display_types(types: &[&Type]) -> Vec<String> {
+    let mut namer = VariableNamer::default();
+    types.iter().map(|ty| namer.show(ty)).collect()
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", VariableNamer::default().show(self))
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut namer = VariableNamer::default();
+        let ty = namer.show(&self.ty);
+        if self.generics.is_empty() {
+            return write!(f, "{}", ty);
+        }
+
+        // Generics are listed in the order their names were given out.
+        let mut generics: Vec<String> = self.generics.iter().map(|var| namer.name(*var)).collect();
+        generics.sort_by_key(|name| (name.len(), name.clone()));
+        write!(f, "(All ({}) {})", generics.join(" "), ty)
+    }
+}
+
+// Reads a type written in Typed Racket syntax. `names` holds the struct types and type variables in
+// scope. Single lowercase letters that are not in scope are new type variables, which are created
+// with `fresh` and added to `names`.
+pub fn parse_type(datum: &Datum, names: &mut HashMap<String, Type>, fresh: &mut dyn FnMut() -> Type) -> Result<Type, TypeError> {
+    if let Some(name) = datum.as_symbol() {
+        return match name {
+            "Integer" | "Number" => Ok(Type::Integer),
+            "Boolean" => Ok(Type::Boolean),
+            "String" => Ok(Type::String),
+            "Char" => Ok(Type::Char),
+            "Symbol" => Ok(Type::Symbol),
+            "Exn" => Ok(Type::Exn),
+            "Any" => Ok(Type::Any),
+            _ if names.contains_key(name) => Ok(names[name].clone()),
+            _ if is_type_variable(name) => Ok(names.entry(name.to_string()).or_insert_with(&mut *fresh).clone()),
+            _ => Err(TypeError::new(datum, format!("Unknown type {}", name))),
+        };
+    }
+
+    let items = match &datum.kind {
+        DatumKind::List(items) => items,
+        _ => return Err(TypeError::new(datum, format!("Invalid type {}", datum))),
+    };
+
+    let head = items.first().and_then(|head| head.as_symbol());
+
+    // Type variables are implicitly polymorphic, so (All (a) t) only has to bring a into scope.
+    if let (Some("All"), [_, variables, ty]) = (head, &items[..]) {
+        for variable in variables.as_list().into_iter().flatten() {
+            match variable.as_symbol() {
+                Some(name) => names.insert(name.to_string(), fresh()),
+                None => return Err(TypeError::new(variable, format!("Invalid type variable {}", variable))),
+            };
+        }
+        return parse_type(ty, names, fresh);
+    }
+
+    let mut parse = |datum: &Datum| parse_type(datum, names, fresh);
+    match (head, &items[..]) {
+        (Some("Listof"), [_, element]) => Ok(Type::List(Box::new(parse(element)?))),
+        (Some("Vectorof"), [_, element]) => Ok(Type::Vector(Box::new(parse(element)?))),
+        (Some("HashTable"), [_, key, value]) => Ok(Type::Hash(Box::new(parse(key)?), Box::new(parse(value)?))),
+        (Some("U"), [_, members @ ..]) => Ok(Type::Union(
+            members.iter().map(&mut parse).collect::<Result<Vec<Type>, TypeError>>()?,
+        )),
+        (Some("->"), [_, parameters @ .., result]) => Ok(Type::Function(
+            parameters.iter().map(&mut parse).collect::<Result<Vec<Type>, TypeError>>()?,
+            Box::new(parse(result)?),
+        )),
+        _ => Err(TypeError::new(datum, format!("Invalid type {}", datum))),
+    }
+}
+
+fn is_type_variable(name: &str) -> bool {
+    name.len() == 1 && name.chars().all(|c| c.is_ascii_lowercase())
+}
+
+// Whether a value belongs to a type, as checked when an annotated function is called. Functions are
+// only checked to be functions, and type variables accept any value.
+pub fn value_has_type(value: &Value, ty: &Type) -> bool {
+    match (ty, value) {
+        (Type::Any | Type::Var(_), _) => true,
+        (Type::Integer, Value::NumValue(_))
+        | (Type::Boolean, Value::BoolValue(_))
+        | (Type::String, Value::StringValue(_))
+        | (Type::Char, Value::CharValue(_))
+        | (Type::Symbol, Value::SymbolValue(_))
+        | (Type::Exn, Value::ExnValue(_))
+        | (Type::Function(_, _), Value::FunctionValue(_)) => true,
+        (Type::List(element), Value::ListValue(list)) => list.to_vec().iter().all(|value| value_has_type(value, element)),
+        (Type::Vector(element), Value::VectorValue(vector)) => vector.iter().all(|value| value_has_type(value, element)),
+        (Type::Hash(key, value), Value::HashValue(hash)) => {
+            hash.iter().all(|(k, v)| value_has_type(k, key) && value_has_type(v, value))
+        }
+        (Type::Struct(name), Value::StructValue(instance)) => instance.name == *name,
+        (Type::Union(members), _) => members.iter().any(|member| value_has_type(value, member)),
+        _ => false,
+    }
+}